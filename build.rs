@@ -0,0 +1,52 @@
+//! Records the declared mqtt-protocol-core requirement for `build_info()`
+
+use std::path::{Path, PathBuf};
+
+fn main() {
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    let manifest = manifest_dir.join("Cargo.toml");
+
+    let version = declared_requirement(&manifest, "mqtt-protocol-core")
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=MQTT_PROTOCOL_CORE_VERSION={}", version);
+    println!("cargo:rerun-if-changed={}", manifest.display());
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
+/// Version requirement of dependency `package` in this crate's Cargo.toml
+///
+/// Accepts `package = "1.0"`, `package = { version = "1.0", .. }` and a
+/// `[dependencies.package]` table, as written by `cargo publish`.
+fn declared_requirement(manifest: &Path, package: &str) -> Option<String> {
+    let content = std::fs::read_to_string(manifest).ok()?;
+    let table_header = format!("[dependencies.{}]", package);
+    let mut section = "";
+    for line in content.lines().map(str::trim) {
+        if line.starts_with('[') {
+            section = line;
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if section == table_header {
+            if key == "version" {
+                return quoted(value);
+            }
+        } else if section == "[dependencies]" && key == package {
+            if value.starts_with('{') {
+                let (_, rest) = value.split_once("version")?;
+                return quoted(rest.trim_start().strip_prefix('=')?.trim_start());
+            }
+            return quoted(value);
+        }
+    }
+    None
+}
+
+/// Contents of the string literal at the start of `value`
+fn quoted(value: &str) -> Option<String> {
+    let rest = value.strip_prefix('"')?;
+    Some(rest[..rest.find('"')?].to_string())
+}
@@ -17,9 +17,16 @@ const {
     WasmMqttClient,
     WasmMqttConfig,
     WasmMqttPacket,
+    WasmPacketFactory,
     WasmPacketType,
+    WasmQos,
+    WasmReasonCode,
+    WasmRetainHandling,
     JsTransport,
     createClientWithJsTransport,
+    buildInfo,
+    validateTopicFilter,
+    validateTopicName,
     init
 } = require('../pkg-nodejs/mqtt_client_wasm.js');
 
@@ -471,9 +478,16 @@ module.exports = {
     WasmMqttClient,
     WasmMqttConfig,
     WasmMqttPacket,
+    WasmPacketFactory,
     WasmPacketType,
+    WasmQos,
+    WasmReasonCode,
+    WasmRetainHandling,
     JsTransport,
     createClientWithJsTransport,
+    buildInfo,
+    validateTopicFilter,
+    validateTopicName,
     init,
 
     // Transport types
@@ -0,0 +1,40 @@
+//! Build metadata for bug reports
+//!
+//! Tells which crate version, protocol library and features a binary was
+//! built with, e.g. to spot a stale WASM bundle served from a cache.
+
+use serde::Serialize;
+
+/// Versions, target and enabled cargo features of this build
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildInfo {
+    /// Version of mqtt-client-wasm
+    pub crate_version: &'static str,
+    /// Version requirement on mqtt-protocol-core declared in Cargo.toml, e.g.
+    /// "0.7" ("unknown" if it could not be read at build time)
+    pub mqtt_protocol_core_version: &'static str,
+    /// Target architecture, e.g. "wasm32" or "x86_64"
+    pub target_arch: &'static str,
+    /// Enabled cargo features
+    pub features: Vec<&'static str>,
+}
+
+/// Get the build metadata of this library
+pub fn build_info() -> BuildInfo {
+    let features = [
+        ("js-wrappers", cfg!(feature = "js-wrappers")),
+        ("native", cfg!(feature = "native")),
+        ("test-hooks", cfg!(feature = "test-hooks")),
+    ];
+    BuildInfo {
+        crate_version: env!("CARGO_PKG_VERSION"),
+        mqtt_protocol_core_version: env!("MQTT_PROTOCOL_CORE_VERSION"),
+        target_arch: std::env::consts::ARCH,
+        features: features
+            .into_iter()
+            .filter(|(_, enabled)| *enabled)
+            .map(|(name, _)| name)
+            .collect(),
+    }
+}
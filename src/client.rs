@@ -4,7 +4,7 @@
 //! to ensure complete separation and testability
 
 #[cfg(target_arch = "wasm32")]
-use crate::log;
+use crate::log_labeled;
 #[cfg(target_arch = "wasm32")]
 use crate::websocket::BrowserWebSocket;
 use crate::{error::*, types::*, websocket::*};
@@ -13,7 +13,12 @@ use futures::stream::StreamExt;
 use futures::{select, FutureExt};
 use mqtt_protocol_core::mqtt;
 use mqtt_protocol_core::mqtt::prelude::*;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Transport outcomes of the writes caused by one send, awaited by the caller
+type SendResults = Vec<oneshot::Receiver<Result<()>>>;
 
 /// Requests from public API to internal processor
 #[derive(Debug)]
@@ -21,19 +26,59 @@ pub enum Request {
     /// Connect WebSocket to broker
     Connect {
         url: String,
+        timeout_ms: Option<u64>,
         reply: oneshot::Sender<Result<()>>,
     },
     /// Send a packet
     Send {
         packet: mqtt::packet::Packet,
-        reply: oneshot::Sender<Result<()>>,
+        reply: oneshot::Sender<Result<SendResults>>,
+    },
+    /// Send a packet and report the number of bytes written
+    SendSized {
+        packet: mqtt::packet::Packet,
+        reply: oneshot::Sender<Result<(usize, SendResults)>>,
+    },
+    /// Send one pre-encoded packet as is
+    SendEncoded {
+        bytes: Vec<u8>,
+        reply: oneshot::Sender<Result<SendResults>>,
+    },
+    /// Send a SUBSCRIBE and wait for the matching SUBACK
+    Subscribe {
+        packet: mqtt::packet::Packet,
+        reply: oneshot::Sender<Result<mqtt::packet::Packet>>,
     },
     /// Receive a packet
     Recv {
         reply: oneshot::Sender<Result<mqtt::packet::Packet>>,
     },
+    /// Receive packet along with how it was processed
+    RecvWithMeta {
+        reply: oneshot::Sender<Result<(mqtt::packet::Packet, ReceiveMeta)>>,
+    },
+    /// Receive the first packet of any of the given types
+    RecvOneOf {
+        types: Vec<mqtt::packet::PacketType>,
+        reply: oneshot::Sender<Result<mqtt::packet::Packet>>,
+    },
+    /// Receive the first v5.0 PUBLISH carrying the given subscription identifier
+    RecvForSubscription {
+        subscription_id: u32,
+        reply: oneshot::Sender<Result<mqtt::packet::Packet>>,
+    },
     /// Close connection
     Close { reply: oneshot::Sender<Result<()>> },
+    /// Send a DISCONNECT, give the broker shutdown_timeout_ms to close, then close
+    CloseWithDisconnect {
+        packet: mqtt::packet::Packet,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// End the session with or without the will message, then close
+    Disconnect {
+        trigger_will: bool,
+        reply: oneshot::Sender<Result<()>>,
+    },
     /// Get connection state
     State {
         reply: oneshot::Sender<ConnectionState>,
@@ -42,43 +87,474 @@ pub enum Request {
     IsConnected { reply: oneshot::Sender<bool> },
     /// Acquire packet ID
     AcquirePacketId { reply: oneshot::Sender<Option<u16>> },
+    /// Acquire packet ID, waiting until one is released if all are in use
+    AcquirePacketIdWait { reply: oneshot::Sender<Result<u16>> },
+    /// Send a PINGRESP for the configured protocol version
+    SendPingresp { reply: oneshot::Sender<Result<()>> },
     /// Register packet ID
     RegisterPacketId {
         packet_id: u16,
         reply: oneshot::Sender<bool>,
     },
+    /// Register several packet IDs in one request
+    RegisterPacketIds {
+        packet_ids: Vec<u16>,
+        reply: oneshot::Sender<Vec<bool>>,
+    },
     /// Release packet ID
     ReleasePacketId {
         packet_id: u16,
         reply: oneshot::Sender<Result<()>>,
     },
+    /// Release packet ID after verifying its QoS flow is complete
+    CompleteQos {
+        packet_id: u16,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Query bytes buffered by the transport
+    BufferedAmount { reply: oneshot::Sender<Result<u64>> },
+    /// Query the transport's WebSocket readyState
+    TransportReadyState { reply: oneshot::Sender<Result<u8>> },
+    /// Receive an AUTH packet (enhanced authentication)
+    RecvAuth {
+        reply: oneshot::Sender<Result<mqtt::packet::Packet>>,
+    },
+    /// Route AUTH packets back to recv()
+    StopRecvAuth { reply: oneshot::Sender<Result<()>> },
+    /// Get ping round-trip statistics
+    RttStats { reply: oneshot::Sender<RttStats> },
+    /// Build a health report around the transport's buffered amount
+    Health {
+        buffered_amount: u64,
+        reply: oneshot::Sender<HealthReport>,
+    },
+    /// Discard received packets not yet delivered to recv()
+    ClearInbound { reply: oneshot::Sender<usize> },
+    /// Close the current transport and switch to another one
+    ReplaceTransport {
+        events: mpsc::UnboundedReceiver<UnderlyingLayerEvent>,
+        commands: mpsc::UnboundedSender<UnderlyingLayerCommand>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Set the client identifier (before connect)
+    SetClientId {
+        client_id: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Wait until the transport is connected
+    WaitConnected { reply: oneshot::Sender<Result<()>> },
+    /// Connect unless already connected or connecting to the URL
+    EnsureConnected {
+        url: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Wait until the transport is closed
+    WaitClosed { reply: oneshot::Sender<()> },
+    /// Wait until no outgoing QoS 1/2 PUBLISH awaits its final acknowledgement
+    WaitInflightDrained {
+        timeout_ms: u64,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Snapshot the session state
+    ExportSession {
+        reply: oneshot::Sender<SessionSnapshot>,
+    },
+    /// Restore session state before connecting
+    ImportSession {
+        snapshot: SessionSnapshot,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Get the subscriptions confirmed by the broker
+    Subscriptions {
+        reply: oneshot::Sender<Vec<(String, u8)>>,
+    },
+    /// Get the number of subscriptions confirmed by the broker
+    SubscriptionCount { reply: oneshot::Sender<usize> },
+    /// Get the session-present flag of the most recent accepted CONNACK
+    SessionPresent {
+        reply: oneshot::Sender<Option<bool>>,
+    },
+    /// Get the reason code of the DISCONNECT that ended the latest connection
+    LastDisconnectReason { reply: oneshot::Sender<Option<u8>> },
+    /// Receive the next connection event
+    NextEvent {
+        reply: oneshot::Sender<ConnectionEvent>,
+    },
+    /// Receive the next connection state transition
+    NextStateChange { reply: oneshot::Sender<StateChange> },
+    /// Replace the outgoing topic rewriter
+    SetTopicRewriter {
+        rewriter: Option<Arc<dyn crate::topic_rewrite::TopicRewriter>>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Replace the outgoing packet inspector
+    SetSendInspector {
+        inspector: Option<Arc<dyn crate::send_inspector::SendInspector>>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Replace the wire-level packet tap
+    SetPacketTap {
+        tap: Option<Arc<dyn crate::packet_tap::PacketTap>>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Replace the handler for streamed PUBLISH payloads
+    SetPayloadChunkHandler {
+        handler: Option<Arc<dyn crate::payload_stream::PayloadChunkHandler>>,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Pause or resume inbound packet processing
+    SetRecvPaused {
+        paused: bool,
+        reply: oneshot::Sender<Result<()>>,
+    },
+    /// Handle a timer expiration as if the transport had reported it
+    #[cfg(feature = "test-hooks")]
+    TimerExpired {
+        kind: String,
+        reply: oneshot::Sender<Result<()>>,
+    },
+}
+
+/// Number of ping round-trips kept for RttStats
+const RTT_WINDOW_SIZE: usize = 20;
+
+/// Timer kind used for automatic reconnection backoff
+const RECONNECT_TIMER: &str = "Reconnect";
+
+/// Timer kind used for the `connect_timeout()` deadline
+const CONNECT_TIMER: &str = "Connect";
+
+/// Timer kind for `shutdown_timeout_ms` after `close_with_disconnect()`
+const SHUTDOWN_TIMER: &str = "Shutdown";
+
+/// Timer kind for releasing publishes held back by `max_publish_rate_per_sec`
+const PUBLISH_RATE_TIMER: &str = "PublishRate";
+
+/// Timer kind for the next packet ID whose `packet_id_reuse_delay_ms` ends
+const PACKET_ID_COOLDOWN_TIMER: &str = "PacketIdCooldown";
+
+/// Timer kind prefix for `publish_ack_timeout_ms`; the packet ID follows
+const PUBLISH_ACK_TIMER_PREFIX: &str = "PublishAck:";
+
+/// Timer kind prefix for `wait_inflight_drained()` deadlines; the waiter ID follows
+const INFLIGHT_DRAINED_TIMER_PREFIX: &str = "InflightDrained:";
+
+/// Maximum number of undelivered connection events (oldest are dropped)
+const EVENT_QUEUE_CAPACITY: usize = 256;
+
+/// Initial read buffer capacity (also what a drained buffer shrinks back to
+/// under `max_total_buffer_bytes`)
+const READ_BUFFER_CAPACITY: usize = 8192;
+
+/// Reply of a send() or send_sized() request
+enum SendReply {
+    Send(oneshot::Sender<Result<SendResults>>),
+    SendSized(oneshot::Sender<Result<(usize, SendResults)>>),
+}
+
+impl SendReply {
+    fn fail(self, error: Error) {
+        match self {
+            SendReply::Send(reply) => {
+                let _ = reply.send(Err(error));
+            }
+            SendReply::SendSized(reply) => {
+                let _ = reply.send(Err(error));
+            }
+        }
+    }
+}
+
+/// Reply of a recv() or recv_with_meta() request
+enum RecvReply {
+    Packet(oneshot::Sender<Result<mqtt::packet::Packet>>),
+    WithMeta(oneshot::Sender<Result<(mqtt::packet::Packet, ReceiveMeta)>>),
+}
+
+impl RecvReply {
+    /// Hand over a packet; gives it back if the caller stopped waiting
+    fn deliver(
+        self,
+        packet: mqtt::packet::Packet,
+        meta: ReceiveMeta,
+    ) -> Option<mqtt::packet::Packet> {
+        match self {
+            RecvReply::Packet(reply) => reply.send(Ok(packet)).err()?.ok(),
+            RecvReply::WithMeta(reply) => reply
+                .send(Ok((packet, meta)))
+                .err()?
+                .ok()
+                .map(|(packet, _)| packet),
+        }
+    }
+
+    fn fail(self, error: Error) {
+        let _ = self.refuse(error);
+    }
+
+    /// Answer with an error instead of a packet; false if the caller stopped waiting
+    fn refuse(self, error: Error) -> bool {
+        match self {
+            RecvReply::Packet(reply) => reply.send(Err(error)).is_ok(),
+            RecvReply::WithMeta(reply) => reply.send(Err(error)).is_ok(),
+        }
+    }
+}
+
+/// Which packets a recv_one_of() or recv_for_subscription() request takes
+enum RecvFilter {
+    Types(Vec<mqtt::packet::PacketType>),
+    SubscriptionId(u32),
+}
+
+impl RecvFilter {
+    fn matches(&self, packet: &mqtt::packet::Packet) -> bool {
+        match self {
+            RecvFilter::Types(types) => types.contains(&packet.packet_type()),
+            RecvFilter::SubscriptionId(id) => match packet {
+                mqtt::packet::Packet::V5_0Publish(p) => p.props.iter().any(|prop| match prop {
+                    mqtt::packet::Property::SubscriptionIdentifier(s) => s.val() == *id,
+                    _ => false,
+                }),
+                _ => false,
+            },
+        }
+    }
+
+    /// Whether a PUBLISH can satisfy the filter
+    fn accepts_publish(&self) -> bool {
+        match self {
+            RecvFilter::Types(types) => types.contains(&mqtt::packet::PacketType::Publish),
+            RecvFilter::SubscriptionId(_) => true,
+        }
+    }
+}
+
+/// A received PUBLISH whose payload is being handed to the chunk handler
+struct PayloadStream {
+    topic: String,
+    total_len: usize,
+    received: usize,
+    // The PUBLISH without its payload, parsed once the payload is through
+    empty_packet: Vec<u8>,
 }
 
 /// MQTT client with clean channel-based design
+///
+/// Cloning is cheap; all clones share the same connection. Clones may call
+/// `send()` and `recv()` concurrently (e.g. a sender loop and a receiver
+/// loop): a pending `recv()` waits without blocking other requests, and
+/// requests are handled in the order they were made.
+#[derive(Clone)]
 pub struct MqttClient {
     request_sender: mpsc::UnboundedSender<Request>,
+    // Mirrors `state == Connected`, updated by the processor on every state change
+    connected: Arc<AtomicBool>,
+    // Panic message of the processor task, set if it died
+    fatal_error: FatalError,
+}
+
+/// Where the panic message of a processor task is kept for `last_fatal_error()`
+type FatalError = Arc<std::sync::Mutex<Option<String>>>;
+
+/// The fatal error cell shared by all clients on wasm32
+///
+/// A panic aborts the whole module there, so it cannot be caught per task;
+/// a panic hook (chained to the existing one, which still logs) records the
+/// first panic anywhere in the module, not only in a processor task.
+#[cfg(target_arch = "wasm32")]
+fn wasm_fatal_error() -> FatalError {
+    static FATAL_ERROR: std::sync::OnceLock<FatalError> = std::sync::OnceLock::new();
+    FATAL_ERROR
+        .get_or_init(|| {
+            let fatal_error = FatalError::default();
+            let cell = fatal_error.clone();
+            let previous = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                if let Ok(mut error) = cell.lock() {
+                    if error.is_none() {
+                        *error = Some(info.to_string());
+                    }
+                }
+                previous(info);
+            }));
+            fatal_error
+        })
+        .clone()
+}
+
+/// Message of a caught panic
+#[cfg(not(target_arch = "wasm32"))]
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "processor panicked".to_string()
+    }
 }
 
 /// Internal MQTT processor
 struct MqttProcessor {
     config: MqttConfig,
     state: ConnectionState,
+    connected: Arc<AtomicBool>,
     mqtt_connection: mqtt::Connection<mqtt::role::Client>,
 
     // Buffer management
     read_buffer: Vec<u8>,
     buffer_size: usize,
     consumed_bytes: usize,
+    // While paused, received bytes stay in read_buffer unparsed
+    recv_paused: bool,
+    // Large PUBLISH payload currently streamed to payload_chunk_handler
+    payload_stream: Option<PayloadStream>,
+    // The next PUBLISH parsed is a streamed one rebuilt without its payload,
+    // whose wire bytes were already tapped as they arrived
+    streamed_publish_tapped: bool,
 
     // Timer management - tracks which timers are active
     // Actual timer handling is done by the underlying layer
     active_timers: HashSet<String>,
 
+    // Outgoing QoS 1/2 PUBLISH flows keyed by packet ID
+    qos_flows: HashMap<u16, InflightPhase>,
+    // Tracked flows whose packet ID the connection has already released
+    released_flow_ids: HashSet<u16>,
+    // Flows from import_session(), taken over by the next connect()
+    imported_flows: Vec<(u16, InflightPhase)>,
+    // Client identifier of the last CONNECT
+    connect_client_id: Option<String>,
+
+    // max_publish_rate_per_sec token bucket (tokens and when it was last
+    // refilled, monotonic; None = full) and the publishes waiting for a token,
+    // oldest first
+    publish_tokens: f64,
+    publish_tokens_at: Option<f64>,
+    // (with when each was queued, monotonic)
+    throttled_publishes: VecDeque<(mqtt::packet::Packet, SendReply, f64)>,
+
+    // IDs in use from config.packet_id_allocator, given back to it when the
+    // connection frees them (or is replaced on reconnection)
+    allocator_packet_ids: HashSet<u16>,
+
+    // acquire_packet_id_wait() requests waiting for a free packet ID (oldest first)
+    packet_id_waiters: VecDeque<oneshot::Sender<Result<u16>>>,
+
+    // While handling send()/send_sized(), outcomes of the transport writes
+    send_results: Option<SendResults>,
+    // Why the connection refused the packet of the last send, if it did
+    send_rejection: Option<Error>,
+    // While handling send_encoded(), the encoding of the decoded packet and
+    // the caller's bytes to write in its place
+    encoded_bytes: Option<(Vec<u8>, Vec<u8>)>,
+
+    // Ping round-trip tracking (monotonic send time of the outstanding PINGREQ)
+    pingreq_sent_at: Option<f64>,
+    rtt_samples: VecDeque<f64>,
+
     // Packet handling
-    packet_sender: mpsc::UnboundedSender<mqtt::packet::Packet>,
-    packet_receiver: mpsc::UnboundedReceiver<mqtt::packet::Packet>,
-    pending_recv_requests: Vec<oneshot::Sender<Result<mqtt::packet::Packet>>>,
-    undelivered_packet: Option<mqtt::packet::Packet>,
+    pending_recv_requests: Vec<RecvReply>,
+    // recv_one_of() and recv_for_subscription() requests with the packets
+    // they wait for, oldest first
+    pending_typed_recv_requests: Vec<(RecvFilter, oneshot::Sender<Result<mqtt::packet::Packet>>)>,
+    // Received packets not yet taken by recv(), in wire order (PUBLISH bounded
+    // by config.max_undelivered_packets; beyond that their bytes stay unparsed)
+    undelivered_packets: VecDeque<(mqtt::packet::Packet, ReceiveMeta)>,
+    // Packets parsed ahead of held-back PUBLISH bytes, with the number of those
+    // PUBLISH packets still unparsed ahead of them; queued for recv() once
+    // that reaches zero, so recv() keeps wire order
+    forwarded_packets: VecDeque<(usize, mqtt::packet::Packet, ReceiveMeta)>,
+    // While parsing a frame moved ahead of held-back PUBLISH packets, how many
+    forwarded_ahead: Option<usize>,
+    // Processing details of the packet being parsed, queued along with it
+    inbound_meta: ReceiveMeta,
+
+    // Memory accounting for config.max_total_buffer_bytes (only kept when it is
+    // set): encoded size of undelivered packets and of unacknowledged outgoing
+    // QoS 1/2 PUBLISH by packet ID, and whether MemoryPressure has been emitted
+    // since usage was last within the limit
+    undelivered_bytes: usize,
+    inflight_bytes: HashMap<u16, usize>,
+    memory_pressure: bool,
+
+    // Released packet IDs held (registered) until packet_id_reuse_delay_ms has
+    // passed, oldest first, with their (monotonic) release time
+    cooling_packet_ids: VecDeque<(u16, f64)>,
+
+    // subscribe() requests waiting for their SUBACK, keyed by packet ID
+    pending_subacks: HashMap<u16, oneshot::Sender<Result<mqtt::packet::Packet>>>,
+
+    // Inbound QoS 2 PUBLISH packet IDs delivered to the app whose PUBREL has
+    // not arrived; kept across reconnections like the session itself
+    inbound_qos2: HashSet<u16>,
+
+    // Subscriptions granted by SUBACK (filter, requested QoS) in subscribe order,
+    // and sent SUBSCRIBE/UNSUBSCRIBE filters waiting for their acknowledgement
+    subscriptions: Vec<(String, u8)>,
+    requested_subscriptions: HashMap<u16, Vec<(String, u8)>>,
+    requested_unsubscriptions: HashMap<u16, Vec<String>>,
+
+    // Whether the last CONNECT asked to resume a session (clean session/start
+    // false), and the session-present flag of the last accepted CONNACK
+    session_requested: bool,
+    session_present: Option<bool>,
+
+    // AUTH packets are routed separately once the app asks for them
+    auth_routing: bool,
+    pending_auth_requests: Vec<oneshot::Sender<Result<mqtt::packet::Packet>>>,
+    undelivered_auth: VecDeque<mqtt::packet::Packet>,
+
+    // Close code/reason from the transport, reported with the next Closed event
+    close_info: Option<(u16, String)>,
+
+    // Total bytes handed to the transport (used to measure a single send)
+    sent_bytes: usize,
+    // Time bytes were last received, keep alive of the last CONNECT, and the
+    // buffered amount seen by the previous health() call
+    last_recv_at: Option<f64>,
+    // Reason code of the v5.0 DISCONNECT received since the last CONNECT
+    last_disconnect_reason: Option<u8>,
+    // Maximum Packet Size of the last v5.0 CONNACK, if the broker set one
+    server_maximum_packet_size: Option<u32>,
+    keep_alive_secs: u16,
+    last_buffered_amount: u64,
+
+    // wait_connected() requests, resolved by the next Connected or Closed event
+    connected_waiters: Vec<oneshot::Sender<Result<()>>>,
+    // ensure_connected() callers sharing the outcome of the attempt in progress
+    connect_attempt_waiters: Vec<oneshot::Sender<Result<()>>>,
+    // wait_inflight_drained() callers by waiter ID (names their deadline timer)
+    inflight_drained_waiters: Vec<(u64, oneshot::Sender<Result<()>>)>,
+    next_inflight_drained_waiter: u64,
+    // wait_closed() requests, resolved by the next Closed event
+    closed_waiters: Vec<oneshot::Sender<()>>,
+    // close_with_disconnect() requests waiting for the broker to close
+    shutdown_waiters: Vec<oneshot::Sender<Result<()>>>,
+
+    // Whether connect() has ever been called (see allow_recv_before_connect)
+    connect_attempted: bool,
+
+    // Reply of a connect_timeout() call still waiting for the transport
+    pending_connect: Option<ConnectReplySender>,
+
+    // Automatic reconnection: URL of the last connect() (None after close())
+    // and the number of attempts since the connection was lost
+    reconnect_url: Option<String>,
+    reconnect_attempt: u32,
+    // Where the Closed handler connects next after a followed Server Reference
+    redirect_url: Option<String>,
+    // Whether the Closed handler already ran for the current Closed state;
+    // close() and the broker closing at once make the transport report twice
+    close_handled: bool,
+
+    // Connection events waiting for next_event()
+    pending_event_requests: Vec<oneshot::Sender<ConnectionEvent>>,
+    queued_events: VecDeque<ConnectionEvent>,
+    // State transitions waiting for next_state_change(), queued like events
+    pending_state_change_requests: Vec<oneshot::Sender<StateChange>>,
+    queued_state_changes: VecDeque<StateChange>,
 
     // WebSocket communication
     websocket_events: mpsc::UnboundedReceiver<UnderlyingLayerEvent>,
@@ -94,24 +570,81 @@ impl MqttProcessor {
         config: MqttConfig,
         mut websocket: W,
         request_sender: mpsc::UnboundedSender<Request>,
+        connected: Arc<AtomicBool>,
     ) -> (Self, W) {
         let mqtt_connection = mqtt::Connection::<mqtt::role::Client>::new(config.version);
-        let (packet_sender, packet_receiver) = mpsc::unbounded();
         let websocket_events = websocket.event_receiver();
         let websocket_commands = websocket.command_sender();
 
         let processor = Self {
             config,
             state: ConnectionState::Disconnected,
+            connected,
             mqtt_connection,
-            read_buffer: Vec::with_capacity(8192),
+            read_buffer: Vec::with_capacity(READ_BUFFER_CAPACITY),
             buffer_size: 0,
             consumed_bytes: 0,
+            recv_paused: false,
+            payload_stream: None,
+            streamed_publish_tapped: false,
             active_timers: HashSet::new(),
-            packet_sender,
-            packet_receiver,
+            qos_flows: HashMap::new(),
+            released_flow_ids: HashSet::new(),
+            imported_flows: Vec::new(),
+            connect_client_id: None,
+            publish_tokens: 0.0,
+            publish_tokens_at: None,
+            throttled_publishes: VecDeque::new(),
+            allocator_packet_ids: HashSet::new(),
+            packet_id_waiters: VecDeque::new(),
+            send_results: None,
+            send_rejection: None,
+            encoded_bytes: None,
+            pingreq_sent_at: None,
+            rtt_samples: VecDeque::with_capacity(RTT_WINDOW_SIZE),
             pending_recv_requests: Vec::new(),
-            undelivered_packet: None,
+            pending_typed_recv_requests: Vec::new(),
+            undelivered_packets: VecDeque::new(),
+            forwarded_packets: VecDeque::new(),
+            forwarded_ahead: None,
+            inbound_meta: ReceiveMeta::default(),
+            undelivered_bytes: 0,
+            inflight_bytes: HashMap::new(),
+            memory_pressure: false,
+            cooling_packet_ids: VecDeque::new(),
+            pending_subacks: HashMap::new(),
+            inbound_qos2: HashSet::new(),
+            subscriptions: Vec::new(),
+            requested_subscriptions: HashMap::new(),
+            requested_unsubscriptions: HashMap::new(),
+            session_requested: false,
+            session_present: None,
+            auth_routing: false,
+            pending_auth_requests: Vec::new(),
+            undelivered_auth: VecDeque::new(),
+            close_info: None,
+            sent_bytes: 0,
+            last_recv_at: None,
+            last_disconnect_reason: None,
+            server_maximum_packet_size: None,
+            keep_alive_secs: 0,
+            last_buffered_amount: 0,
+            connected_waiters: Vec::new(),
+            connect_attempt_waiters: Vec::new(),
+            inflight_drained_waiters: Vec::new(),
+            next_inflight_drained_waiter: 0,
+            closed_waiters: Vec::new(),
+            shutdown_waiters: Vec::new(),
+            connect_attempted: false,
+            pending_connect: None,
+            reconnect_url: None,
+            redirect_url: None,
+            reconnect_attempt: 0,
+            close_handled: false,
+            pending_event_requests: Vec::new(),
+            queued_events: VecDeque::new(),
+            pending_state_change_requests: Vec::new(),
+            queued_state_changes: VecDeque::new(),
             websocket_events,
             websocket_commands,
             request_sender,
@@ -123,30 +656,34 @@ impl MqttProcessor {
     /// Main processing loop
     async fn run(&mut self, mut request_receiver: mpsc::UnboundedReceiver<Request>) {
         #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(
-            &"★★★ MQTT: Starting MQTT processor main loop (NEW VERSION) ★★★".into(),
+        log_labeled!(
+            self.log_label(),
+            "★★★ MQTT: Starting MQTT processor main loop (NEW VERSION) ★★★"
         );
 
         // Configure MQTT connection
         #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(
-            &format!(
-                "Config pingreq_send_interval_ms: {:?}",
-                self.config.pingreq_send_interval_ms
-            )
-            .into(),
+        log_labeled!(
+            self.log_label(),
+            "Config pingreq_send_interval_ms: {:?}",
+            self.config.pingreq_send_interval_ms
         );
 
         if let Some(interval) = self.config.pingreq_send_interval_ms {
             self.mqtt_connection
                 .set_pingreq_send_interval(Some(interval));
             #[cfg(target_arch = "wasm32")]
-            web_sys::console::log_1(&format!("Set pingreq_send_interval to {}ms", interval).into());
+            log_labeled!(
+                self.log_label(),
+                "Set pingreq_send_interval to {}ms",
+                interval
+            );
         } else {
             // Use default interval based on keep_alive (will be set when CONNECT packet is processed)
             #[cfg(target_arch = "wasm32")]
-            web_sys::console::log_1(
-                &"pingreq_send_interval will be auto-configured from CONNECT keep_alive".into(),
+            log_labeled!(
+                self.log_label(),
+                "pingreq_send_interval will be auto-configured from CONNECT keep_alive"
             );
         }
         self.mqtt_connection
@@ -161,14 +698,17 @@ impl MqttProcessor {
             .set_pingresp_recv_timeout(self.config.pingresp_recv_timeout_ms);
 
         #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(&"MQTT connection configured".into());
+        log_labeled!(self.log_label(), "MQTT connection configured");
 
         loop {
+            // select! picks randomly among ready branches, so a flood of
+            // requests (e.g. send() in a tight loop) cannot starve incoming
+            // data, and vice versa
             select! {
                 // Handle API requests
                 request = request_receiver.next().fuse() => {
                     #[cfg(target_arch = "wasm32")]
-                    web_sys::console::log_1(&"Received API request".into());
+                    log_labeled!(self.log_label(), "Received API request");
                     match request {
                         Some(req) => {
                             if !self.handle_request(req).await {
@@ -182,58 +722,209 @@ impl MqttProcessor {
                 // Handle WebSocket events
                 event = self.websocket_events.next().fuse() => {
                     #[cfg(target_arch = "wasm32")]
-                    web_sys::console::log_1(&"Received WebSocket event".into());
+                    log_labeled!(self.log_label(), "Received WebSocket event");
                     if let Some(event) = event {
                         // Handle the event - do NOT break on close to allow reconnection
                         self.handle_websocket_event(event).await;
                     } else {
                         // WebSocket event stream ended
                         #[cfg(target_arch = "wasm32")]
-                        web_sys::console::log_1(&"WebSocket event stream ended".into());
+                        log_labeled!(self.log_label(), "WebSocket event stream ended");
                         break;
                     }
                 }
 
-                // Handle received packets and forward to pending recv requests
-                packet = self.packet_receiver.next().fuse() => {
-                    #[cfg(target_arch = "wasm32")]
-                    web_sys::console::log_1(&"Received packet from MQTT connection".into());
-                    if let Some(packet) = packet {
-                        self.handle_received_packet(packet);
-                    }
-                }
+            }
+        }
+    }
+
+    /// Hand free packet IDs to waiting acquire_packet_id_wait() requests
+    fn serve_packet_id_waiters(&mut self) {
+        while let Some(reply) = self.packet_id_waiters.pop_front() {
+            if reply.is_canceled() {
+                continue;
+            }
+            let Some(packet_id) = self.acquire_packet_id() else {
+                // Still exhausted; wait for the next release
+                self.packet_id_waiters.push_front(reply);
+                return;
+            };
+            if reply.send(Ok(packet_id)).is_err() {
+                // Waiter went away in the meantime; give the ID back
+                let events = self.mqtt_connection.release_packet_id(packet_id);
+                let _ = self.handle_mqtt_events(events);
+            }
+        }
+    }
+
+    /// Acquire a packet ID from `packet_id_allocator`, or from the connection
+    ///
+    /// An ID from the allocator is registered with the connection, which
+    /// rejects packets using IDs it does not know.
+    fn acquire_packet_id(&mut self) -> Option<u16> {
+        let Some(allocator) = self.config.packet_id_allocator.clone() else {
+            return self.mqtt_connection.acquire_packet_id().ok();
+        };
+        let packet_id = allocator.acquire()?;
+        if self.mqtt_connection.register_packet_id(packet_id).is_err() {
+            // Still in use on the connection; the allocator is out of step
+            allocator.release(packet_id);
+            return None;
+        }
+        self.allocator_packet_ids.insert(packet_id);
+        Some(packet_id)
+    }
+
+    /// Mark a packet ID in use with `packet_id_allocator` (if set) and the connection
+    fn register_packet_id(&mut self, packet_id: u16) -> bool {
+        let Some(allocator) = self.config.packet_id_allocator.clone() else {
+            return self.mqtt_connection.register_packet_id(packet_id).is_ok();
+        };
+        if !allocator.register(packet_id) {
+            return false;
+        }
+        if self.mqtt_connection.register_packet_id(packet_id).is_err() {
+            allocator.release(packet_id);
+            return false;
+        }
+        self.allocator_packet_ids.insert(packet_id);
+        true
+    }
+
+    /// Give a packet ID the connection has freed back to `packet_id_allocator`
+    fn release_to_allocator(&mut self, packet_id: u16) {
+        if let Some(ref allocator) = self.config.packet_id_allocator {
+            if self.allocator_packet_ids.remove(&packet_id) {
+                allocator.release(packet_id);
+            }
+        }
+    }
+
+    /// Prefix for this client's log lines (`instance_label`)
+    #[cfg(target_arch = "wasm32")]
+    fn log_label(&self) -> Option<&str> {
+        self.config.instance_label.as_deref()
+    }
+
+    /// Update the connection state and the synchronously readable connected flag
+    fn set_state(&mut self, state: ConnectionState) {
+        if state != ConnectionState::Closed {
+            self.close_handled = false;
+        }
+        let from = self.state;
+        self.state = state;
+        self.connected
+            .store(state == ConnectionState::Connected, Ordering::Release);
+        if from != state {
+            self.emit_state_change(StateChange { from, to: state });
+        }
+    }
 
+    /// Hand a state transition to a waiting next_state_change(), or queue it
+    fn emit_state_change(&mut self, change: StateChange) {
+        while !self.pending_state_change_requests.is_empty() {
+            let reply = self.pending_state_change_requests.remove(0);
+            if reply.send(change).is_ok() {
+                return;
             }
         }
+        if self.queued_state_changes.len() == EVENT_QUEUE_CAPACITY {
+            self.queued_state_changes.pop_front();
+        }
+        self.queued_state_changes.push_back(change);
     }
 
     /// Handle API requests
     async fn handle_request(&mut self, request: Request) -> bool {
+        // Reuse delays are applied lazily, before a request can acquire an ID
+        self.release_cooled_packet_ids();
+
         match request {
-            Request::Connect { url, reply } => {
-                let _ = self.connect(&url, reply).await;
+            Request::Connect {
+                url,
+                timeout_ms,
+                reply,
+            } => {
+                let _ = self.connect(&url, timeout_ms, reply).await;
                 // Reply is always handled inside connect() method
             }
             Request::Send { packet, reply } => {
-                let result = self.send_packet(packet).await;
-                let _ = reply.send(result);
+                self.send_or_throttle(packet, SendReply::Send(reply));
             }
-            Request::Recv { reply } => {
-                // Check if there's an undelivered packet from previous recv() timeout
-                if let Some(packet) = self.undelivered_packet.take() {
-                    // Deliver the saved packet immediately
-                    let _ = reply.send(Ok(packet));
-                } else {
-                    // Queue the recv request to be fulfilled when packet arrives
-                    self.pending_recv_requests.push(reply);
+            Request::SendSized { packet, reply } => {
+                self.send_or_throttle(packet, SendReply::SendSized(reply));
+            }
+            Request::SendEncoded { bytes, reply } => {
+                let _ = reply.send(self.send_encoded_packet(bytes));
+            }
+            Request::Subscribe { packet, reply } => {
+                let packet_id = match &packet {
+                    mqtt::packet::Packet::V3_1_1Subscribe(p) => p.packet_id(),
+                    mqtt::packet::Packet::V5_0Subscribe(p) => p.packet_id(),
+                    _ => {
+                        let _ = reply.send(Err(Error::Other(
+                            "subscribe() requires a SUBSCRIBE packet".to_string(),
+                        )));
+                        return true;
+                    }
+                };
+                // A SUBSCRIBE rejected before reaching the wire would never be answered
+                if self.state != ConnectionState::Connected {
+                    let _ = reply.send(Err(Error::NotConnected));
+                    return true;
+                }
+                if let Err(e) = self.check_subscription_limit(&packet) {
+                    let _ = reply.send(Err(e));
+                    return true;
+                }
+                // A SUBSCRIBE the connection refused (e.g. unregistered packet
+                // ID) never reaches the broker, so no SUBACK will follow
+                match self.send_packet(packet) {
+                    Ok(()) => match self.send_rejection.take() {
+                        None => {
+                            self.pending_subacks.insert(packet_id, reply);
+                        }
+                        Some(e) => {
+                            self.requested_subscriptions.remove(&packet_id);
+                            let _ = reply.send(Err(e));
+                        }
+                    },
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                    }
                 }
             }
+            Request::Recv { reply } => {
+                self.queue_recv(RecvReply::Packet(reply));
+            }
+            Request::RecvWithMeta { reply } => {
+                self.queue_recv(RecvReply::WithMeta(reply));
+            }
+            Request::RecvOneOf { types, reply } => {
+                self.queue_filtered_recv(RecvFilter::Types(types), reply);
+            }
+            Request::RecvForSubscription {
+                subscription_id,
+                reply,
+            } => {
+                self.queue_filtered_recv(RecvFilter::SubscriptionId(subscription_id), reply);
+            }
             Request::Close { reply } => {
                 let result = self.close().await;
                 let _ = reply.send(result);
                 // Do NOT exit the loop - allow reconnection by continuing to process requests
                 // The loop only exits when the request channel is closed (client dropped)
             }
+            Request::CloseWithDisconnect { packet, reply } => {
+                self.close_with_disconnect(packet, reply).await;
+            }
+            Request::Disconnect {
+                trigger_will,
+                reply,
+            } => {
+                let result = self.disconnect(trigger_will).await;
+                let _ = reply.send(result);
+            }
             Request::State { reply } => {
                 let _ = reply.send(self.state);
             }
@@ -241,17 +932,199 @@ impl MqttProcessor {
                 let _ = reply.send(matches!(self.state, ConnectionState::Connected));
             }
             Request::AcquirePacketId { reply } => {
-                let packet_id = self.mqtt_connection.acquire_packet_id().ok();
+                let packet_id = self.acquire_packet_id();
                 let _ = reply.send(packet_id);
             }
+            Request::AcquirePacketIdWait { reply } => {
+                // Queue behind earlier waiters so IDs are handed out in request order
+                self.packet_id_waiters.push_back(reply);
+                self.serve_packet_id_waiters();
+            }
+            Request::SendPingresp { reply } => {
+                let pingresp = match self.config.version {
+                    mqtt::Version::V5_0 => {
+                        mqtt::packet::Packet::V5_0Pingresp(mqtt::packet::v5_0::Pingresp::new())
+                    }
+                    _ => {
+                        mqtt::packet::Packet::V3_1_1Pingresp(mqtt::packet::v3_1_1::Pingresp::new())
+                    }
+                };
+                let result = self.send_packet(pingresp);
+                let _ = reply.send(result);
+            }
             Request::RegisterPacketId { packet_id, reply } => {
-                let result = self.mqtt_connection.register_packet_id(packet_id).is_ok();
+                let result = self.register_packet_id(packet_id);
                 let _ = reply.send(result);
             }
+            Request::RegisterPacketIds { packet_ids, reply } => {
+                let results = packet_ids
+                    .into_iter()
+                    .map(|packet_id| self.register_packet_id(packet_id))
+                    .collect();
+                let _ = reply.send(results);
+            }
             Request::ReleasePacketId { packet_id, reply } => {
                 let events = self.mqtt_connection.release_packet_id(packet_id);
                 let _ = self.handle_mqtt_events(events);
                 let _ = reply.send(Ok(()));
+                self.serve_packet_id_waiters();
+            }
+            Request::CompleteQos { packet_id, reply } => {
+                let result = self.complete_qos(packet_id);
+                let _ = reply.send(result);
+                self.serve_packet_id_waiters();
+            }
+            Request::BufferedAmount { reply } => {
+                // The transport replies directly; if the command cannot be sent,
+                // the reply is dropped and the caller sees a cancelled request
+                let reply_arc = std::sync::Arc::new(std::sync::Mutex::new(Some(reply)));
+                let _ = self
+                    .websocket_commands
+                    .unbounded_send(UnderlyingLayerCommand::QueryBufferedAmount(reply_arc));
+            }
+            Request::TransportReadyState { reply } => {
+                let reply_arc = std::sync::Arc::new(std::sync::Mutex::new(Some(reply)));
+                let _ = self
+                    .websocket_commands
+                    .unbounded_send(UnderlyingLayerCommand::QueryReadyState(reply_arc));
+            }
+            Request::NextEvent { reply } => {
+                if let Some(event) = self.queued_events.pop_front() {
+                    let _ = reply.send(event);
+                } else {
+                    self.pending_event_requests.push(reply);
+                }
+            }
+            Request::NextStateChange { reply } => {
+                if let Some(change) = self.queued_state_changes.pop_front() {
+                    let _ = reply.send(change);
+                } else {
+                    self.pending_state_change_requests.push(reply);
+                }
+            }
+            Request::SetRecvPaused { paused, reply } => {
+                self.recv_paused = paused;
+                if !paused {
+                    // Deliver packets queued while paused, then continue with
+                    // bytes that arrived while paused
+                    self.deliver_packets();
+                    self.parse_read_buffer();
+                }
+                let _ = reply.send(Ok(()));
+            }
+            Request::SetTopicRewriter { rewriter, reply } => {
+                self.config.topic_rewrite = rewriter;
+                let _ = reply.send(Ok(()));
+            }
+            Request::SetSendInspector { inspector, reply } => {
+                self.config.send_inspector = inspector;
+                let _ = reply.send(Ok(()));
+            }
+            Request::SetPacketTap { tap, reply } => {
+                self.config.packet_tap = tap;
+                let _ = reply.send(Ok(()));
+            }
+            Request::SetPayloadChunkHandler { handler, reply } => {
+                self.config.payload_chunk_handler = handler;
+                let _ = reply.send(Ok(()));
+            }
+            Request::RttStats { reply } => {
+                let _ = reply.send(self.rtt_stats());
+            }
+            Request::Health {
+                buffered_amount,
+                reply,
+            } => {
+                let _ = reply.send(self.health(buffered_amount));
+            }
+            Request::ClearInbound { reply } => {
+                let _ = reply.send(self.clear_inbound());
+            }
+            Request::ReplaceTransport {
+                events,
+                commands,
+                reply,
+            } => {
+                self.replace_transport(events, commands);
+                let _ = reply.send(Ok(()));
+            }
+            Request::SetClientId { client_id, reply } => {
+                let result = if matches!(
+                    self.state,
+                    ConnectionState::Connecting | ConnectionState::Connected
+                ) {
+                    Err(Error::Other(
+                        "Client id can only be set while not connected".to_string(),
+                    ))
+                } else {
+                    self.config.client_id = Some(client_id);
+                    Ok(())
+                };
+                let _ = reply.send(result);
+            }
+            Request::WaitConnected { reply } => {
+                if self.state == ConnectionState::Connected {
+                    let _ = reply.send(Ok(()));
+                } else {
+                    self.connected_waiters.push(reply);
+                }
+            }
+            Request::EnsureConnected { url, reply } => {
+                self.ensure_connected(&url, reply).await;
+            }
+            Request::WaitInflightDrained { timeout_ms, reply } => {
+                self.wait_inflight_drained(timeout_ms, reply);
+            }
+            Request::WaitClosed { reply } => {
+                if matches!(
+                    self.state,
+                    ConnectionState::Connecting | ConnectionState::Connected
+                ) {
+                    self.closed_waiters.push(reply);
+                } else {
+                    let _ = reply.send(());
+                }
+            }
+            Request::ExportSession { reply } => {
+                let _ = reply.send(self.export_session());
+            }
+            Request::ImportSession { snapshot, reply } => {
+                let _ = reply.send(self.import_session(snapshot));
+            }
+            Request::Subscriptions { reply } => {
+                let _ = reply.send(self.subscriptions.clone());
+            }
+            Request::SubscriptionCount { reply } => {
+                let _ = reply.send(self.subscriptions.len());
+            }
+            Request::SessionPresent { reply } => {
+                let _ = reply.send(self.session_present);
+            }
+            Request::LastDisconnectReason { reply } => {
+                let _ = reply.send(self.last_disconnect_reason);
+            }
+            Request::RecvAuth { reply } => {
+                self.auth_routing = true;
+                if let Some(packet) = self.undelivered_auth.pop_front() {
+                    let _ = reply.send(Ok(packet));
+                } else {
+                    self.pending_auth_requests.push(reply);
+                }
+            }
+            Request::StopRecvAuth { reply } => {
+                self.auth_routing = false;
+                // Waiting recv_auth() calls end with "Request cancelled"
+                self.pending_auth_requests.clear();
+                for packet in std::mem::take(&mut self.undelivered_auth) {
+                    self.handle_received_packet(packet);
+                }
+                let _ = reply.send(Ok(()));
+            }
+            #[cfg(feature = "test-hooks")]
+            Request::TimerExpired { kind, reply } => {
+                self.handle_websocket_event(UnderlyingLayerEvent::TimerExpired(kind))
+                    .await;
+                let _ = reply.send(Ok(()));
             }
         }
         true
@@ -260,53 +1133,115 @@ impl MqttProcessor {
     /// Handle WebSocket events
     async fn handle_websocket_event(&mut self, event: UnderlyingLayerEvent) {
         #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(&format!("Handling WebSocket event: {:?}", event).into());
+        log_labeled!(self.log_label(), "Handling WebSocket event: {:?}", event);
 
         match event {
             UnderlyingLayerEvent::Connected => {
                 #[cfg(target_arch = "wasm32")]
-                web_sys::console::log_1(&"WebSocket Connected event - updating state".into());
-                self.state = ConnectionState::Connected;
+                log_labeled!(
+                    self.log_label(),
+                    "WebSocket Connected event - updating state"
+                );
+                self.set_state(ConnectionState::Connected);
+                // An adopted WebSocket is connected without connect()
+                self.connect_attempted = true;
+                self.cancel_connect_timeout();
+                for reply in self.connected_waiters.drain(..) {
+                    let _ = reply.send(Ok(()));
+                }
+                for reply in self.connect_attempt_waiters.drain(..) {
+                    let _ = reply.send(Ok(()));
+                }
+                self.emit_event(ConnectionEvent::Connected);
+                if self.reconnect_attempt > 0 {
+                    self.reconnect_attempt = 0;
+                    self.emit_event(ConnectionEvent::Reconnected);
+                }
             }
             UnderlyingLayerEvent::Message(data) => {
                 #[cfg(target_arch = "wasm32")]
-                web_sys::console::log_1(
-                    &format!(
-                        "🔥 NEW VERSION: WebSocket Message event - processing {} bytes",
-                        data.len()
-                    )
-                    .into(),
+                log_labeled!(
+                    self.log_label(),
+                    "🔥 NEW VERSION: WebSocket Message event - processing {} bytes",
+                    data.len()
                 );
                 self.process_incoming_data(data);
             }
-            UnderlyingLayerEvent::Error(_error) => {
+            UnderlyingLayerEvent::Error(error) => {
                 #[cfg(target_arch = "wasm32")]
-                web_sys::console::log_1(&format!("WebSocket Error event: {}", _error).into());
-                self.state = ConnectionState::Disconnected;
+                log_labeled!(self.log_label(), "WebSocket Error event: {}", error);
+                self.set_state(ConnectionState::Disconnected);
+                self.cancel_connect_timeout();
+                for reply in self.connect_attempt_waiters.drain(..) {
+                    let _ = reply.send(Err(Error::WebSocketError(error.clone())));
+                }
+                self.emit_event(ConnectionEvent::Error(Error::WebSocketError(error)));
+            }
+            UnderlyingLayerEvent::CloseInfo { code, reason } => {
+                // Reported with the Closed event that follows
+                self.close_info = Some((code, reason));
             }
             UnderlyingLayerEvent::Closed => {
                 #[cfg(target_arch = "wasm32")]
-                web_sys::console::log_1(&"WebSocket Closed event - updating state".into());
-                self.state = ConnectionState::Closed;
-                let events = self.mqtt_connection.notify_closed();
-                let _ = self.handle_mqtt_events(events);
+                log_labeled!(self.log_label(), "WebSocket Closed event - updating state");
+                // Closed is terminal: a second report of the same close (e.g.
+                // the broker closed while close() was closing) changes nothing
+                if self.state == ConnectionState::Closed && self.close_handled {
+                    #[cfg(target_arch = "wasm32")]
+                    log_labeled!(self.log_label(), "Connection already closed, ignoring");
+                    return;
+                }
+                self.set_state(ConnectionState::Closed);
+
+                // Bytes never parsed: the connection was cut in the middle of a
+                // frame, or whole PUBLISH packets were still held back
+                let mut unparsed = self.buffer_size - self.consumed_bytes;
+                if let Some(stream) = self.payload_stream.take() {
+                    unparsed += stream.received;
+                }
+                if unparsed > 0 {
+                    self.emit_event(ConnectionEvent::TruncatedFrame {
+                        bytes_lost: unparsed,
+                    });
+                }
 
-                // Clear any remaining timers
-                self.active_timers.clear();
+                self.fail_connection_waiters();
                 #[cfg(target_arch = "wasm32")]
-                web_sys::console::log_1(&"All timers cleared on connection close".into());
-            }
-            UnderlyingLayerEvent::TimerExpired(timer_kind) => {
+                log_labeled!(self.log_label(), "All timers cleared on connection close");
+
+                let (code, reason) = match self.close_info.take() {
+                    Some((code, reason)) => (Some(code), Some(reason)),
+                    None => (None, None),
+                };
+                self.emit_event(ConnectionEvent::Closed { code, reason });
+
+                // A followed Server Reference connects right away, without backoff
+                if let Some(url) = self.redirect_url.take() {
+                    self.reconnect_url = Some(url);
+                    self.reconnect();
+                } else {
+                    self.schedule_reconnect();
+                }
+            }
+            UnderlyingLayerEvent::TimerExpired(timer_kind) => {
                 // Handle timer expiration from underlying layer
                 #[cfg(target_arch = "wasm32")]
-                log!("Timer expired event received: {}", timer_kind);
+                log_labeled!(
+                    self.log_label(),
+                    "Timer expired event received: {}",
+                    timer_kind
+                );
                 #[cfg(not(target_arch = "wasm32"))]
                 println!("Timer expired event received: {}", timer_kind);
 
                 // Check if connection is closed - if so, ignore all timers
                 if matches!(self.state, ConnectionState::Closed) {
                     #[cfg(target_arch = "wasm32")]
-                    log!("Connection closed, ignoring timer: {}", timer_kind);
+                    log_labeled!(
+                        self.log_label(),
+                        "Connection closed, ignoring timer: {}",
+                        timer_kind
+                    );
                     #[cfg(not(target_arch = "wasm32"))]
                     println!("Connection closed, ignoring timer: {}", timer_kind);
                     return;
@@ -315,7 +1250,11 @@ impl MqttProcessor {
                 // Check if timer was cancelled - if so, ignore the expiration
                 if !self.active_timers.contains(&timer_kind) {
                     #[cfg(target_arch = "wasm32")]
-                    log!("Timer {} was cancelled, ignoring expiration", timer_kind);
+                    log_labeled!(
+                        self.log_label(),
+                        "Timer {} was cancelled, ignoring expiration",
+                        timer_kind
+                    );
                     #[cfg(not(target_arch = "wasm32"))]
                     println!("Timer {} was cancelled, ignoring expiration", timer_kind);
                     return;
@@ -324,7 +1263,8 @@ impl MqttProcessor {
                 // Remove the timer from active_timers since it has now expired
                 self.active_timers.remove(&timer_kind);
                 #[cfg(target_arch = "wasm32")]
-                log!(
+                log_labeled!(
+                    self.log_label(),
                     "Timer {} expired and removed from active timers",
                     timer_kind
                 );
@@ -345,9 +1285,30 @@ impl MqttProcessor {
                         .mqtt_connection
                         .notify_timer_fired(mqtt::connection::TimerKind::PingrespRecv);
                     let _ = self.handle_mqtt_events(events);
+                } else if timer_kind == RECONNECT_TIMER {
+                    self.reconnect();
+                } else if timer_kind == CONNECT_TIMER {
+                    self.connect_timed_out().await;
+                } else if timer_kind == SHUTDOWN_TIMER {
+                    // The broker did not close after the DISCONNECT
+                    let _ = self.close().await;
+                } else if timer_kind == PUBLISH_RATE_TIMER {
+                    self.release_throttled_publishes();
+                } else if timer_kind == PACKET_ID_COOLDOWN_TIMER {
+                    self.release_cooled_packet_ids();
+                } else if let Some(packet_id) = timer_kind
+                    .strip_prefix(PUBLISH_ACK_TIMER_PREFIX)
+                    .and_then(|id| id.parse::<u16>().ok())
+                {
+                    self.publish_ack_timed_out(packet_id);
+                } else if let Some(waiter) = timer_kind
+                    .strip_prefix(INFLIGHT_DRAINED_TIMER_PREFIX)
+                    .and_then(|id| id.parse::<u64>().ok())
+                {
+                    self.inflight_drain_timed_out(waiter);
                 } else {
                     #[cfg(target_arch = "wasm32")]
-                    log!("Unknown timer kind: {}", timer_kind);
+                    log_labeled!(self.log_label(), "Unknown timer kind: {}", timer_kind);
                     #[cfg(not(target_arch = "wasm32"))]
                     println!("Unknown timer kind: {}", timer_kind);
                 }
@@ -355,41 +1316,441 @@ impl MqttProcessor {
         }
     }
 
-    /// Handle received packet - try to deliver to pending recv requests
-    /// If delivery fails (receiver dropped due to timeout), save packet for next recv()
-    fn handle_received_packet(&mut self, packet: mqtt::packet::Packet) {
-        // Try to deliver packet to pending recv requests
-        // If receiver is dropped (timeout), try next request
-        let mut packet_to_deliver = Some(packet);
+    /// Schedule the next automatic reconnection attempt, if enabled
+    ///
+    /// The delay starts at `reconnect_initial_delay_ms` and doubles per attempt
+    /// up to `reconnect_max_delay_ms`. After `reconnect_max_attempts` failed
+    /// attempts the client gives up and stays Closed.
+    fn schedule_reconnect(&mut self) {
+        let Some(initial_delay_ms) = self.config.reconnect_initial_delay_ms else {
+            return;
+        };
+        // Closed by close(), or never connected
+        if self.reconnect_url.is_none() {
+            return;
+        }
+        if let Some(max_attempts) = self.config.reconnect_max_attempts {
+            if self.reconnect_attempt >= max_attempts {
+                let attempts = self.reconnect_attempt;
+                self.reconnect_attempt = 0;
+                self.emit_event(ConnectionEvent::ReconnectGaveUp { attempts });
+                return;
+            }
+        }
+
+        self.reconnect_attempt = self.reconnect_attempt.saturating_add(1);
+        let exponent = (self.reconnect_attempt - 1).min(31);
+        let delay_ms = initial_delay_ms
+            .saturating_mul(1u64 << exponent)
+            .min(self.config.reconnect_max_delay_ms.max(initial_delay_ms));
 
-        while let Some(pkt) = packet_to_deliver.take() {
-            if self.pending_recv_requests.is_empty() {
-                // No more requests, save packet for next recv() call
-                self.undelivered_packet = Some(pkt);
-                break;
+        self.set_state(ConnectionState::Reconnecting);
+        self.emit_event(ConnectionEvent::Reconnecting {
+            attempt: self.reconnect_attempt,
+            delay_ms,
+        });
+
+        self.active_timers.insert(RECONNECT_TIMER.to_string());
+        let _ = self
+            .websocket_commands
+            .unbounded_send(UnderlyingLayerCommand::TimerReset {
+                kind: RECONNECT_TIMER.to_string(),
+                duration_ms: delay_ms,
+            });
+    }
+
+    /// Reconnect the transport to the last URL after the backoff delay
+    ///
+    /// The outcome arrives as Connected (then Reconnected) or Closed, which
+    /// schedules the next attempt.
+    fn reconnect(&mut self) {
+        let Some(url) = self.reconnect_url.clone() else {
+            return;
+        };
+        self.reset_for_reconnection();
+        self.serve_packet_id_waiters();
+        self.set_state(ConnectionState::Connecting);
+
+        let (reply, _) = oneshot::channel();
+        let reply_arc = std::sync::Arc::new(std::sync::Mutex::new(Some(reply)));
+        let _ = self
+            .websocket_commands
+            .unbounded_send(UnderlyingLayerCommand::Connect(url, reply_arc));
+    }
+
+    /// Switch to a new transport, closing the current one
+    ///
+    /// The old transport's remaining events are dropped, so its close is
+    /// handled here. The client ends up Closed; the next connect() goes through
+    /// the new transport. State kept across reconnections is kept here too.
+    fn replace_transport(
+        &mut self,
+        events: mpsc::UnboundedReceiver<UnderlyingLayerEvent>,
+        commands: mpsc::UnboundedSender<UnderlyingLayerCommand>,
+    ) {
+        self.cancel_reconnect();
+        let _ = self
+            .websocket_commands
+            .unbounded_send(UnderlyingLayerCommand::Close);
+
+        let was_open =
+            self.state != ConnectionState::Disconnected && self.state != ConnectionState::Closed;
+        self.set_state(ConnectionState::Closed);
+        self.close_info = None;
+        // Its timers are cancelled on the old transport
+        self.fail_connection_waiters();
+        self.websocket_events = events;
+        self.websocket_commands = commands;
+        if was_open {
+            self.emit_event(ConnectionEvent::Closed {
+                code: None,
+                reason: None,
+            });
+        }
+    }
+
+    /// Wind down everything tied to the connection that just closed
+    ///
+    /// Shared by the Closed event and `replace_transport()`, whose old
+    /// transport's Closed event is never seen: the state machine is told, timers
+    /// are cancelled, and everything waiting on the connection is answered.
+    fn fail_connection_waiters(&mut self) {
+        self.close_handled = true;
+        let events = self.mqtt_connection.notify_closed();
+        let _ = self.handle_mqtt_events(events);
+
+        // Cancel any remaining timers in the transport too
+        if let Some(reply_arc) = self.pending_connect.take() {
+            complete_reply(&reply_arc, Err(Error::ConnectionClosed));
+        }
+        self.cancel_connect_timeout();
+        for kind in self.active_timers.drain() {
+            let _ = self
+                .websocket_commands
+                .unbounded_send(UnderlyingLayerCommand::TimerCancel { kind });
+        }
+        self.pingreq_sent_at = None;
+
+        // SUBACKs can no longer arrive on this connection
+        for (_, reply) in self.pending_subacks.drain() {
+            let _ = reply.send(Err(Error::ConnectionClosed));
+        }
+        for reply in self
+            .connected_waiters
+            .drain(..)
+            .chain(self.connect_attempt_waiters.drain(..))
+        {
+            let _ = reply.send(Err(Error::ConnectionClosed));
+        }
+        for (_, reply) in self.inflight_drained_waiters.drain(..) {
+            let _ = reply.send(Err(Error::ConnectionClosed));
+        }
+        // close_with_disconnect() is done once the connection is gone
+        for reply in self.shutdown_waiters.drain(..) {
+            let _ = reply.send(Ok(()));
+        }
+        for (_, reply, _) in self.throttled_publishes.drain(..) {
+            reply.fail(Error::ConnectionClosed);
+        }
+        for reply in self.closed_waiters.drain(..) {
+            let _ = reply.send(());
+        }
+    }
+
+    /// Cancel a pending automatic reconnection attempt
+    fn cancel_reconnect(&mut self) {
+        self.reconnect_attempt = 0;
+        self.redirect_url = None;
+        if self.active_timers.remove(RECONNECT_TIMER) {
+            let _ = self
+                .websocket_commands
+                .unbounded_send(UnderlyingLayerCommand::TimerCancel {
+                    kind: RECONNECT_TIMER.to_string(),
+                });
+        }
+    }
+
+    /// Handle received packet - queue it and deliver to pending recv requests
+    ///
+    /// Packets reach this point from `handle_mqtt_events` in wire order and are
+    /// queued in `undelivered_packets`, so delivery order matches the wire. A
+    /// packet parsed ahead of held-back PUBLISH packets waits in
+    /// `forwarded_packets` until they are queued.
+    fn handle_received_packet(&mut self, mut packet: mqtt::packet::Packet) {
+        // SUBACK answering a subscribe() goes back to that call
+        let suback_packet_id = match &packet {
+            mqtt::packet::Packet::V3_1_1Suback(p) => Some(p.packet_id()),
+            mqtt::packet::Packet::V5_0Suback(p) => Some(p.packet_id()),
+            _ => None,
+        };
+        if let Some(reply) = suback_packet_id.and_then(|id| self.pending_subacks.remove(&id)) {
+            match reply.send(Ok(packet)) {
+                Ok(()) => return,
+                // Caller gave up waiting; deliver via recv() instead
+                Err(Ok(returned_packet)) => packet = returned_packet,
+                Err(Err(_)) => return,
             }
+        }
+
+        // AUTH (e.g. continue-authentication before CONNACK) goes to recv_auth()
+        // once the app has asked for it
+        if self.auth_routing && matches!(packet, mqtt::packet::Packet::V5_0Auth(_)) {
+            self.handle_received_auth(packet);
+            return;
+        }
+
+        if let Some(ahead) = self.forwarded_ahead {
+            self.forwarded_packets
+                .push_back((ahead, packet, self.inbound_meta));
+            self.deliver_typed_packets();
+            return;
+        }
+        self.undelivered_bytes += self.accounted_size(&packet);
+        self.undelivered_packets
+            .push_back((packet, self.inbound_meta));
+        self.check_memory_pressure();
+        self.deliver_packets();
+    }
+
+    /// Count one held-back PUBLISH as parsed and queue the forwarded packets
+    /// no PUBLISH is left ahead of
+    fn release_forwarded_packets(&mut self) {
+        for (ahead, _, _) in self.forwarded_packets.iter_mut() {
+            *ahead = ahead.saturating_sub(1);
+        }
+        while self
+            .forwarded_packets
+            .front()
+            .is_some_and(|(ahead, _, _)| *ahead == 0)
+        {
+            let Some((_, packet, meta)) = self.forwarded_packets.pop_front() else {
+                break;
+            };
+            self.undelivered_bytes += self.accounted_size(&packet);
+            self.undelivered_packets.push_back((packet, meta));
+        }
+        self.check_memory_pressure();
+        self.deliver_packets();
+    }
+
+    /// Queue a recv()/recv_with_meta() request behind earlier ones
+    ///
+    /// It is fulfilled from undelivered packets now or when a packet arrives.
+    fn queue_recv(&mut self, reply: RecvReply) {
+        // Nothing can arrive before the first connect(), so fail fast
+        // unless another task is expected to connect
+        if !self.connect_attempted && !self.config.allow_recv_before_connect {
+            reply.fail(Error::NotConnected);
+            return;
+        }
+        self.pending_recv_requests.push(reply);
+        self.deliver_packets();
+        // Taking a packet may make room to parse held-back bytes
+        self.parse_read_buffer();
+    }
+
+    /// Queue a recv_one_of()/recv_for_subscription() request, as queue_recv() does
+    fn queue_filtered_recv(
+        &mut self,
+        filter: RecvFilter,
+        reply: oneshot::Sender<Result<mqtt::packet::Packet>>,
+    ) {
+        if !self.connect_attempted && !self.config.allow_recv_before_connect {
+            let _ = reply.send(Err(Error::NotConnected));
+            return;
+        }
+        self.pending_typed_recv_requests.push((filter, reply));
+        self.deliver_packets();
+        self.parse_read_buffer();
+    }
+
+    /// Whether a packet is a PUBLISH (held back while recv is paused)
+    fn is_publish(packet: &mqtt::packet::Packet) -> bool {
+        matches!(
+            packet,
+            mqtt::packet::Packet::V3_1_1Publish(_) | mqtt::packet::Packet::V5_0Publish(_)
+        )
+    }
+
+    /// Whether a packet is the answer to a received one (as sent automatically)
+    fn is_response_packet(packet: &mqtt::packet::Packet) -> bool {
+        matches!(
+            packet,
+            mqtt::packet::Packet::V3_1_1Puback(_)
+                | mqtt::packet::Packet::V3_1_1Pubrec(_)
+                | mqtt::packet::Packet::V3_1_1Pubrel(_)
+                | mqtt::packet::Packet::V3_1_1Pubcomp(_)
+                | mqtt::packet::Packet::V3_1_1Pingresp(_)
+                | mqtt::packet::Packet::V5_0Puback(_)
+                | mqtt::packet::Packet::V5_0Pubrec(_)
+                | mqtt::packet::Packet::V5_0Pubrel(_)
+                | mqtt::packet::Packet::V5_0Pubcomp(_)
+                | mqtt::packet::Packet::V5_0Pingresp(_)
+        )
+    }
 
-            // Try to deliver to the first (oldest) pending request
+    /// Hand undelivered packets to pending recv() requests, oldest first
+    ///
+    /// A request whose receiver was dropped (e.g. recv() timed out) is skipped
+    /// and the packet stays in place for the next request, so nothing is lost
+    /// and wire order is kept. While paused delivery stops at the first
+    /// PUBLISH, so nothing overtakes it.
+    fn deliver_packets(&mut self) {
+        self.deliver_typed_packets();
+        while !self.pending_recv_requests.is_empty() {
+            let held = self.recv_paused
+                && self
+                    .undelivered_packets
+                    .front()
+                    .is_some_and(|(packet, _)| Self::is_publish(packet));
+            if held {
+                break;
+            }
+            let Some((packet, meta)) = self.undelivered_packets.pop_front() else {
+                break;
+            };
+            let size = self.accounted_size(&packet);
             let reply = self.pending_recv_requests.remove(0);
-            match reply.send(Ok(pkt)) {
-                Ok(()) => {
-                    // Successfully delivered packet
-                    break;
-                }
-                Err(packet_result) => {
-                    // Receiver dropped (e.g., due to timeout)
-                    // Get packet back and try next request
-                    if let Ok(returned_packet) = packet_result {
-                        packet_to_deliver = Some(returned_packet);
+            let returned = match self.connack_refusal(&packet) {
+                Some(error) => (!reply.refuse(error)).then_some(packet),
+                None => reply.deliver(packet, meta),
+            };
+            match returned {
+                None => self.undelivered_bytes = self.undelivered_bytes.saturating_sub(size),
+                Some(returned_packet) => {
+                    self.undelivered_packets.push_front((returned_packet, meta))
+                }
+            }
+        }
+    }
+
+    /// Hand each recv_one_of()/recv_for_subscription() request the oldest
+    /// undelivered packet it wants
+    ///
+    /// Other packets keep their place in the queue for recv(). A packet both
+    /// could take goes to the filtered request, which is served first. Packets
+    /// parsed ahead of held-back PUBLISH packets can be taken this way too.
+    fn deliver_typed_packets(&mut self) {
+        self.pending_typed_recv_requests
+            .retain(|(_, reply)| !reply.is_canceled());
+        let paused = self.recv_paused;
+        let mut index = 0;
+        while index < self.pending_typed_recv_requests.len() {
+            let filter = &self.pending_typed_recv_requests[index].0;
+            let Some(position) = self.undelivered_packets.iter().position(|(packet, _)| {
+                filter.matches(packet) && !(paused && Self::is_publish(packet))
+            }) else {
+                if let Some(position) = self
+                    .forwarded_packets
+                    .iter()
+                    .position(|(_, packet, _)| filter.matches(packet))
+                {
+                    let (_, reply) = self.pending_typed_recv_requests.remove(index);
+                    let Some((ahead, packet, meta)) = self.forwarded_packets.remove(position)
+                    else {
+                        break;
+                    };
+                    let returned = match self.connack_refusal(&packet) {
+                        Some(error) => reply.send(Err(error)).is_err().then_some(packet),
+                        None => reply
+                            .send(Ok(packet))
+                            .err()
+                            .and_then(|returned| returned.ok()),
+                    };
+                    if let Some(returned_packet) = returned {
+                        self.forwarded_packets
+                            .insert(position, (ahead, returned_packet, meta));
                     }
-                    // Continue loop to try next request
+                    continue;
                 }
+                index += 1;
+                continue;
+            };
+            let (_, reply) = self.pending_typed_recv_requests.remove(index);
+            let Some((packet, meta)) = self.undelivered_packets.remove(position) else {
+                break;
+            };
+            let size = self.accounted_size(&packet);
+            let returned = match self.connack_refusal(&packet) {
+                Some(error) => reply.send(Err(error)).is_err().then_some(packet),
+                None => reply
+                    .send(Ok(packet))
+                    .err()
+                    .and_then(|returned| returned.ok()),
+            };
+            match returned {
+                None => self.undelivered_bytes = self.undelivered_bytes.saturating_sub(size),
+                Some(returned_packet) => self
+                    .undelivered_packets
+                    .insert(position, (returned_packet, meta)),
+            }
+        }
+    }
+
+    /// The error a failing CONNACK is delivered as with `error_on_connack_failure`
+    fn connack_refusal(&self, packet: &mqtt::packet::Packet) -> Option<Error> {
+        if !self.config.error_on_connack_failure {
+            return None;
+        }
+        match packet {
+            mqtt::packet::Packet::V3_1_1Connack(p)
+                if p.return_code() != mqtt::result_code::ConnectReturnCode::Accepted =>
+            {
+                Some(Error::ConnectionRefused {
+                    reason_code: p.return_code() as u8,
+                    reason_string: None,
+                })
+            }
+            mqtt::packet::Packet::V5_0Connack(p) if p.reason_code() as u8 >= 0x80 => {
+                Some(Error::ConnectionRefused {
+                    reason_code: p.reason_code() as u8,
+                    reason_string: p.props.iter().find_map(|prop| match prop {
+                        mqtt::packet::Property::ReasonString(p) => Some(p.val().to_string()),
+                        _ => None,
+                    }),
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Deliver AUTH packet to the oldest live recv_auth() request, or queue it
+    fn handle_received_auth(&mut self, packet: mqtt::packet::Packet) {
+        let mut packet_to_deliver = packet;
+        while !self.pending_auth_requests.is_empty() {
+            let reply = self.pending_auth_requests.remove(0);
+            match reply.send(Ok(packet_to_deliver)) {
+                Ok(()) => return,
+                Err(Ok(returned_packet)) => packet_to_deliver = returned_packet,
+                Err(Err(_)) => return,
+            }
+        }
+        self.undelivered_auth.push_back(packet_to_deliver);
+    }
+
+    /// Deliver connection event to the oldest live next_event() request, or queue it
+    fn emit_event(&mut self, event: ConnectionEvent) {
+        let mut event_to_deliver = event;
+        while !self.pending_event_requests.is_empty() {
+            let reply = self.pending_event_requests.remove(0);
+            match reply.send(event_to_deliver) {
+                Ok(()) => return,
+                Err(returned_event) => event_to_deliver = returned_event,
             }
         }
+        if self.queued_events.len() == EVENT_QUEUE_CAPACITY {
+            self.queued_events.pop_front();
+        }
+        self.queued_events.push_back(event_to_deliver);
     }
 
     /// Connect WebSocket to MQTT broker
-    async fn connect(&mut self, url: &str, reply: oneshot::Sender<Result<()>>) -> Result<()> {
+    async fn connect(
+        &mut self,
+        url: &str,
+        timeout_ms: Option<u64>,
+        reply: oneshot::Sender<Result<()>>,
+    ) -> Result<()> {
         // Allow connection from Disconnected or Closed states (for reconnection support)
         if self.state == ConnectionState::Connecting || self.state == ConnectionState::Connected {
             let _ = reply.send(Err(Error::Other(
@@ -398,16 +1759,39 @@ impl MqttProcessor {
             return Ok(());
         }
 
+        self.connect_attempted = true;
+
+        // An explicit connect() replaces any pending automatic attempt
+        self.cancel_reconnect();
+        self.reconnect_url = Some(url.to_string());
+
         // Reset internal state for reconnection
-        if self.state == ConnectionState::Closed {
+        if self.state == ConnectionState::Closed || self.state == ConnectionState::Reconnecting {
             self.reset_for_reconnection();
+            self.take_over_imported_flows();
+            // The new connection starts with every packet ID free
+            self.serve_packet_id_waiters();
+        } else {
+            self.take_over_imported_flows();
         }
 
-        self.state = ConnectionState::Connecting;
+        self.set_state(ConnectionState::Connecting);
         let reply_arc = std::sync::Arc::new(std::sync::Mutex::new(Some(reply)));
 
         #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(&"Sending Connect command with reply_arc".into());
+        log_labeled!(self.log_label(), "Sending Connect command with reply_arc");
+
+        // Kept to fail the connect() call if the transport does not connect in time
+        if let Some(duration_ms) = timeout_ms {
+            self.pending_connect = Some(reply_arc.clone());
+            self.active_timers.insert(CONNECT_TIMER.to_string());
+            let _ = self
+                .websocket_commands
+                .unbounded_send(UnderlyingLayerCommand::TimerReset {
+                    kind: CONNECT_TIMER.to_string(),
+                    duration_ms,
+                });
+        }
 
         let _ = self
             .websocket_commands
@@ -416,274 +1800,2872 @@ impl MqttProcessor {
         Ok(())
     }
 
+    /// Connect unless already connected or connecting to `url` (ensure_connected())
+    ///
+    /// A caller arriving while an attempt is in progress shares its outcome
+    /// instead of being rejected like a second connect().
+    async fn ensure_connected(&mut self, url: &str, reply: oneshot::Sender<Result<()>>) {
+        let same_url = self.reconnect_url.as_deref() == Some(url);
+        match self.state {
+            ConnectionState::Connected | ConnectionState::Connecting if !same_url => {
+                let _ = reply.send(Err(Error::Other(format!(
+                    "Already connecting or connected to a URL other than {}",
+                    url
+                ))));
+            }
+            ConnectionState::Connected => {
+                let _ = reply.send(Ok(()));
+            }
+            ConnectionState::Connecting => self.connect_attempt_waiters.push(reply),
+            _ => {
+                let _ = self.connect(url, None, reply).await;
+            }
+        }
+    }
+
+    /// Snapshot of the session state for export_session()
+    fn export_session(&self) -> SessionSnapshot {
+        let mut inflight: Vec<InflightEntry> = self
+            .qos_flows
+            .iter()
+            .map(|(packet_id, phase)| (*packet_id, *phase))
+            .chain(self.imported_flows.iter().copied())
+            .map(|(packet_id, phase)| InflightEntry { packet_id, phase })
+            .collect();
+        inflight.sort_by_key(|entry| entry.packet_id);
+        let mut inbound_qos2: Vec<u16> = self.inbound_qos2.iter().copied().collect();
+        inbound_qos2.sort_unstable();
+        SessionSnapshot {
+            version: version_name(self.config.version).to_string(),
+            client_id: self
+                .config
+                .client_id
+                .clone()
+                .or_else(|| self.connect_client_id.clone()),
+            keep_alive_secs: self.keep_alive_secs,
+            session_present: self.session_present,
+            server_maximum_packet_size: self.server_maximum_packet_size,
+            inflight,
+            subscriptions: self
+                .subscriptions
+                .iter()
+                .map(|(topic_filter, qos)| SubscriptionEntry {
+                    topic_filter: topic_filter.clone(),
+                    qos: *qos,
+                })
+                .collect(),
+            inbound_qos2,
+        }
+    }
+
+    /// Restore an exported session; its inflight flows wait for connect()
+    fn import_session(&mut self, snapshot: SessionSnapshot) -> Result<()> {
+        if matches!(
+            self.state,
+            ConnectionState::Connecting
+                | ConnectionState::Connected
+                | ConnectionState::Reconnecting
+        ) {
+            return Err(Error::Other(
+                "A session can only be imported while not connected".to_string(),
+            ));
+        }
+        let version = version_name(self.config.version);
+        if snapshot.version != version {
+            return Err(Error::Other(format!(
+                "Session is for MQTT {}, but the client uses {}",
+                snapshot.version, version
+            )));
+        }
+
+        if snapshot.client_id.is_some() {
+            self.config.client_id = snapshot.client_id;
+        }
+        self.keep_alive_secs = snapshot.keep_alive_secs;
+        self.session_present = snapshot.session_present;
+        self.server_maximum_packet_size = snapshot.server_maximum_packet_size;
+        self.subscriptions = snapshot
+            .subscriptions
+            .into_iter()
+            .map(|entry| (entry.topic_filter, entry.qos))
+            .collect();
+        self.inbound_qos2 = snapshot.inbound_qos2.into_iter().collect();
+        self.imported_flows = snapshot
+            .inflight
+            .into_iter()
+            .map(|entry| (entry.packet_id, entry.phase))
+            .collect();
+        Ok(())
+    }
+
+    /// Hold the packet IDs of imported flows on the connection about to start
+    ///
+    /// The packets themselves are not part of a snapshot; the app re-sends
+    /// them with these packet IDs (PUBLISH with DUP set, or PUBREL for a flow
+    /// awaiting PUBCOMP).
+    fn take_over_imported_flows(&mut self) {
+        for (packet_id, phase) in std::mem::take(&mut self.imported_flows) {
+            if self.register_packet_id(packet_id) {
+                self.qos_flows.insert(packet_id, phase);
+                self.released_flow_ids.remove(&packet_id);
+            }
+        }
+    }
+
+    /// Stop the connect_timeout() timer once the attempt has an outcome
+    fn cancel_connect_timeout(&mut self) {
+        self.pending_connect = None;
+        if self.active_timers.remove(CONNECT_TIMER) {
+            let _ = self
+                .websocket_commands
+                .unbounded_send(UnderlyingLayerCommand::TimerCancel {
+                    kind: CONNECT_TIMER.to_string(),
+                });
+        }
+    }
+
+    /// Fail a connect_timeout() attempt that did not connect in time and close the transport
+    async fn connect_timed_out(&mut self) {
+        if self.state != ConnectionState::Connecting {
+            return;
+        }
+        if let Some(reply_arc) = self.pending_connect.take() {
+            complete_reply(&reply_arc, Err(Error::Timeout));
+        }
+        let _ = self.close().await;
+    }
+
     /// Send MQTT packet
-    async fn send_packet(&mut self, packet: mqtt::packet::Packet) -> Result<()> {
+    fn send_packet(&mut self, packet: mqtt::packet::Packet) -> Result<()> {
         #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(&format!("Sending MQTT packet: {:?}", packet).into());
+        log_labeled!(self.log_label(), "Sending MQTT packet: {:?}", packet);
+        // Recorded with the app's own topics, before any rewrite
+        self.track_subscription_request(&packet);
+        let packet = match self.config.topic_rewrite {
+            Some(ref rewriter) => crate::topic_rewrite::rewrite_packet(rewriter.as_ref(), packet)?,
+            None => packet,
+        };
+        let packet = match self.config.payload_codec {
+            Some(ref codec) => crate::codec::encode_packet(codec.as_ref(), packet)?,
+            None => packet,
+        };
+        self.send_prepared_packet(packet)
+    }
+
+    /// Send a packet that already is in its wire form (rewritten and encoded)
+    fn send_prepared_packet(&mut self, packet: mqtt::packet::Packet) -> Result<()> {
+        self.check_publish_size(&packet)?;
+        let outgoing_flow = Self::outgoing_qos_flow(&packet);
+        let stored_size = self.reserve_send_memory(&packet, outgoing_flow.is_some())?;
         let events = self.mqtt_connection.send(packet);
         #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(&format!("MQTT send returned {} events", events.len()).into());
+        log_labeled!(
+            self.log_label(),
+            "MQTT send returned {} events",
+            events.len()
+        );
+
+        self.send_rejection = events.iter().find_map(|e| match e {
+            mqtt::connection::Event::NotifyError(error) => {
+                Some(Error::ProtocolError(format!("{:?}", error)))
+            }
+            _ => None,
+        });
+
+        // Only track the flow if the connection accepted the PUBLISH
+        if let Some((packet_id, phase)) = outgoing_flow {
+            if self.send_rejection.is_none() {
+                self.qos_flows.insert(packet_id, phase);
+                self.released_flow_ids.remove(&packet_id);
+                if stored_size > 0 {
+                    self.inflight_bytes.insert(packet_id, stored_size);
+                }
+                self.arm_publish_ack_timer(packet_id);
+            }
+        }
         self.handle_mqtt_events(events)
     }
 
-    /// Close connection
-    async fn close(&mut self) -> Result<()> {
+    /// Send a packet for send()/send_sized() and reply with the outcome
+    fn complete_send(&mut self, packet: mqtt::packet::Packet, reply: SendReply) {
+        match reply {
+            SendReply::Send(reply) => {
+                let result = self.send_packet_with_results(packet);
+                let _ = reply.send(result);
+            }
+            SendReply::SendSized(reply) => {
+                // Everything written while handling this send belongs to it,
+                // including packets generated by the state machine
+                let before = self.sent_bytes;
+                let result = self.send_packet_with_results(packet);
+                let _ = reply.send(result.map(|results| (self.sent_bytes - before, results)));
+            }
+        }
+    }
+
+    /// Send a packet for send()/send_sized(), or queue it if it is a PUBLISH
+    /// over `max_publish_rate_per_sec`
+    ///
+    /// Once a publish waits, later ones queue behind it so publishes keep
+    /// their order; other packets (acks, pings, subscribes) go out right away.
+    fn send_or_throttle(&mut self, packet: mqtt::packet::Packet, reply: SendReply) {
+        let is_publish = matches!(
+            packet,
+            mqtt::packet::Packet::V3_1_1Publish(_) | mqtt::packet::Packet::V5_0Publish(_)
+        );
+        let Some(rate) = self.publish_rate().filter(|_| is_publish) else {
+            self.complete_send(packet, reply);
+            return;
+        };
+        if self.throttled_publishes.is_empty() && self.take_publish_token(rate) {
+            self.complete_send(packet, reply);
+            return;
+        }
+
+        self.throttled_publishes
+            .push_back((packet, reply, crate::platform::monotonic_now()));
+        self.emit_event(ConnectionEvent::PublishThrottled {
+            queued: self.throttled_publishes.len(),
+        });
+        self.arm_publish_rate_timer(rate);
+    }
+
+    /// `max_publish_rate_per_sec`, if publishes are limited
+    fn publish_rate(&self) -> Option<u32> {
+        self.config
+            .max_publish_rate_per_sec
+            .filter(|rate| *rate > 0)
+    }
+
+    /// Refill the publish token bucket for the time passed and take a token
+    ///
+    /// The bucket holds up to one second's worth of tokens, so a burst of
+    /// `rate` publishes goes out at once after an idle second.
+    fn take_publish_token(&mut self, rate: u32) -> bool {
+        let now = crate::platform::monotonic_now();
+        self.publish_tokens = match self.publish_tokens_at {
+            Some(refilled_at) => {
+                let elapsed_ms = (now - refilled_at).max(0.0);
+                (self.publish_tokens + elapsed_ms * rate as f64 / 1000.0).min(rate as f64)
+            }
+            None => rate as f64,
+        };
+        self.publish_tokens_at = Some(now);
+        if self.publish_tokens >= 1.0 {
+            self.publish_tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Start the timer that fires when the next publish token is available
+    fn arm_publish_rate_timer(&mut self, rate: u32) {
+        if self.active_timers.contains(PUBLISH_RATE_TIMER) {
+            return;
+        }
+        let wait_ms = ((1.0 - self.publish_tokens) * 1000.0 / rate as f64)
+            .ceil()
+            .max(1.0) as u64;
+        self.active_timers.insert(PUBLISH_RATE_TIMER.to_string());
         let _ = self
             .websocket_commands
-            .unbounded_send(UnderlyingLayerCommand::Close);
-        self.state = ConnectionState::Closed;
-        Ok(())
+            .unbounded_send(UnderlyingLayerCommand::TimerReset {
+                kind: PUBLISH_RATE_TIMER.to_string(),
+                duration_ms: wait_ms,
+            });
     }
 
-    /// Process incoming WebSocket data
-    fn process_incoming_data(&mut self, data: Vec<u8>) {
-        // Append to buffer
-        let new_data_len = data.len();
+    /// Send queued publishes for which tokens are available again
+    ///
+    /// A v5.0 PUBLISH goes out with its Message Expiry Interval lowered by the
+    /// time it waited; one that expired while waiting is not sent and its
+    /// send() fails.
+    fn release_throttled_publishes(&mut self) {
+        let Some(rate) = self.publish_rate() else {
+            return;
+        };
+        while !self.throttled_publishes.is_empty() && self.take_publish_token(rate) {
+            if let Some((packet, reply, queued_at)) = self.throttled_publishes.pop_front() {
+                let queued_ms = crate::platform::monotonic_now() - queued_at;
+                match age_queued_publish(packet, queued_ms) {
+                    Some(packet) => self.complete_send(packet, reply),
+                    None => {
+                        // Nothing was sent, so the token is still available
+                        self.publish_tokens += 1.0;
+                        reply.fail(Error::SendFailed(
+                            "Message Expiry Interval passed while throttled".to_string(),
+                        ));
+                    }
+                }
+            }
+        }
+        if !self.throttled_publishes.is_empty() {
+            self.arm_publish_rate_timer(rate);
+        }
+    }
 
-        // Compact buffer if needed
-        if self.consumed_bytes > 0 {
-            let unconsumed_len = self.buffer_size - self.consumed_bytes;
-            if unconsumed_len > 0 {
-                self.read_buffer
-                    .copy_within(self.consumed_bytes..self.buffer_size, 0);
+    /// Send a packet and collect the transport's outcome for every write it causes
+    fn send_packet_with_results(&mut self, packet: mqtt::packet::Packet) -> Result<SendResults> {
+        self.send_results = Some(Vec::new());
+        let result = self.send_packet(packet);
+        let results = self.send_results.take().unwrap_or_default();
+        result.map(|_| results)
+    }
+
+    /// Validate captured bytes as exactly one packet and send it for send_encoded()
+    ///
+    /// Topic rewriting and the payload codec are skipped: the bytes are sent as
+    /// captured, even where the connection would encode the packet differently.
+    /// The decoded packet still passes the protocol state machine, so packet
+    /// IDs and QoS flows are tracked as for send(). The bytes replace only that
+    /// packet as decoded: a PUBLISH is refused while topic aliases are mapped
+    /// automatically, as the connection would alter it, and nothing is written
+    /// if the connection changes the packet anyway.
+    fn send_encoded_packet(&mut self, bytes: Vec<u8>) -> Result<SendResults> {
+        let (packet, len) = crate::packet_parse::parse_packet(&bytes, self.config.version)
+            .map_err(|detail| Error::DecodeError { offset: 0, detail })?;
+        if len < bytes.len() {
+            return Err(Error::DecodeError {
+                offset: len,
+                detail: format!("{} bytes after the packet", bytes.len() - len),
+            });
+        }
+        if Self::is_publish(&packet)
+            && (self.config.auto_map_topic_alias_send || self.config.auto_replace_topic_alias_send)
+        {
+            return Err(Error::SendFailed(
+                "send_encoded() cannot send PUBLISH while topic aliases are mapped automatically"
+                    .to_string(),
+            ));
+        }
+        self.track_subscription_request(&packet);
+        self.send_results = Some(Vec::new());
+        self.encoded_bytes = Some((packet.to_continuous_buffer(), bytes));
+        let result = self.send_prepared_packet(packet);
+        self.encoded_bytes = None;
+        let results = self.send_results.take().unwrap_or_default();
+        result?;
+        match self.send_rejection.take() {
+            Some(error) => Err(error),
+            None => Ok(results),
+        }
+    }
+
+    /// Initial QoS phase for an outgoing QoS 1/2 PUBLISH, if any
+    fn outgoing_qos_flow(packet: &mqtt::packet::Packet) -> Option<(u16, InflightPhase)> {
+        let (qos, packet_id) = match packet {
+            mqtt::packet::Packet::V3_1_1Publish(p) => (p.qos(), p.packet_id()),
+            mqtt::packet::Packet::V5_0Publish(p) => (p.qos(), p.packet_id()),
+            _ => return None,
+        };
+        let phase = match qos {
+            mqtt::packet::Qos::AtLeastOnce => InflightPhase::AwaitingPuback,
+            mqtt::packet::Qos::ExactlyOnce => InflightPhase::AwaitingPubrec,
+            _ => return None,
+        };
+        packet_id.map(|id| (id, phase))
+    }
+
+    /// Remember the filters of an outgoing SUBSCRIBE/UNSUBSCRIBE until acknowledged
+    fn track_subscription_request(&mut self, packet: &mqtt::packet::Packet) {
+        let subscribe = |entries: &[mqtt::packet::SubEntry]| -> Vec<(String, u8)> {
+            entries
+                .iter()
+                .map(|e| (e.topic_filter().to_string(), e.sub_opts().qos() as u8))
+                .collect()
+        };
+        match packet {
+            mqtt::packet::Packet::V3_1_1Subscribe(p) => {
+                self.requested_subscriptions
+                    .insert(p.packet_id(), subscribe(p.entries()));
             }
-            self.buffer_size = unconsumed_len;
-            self.consumed_bytes = 0;
+            mqtt::packet::Packet::V5_0Subscribe(p) => {
+                self.requested_subscriptions
+                    .insert(p.packet_id(), subscribe(p.entries()));
+            }
+            mqtt::packet::Packet::V3_1_1Unsubscribe(p) => {
+                let filters = p.entries().iter().map(|t| t.as_ref().to_string()).collect();
+                self.requested_unsubscriptions
+                    .insert(p.packet_id(), filters);
+            }
+            mqtt::packet::Packet::V5_0Unsubscribe(p) => {
+                let filters = p.entries().iter().map(|t| t.as_ref().to_string()).collect();
+                self.requested_unsubscriptions
+                    .insert(p.packet_id(), filters);
+            }
+            _ => {}
         }
+    }
 
-        // Ensure capacity and copy data
-        self.read_buffer.resize(self.buffer_size + new_data_len, 0);
-        self.read_buffer[self.buffer_size..self.buffer_size + new_data_len].copy_from_slice(&data);
-        self.buffer_size += new_data_len;
+    /// Refuse a SUBSCRIBE that would take the session past `max_subscriptions`
+    ///
+    /// Counts the distinct filters confirmed, awaiting SUBACK and in the packet.
+    fn check_subscription_limit(&self, packet: &mqtt::packet::Packet) -> Result<()> {
+        let Some(limit) = self.config.max_subscriptions else {
+            return Ok(());
+        };
+        let entries = match packet {
+            mqtt::packet::Packet::V3_1_1Subscribe(p) => p.entries(),
+            mqtt::packet::Packet::V5_0Subscribe(p) => p.entries(),
+            _ => return Ok(()),
+        };
+        let filters: HashSet<&str> = self
+            .subscriptions
+            .iter()
+            .chain(self.requested_subscriptions.values().flatten())
+            .map(|(filter, _)| filter.as_str())
+            .chain(entries.iter().map(|e| e.topic_filter()))
+            .collect();
+        if filters.len() > limit {
+            return Err(Error::SubscriptionLimitReached(limit));
+        }
+        Ok(())
+    }
 
-        // Process buffer
-        if self.consumed_bytes < self.buffer_size {
-            let unconsumed_data = &self.read_buffer[self.consumed_bytes..self.buffer_size];
-            let mut cursor = mqtt::common::Cursor::new(unconsumed_data);
+    /// Apply a received SUBACK/UNSUBACK to the subscription list
+    ///
+    /// Only filters the broker accepted (reason code below 0x80) are added or removed.
+    fn update_subscriptions(&mut self, packet: &mqtt::packet::Packet) {
+        let (packet_id, codes, subscribe): (u16, Vec<u8>, bool) = match packet {
+            mqtt::packet::Packet::V3_1_1Suback(p) => (
+                p.packet_id(),
+                p.return_codes().iter().map(|c| *c as u8).collect(),
+                true,
+            ),
+            mqtt::packet::Packet::V5_0Suback(p) => (
+                p.packet_id(),
+                p.reason_codes().iter().map(|c| *c as u8).collect(),
+                true,
+            ),
+            // v3.1.1 UNSUBACK has no reason codes; every filter is removed
+            mqtt::packet::Packet::V3_1_1Unsuback(p) => (p.packet_id(), Vec::new(), false),
+            mqtt::packet::Packet::V5_0Unsuback(p) => (
+                p.packet_id(),
+                p.reason_codes().iter().map(|c| *c as u8).collect(),
+                false,
+            ),
+            _ => return,
+        };
 
-            let events = self.mqtt_connection.recv(&mut cursor);
-            self.consumed_bytes += cursor.position() as usize;
+        if subscribe {
+            let Some(requested) = self.requested_subscriptions.remove(&packet_id) else {
+                return;
+            };
+            for ((filter, qos), code) in requested.into_iter().zip(codes) {
+                if code >= 0x80 {
+                    continue;
+                }
+                match self.subscriptions.iter_mut().find(|(f, _)| *f == filter) {
+                    Some(existing) => existing.1 = qos,
+                    None => self.subscriptions.push((filter, qos)),
+                }
+            }
+        } else {
+            let Some(requested) = self.requested_unsubscriptions.remove(&packet_id) else {
+                return;
+            };
+            for (i, filter) in requested.into_iter().enumerate() {
+                if codes.get(i).is_some_and(|code| *code >= 0x80) {
+                    continue;
+                }
+                self.subscriptions.retain(|(f, _)| *f != filter);
+            }
+        }
+    }
 
-            let _ = self.handle_mqtt_events(events);
+    /// Check whether a CONNACK started a new session that lacks the remembered subscriptions
+    fn subscriptions_lost(&self, packet: &mqtt::packet::Packet) -> bool {
+        if !self.config.restore_subscriptions || self.subscriptions.is_empty() {
+            return false;
+        }
+        match packet {
+            mqtt::packet::Packet::V3_1_1Connack(p) => {
+                p.return_code() == mqtt::result_code::ConnectReturnCode::Accepted
+                    && !p.session_present()
+            }
+            mqtt::packet::Packet::V5_0Connack(p) => {
+                p.reason_code() == mqtt::result_code::ConnectReasonCode::Success
+                    && !p.session_present()
+            }
+            _ => false,
         }
     }
 
-    /// Handle MQTT events (store send requests for async processing)
-    #[allow(unused_variables)]
-    fn handle_mqtt_events(&mut self, events: Vec<mqtt::connection::Event>) -> Result<()> {
+    /// Check for an inbound QoS 2 PUBLISH the app already received
+    ///
+    /// The connection only recognizes a re-sent QoS 2 PUBLISH while it awaits
+    /// the PUBREL, and it is replaced on reconnection. A broker resuming the
+    /// session may send such a PUBLISH again, so its packet ID is remembered
+    /// here until the PUBREL arrives. A replay is answered with PUBREC (the
+    /// connection already did if `auto_pub_response` is on) and not delivered.
+    fn is_replayed_qos2(&mut self, packet: &mqtt::packet::Packet) -> bool {
+        let (qos, packet_id) = match packet {
+            mqtt::packet::Packet::V3_1_1Publish(p) => (p.qos(), p.packet_id()),
+            mqtt::packet::Packet::V5_0Publish(p) => (p.qos(), p.packet_id()),
+            mqtt::packet::Packet::V3_1_1Pubrel(p) => {
+                self.inbound_qos2.remove(&p.packet_id());
+                return false;
+            }
+            mqtt::packet::Packet::V5_0Pubrel(p) => {
+                self.inbound_qos2.remove(&p.packet_id());
+                return false;
+            }
+            _ => return false,
+        };
+        let Some(packet_id) = packet_id.filter(|_| qos == mqtt::packet::Qos::ExactlyOnce) else {
+            return false;
+        };
+        if self.inbound_qos2.insert(packet_id) {
+            return false;
+        }
+
         #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(
-            &format!(
-                "🚀🚀🚀 FORCE UPDATE: Processing {} MQTT events 🚀🚀🚀",
-                events.len()
-            )
-            .into(),
+        log_labeled!(
+            self.log_label(),
+            "Dropping re-sent QoS 2 PUBLISH {} delivered before",
+            packet_id
         );
-
-        for (i, event) in events.iter().enumerate() {
-            #[cfg(target_arch = "wasm32")]
-            web_sys::console::log_1(&format!("Event {}: {:?}", i + 1, event).into());
+        if !self.config.auto_pub_response {
+            let pubrec = match self.config.version {
+                mqtt::Version::V5_0 => mqtt::packet::v5_0::Pubrec::builder()
+                    .packet_id(packet_id)
+                    .build()
+                    .map(mqtt::packet::Packet::V5_0Pubrec)
+                    .ok(),
+                _ => mqtt::packet::v3_1_1::Pubrec::builder()
+                    .packet_id(packet_id)
+                    .build()
+                    .map(mqtt::packet::Packet::V3_1_1Pubrec)
+                    .ok(),
+            };
+            if let Some(pubrec) = pubrec {
+                let _ = self.send_packet(pubrec);
+            }
         }
+        true
+    }
 
-        for event in events {
-            match event {
-                mqtt::connection::Event::RequestSendPacket { packet, .. } => {
-                    let buffer = packet.to_continuous_buffer();
-                    #[cfg(target_arch = "wasm32")]
-                    web_sys::console::log_1(
-                        &format!("Sending packet: {} bytes", buffer.len()).into(),
-                    );
-                    // Send via WebSocket command
-                    match self
-                        .websocket_commands
-                        .unbounded_send(UnderlyingLayerCommand::SendData(buffer))
-                    {
-                        Ok(_) => {
-                            #[cfg(target_arch = "wasm32")]
-                            web_sys::console::log_1(
-                                &"Sent packet successfully via WebSocket command".into(),
-                            );
-                        }
-                        Err(e) => {
-                            #[cfg(target_arch = "wasm32")]
-                            web_sys::console::log_1(
-                                &format!("Failed to send packet via WebSocket command: {:?}", e)
-                                    .into(),
-                            );
-                        }
-                    }
-                }
-                mqtt::connection::Event::NotifyPacketReceived(packet) => {
-                    if self.packet_sender.unbounded_send(packet).is_err() {
-                        eprintln!("Failed to forward received packet");
+    /// Record the session-present flag of an accepted CONNACK
+    ///
+    /// Returns true if the CONNECT asked to resume a session but the broker
+    /// has none, so state the app kept for that session is stale.
+    fn track_session_present(&mut self, packet: &mqtt::packet::Packet) -> bool {
+        let session_present = match packet {
+            mqtt::packet::Packet::V3_1_1Connack(p)
+                if p.return_code() == mqtt::result_code::ConnectReturnCode::Accepted =>
+            {
+                p.session_present()
+            }
+            mqtt::packet::Packet::V5_0Connack(p)
+                if p.reason_code() == mqtt::result_code::ConnectReasonCode::Success =>
+            {
+                p.session_present()
+            }
+            _ => return false,
+        };
+        self.session_present = Some(session_present);
+        if !session_present {
+            self.inbound_qos2.clear();
+        }
+        self.session_requested && !session_present
+    }
+
+    /// Note where a v5.0 CONNACK or DISCONNECT refers the client to (`follow_server_reference`)
+    ///
+    /// Only Use another server (0x9C) and Server moved (0x9D) are followed. The
+    /// transport is closed by `handle_mqtt_events`; the Closed handler then
+    /// connects to the referenced server instead of scheduling a reconnect.
+    fn follow_server_reference(&mut self, packet: &mqtt::packet::Packet) {
+        if !self.config.follow_server_reference {
+            return;
+        }
+        let (reason_code, reference) = match packet {
+            mqtt::packet::Packet::V5_0Connack(p) => {
+                (p.reason_code() as u8, server_reference(p.props.iter()))
+            }
+            mqtt::packet::Packet::V5_0Disconnect(p) => (
+                p.reason_code().map_or(0, |c| c as u8),
+                server_reference(p.props.iter().flatten()),
+            ),
+            _ => return,
+        };
+        if reason_code != 0x9C && reason_code != 0x9D {
+            return;
+        }
+        let Some(server) = reference else {
+            return;
+        };
+        let Some(url) = redirect_target(self.reconnect_url.as_deref(), &server) else {
+            return;
+        };
+        #[cfg(target_arch = "wasm32")]
+        log_labeled!(
+            self.log_label(),
+            "Following server reference {} to {}",
+            server,
+            url
+        );
+        self.redirect_url = Some(url);
+        self.emit_event(ConnectionEvent::Redirected { server });
+    }
+
+    /// Add the random `pingreq_jitter_ms` offset to a PINGREQ interval
+    fn jittered_ping_interval(&self, duration_ms: u64) -> u64 {
+        match self.config.pingreq_jitter_ms {
+            Some(jitter_ms) if jitter_ms > 0 => {
+                duration_ms + (crate::platform::random() * jitter_ms as f64) as u64
+            }
+            _ => duration_ms,
+        }
+    }
+
+    /// Remember the reason code of an inbound v5.0 DISCONNECT and report a
+    /// Keep Alive timeout (0x8D)
+    fn track_disconnect_reason(&mut self, packet: &mqtt::packet::Packet) {
+        let mqtt::packet::Packet::V5_0Disconnect(p) = packet else {
+            return;
+        };
+        let reason_code = p.reason_code().map_or(0, |c| c as u8);
+        self.last_disconnect_reason = Some(reason_code);
+        if reason_code == 0x8D {
+            self.emit_event(ConnectionEvent::KeepAliveTimeout);
+        }
+    }
+
+    /// Remember the Maximum Packet Size the broker accepts, from its v5.0 CONNACK
+    fn track_maximum_packet_size(&mut self, packet: &mqtt::packet::Packet) {
+        if let mqtt::packet::Packet::V5_0Connack(p) = packet {
+            self.server_maximum_packet_size = p.props.iter().find_map(|prop| match prop {
+                mqtt::packet::Property::MaximumPacketSize(p) => Some(p.val()),
+                _ => None,
+            });
+        }
+    }
+
+    /// Reject a PUBLISH larger than the broker's Maximum Packet Size with a size breakdown
+    ///
+    /// MQTT cannot split a PUBLISH, so the payload has to shrink by the excess.
+    /// Skipped while topic aliases are applied automatically, as they may
+    /// shorten the packet.
+    fn check_publish_size(&self, packet: &mqtt::packet::Packet) -> Result<()> {
+        let Some(max_packet_size) = self.server_maximum_packet_size else {
+            return Ok(());
+        };
+        if self.config.auto_map_topic_alias_send || self.config.auto_replace_topic_alias_send {
+            return Ok(());
+        }
+        let mqtt::packet::Packet::V5_0Publish(p) = packet else {
+            return Ok(());
+        };
+        let size = packet.to_continuous_buffer().len();
+        if size <= max_packet_size as usize {
+            return Ok(());
+        }
+        let payload_size = p.payload().len();
+        Err(Error::PayloadTooLarge {
+            payload_size,
+            max_packet_size,
+            overhead: size - payload_size,
+        })
+    }
+
+    /// Re-send the remembered subscriptions in one SUBSCRIBE with a fresh packet ID
+    ///
+    /// The SUBACK is delivered to recv() like any other.
+    fn restore_subscriptions(&mut self) {
+        let Some(packet_id) = self.acquire_packet_id() else {
+            self.emit_event(ConnectionEvent::Error(Error::Other(
+                "No packet ID available to restore subscriptions".to_string(),
+            )));
+            return;
+        };
+        let entries: Vec<mqtt::packet::SubEntry> = self
+            .subscriptions
+            .iter()
+            .filter_map(|(filter, qos)| {
+                let qos = mqtt::packet::Qos::try_from(*qos).ok()?;
+                mqtt::packet::SubEntry::new(filter, mqtt::packet::SubOpts::new().set_qos(qos)).ok()
+            })
+            .collect();
+        let count = entries.len();
+        let subscribe = match self.config.version {
+            mqtt::Version::V5_0 => mqtt::packet::v5_0::Subscribe::builder()
+                .packet_id(packet_id)
+                .entries(entries)
+                .build()
+                .map(mqtt::packet::Packet::V5_0Subscribe)
+                .map_err(|e| format!("{:?}", e)),
+            _ => mqtt::packet::v3_1_1::Subscribe::builder()
+                .packet_id(packet_id)
+                .entries(entries)
+                .build()
+                .map(mqtt::packet::Packet::V3_1_1Subscribe)
+                .map_err(|e| format!("{:?}", e)),
+        };
+        let result = match subscribe {
+            Ok(packet) => self.send_packet(packet),
+            Err(e) => {
+                let events = self.mqtt_connection.release_packet_id(packet_id);
+                let _ = self.handle_mqtt_events(events);
+                Err(Error::Other(format!("Failed to build SUBSCRIBE: {}", e)))
+            }
+        };
+        match result {
+            Ok(()) => self.emit_event(ConnectionEvent::SubscriptionsRestored { count }),
+            Err(e) => self.emit_event(ConnectionEvent::Error(e)),
+        }
+    }
+
+    /// Give back packet IDs whose reuse delay has passed
+    ///
+    /// Runs before each request and when the cool-down timer fires, so an
+    /// acquire_packet_id() waiting for a free ID is served without another request.
+    fn release_cooled_packet_ids(&mut self) {
+        let now = crate::platform::monotonic_now();
+        let delay_ms = self.config.packet_id_reuse_delay_ms as f64;
+        let mut released = false;
+        while let Some(&(packet_id, released_at)) = self.cooling_packet_ids.front() {
+            if now - released_at < delay_ms {
+                break;
+            }
+            self.cooling_packet_ids.pop_front();
+            let mut events = self.mqtt_connection.release_packet_id(packet_id);
+            // This release ends the delay; it must not start another one
+            events.retain(|e| !matches!(e, mqtt::connection::Event::NotifyPacketIdReleased(_)));
+            let _ = self.handle_mqtt_events(events);
+            self.release_to_allocator(packet_id);
+            released = true;
+        }
+        if released {
+            self.serve_packet_id_waiters();
+        }
+        if released || !self.active_timers.contains(PACKET_ID_COOLDOWN_TIMER) {
+            self.arm_packet_id_cooldown_timer();
+        }
+    }
+
+    /// Arm the cool-down timer for the oldest cooling packet ID
+    fn arm_packet_id_cooldown_timer(&mut self) {
+        let Some(&(_, released_at)) = self.cooling_packet_ids.front() else {
+            return;
+        };
+        let deadline = released_at + self.config.packet_id_reuse_delay_ms as f64;
+        let remaining_ms = (deadline - crate::platform::monotonic_now())
+            .max(0.0)
+            .ceil() as u64;
+        self.active_timers
+            .insert(PACKET_ID_COOLDOWN_TIMER.to_string());
+        let _ = self
+            .websocket_commands
+            .unbounded_send(UnderlyingLayerCommand::TimerReset {
+                kind: PACKET_ID_COOLDOWN_TIMER.to_string(),
+                duration_ms: remaining_ms,
+            });
+    }
+
+    /// Advance tracked QoS flows on received PUBACK/PUBREC/PUBCOMP
+    /// and report finished flows as `ConnectionEvent::PublishComplete`
+    fn update_qos_flow(&mut self, packet: &mqtt::packet::Packet) {
+        if let mqtt::packet::Packet::V5_0Pubrec(p) = packet {
+            let reason_code = p.reason_code().map_or(0, |c| c as u8);
+            if reason_code >= 0x80 {
+                self.fail_qos2_flow(p.packet_id(), reason_code);
+                return;
+            }
+        }
+        let (packet_id, expected, next) = match packet {
+            mqtt::packet::Packet::V3_1_1Puback(p) => (
+                p.packet_id(),
+                InflightPhase::AwaitingPuback,
+                InflightPhase::Complete,
+            ),
+            mqtt::packet::Packet::V5_0Puback(p) => (
+                p.packet_id(),
+                InflightPhase::AwaitingPuback,
+                InflightPhase::Complete,
+            ),
+            mqtt::packet::Packet::V3_1_1Pubrec(p) => (
+                p.packet_id(),
+                InflightPhase::AwaitingPubrec,
+                InflightPhase::AwaitingPubcomp,
+            ),
+            mqtt::packet::Packet::V5_0Pubrec(p) => (
+                p.packet_id(),
+                InflightPhase::AwaitingPubrec,
+                InflightPhase::AwaitingPubcomp,
+            ),
+            mqtt::packet::Packet::V3_1_1Pubcomp(p) => (
+                p.packet_id(),
+                InflightPhase::AwaitingPubcomp,
+                InflightPhase::Complete,
+            ),
+            mqtt::packet::Packet::V5_0Pubcomp(p) => (
+                p.packet_id(),
+                InflightPhase::AwaitingPubcomp,
+                InflightPhase::Complete,
+            ),
+            _ => return,
+        };
+        let advanced = match self.qos_flows.get_mut(&packet_id) {
+            Some(phase) if *phase == expected => {
+                *phase = next;
+                true
+            }
+            _ => false,
+        };
+        if advanced && next == InflightPhase::Complete {
+            // The connection no longer stores the PUBLISH for retransmission
+            self.inflight_bytes.remove(&packet_id);
+            self.cancel_publish_ack_timer(packet_id);
+            let qos = if expected == InflightPhase::AwaitingPuback {
+                1
+            } else {
+                2
+            };
+            self.emit_event(ConnectionEvent::PublishComplete { packet_id, qos });
+        }
+    }
+
+    /// End a QoS 2 flow the broker refused with a failing v5.0 PUBREC (>= 0x80)
+    ///
+    /// No PUBREL/PUBCOMP follow, so the packet ID is released right away and
+    /// the flow is reported as `ConnectionEvent::PublishFailed`.
+    fn fail_qos2_flow(&mut self, packet_id: u16, reason_code: u8) {
+        if self.qos_flows.get(&packet_id) != Some(&InflightPhase::AwaitingPubrec) {
+            return;
+        }
+        self.qos_flows.remove(&packet_id);
+        self.released_flow_ids.remove(&packet_id);
+        self.inflight_bytes.remove(&packet_id);
+        self.cancel_publish_ack_timer(packet_id);
+        let events = self.mqtt_connection.release_packet_id(packet_id);
+        let _ = self.handle_mqtt_events(events);
+        self.serve_packet_id_waiters();
+        self.emit_event(ConnectionEvent::PublishFailed {
+            packet_id,
+            reason_code,
+        });
+    }
+
+    /// Start the `publish_ack_timeout_ms` timer for an outgoing QoS 1/2 PUBLISH
+    fn arm_publish_ack_timer(&mut self, packet_id: u16) {
+        let Some(timeout_ms) = self.config.publish_ack_timeout_ms else {
+            return;
+        };
+        let kind = format!("{}{}", PUBLISH_ACK_TIMER_PREFIX, packet_id);
+        self.active_timers.insert(kind.clone());
+        let _ = self
+            .websocket_commands
+            .unbounded_send(UnderlyingLayerCommand::TimerReset {
+                kind,
+                duration_ms: timeout_ms,
+            });
+    }
+
+    /// Stop the `publish_ack_timeout_ms` timer once the final acknowledgement arrived
+    fn cancel_publish_ack_timer(&mut self, packet_id: u16) {
+        let kind = format!("{}{}", PUBLISH_ACK_TIMER_PREFIX, packet_id);
+        if self.active_timers.remove(&kind) {
+            let _ = self
+                .websocket_commands
+                .unbounded_send(UnderlyingLayerCommand::TimerCancel { kind });
+        }
+    }
+
+    /// Number of outgoing QoS 1/2 PUBLISH still awaiting their final acknowledgement
+    fn inflight_count(&self) -> usize {
+        self.qos_flows
+            .values()
+            .filter(|phase| **phase != InflightPhase::Complete)
+            .count()
+    }
+
+    /// Answer `reply` once no QoS 1/2 PUBLISH is inflight, or with
+    /// `Error::Timeout` after `timeout_ms`
+    fn wait_inflight_drained(&mut self, timeout_ms: u64, reply: oneshot::Sender<Result<()>>) {
+        if self.inflight_count() == 0 {
+            let _ = reply.send(Ok(()));
+            return;
+        }
+        // Flows only finish on a live connection
+        if self.state != ConnectionState::Connected {
+            let _ = reply.send(Err(Error::NotConnected));
+            return;
+        }
+        let waiter = self.next_inflight_drained_waiter;
+        self.next_inflight_drained_waiter = waiter.wrapping_add(1);
+        let kind = format!("{}{}", INFLIGHT_DRAINED_TIMER_PREFIX, waiter);
+        self.active_timers.insert(kind.clone());
+        let _ = self
+            .websocket_commands
+            .unbounded_send(UnderlyingLayerCommand::TimerReset {
+                kind,
+                duration_ms: timeout_ms,
+            });
+        self.inflight_drained_waiters.push((waiter, reply));
+    }
+
+    /// Resolve the wait_inflight_drained() callers once nothing is inflight
+    fn serve_inflight_drained_waiters(&mut self) {
+        if self.inflight_drained_waiters.is_empty() || self.inflight_count() > 0 {
+            return;
+        }
+        for (waiter, reply) in std::mem::take(&mut self.inflight_drained_waiters) {
+            let kind = format!("{}{}", INFLIGHT_DRAINED_TIMER_PREFIX, waiter);
+            if self.active_timers.remove(&kind) {
+                let _ = self
+                    .websocket_commands
+                    .unbounded_send(UnderlyingLayerCommand::TimerCancel { kind });
+            }
+            let _ = reply.send(Ok(()));
+        }
+    }
+
+    /// Fail a wait_inflight_drained() caller whose deadline passed
+    fn inflight_drain_timed_out(&mut self, waiter: u64) {
+        if let Some(index) = self
+            .inflight_drained_waiters
+            .iter()
+            .position(|(id, _)| *id == waiter)
+        {
+            let (_, reply) = self.inflight_drained_waiters.remove(index);
+            let _ = reply.send(Err(Error::Timeout));
+        }
+    }
+
+    /// Report a QoS 1/2 PUBLISH whose final acknowledgement did not arrive in time
+    fn publish_ack_timed_out(&mut self, packet_id: u16) {
+        // Completed or released in the meantime
+        match self.qos_flows.get(&packet_id) {
+            None | Some(InflightPhase::Complete) => return,
+            Some(_) => {}
+        }
+        self.emit_event(ConnectionEvent::PublishTimedOut { packet_id });
+        if self.config.release_packet_id_on_ack_timeout {
+            self.qos_flows.remove(&packet_id);
+            self.released_flow_ids.remove(&packet_id);
+            self.inflight_bytes.remove(&packet_id);
+            let events = self.mqtt_connection.release_packet_id(packet_id);
+            let _ = self.handle_mqtt_events(events);
+            self.serve_packet_id_waiters();
+        }
+    }
+
+    /// Decode a received PUBLISH payload with the configured codec
+    ///
+    /// On failure the packet is delivered unchanged (marker included) and the
+    /// codec error is emitted as `ConnectionEvent::Error`.
+    fn decode_payload(&mut self, packet: mqtt::packet::Packet) -> mqtt::packet::Packet {
+        let codec = match self.config.payload_codec {
+            Some(ref codec) => codec.clone(),
+            None => return packet,
+        };
+        if !crate::codec::is_encoded_by(codec.as_ref(), &packet) {
+            return packet;
+        }
+        let fallback = packet.clone();
+        match crate::codec::decode_packet(codec.as_ref(), packet) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                self.emit_event(ConnectionEvent::Error(e));
+                fallback
+            }
+        }
+    }
+
+    /// Record ping round-trip times from PINGREQ sends and PINGRESP receipts
+    fn track_ping(&mut self, packet: &mqtt::packet::Packet, outgoing: bool) {
+        match packet {
+            mqtt::packet::Packet::V3_1_1Pingreq(_) | mqtt::packet::Packet::V5_0Pingreq(_)
+                if outgoing =>
+            {
+                self.pingreq_sent_at = Some(crate::platform::monotonic_now());
+            }
+            mqtt::packet::Packet::V3_1_1Pingresp(_) | mqtt::packet::Packet::V5_0Pingresp(_)
+                if !outgoing =>
+            {
+                if let Some(sent_at) = self.pingreq_sent_at.take() {
+                    if self.rtt_samples.len() == RTT_WINDOW_SIZE {
+                        self.rtt_samples.pop_front();
                     }
+                    let rtt = crate::platform::monotonic_now() - sent_at;
+                    self.rtt_samples.push_back(rtt);
                 }
-                mqtt::connection::Event::RequestTimerReset { kind, duration_ms } => {
-                    let kind_str = format!("{:?}", kind);
+            }
+            _ => {}
+        }
+    }
 
-                    // Track the timer as active
-                    self.active_timers.insert(kind_str.clone());
+    /// Summarize the ping round-trip window
+    fn rtt_stats(&self) -> RttStats {
+        let sample_count = self.rtt_samples.len();
+        if sample_count == 0 {
+            return RttStats::default();
+        }
+        RttStats {
+            last_ms: self.rtt_samples.back().copied().unwrap_or(0.0),
+            avg_ms: self.rtt_samples.iter().sum::<f64>() / sample_count as f64,
+            max_ms: self.rtt_samples.iter().copied().fold(0.0, f64::max),
+            sample_count,
+        }
+    }
 
-                    // Delegate timer handling to underlying layer
-                    // The underlying layer will send TimerExpired event when the timer fires
-                    let _ = self.websocket_commands.unbounded_send(
-                        UnderlyingLayerCommand::TimerReset {
-                            kind: kind_str,
-                            duration_ms,
-                        },
-                    );
+    /// Combine the tracked state into a health report
+    fn health(&mut self, buffered_amount: u64) -> HealthReport {
+        let buffered_amount_growing = buffered_amount > self.last_buffered_amount;
+        self.last_buffered_amount = buffered_amount;
+        let now = crate::platform::date_now();
+        HealthReport {
+            state: self.state,
+            last_rtt_ms: self.rtt_samples.back().copied(),
+            buffered_amount,
+            buffered_amount_growing,
+            inflight_count: self.inflight_count(),
+            seconds_since_last_recv: self.last_recv_at.map(|at| (now - at).max(0.0) / 1000.0),
+            keep_alive_secs: self.keep_alive_secs,
+        }
+    }
+
+    /// Release a packet ID whose QoS flow has finished
+    fn complete_qos(&mut self, packet_id: u16) -> Result<()> {
+        match self.qos_flows.get(&packet_id) {
+            None => Err(Error::PacketIdNotInUse(packet_id)),
+            Some(InflightPhase::Complete) => {
+                self.qos_flows.remove(&packet_id);
+                // The connection may have released the ID with the final
+                // acknowledgement; releasing it again could free an ID that
+                // has been handed out since
+                if self.released_flow_ids.remove(&packet_id) {
+                    return Ok(());
                 }
-                mqtt::connection::Event::RequestTimerCancel(kind) => {
-                    let kind_str = format!("{:?}", kind);
+                let events = self.mqtt_connection.release_packet_id(packet_id);
+                self.handle_mqtt_events(events)
+            }
+            Some(_) => Err(Error::QosFlowIncomplete(packet_id)),
+        }
+    }
 
-                    // Remove from tracking
-                    self.active_timers.remove(&kind_str);
+    /// Close connection
+    async fn close(&mut self) -> Result<()> {
+        // No automatic reconnection after an explicit close
+        self.reconnect_url = None;
+        self.cancel_reconnect();
+        let _ = self
+            .websocket_commands
+            .unbounded_send(UnderlyingLayerCommand::Close);
+        self.set_state(ConnectionState::Closed);
+        Ok(())
+    }
 
-                    // Delegate cancellation to underlying layer
-                    let _ = self
-                        .websocket_commands
-                        .unbounded_send(UnderlyingLayerCommand::TimerCancel { kind: kind_str });
-                }
-                mqtt::connection::Event::NotifyError(error) => {
-                    eprintln!("MQTT protocol error: {:?}", error);
+    /// Send an app-built DISCONNECT and close once the broker has closed the
+    /// connection, or after `shutdown_timeout_ms` (0 = right away)
+    ///
+    /// The broker closing is what shows the DISCONNECT went out; `reply` is
+    /// answered by the Closed handler either way.
+    async fn close_with_disconnect(
+        &mut self,
+        packet: mqtt::packet::Packet,
+        reply: oneshot::Sender<Result<()>>,
+    ) {
+        if !matches!(
+            packet,
+            mqtt::packet::Packet::V3_1_1Disconnect(_) | mqtt::packet::Packet::V5_0Disconnect(_)
+        ) {
+            let _ = reply.send(Err(Error::Other(
+                "close_with_disconnect() requires a DISCONNECT packet".to_string(),
+            )));
+            return;
+        }
+        // Nothing to send on; close like disconnect() does
+        if self.state != ConnectionState::Connected {
+            let _ = reply.send(self.close().await);
+            return;
+        }
+        let packet = self.fit_disconnect(packet);
+        if let Err(e) = self.send_packet(packet) {
+            let _ = self.close().await;
+            let _ = reply.send(Err(e));
+            return;
+        }
+        // The connection may have closed itself after sending DISCONNECT
+        if self.state == ConnectionState::Closed || self.config.shutdown_timeout_ms == 0 {
+            let _ = reply.send(self.close().await);
+            return;
+        }
+        // No automatic reconnection when the broker closes
+        self.reconnect_url = None;
+        self.cancel_reconnect();
+        self.active_timers.insert(SHUTDOWN_TIMER.to_string());
+        let _ = self
+            .websocket_commands
+            .unbounded_send(UnderlyingLayerCommand::TimerReset {
+                kind: SHUTDOWN_TIMER.to_string(),
+                duration_ms: self.config.shutdown_timeout_ms,
+            });
+        self.shutdown_waiters.push(reply);
+    }
+
+    /// Drop the Reason String of a v5.0 DISCONNECT that would exceed the
+    /// broker's Maximum Packet Size, as the spec requires
+    ///
+    /// The packet goes out without it and `ReasonStringDropped` is emitted.
+    fn fit_disconnect(&mut self, packet: mqtt::packet::Packet) -> mqtt::packet::Packet {
+        let Some(max_packet_size) = self.server_maximum_packet_size else {
+            return packet;
+        };
+        let mqtt::packet::Packet::V5_0Disconnect(ref p) = packet else {
+            return packet;
+        };
+        if packet.to_continuous_buffer().len() <= max_packet_size as usize {
+            return packet;
+        }
+        let props: Vec<mqtt::packet::Property> = p
+            .props
+            .iter()
+            .filter(|prop| !matches!(prop, mqtt::packet::Property::ReasonString(_)))
+            .cloned()
+            .collect();
+        if props.len() == p.props.len() {
+            return packet;
+        }
+        let mut builder = mqtt::packet::v5_0::Disconnect::builder();
+        if let Some(reason_code) = p.reason_code() {
+            builder = builder.reason_code(reason_code);
+        }
+        if !props.is_empty() {
+            builder = builder.props(mqtt::packet::Properties::from(props));
+        }
+        match builder.build() {
+            Ok(disconnect) => {
+                self.emit_event(ConnectionEvent::ReasonStringDropped { max_packet_size });
+                mqtt::packet::Packet::V5_0Disconnect(disconnect)
+            }
+            Err(_) => packet,
+        }
+    }
+
+    /// Send the DISCONNECT matching the will intent, then close
+    ///
+    /// A v5.0 DISCONNECT carries reason code 0x00 (will suppressed) or 0x04
+    /// (Disconnect with Will Message). v3.1.1 has no such reason code: any
+    /// DISCONNECT suppresses the will, so to trigger it none is sent.
+    async fn disconnect(&mut self, trigger_will: bool) -> Result<()> {
+        if self.state == ConnectionState::Connected {
+            let disconnect = match self.config.version {
+                mqtt::Version::V5_0 => {
+                    let reason_code = if trigger_will {
+                        mqtt::result_code::DisconnectReasonCode::DisconnectWithWillMessage
+                    } else {
+                        mqtt::result_code::DisconnectReasonCode::NormalDisconnection
+                    };
+                    let disconnect = mqtt::packet::v5_0::Disconnect::builder()
+                        .reason_code(reason_code)
+                        .build()
+                        .map_err(|e| Error::ProtocolError(format!("{:?}", e)))?;
+                    Some(mqtt::packet::Packet::V5_0Disconnect(disconnect))
                 }
-                mqtt::connection::Event::RequestClose => {
-                    let _ = self
-                        .websocket_commands
-                        .unbounded_send(UnderlyingLayerCommand::Close);
-                    self.state = ConnectionState::Closed;
+                _ if trigger_will => None,
+                _ => {
+                    let disconnect = mqtt::packet::v3_1_1::Disconnect::builder()
+                        .build()
+                        .map_err(|e| Error::ProtocolError(format!("{:?}", e)))?;
+                    Some(mqtt::packet::Packet::V3_1_1Disconnect(disconnect))
                 }
-                _ => {}
+            };
+            if let Some(packet) = disconnect {
+                self.send_packet(packet)?;
+            }
+        }
+        self.close().await
+    }
+
+    /// Process incoming WebSocket data
+    fn process_incoming_data(&mut self, data: Vec<u8>) {
+        self.last_recv_at = Some(crate::platform::date_now());
+
+        // Append to buffer
+        let new_data_len = data.len();
+
+        // Compact buffer if needed
+        if self.consumed_bytes > 0 {
+            let unconsumed_len = self.buffer_size - self.consumed_bytes;
+            if unconsumed_len > 0 {
+                self.read_buffer
+                    .copy_within(self.consumed_bytes..self.buffer_size, 0);
+            }
+            self.buffer_size = unconsumed_len;
+            self.consumed_bytes = 0;
+        }
+
+        // Ensure capacity and copy data
+        self.read_buffer.resize(self.buffer_size + new_data_len, 0);
+        self.read_buffer[self.buffer_size..self.buffer_size + new_data_len].copy_from_slice(&data);
+        self.buffer_size += new_data_len;
+
+        self.check_memory_pressure();
+        self.parse_read_buffer();
+    }
+
+    /// Encoded size of a packet for `max_total_buffer_bytes` accounting
+    /// (0 without a limit, so nothing is re-encoded then)
+    fn accounted_size(&self, packet: &mqtt::packet::Packet) -> usize {
+        match self.config.max_total_buffer_bytes {
+            Some(_) => packet.to_continuous_buffer().len(),
+            None => 0,
+        }
+    }
+
+    /// Bytes held by the client: read buffer capacity, undelivered packets and
+    /// outgoing QoS 1/2 PUBLISH kept for retransmission
+    fn memory_used(&self) -> usize {
+        self.read_buffer.capacity()
+            + self.undelivered_bytes
+            + self.inflight_bytes.values().sum::<usize>()
+    }
+
+    /// Emit MemoryPressure when usage first exceeds `max_total_buffer_bytes`;
+    /// returns whether it is exceeded
+    fn check_memory_pressure(&mut self) -> bool {
+        let Some(limit_bytes) = self.config.max_total_buffer_bytes else {
+            return false;
+        };
+        let used_bytes = self.memory_used();
+        let exceeded = used_bytes > limit_bytes;
+        if exceeded && !self.memory_pressure {
+            self.emit_event(ConnectionEvent::MemoryPressure {
+                used_bytes,
+                limit_bytes,
+            });
+        }
+        self.memory_pressure = exceeded;
+        exceeded
+    }
+
+    /// Whether received PUBLISH packets stay unparsed for now
+    ///
+    /// They wait while paused, and until recv() takes packets once
+    /// `max_undelivered_packets` is reached or, with undelivered packets to
+    /// take, `max_total_buffer_bytes` is exceeded. A filtered receive waiting
+    /// for a PUBLISH lets them through regardless, or it could never be served.
+    fn publish_held_back(&mut self) -> bool {
+        if self.recv_paused {
+            return true;
+        }
+        let full = self.undelivered_packets.len() >= self.config.max_undelivered_packets
+            || (!self.undelivered_packets.is_empty() && self.check_memory_pressure());
+        full && !self
+            .pending_typed_recv_requests
+            .iter()
+            .any(|(filter, reply)| !reply.is_canceled() && filter.accepts_publish())
+    }
+
+    /// Check room for an outgoing PUBLISH under `max_total_buffer_bytes`
+    ///
+    /// Returns the bytes the connection will store for retransmission (QoS 1/2),
+    /// or `Error::BufferOverflow` if the PUBLISH would exceed the limit. Other
+    /// packets (acknowledgements, PINGREQ, DISCONNECT, ...) are never refused.
+    fn reserve_send_memory(
+        &mut self,
+        packet: &mqtt::packet::Packet,
+        stored: bool,
+    ) -> Result<usize> {
+        let Some(limit_bytes) = self.config.max_total_buffer_bytes else {
+            return Ok(0);
+        };
+        if !matches!(
+            packet,
+            mqtt::packet::Packet::V3_1_1Publish(_) | mqtt::packet::Packet::V5_0Publish(_)
+        ) {
+            return Ok(0);
+        }
+        let stored_size = if stored {
+            self.accounted_size(packet)
+        } else {
+            0
+        };
+        let used_bytes = self.memory_used() + stored_size;
+        if used_bytes > limit_bytes {
+            if !self.memory_pressure {
+                self.memory_pressure = true;
+                self.emit_event(ConnectionEvent::MemoryPressure {
+                    used_bytes,
+                    limit_bytes,
+                });
             }
+            return Err(Error::BufferOverflow);
+        }
+        Ok(stored_size)
+    }
+
+    /// Release a large read buffer once everything in it has been parsed
+    fn shrink_drained_read_buffer(&mut self) {
+        if self.config.max_total_buffer_bytes.is_none()
+            || self.read_buffer.capacity() <= READ_BUFFER_CAPACITY
+        {
+            return;
+        }
+        self.read_buffer.clear();
+        self.read_buffer.shrink_to(READ_BUFFER_CAPACITY);
+        self.buffer_size = 0;
+        self.consumed_bytes = 0;
+    }
+
+    /// Discard undelivered packets, including those still held back as bytes
+    ///
+    /// Held-back bytes are parsed rather than dropped, so the state machine
+    /// still sees every packet (QoS acknowledgements are sent as usual).
+    /// PUBLISH bytes received while paused stay buffered.
+    fn clear_inbound(&mut self) -> usize {
+        let mut discarded = 0;
+        while !self.undelivered_packets.is_empty() {
+            discarded += self.undelivered_packets.len();
+            self.undelivered_packets.clear();
+            self.undelivered_bytes = 0;
+            self.parse_read_buffer();
+        }
+        discarded
+    }
+
+    /// Parse unconsumed bytes in the read buffer
+    ///
+    /// One WebSocket message may carry several MQTT packets, so this keeps
+    /// calling the parser until the buffer is drained or no progress is made.
+    fn parse_read_buffer(&mut self) {
+        loop {
+            if self.consumed_bytes >= self.buffer_size {
+                self.shrink_drained_read_buffer();
+                return;
+            }
+            // While paused or while recv() has not caught up a PUBLISH stays
+            // unparsed (and unacknowledged); packets behind it still reach the
+            // connection so CONNACK, PINGRESP and acknowledgements of outgoing
+            // PUBLISH keep being handled, but wait for recv() behind it
+            if self.publish_held_back() {
+                match self.bring_control_frame_forward() {
+                    Some(0) => {}
+                    Some(ahead) => self.forwarded_ahead = Some(ahead),
+                    None => return,
+                }
+            }
+            let held_publish = self.forwarded_ahead.is_none()
+                && self.payload_stream.is_none()
+                && self.read_buffer[self.consumed_bytes] >> 4 == 3;
+
+            if self.payload_stream.is_some() || self.start_payload_stream() {
+                self.feed_payload_stream();
+                continue;
+            }
+
+            let unconsumed_data = &self.read_buffer[self.consumed_bytes..self.buffer_size];
+            let mut cursor = mqtt::common::Cursor::new(unconsumed_data);
+
+            let mut events = self.mqtt_connection.recv(&mut cursor);
+            let progress = cursor.position() as usize;
+            let bytes = &unconsumed_data[..progress];
+            // Only the first PUBLISH parsed after a streamed one can be its
+            // rebuilt form: PUBLISH packets are never reordered
+            if self.streamed_publish_tapped && bytes.first().is_some_and(|b| b >> 4 == 3) {
+                self.streamed_publish_tapped = false;
+            } else {
+                self.tap_received(bytes, crate::packet_tap::packet_type_name(bytes));
+            }
+            let packet_start = self.consumed_bytes;
+            self.consumed_bytes += progress;
+
+            // Report where in the buffer decoding failed: the start of the
+            // packet that could not be decoded. Other errors (e.g. a packet
+            // not allowed in the current state) are handled as usual
+            for event in &events {
+                if let mqtt::connection::Event::NotifyError(
+                    e @ mqtt::result_code::MqttError::MalformedPacket,
+                ) = event
+                {
+                    self.emit_event(ConnectionEvent::Error(Error::DecodeError {
+                        offset: packet_start,
+                        detail: format!("{:?}", e),
+                    }));
+                }
+            }
+            // Already reported as DecodeError
+            events.retain(|e| {
+                !matches!(
+                    e,
+                    mqtt::connection::Event::NotifyError(
+                        mqtt::result_code::MqttError::MalformedPacket
+                    )
+                )
+            });
+
+            // Packets the connection sends while parsing answer the parsed packet
+            self.inbound_meta = ReceiveMeta {
+                auto_responded: events.iter().any(|e| {
+                    matches!(
+                        e,
+                        mqtt::connection::Event::RequestSendPacket { packet, .. }
+                            if Self::is_response_packet(packet)
+                    )
+                }),
+            };
+            let _ = self.handle_mqtt_events(events);
+            self.inbound_meta = ReceiveMeta::default();
+            self.forwarded_ahead = None;
+            if held_publish && progress > 0 && !self.forwarded_packets.is_empty() {
+                self.release_forwarded_packets();
+            }
+
+            // PUBACK, PUBCOMP, SUBACK and UNSUBACK release their packet IDs
+            self.serve_packet_id_waiters();
+
+            // Only an incomplete packet is left
+            if progress == 0 {
+                return;
+            }
+        }
+    }
+
+    /// Move the first complete packet other than PUBLISH to the parse position,
+    /// ahead of the PUBLISH packets before it; returns how many it overtook
+    ///
+    /// The skipped PUBLISH packets keep their order and are parsed later. The
+    /// scan stops at DISCONNECT and AUTH, which must not be handled before the
+    /// PUBLISH packets in front of them.
+    fn bring_control_frame_forward(&mut self) -> Option<usize> {
+        if self.payload_stream.is_some() {
+            return None;
+        }
+        let mut position = self.consumed_bytes;
+        let mut overtaken = 0;
+        while let Some(len) =
+            crate::packet_parse::frame_len(&self.read_buffer[position..self.buffer_size])
+        {
+            match self.read_buffer[position] >> 4 {
+                3 => {
+                    overtaken += 1;
+                    position += len;
+                }
+                14 | 15 => return None,
+                _ => {
+                    self.read_buffer[self.consumed_bytes..position + len].rotate_right(len);
+                    return Some(overtaken);
+                }
+            }
+        }
+        None
+    }
+
+    /// Start streaming the payload of the PUBLISH at the parse position if it
+    /// exceeds `payload_stream_threshold`; returns whether it did
+    fn start_payload_stream(&mut self) -> bool {
+        if self.config.payload_chunk_handler.is_none() {
+            return false;
+        }
+        let v5 = matches!(self.config.version, mqtt::Version::V5_0);
+        let unconsumed_data = &self.read_buffer[self.consumed_bytes..self.buffer_size];
+        let headers = match crate::payload_stream::publish_headers(unconsumed_data, v5) {
+            Some(headers) if headers.payload_len > self.config.payload_stream_threshold => headers,
+            _ => return false,
+        };
+        let header =
+            &self.read_buffer[self.consumed_bytes..self.consumed_bytes + headers.header_len];
+        self.tap_received(header, "Publish");
+        self.consumed_bytes += headers.header_len;
+        self.payload_stream = Some(PayloadStream {
+            topic: headers.topic,
+            total_len: headers.payload_len,
+            received: 0,
+            empty_packet: headers.empty_packet,
+        });
+        true
+    }
+
+    /// Hand buffered payload bytes of the streamed PUBLISH to the chunk handler
+    ///
+    /// Once the payload is through, the PUBLISH without it is put in front of
+    /// the remaining bytes to be parsed like any other packet.
+    fn feed_payload_stream(&mut self) {
+        let Some(mut stream) = self.payload_stream.take() else {
+            return;
+        };
+        let available = self.buffer_size - self.consumed_bytes;
+        let len = available.min(stream.total_len - stream.received);
+        let chunk = &self.read_buffer[self.consumed_bytes..self.consumed_bytes + len];
+        // Still consumed without a handler (cleared mid-stream)
+        if len > 0 {
+            self.tap_received(chunk, "Publish");
+            if let Some(ref handler) = self.config.payload_chunk_handler {
+                handler.chunk(&stream.topic, stream.received, chunk, stream.total_len);
+            }
+        }
+        self.consumed_bytes += len;
+        stream.received += len;
+
+        if stream.received < stream.total_len {
+            self.payload_stream = Some(stream);
+            return;
+        }
+        let packet_len = stream.empty_packet.len();
+        self.read_buffer.splice(
+            self.consumed_bytes..self.consumed_bytes,
+            stream.empty_packet,
+        );
+        self.buffer_size += packet_len;
+        self.streamed_publish_tapped = true;
+    }
+
+    /// Report received wire bytes to the packet tap, if any
+    fn tap_received(&self, bytes: &[u8], packet_type: &str) {
+        if let Some(ref tap) = self.config.packet_tap {
+            if !bytes.is_empty() {
+                tap.tap(crate::packet_tap::PacketDirection::In, bytes, packet_type);
+            }
+        }
+    }
+
+    /// Handle MQTT events (store send requests for async processing)
+    #[allow(unused_variables)]
+    fn handle_mqtt_events(&mut self, events: Vec<mqtt::connection::Event>) -> Result<()> {
+        #[cfg(target_arch = "wasm32")]
+        log_labeled!(
+            self.log_label(),
+            "🚀🚀🚀 FORCE UPDATE: Processing {} MQTT events 🚀🚀🚀",
+            events.len()
+        );
+
+        for (i, event) in events.iter().enumerate() {
+            #[cfg(target_arch = "wasm32")]
+            log_labeled!(self.log_label(), "Event {}: {:?}", i + 1, event);
+        }
+
+        for event in events {
+            match event {
+                mqtt::connection::Event::RequestSendPacket { packet, .. } => {
+                    self.track_ping(&packet, true);
+                    match &packet {
+                        mqtt::packet::Packet::V3_1_1Connect(p) => {
+                            self.session_requested = !p.clean_session();
+                            self.connect_client_id = Some(p.client_id().to_string());
+                            self.keep_alive_secs = p.keep_alive();
+                            self.last_disconnect_reason = None;
+                        }
+                        mqtt::packet::Packet::V5_0Connect(p) => {
+                            self.session_requested = !p.clean_start();
+                            self.connect_client_id = Some(p.client_id().to_string());
+                            self.keep_alive_secs = p.keep_alive();
+                            self.last_disconnect_reason = None;
+                            self.server_maximum_packet_size = None;
+                        }
+                        _ => {}
+                    }
+                    let mut buffer = packet.to_continuous_buffer();
+                    // send_encoded(): the caller's bytes replace the decoded
+                    // packet; if the connection changed it, the broker would
+                    // not see what the connection recorded, so nothing is sent
+                    if let Some((encoded, bytes)) = self.encoded_bytes.take() {
+                        if buffer == encoded {
+                            buffer = bytes;
+                        } else if buffer[0] >> 4 == encoded[0] >> 4 {
+                            self.send_rejection = Some(Error::SendFailed(
+                                "the connection changed the encoded packet".to_string(),
+                            ));
+                            continue;
+                        } else {
+                            self.encoded_bytes = Some((encoded, bytes));
+                        }
+                    }
+                    #[cfg(target_arch = "wasm32")]
+                    log_labeled!(self.log_label(), "Sending packet: {} bytes", buffer.len());
+                    self.sent_bytes = self.sent_bytes.wrapping_add(buffer.len());
+                    if let Some(ref inspector) = self.config.send_inspector {
+                        inspector.inspect(&packet, buffer.len());
+                    }
+                    if let Some(ref tap) = self.config.packet_tap {
+                        tap.tap(
+                            crate::packet_tap::PacketDirection::Out,
+                            &buffer,
+                            crate::packet_tap::packet_type_name(&buffer),
+                        );
+                    }
+                    // Writes caused by send() report back to it
+                    let reply = self.send_results.as_mut().map(|results| {
+                        let (reply, result) = oneshot::channel();
+                        results.push(result);
+                        std::sync::Arc::new(std::sync::Mutex::new(Some(reply)))
+                    });
+                    // Send via WebSocket command
+                    match self
+                        .websocket_commands
+                        .unbounded_send(UnderlyingLayerCommand::SendData(buffer, reply))
+                    {
+                        Ok(_) => {
+                            #[cfg(target_arch = "wasm32")]
+                            log_labeled!(
+                                self.log_label(),
+                                "Sent packet successfully via WebSocket command"
+                            );
+                        }
+                        Err(e) => {
+                            #[cfg(target_arch = "wasm32")]
+                            log_labeled!(
+                                self.log_label(),
+                                "Failed to send packet via WebSocket command: {:?}",
+                                e
+                            );
+                        }
+                    }
+                }
+                // A retransmitted QoS 2 PUBLISH whose PUBREL has not arrived yet is
+                // answered with PUBREC by the connection and not notified, so it
+                // never reaches recv() twice; a repeated PUBREL is answered with
+                // PUBCOMP again.
+                mqtt::connection::Event::NotifyPacketReceived(packet) => {
+                    if self.is_replayed_qos2(&packet) {
+                        continue;
+                    }
+                    self.update_qos_flow(&packet);
+                    self.update_subscriptions(&packet);
+                    self.track_ping(&packet, false);
+                    if matches!(
+                        packet,
+                        mqtt::packet::Packet::V3_1_1Pingreq(_)
+                            | mqtt::packet::Packet::V5_0Pingreq(_)
+                    ) {
+                        self.emit_event(ConnectionEvent::PingReceived);
+                    }
+                    let restore = self.subscriptions_lost(&packet);
+                    let session_lost = self.track_session_present(&packet);
+                    self.follow_server_reference(&packet);
+                    self.track_disconnect_reason(&packet);
+                    self.track_maximum_packet_size(&packet);
+                    let packet = self.decode_payload(packet);
+                    self.handle_received_packet(packet);
+                    if session_lost {
+                        self.emit_event(ConnectionEvent::SessionLost);
+                    }
+                    // After the CONNACK is queued, so it is received first
+                    if restore {
+                        self.restore_subscriptions();
+                    }
+                }
+                mqtt::connection::Event::RequestTimerReset { kind, duration_ms } => {
+                    let kind_str = format!("{:?}", kind);
+                    let duration_ms = match kind {
+                        mqtt::connection::TimerKind::PingreqSend => {
+                            self.jittered_ping_interval(duration_ms)
+                        }
+                        _ => duration_ms,
+                    };
+
+                    // Track the timer as active
+                    self.active_timers.insert(kind_str.clone());
+
+                    // Delegate timer handling to underlying layer
+                    // The underlying layer will send TimerExpired event when the timer fires
+                    let _ = self.websocket_commands.unbounded_send(
+                        UnderlyingLayerCommand::TimerReset {
+                            kind: kind_str,
+                            duration_ms,
+                        },
+                    );
+                }
+                mqtt::connection::Event::RequestTimerCancel(kind) => {
+                    let kind_str = format!("{:?}", kind);
+
+                    // Remove from tracking
+                    self.active_timers.remove(&kind_str);
+
+                    // Delegate cancellation to underlying layer
+                    let _ = self
+                        .websocket_commands
+                        .unbounded_send(UnderlyingLayerCommand::TimerCancel { kind: kind_str });
+                }
+                mqtt::connection::Event::NotifyError(error) => {
+                    eprintln!("MQTT protocol error: {:?}", error);
+                    self.emit_event(ConnectionEvent::Error(Error::ProtocolError(format!(
+                        "{:?}",
+                        error
+                    ))));
+                }
+                mqtt::connection::Event::NotifyPacketIdReleased(packet_id)
+                    if self.config.packet_id_reuse_delay_ms > 0 =>
+                {
+                    if self.qos_flows.contains_key(&packet_id) {
+                        self.released_flow_ids.insert(packet_id);
+                    }
+                    // Hold the ID so the allocator cannot hand it out yet
+                    if self.mqtt_connection.register_packet_id(packet_id).is_ok() {
+                        self.cooling_packet_ids
+                            .push_back((packet_id, crate::platform::monotonic_now()));
+                        // Later IDs end their delay after this one
+                        if self.cooling_packet_ids.len() == 1 {
+                            self.arm_packet_id_cooldown_timer();
+                        }
+                    }
+                }
+                mqtt::connection::Event::NotifyPacketIdReleased(packet_id) => {
+                    if self.qos_flows.contains_key(&packet_id) {
+                        self.released_flow_ids.insert(packet_id);
+                    }
+                    self.release_to_allocator(packet_id);
+                }
+                mqtt::connection::Event::RequestClose => {
+                    let _ = self
+                        .websocket_commands
+                        .unbounded_send(UnderlyingLayerCommand::Close);
+                    self.set_state(ConnectionState::Closed);
+                }
+                _ => {}
+            }
+        }
+
+        // Acknowledgements and released IDs may have ended the last inflight flow
+        self.serve_inflight_drained_waiters();
+
+        // Close once the packet is handled; the broker may have asked for it already
+        if self.redirect_url.is_some() && self.state != ConnectionState::Closed {
+            let _ = self
+                .websocket_commands
+                .unbounded_send(UnderlyingLayerCommand::Close);
+            self.set_state(ConnectionState::Closed);
+        }
+        Ok(())
+    }
+
+    /// Reset internal state for reconnection
+    /// Called when attempting to connect from Closed state
+    fn reset_for_reconnection(&mut self) {
+        #[cfg(target_arch = "wasm32")]
+        log_labeled!(
+            self.log_label(),
+            "Resetting internal state for reconnection"
+        );
+
+        // Reset MQTT connection (create new connection with same version)
+        self.mqtt_connection = mqtt::Connection::<mqtt::role::Client>::new(self.config.version);
+
+        // Reconfigure MQTT connection
+        if let Some(interval) = self.config.pingreq_send_interval_ms {
+            self.mqtt_connection
+                .set_pingreq_send_interval(Some(interval));
+        }
+        self.mqtt_connection
+            .set_auto_pub_response(self.config.auto_pub_response);
+        self.mqtt_connection
+            .set_auto_ping_response(self.config.auto_ping_response);
+        self.mqtt_connection
+            .set_auto_map_topic_alias_send(self.config.auto_map_topic_alias_send);
+        self.mqtt_connection
+            .set_auto_replace_topic_alias_send(self.config.auto_replace_topic_alias_send);
+        self.mqtt_connection
+            .set_pingresp_recv_timeout(self.config.pingresp_recv_timeout_ms);
+
+        // Clear buffers
+        self.read_buffer.clear();
+        self.buffer_size = 0;
+        self.consumed_bytes = 0;
+        self.payload_stream = None;
+        self.streamed_publish_tapped = false;
+
+        // Clear pending recv requests (they should have been cleaned up, but just in case)
+        self.pending_recv_requests.clear();
+        self.pending_typed_recv_requests.clear();
+        // recv_auth() calls learn the connection is gone and may ask again
+        for reply in self.pending_auth_requests.drain(..) {
+            let _ = reply.send(Err(Error::ConnectionClosed));
+        }
+        self.undelivered_packets.clear();
+        self.forwarded_packets.clear();
+        self.undelivered_auth.clear();
+        self.undelivered_bytes = 0;
+
+        // Clear timers (should already be cleared on close, but ensure it)
+        self.active_timers.clear();
+
+        // Packet IDs belong to the old connection
+        self.qos_flows.clear();
+        self.released_flow_ids.clear();
+        self.inflight_bytes.clear();
+        self.cooling_packet_ids.clear();
+        if let Some(allocator) = self.config.packet_id_allocator.clone() {
+            for packet_id in self.allocator_packet_ids.drain() {
+                allocator.release(packet_id);
+            }
+        }
+
+        // Unacknowledged SUBSCRIBE/UNSUBSCRIBE belong to the old connection;
+        // confirmed subscriptions are kept (a persistent session retains them)
+        self.requested_subscriptions.clear();
+        self.requested_unsubscriptions.clear();
+
+        #[cfg(target_arch = "wasm32")]
+        log_labeled!(
+            self.log_label(),
+            "Internal state reset complete for reconnection"
+        );
+    }
+}
+
+/// Lower the Message Expiry Interval of a v5.0 PUBLISH by the whole seconds
+/// it was queued, or `None` if it expired meanwhile
+///
+/// Other packets, and a PUBLISH without the property, are returned as is.
+fn age_queued_publish(
+    packet: mqtt::packet::Packet,
+    queued_ms: f64,
+) -> Option<mqtt::packet::Packet> {
+    let mqtt::packet::Packet::V5_0Publish(ref publish) = packet else {
+        return Some(packet);
+    };
+    let Some((index, interval)) =
+        publish
+            .props
+            .iter()
+            .enumerate()
+            .find_map(|(index, prop)| match prop {
+                mqtt::packet::Property::MessageExpiryInterval(p) => Some((index, p.val())),
+                _ => None,
+            })
+    else {
+        return Some(packet);
+    };
+    let queued_secs = (queued_ms.max(0.0) / 1000.0) as u32;
+    if queued_secs == 0 {
+        return Some(packet);
+    }
+    if queued_secs >= interval {
+        return None;
+    }
+
+    let Ok(remaining) = mqtt::packet::MessageExpiryInterval::new(interval - queued_secs) else {
+        return Some(packet);
+    };
+    let mut props = publish.props.clone();
+    props[index] = mqtt::packet::Property::MessageExpiryInterval(remaining);
+    let Ok(mut builder) = mqtt::packet::v5_0::Publish::builder().topic_name(publish.topic_name())
+    else {
+        return Some(packet);
+    };
+    builder = builder
+        .qos(publish.qos())
+        .retain(publish.retain())
+        .dup(publish.dup())
+        .payload(publish.payload().as_slice())
+        .props(props);
+    if let Some(packet_id) = publish.packet_id() {
+        builder = builder.packet_id(packet_id);
+    }
+    match builder.build() {
+        Ok(aged) => Some(mqtt::packet::Packet::V5_0Publish(aged)),
+        Err(_) => Some(packet),
+    }
+}
+
+/// Protocol version as named in a `SessionSnapshot`
+fn version_name(version: mqtt::Version) -> &'static str {
+    match version {
+        mqtt::Version::V5_0 => "5.0",
+        _ => "3.1.1",
+    }
+}
+
+/// The Server Reference property of a v5.0 CONNACK or DISCONNECT
+fn server_reference<'a>(
+    mut props: impl Iterator<Item = &'a mqtt::packet::Property>,
+) -> Option<String> {
+    props.find_map(|prop| match prop {
+        mqtt::packet::Property::ServerReference(p) => Some(p.val().to_string()),
+        _ => None,
+    })
+}
+
+/// URL to connect to for a Server Reference
+///
+/// The first of several space-separated references is used. A full URL is
+/// taken as is unless it would drop TLS (e.g. `wss://` to `ws://`), which is
+/// refused; a bare `host[:port]` replaces the host of `current_url`, keeping
+/// its scheme and path.
+fn redirect_target(current_url: Option<&str>, reference: &str) -> Option<String> {
+    let server = reference.split_whitespace().next()?;
+    if let Some((target_scheme, _)) = server.split_once("://") {
+        let current_scheme = current_url
+            .and_then(|url| url.split_once("://"))
+            .map(|(s, _)| s);
+        if current_scheme.is_some_and(is_secure_scheme) && !is_secure_scheme(target_scheme) {
+            return None;
+        }
+        return Some(server.to_string());
+    }
+    let (scheme, rest) = current_url?.split_once("://")?;
+    let path = rest.find('/').map_or("", |i| &rest[i..]);
+    Some(format!("{}://{}{}", scheme, server, path))
+}
+
+/// Whether a URL scheme runs over TLS
+fn is_secure_scheme(scheme: &str) -> bool {
+    scheme.eq_ignore_ascii_case("wss") || scheme.eq_ignore_ascii_case("https")
+}
+
+impl MqttClient {
+    /// Create new MQTT client with configuration
+    #[cfg(target_arch = "wasm32")]
+    pub fn new(config: MqttConfig) -> Self {
+        let websocket = BrowserWebSocket::new().with_instance_label(config.instance_label.clone());
+        Self::new_with_websocket(config, websocket)
+    }
+
+    /// Create new MQTT client on a WebSocket the app already opened
+    ///
+    /// The client takes over the socket's handlers and is connected without
+    /// connect(); send CONNECT next. Not reconnected automatically.
+    #[cfg(target_arch = "wasm32")]
+    pub fn new_with_existing_websocket(config: MqttConfig, ws: web_sys::WebSocket) -> Self {
+        let websocket = BrowserWebSocket::new()
+            .with_instance_label(config.instance_label.clone())
+            .with_existing(ws);
+        Self::new_with_websocket(config, websocket)
+    }
+
+    /// Create new MQTT client with custom WebSocket (for testing)
+    #[cfg(target_arch = "wasm32")]
+    pub fn new_with_websocket<W: UnderlyingLayerInterface + 'static>(
+        config: MqttConfig,
+        websocket: W,
+    ) -> Self {
+        let (request_sender, request_receiver) = mpsc::unbounded();
+        let connected = Arc::new(AtomicBool::new(false));
+        let label = config.instance_label.clone();
+
+        // Start background processor
+        let (mut processor, mut websocket) =
+            MqttProcessor::new(config, websocket, request_sender.clone(), connected.clone());
+
+        use wasm_bindgen_futures::spawn_local;
+
+        #[cfg(target_arch = "wasm32")]
+        log_labeled!(label.as_deref(), "Starting WebSocket processor task");
+
+        // Start WebSocket processor
+        spawn_local(async move {
+            websocket.run().await;
+        });
+
+        #[cfg(target_arch = "wasm32")]
+        log_labeled!(label.as_deref(), "Starting MQTT processor task");
+
+        // Start MQTT processor
+        spawn_local(async move {
+            processor.run(request_receiver).await;
+        });
+
+        #[cfg(target_arch = "wasm32")]
+        log_labeled!(
+            label.as_deref(),
+            "★★★ CLIENT_CLEAN: Both processors started, returning client ★★★"
+        );
+
+        Self {
+            request_sender,
+            connected,
+            fatal_error: wasm_fatal_error(),
+        }
+    }
+
+    /// Create new MQTT client with custom WebSocket (for testing, non-WASM)
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new_with_websocket<W: UnderlyingLayerInterface + Send + 'static>(
+        config: MqttConfig,
+        websocket: W,
+    ) -> Self {
+        let (request_sender, request_receiver) = mpsc::unbounded();
+        let connected = Arc::new(AtomicBool::new(false));
+
+        // Start background processor
+        let (mut processor, mut websocket) =
+            MqttProcessor::new(config, websocket, request_sender.clone(), connected.clone());
+
+        // Start WebSocket processor with tokio runtime for timer support
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            rt.block_on(websocket.run());
+        });
+
+        // Start MQTT processor with tokio runtime; a panic is kept for last_fatal_error()
+        let fatal_error = FatalError::default();
+        let cell = fatal_error.clone();
+        let processor_connected = connected.clone();
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+                rt.block_on(processor.run(request_receiver))
+            }));
+            if let Err(payload) = result {
+                // The processor can no longer clear it on close
+                processor_connected.store(false, Ordering::Release);
+                if let Ok(mut error) = cell.lock() {
+                    *error = Some(panic_message(payload.as_ref()));
+                }
+            }
+        });
+
+        Self {
+            request_sender,
+            connected,
+            fatal_error,
+        }
+    }
+
+    /// Replace the transport, e.g. for failover or to switch from ws:// to wss://
+    ///
+    /// The current transport is closed (without DISCONNECT) and the client
+    /// becomes Closed; call `connect()` to connect through the new transport.
+    /// Subscriptions, statistics and undelivered events are kept.
+    #[cfg(target_arch = "wasm32")]
+    pub async fn replace_transport<W: UnderlyingLayerInterface + 'static>(
+        &self,
+        mut transport: W,
+    ) -> Result<()> {
+        let events = transport.event_receiver();
+        let commands = transport.command_sender();
+        wasm_bindgen_futures::spawn_local(async move {
+            transport.run().await;
+        });
+        self.send_replace_transport(events, commands).await
+    }
+
+    /// Replace the transport, e.g. for failover or to switch from ws:// to wss://
+    ///
+    /// The current transport is closed (without DISCONNECT) and the client
+    /// becomes Closed; call `connect()` to connect through the new transport.
+    /// Subscriptions, statistics and undelivered events are kept.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn replace_transport<W: UnderlyingLayerInterface + Send + 'static>(
+        &self,
+        mut transport: W,
+    ) -> Result<()> {
+        let events = transport.event_receiver();
+        let commands = transport.command_sender();
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            rt.block_on(transport.run());
+        });
+        self.send_replace_transport(events, commands).await
+    }
+
+    async fn send_replace_transport(
+        &self,
+        events: mpsc::UnboundedReceiver<UnderlyingLayerEvent>,
+        commands: mpsc::UnboundedSender<UnderlyingLayerCommand>,
+    ) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::ReplaceTransport {
+            events,
+            commands,
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Connect to MQTT broker
+    pub async fn connect(&self, url: &str) -> Result<()> {
+        self.connect_with(url, None).await
+    }
+
+    /// Connect to MQTT broker, giving up after `timeout_ms`
+    ///
+    /// Fails with `Error::Timeout` and closes the transport if it is not
+    /// connected in time. The timeout applies to this call only; the config
+    /// is not changed.
+    pub async fn connect_timeout(&self, url: &str, timeout_ms: u64) -> Result<()> {
+        self.connect_with(url, Some(timeout_ms)).await
+    }
+
+    /// Connect to `url` unless already connected or connecting to it
+    ///
+    /// Safe to call repeatedly, e.g. on every UI re-render: resolves at once
+    /// when connected to `url`, shares the outcome of an attempt to `url`
+    /// already in progress, and connects otherwise. Fails while connected or
+    /// connecting to a different URL.
+    pub async fn ensure_connected(&self, url: &str) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::EnsureConnected {
+            url: url.to_string(),
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    async fn connect_with(&self, url: &str, timeout_ms: Option<u64>) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::Connect {
+            url: url.to_string(),
+            timeout_ms,
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Send MQTT packet
+    ///
+    /// Resolves once the transport has accepted the bytes; fails with
+    /// `Error::SendFailed` if it refused them (e.g. the WebSocket is closing).
+    pub async fn send(&self, packet: mqtt::packet::Packet) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::Send {
+            packet,
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        let results = reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))??;
+        Self::await_send_results(results).await
+    }
+
+    /// Send MQTT packet and return the number of bytes written to the transport
+    ///
+    /// The count covers every packet written as a result of this send, e.g. a
+    /// PUBLISH plus any packet the protocol state machine generated with it.
+    pub async fn send_sized(&self, packet: mqtt::packet::Packet) -> Result<usize> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::SendSized {
+            packet,
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        let (sent_bytes, results) = reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))??;
+        Self::await_send_results(results).await?;
+        Ok(sent_bytes)
+    }
+
+    /// Send one packet from its encoded bytes, e.g. a captured frame in a replay tool
+    ///
+    /// `bytes` must hold exactly one complete packet of the configured
+    /// protocol version; a partial packet, trailing bytes or a malformed
+    /// packet fail with `Error::DecodeError` without sending anything. The
+    /// bytes are sent exactly as given (no topic rewrite, payload codec or
+    /// re-encoding), but the decoded packet goes through the protocol state
+    /// machine like `send()`, so e.g. a QoS 1 PUBLISH needs its packet ID
+    /// acquired or registered first. A PUBLISH fails with `Error::SendFailed`
+    /// while `auto_map_topic_alias_send` or `auto_replace_topic_alias_send` is
+    /// on, since the connection would record an alias the bytes do not carry.
+    /// Resolves once the transport has accepted the bytes.
+    pub async fn send_encoded(&self, bytes: Vec<u8>) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::SendEncoded {
+            bytes,
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        let results = reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))??;
+        Self::await_send_results(results).await
+    }
+
+    /// Wait until the transport has handled every write of a send
+    ///
+    /// The first refused write fails the send with `Error::SendFailed`.
+    async fn await_send_results(results: SendResults) -> Result<()> {
+        for result in results {
+            result
+                .await
+                .map_err(|_| Error::SendFailed("Transport dropped the data".to_string()))??;
+        }
+        Ok(())
+    }
+
+    /// Send a SUBSCRIBE packet and wait for its SUBACK
+    ///
+    /// The SUBACK is returned here instead of being delivered to `recv()`.
+    /// The packet ID must already be acquired; it is released by the
+    /// connection when the SUBACK arrives. With `max_subscriptions` set, a
+    /// SUBSCRIBE that would exceed it fails with `Error::SubscriptionLimitReached`
+    /// without being sent.
+    pub async fn subscribe(&self, packet: mqtt::packet::Packet) -> Result<mqtt::packet::Packet> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::Subscribe {
+            packet,
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Receive MQTT packet
+    ///
+    /// Inbound packets are delivered in the order they arrived on the wire.
+    /// Concurrent recv() calls are served oldest first, and neither app sends
+    /// nor automatic responses (PUBACK, PINGRESP, ...) reorder delivered packets.
+    pub async fn recv(&self) -> Result<mqtt::packet::Packet> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::Recv {
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Receive the next packet like `recv()`, along with how the client processed it
+    ///
+    /// `ReceiveMeta::auto_responded` tells whether the client sent the answer
+    /// itself, e.g. the PUBACK for a QoS 1 PUBLISH under `auto_pub_response`.
+    pub async fn recv_with_meta(&self) -> Result<(mqtt::packet::Packet, ReceiveMeta)> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::RecvWithMeta {
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Receive the first inbound packet whose type is one of `types`
+    ///
+    /// Packets of other types are left queued, in wire order, for `recv()`;
+    /// e.g. wait for a SUBACK while still noticing a DISCONNECT from the broker.
+    /// While `max_undelivered_packets` is reached, PUBLISH packets are still
+    /// parsed for a request that includes `PacketType::Publish`, so the queue
+    /// grows past the limit until one is taken.
+    pub async fn recv_one_of(
+        &self,
+        types: &[mqtt::packet::PacketType],
+    ) -> Result<mqtt::packet::Packet> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::RecvOneOf {
+            types: types.to_vec(),
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Receive the first inbound v5.0 PUBLISH carrying `subscription_id` as a
+    /// Subscription Identifier
+    ///
+    /// Other packets stay queued, in wire order, for `recv()` and other
+    /// filtered receives, so a receive loop can be dedicated to one
+    /// subscription. While `max_undelivered_packets` is reached, PUBLISH
+    /// packets are still parsed while this waits, so the queue grows past the
+    /// limit until the matching one arrives.
+    pub async fn recv_for_subscription(
+        &self,
+        subscription_id: u32,
+    ) -> Result<mqtt::packet::Packet> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::RecvForSubscription {
+            subscription_id,
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Close the connection
+    pub async fn close(&self) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::Close {
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Send `disconnect` (a DISCONNECT of the configured version, e.g. with a
+    /// v5.0 reason code), then close
+    ///
+    /// Waits up to `shutdown_timeout_ms` for the broker to close the connection,
+    /// which shows the DISCONNECT was delivered, and closes the transport itself
+    /// if it does not. Without a connection it just closes.
+    pub async fn close_with_disconnect(&self, disconnect: mqtt::packet::Packet) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::CloseWithDisconnect {
+            packet: disconnect,
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Disconnect cleanly so the broker discards the will message, then close
+    ///
+    /// Sends DISCONNECT (v5.0 reason code 0x00 Normal disconnection).
+    pub async fn disconnect_suppress_will(&self) -> Result<()> {
+        self.disconnect(false).await
+    }
+
+    /// Disconnect so the broker publishes the will message, then close
+    ///
+    /// v5.0 sends DISCONNECT with reason code 0x04 (Disconnect with Will
+    /// Message). v3.1.1 cannot request the will in a DISCONNECT, so the
+    /// transport is closed without one.
+    pub async fn disconnect_trigger_will(&self) -> Result<()> {
+        self.disconnect(true).await
+    }
+
+    async fn disconnect(&self, trigger_will: bool) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::Disconnect {
+            trigger_will,
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Get current connection state
+    pub async fn state(&self) -> ConnectionState {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::State {
+            reply: reply_sender,
+        };
+
+        if self.request_sender.unbounded_send(request).is_err() {
+            return ConnectionState::Closed;
+        }
+
+        reply_receiver.await.unwrap_or(ConnectionState::Closed)
+    }
+
+    /// Check if connected
+    pub async fn is_connected(&self) -> bool {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::IsConnected {
+            reply: reply_sender,
+        };
+
+        if self.request_sender.unbounded_send(request).is_err() {
+            return false;
+        }
+
+        reply_receiver.await.unwrap_or(false)
+    }
+
+    /// Check if connected without a round-trip to the processor
+    ///
+    /// Reads a flag the processor updates on every state change, so it can be
+    /// called from synchronous code. It may briefly lag behind a transition that
+    /// is being processed; use `is_connected()` when exactness matters.
+    pub fn is_connected_sync(&self) -> bool {
+        self.connected.load(Ordering::Acquire)
+    }
+
+    /// Panic message of the background processor, if it died
+    ///
+    /// After such a panic every call fails with "Client channel closed" or
+    /// "Request cancelled"; this tells why, and `is_connected_sync()` turns
+    /// false. On wasm32 a panic aborts the whole module, so this records the
+    /// first panic anywhere in the module (not only in a client's task), and
+    /// every client reports it.
+    pub fn last_fatal_error(&self) -> Option<String> {
+        self.fatal_error.lock().ok().and_then(|error| error.clone())
+    }
+
+    /// Acquire a packet ID for use with QoS 1 or 2 messages
+    pub async fn acquire_packet_id(&self) -> Option<u16> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::AcquirePacketId {
+            reply: reply_sender,
+        };
+
+        if self.request_sender.unbounded_send(request).is_err() {
+            return None;
+        }
+
+        reply_receiver.await.unwrap_or(None)
+    }
+
+    /// Acquire a packet ID, waiting until one is released if all are in use
+    ///
+    /// Unlike `acquire_packet_id()`, this does not return `None` when IDs are
+    /// exhausted; it resolves as soon as an ID is freed by `release_packet_id()`,
+    /// `complete_qos()` or an acknowledgement from the broker. Waiters are
+    /// served in request order.
+    pub async fn acquire_packet_id_wait(&self) -> Result<u16> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::AcquirePacketIdWait {
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Send a PINGRESP, e.g. to answer a PINGREQ by hand when `auto_ping_response` is off
+    ///
+    /// Incoming PINGREQ packets are reported as `ConnectionEvent::PingReceived`.
+    pub async fn send_pingresp(&self) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::SendPingresp {
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Register a packet ID as in use
+    pub async fn register_packet_id(&self, packet_id: u16) -> bool {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::RegisterPacketId {
+            packet_id,
+            reply: reply_sender,
+        };
+
+        if self.request_sender.unbounded_send(request).is_err() {
+            return false;
+        }
+
+        reply_receiver.await.unwrap_or(false)
+    }
+
+    /// Register several packet IDs as in use, e.g. the inflight IDs of a
+    /// restored session
+    ///
+    /// Handled as one request, so no other request can take one of the IDs in
+    /// between. Returns per ID whether it was registered (false if it was
+    /// already in use or listed twice); all false if the client is gone.
+    pub async fn register_packet_ids(&self, packet_ids: &[u16]) -> Vec<bool> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::RegisterPacketIds {
+            packet_ids: packet_ids.to_vec(),
+            reply: reply_sender,
+        };
+
+        if self.request_sender.unbounded_send(request).is_err() {
+            return vec![false; packet_ids.len()];
+        }
+
+        reply_receiver
+            .await
+            .unwrap_or_else(|_| vec![false; packet_ids.len()])
+    }
+
+    /// Release a packet ID
+    pub async fn release_packet_id(&self, packet_id: u16) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::ReleasePacketId {
+            packet_id,
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Release a packet ID after its QoS 1/2 flow has completed
+    ///
+    /// Fails with `PacketIdNotInUse` if no outgoing QoS flow uses the ID and with
+    /// `QosFlowIncomplete` if the final acknowledgement has not arrived yet
+    /// (e.g. PUBREC received but PUBCOMP not).
+    pub async fn complete_qos(&self, packet_id: u16) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::CompleteQos {
+            packet_id,
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Get the number of bytes queued by the transport but not yet sent
+    ///
+    /// Backed by `WebSocket.bufferedAmount` in the browser. A value that keeps
+    /// growing means the network cannot keep up with outgoing packets.
+    ///
+    /// The only send queue inside the client holds PUBLISH packets waiting for
+    /// `max_publish_rate_per_sec`, and they are not counted here. Every other
+    /// packet, including PINGREQ, DISCONNECT and QoS acknowledgements, is
+    /// handed to the transport as soon as it is produced, so control packets
+    /// never wait behind queued PUBLISH packets inside the client.
+    pub async fn buffered_amount(&self) -> Result<u64> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::BufferedAmount {
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Get the transport's WebSocket readyState
+    ///
+    /// 0 = CONNECTING, 1 = OPEN, 2 = CLOSING, 3 = CLOSED (also before any
+    /// WebSocket exists). Unlike `state()`, this shows the WebSocket itself, e.g.
+    /// OPEN while the MQTT CONNACK is still outstanding.
+    pub async fn transport_ready_state(&self) -> Result<u8> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::TransportReadyState {
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Receive the next AUTH packet from the broker (MQTT v5.0 enhanced authentication)
+    ///
+    /// After the first call, AUTH packets are delivered here instead of `recv()`.
+    /// This includes continue-authentication AUTH sent in response to CONNECT;
+    /// the connection is established only once CONNACK arrives via `recv()`.
+    pub async fn recv_auth(&self) -> Result<mqtt::packet::Packet> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::RecvAuth {
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Stop routing AUTH packets to `recv_auth()`
+    ///
+    /// Waiting `recv_auth()` calls fail, and AUTH packets (including any not
+    /// yet picked up) are delivered via `recv()` again.
+    pub async fn stop_recv_auth(&self) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::StopRecvAuth {
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
+
+    /// Get round-trip statistics from recent PINGREQ/PINGRESP exchanges
+    ///
+    /// Samples are kept across reconnections so a UI can keep showing the
+    /// latest link quality.
+    pub async fn rtt_stats(&self) -> RttStats {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::RttStats {
+            reply: reply_sender,
+        };
+
+        if self.request_sender.unbounded_send(request).is_err() {
+            return RttStats::default();
         }
-        Ok(())
+
+        reply_receiver.await.unwrap_or_default()
     }
 
-    /// Reset internal state for reconnection
-    /// Called when attempting to connect from Closed state
-    fn reset_for_reconnection(&mut self) {
-        #[cfg(target_arch = "wasm32")]
-        log!("Resetting internal state for reconnection");
+    /// Get a connection health snapshot for dashboards
+    ///
+    /// Combines the state, the last ping round-trip time, the transport's
+    /// buffered amount, the inflight QoS 1/2 count and the time since data was
+    /// last received. `HealthReport::is_healthy()` gives a single verdict;
+    /// `buffered_amount_growing` compares with the previous call, so call this
+    /// periodically. Without a transport the buffered amount counts as 0.
+    pub async fn health(&self) -> Result<HealthReport> {
+        let buffered_amount = self.buffered_amount().await.unwrap_or(0);
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::Health {
+            buffered_amount,
+            reply: reply_sender,
+        };
 
-        // Reset MQTT connection (create new connection with same version)
-        self.mqtt_connection = mqtt::Connection::<mqtt::role::Client>::new(self.config.version);
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
 
-        // Reconfigure MQTT connection
-        if let Some(interval) = self.config.pingreq_send_interval_ms {
-            self.mqtt_connection
-                .set_pingreq_send_interval(Some(interval));
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))
+    }
+
+    /// Discard received packets that have not been delivered to `recv()`
+    ///
+    /// Returns the number of packets discarded. Useful to skip a backlog and
+    /// continue with live data, e.g. after a reconnect or a long pause.
+    pub async fn clear_inbound(&self) -> usize {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::ClearInbound {
+            reply: reply_sender,
+        };
+
+        if self.request_sender.unbounded_send(request).is_err() {
+            return 0;
         }
-        self.mqtt_connection
-            .set_auto_pub_response(self.config.auto_pub_response);
-        self.mqtt_connection
-            .set_auto_ping_response(self.config.auto_ping_response);
-        self.mqtt_connection
-            .set_auto_map_topic_alias_send(self.config.auto_map_topic_alias_send);
-        self.mqtt_connection
-            .set_auto_replace_topic_alias_send(self.config.auto_replace_topic_alias_send);
-        self.mqtt_connection
-            .set_pingresp_recv_timeout(self.config.pingresp_recv_timeout_ms);
 
-        // Clear buffers
-        self.read_buffer.clear();
-        self.buffer_size = 0;
-        self.consumed_bytes = 0;
+        reply_receiver.await.unwrap_or(0)
+    }
 
-        // Clear pending recv requests (they should have been cleaned up, but just in case)
-        self.pending_recv_requests.clear();
-        self.undelivered_packet = None;
+    /// Set the client identifier (`MqttConfig::client_id`) before connecting
+    ///
+    /// Fails while connecting or connected.
+    pub async fn set_client_id(&self, client_id: impl Into<String>) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::SetClientId {
+            client_id: client_id.into(),
+            reply: reply_sender,
+        };
 
-        // Clear timers (should already be cleared on close, but ensure it)
-        self.active_timers.clear();
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
 
-        #[cfg(target_arch = "wasm32")]
-        log!("Internal state reset complete for reconnection");
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
     }
-}
 
-impl MqttClient {
-    /// Create new MQTT client with configuration
-    #[cfg(target_arch = "wasm32")]
-    pub fn new(config: MqttConfig) -> Self {
-        Self::new_with_websocket(config, BrowserWebSocket::new())
+    /// Wait until the transport is connected
+    ///
+    /// Resolves immediately if already connected, otherwise on the next
+    /// successful connection (including automatic reconnects). Fails with
+    /// `Error::ConnectionClosed` if the connection closes first. Any number of
+    /// callers may wait at once.
+    pub async fn wait_connected(&self) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::WaitConnected {
+            reply: reply_sender,
+        };
+
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
+
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
     }
 
-    /// Create new MQTT client with custom WebSocket (for testing)
-    #[cfg(target_arch = "wasm32")]
-    pub fn new_with_websocket<W: UnderlyingLayerInterface + 'static>(
-        config: MqttConfig,
-        websocket: W,
-    ) -> Self {
-        let (request_sender, request_receiver) = mpsc::unbounded();
+    /// Wait until every outgoing QoS 1/2 PUBLISH got its final acknowledgement
+    /// (PUBACK, or PUBCOMP), e.g. to flush before `disconnect()`
+    ///
+    /// Resolves immediately if nothing is inflight. Fails with `Error::Timeout`
+    /// after `timeout_ms`, with `Error::NotConnected` if publishes are inflight
+    /// while not connected, and with `Error::ConnectionClosed` if the
+    /// connection closes first. Packet IDs still have to be released with
+    /// `complete_qos()`.
+    pub async fn wait_inflight_drained(&self, timeout_ms: u64) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::WaitInflightDrained {
+            timeout_ms,
+            reply: reply_sender,
+        };
 
-        // Start background processor
-        let (mut processor, mut websocket) =
-            MqttProcessor::new(config, websocket, request_sender.clone());
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
 
-        use wasm_bindgen_futures::spawn_local;
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+    }
 
-        #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(&"Starting WebSocket processor task".into());
+    /// Wait until the transport is closed
+    ///
+    /// Resolves immediately unless connecting or connected, otherwise when the
+    /// connection closes (for any reason).
+    pub async fn wait_closed(&self) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::WaitClosed {
+            reply: reply_sender,
+        };
 
-        // Start WebSocket processor
-        spawn_local(async move {
-            websocket.run().await;
-        });
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
 
-        #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(&"Starting MQTT processor task".into());
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))
+    }
 
-        // Start MQTT processor
-        spawn_local(async move {
-            processor.run(request_receiver).await;
-        });
+    /// Get the subscriptions confirmed by the broker as (topic filter, requested QoS)
+    ///
+    /// Updated when SUBACK/UNSUBACK arrive; filters the broker refused are not
+    /// listed. The list is kept across reconnections.
+    pub async fn subscriptions(&self) -> Vec<(String, u8)> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::Subscriptions {
+            reply: reply_sender,
+        };
 
-        #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(
-            &"★★★ CLIENT_CLEAN: Both processors started, returning client ★★★".into(),
-        );
+        if self.request_sender.unbounded_send(request).is_err() {
+            return Vec::new();
+        }
 
-        Self { request_sender }
+        reply_receiver.await.unwrap_or_default()
     }
 
-    /// Create new MQTT client with custom WebSocket (for testing, non-WASM)
-    #[cfg(not(target_arch = "wasm32"))]
-    pub fn new_with_websocket<W: UnderlyingLayerInterface + Send + 'static>(
-        config: MqttConfig,
-        websocket: W,
-    ) -> Self {
-        let (request_sender, request_receiver) = mpsc::unbounded();
+    /// Get the number of subscriptions confirmed by the broker
+    ///
+    /// Compare it with `MqttConfig::max_subscriptions` or the broker's own cap.
+    pub async fn subscription_count(&self) -> usize {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::SubscriptionCount {
+            reply: reply_sender,
+        };
 
-        // Start background processor
-        let (mut processor, mut websocket) =
-            MqttProcessor::new(config, websocket, request_sender.clone());
+        if self.request_sender.unbounded_send(request).is_err() {
+            return 0;
+        }
 
-        // Start WebSocket processor with tokio runtime for timer support
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap();
-            rt.block_on(websocket.run());
-        });
+        reply_receiver.await.unwrap_or_default()
+    }
 
-        // Start MQTT processor with tokio runtime
-        std::thread::spawn(move || {
-            let rt = tokio::runtime::Builder::new_current_thread()
-                .enable_all()
-                .build()
-                .unwrap();
-            rt.block_on(processor.run(request_receiver));
-        });
+    /// Snapshot the session state to persist it, e.g. across page reloads
+    ///
+    /// Covers the client identifier, negotiated parameters, unreleased QoS 1/2
+    /// flows by packet ID and the confirmed subscriptions. Restore it with
+    /// `import_session()` and connect with clean session/start false.
+    pub async fn export_session(&self) -> SessionSnapshot {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::ExportSession {
+            reply: reply_sender,
+        };
 
-        Self { request_sender }
+        if self.request_sender.unbounded_send(request).is_err() {
+            return SessionSnapshot::default();
+        }
+
+        reply_receiver.await.unwrap_or_default()
     }
 
-    /// Connect to MQTT broker
-    pub async fn connect(&self, url: &str) -> Result<()> {
+    /// Restore a session from `export_session()` before connecting
+    ///
+    /// Fails while connected or connecting, or if the snapshot is for another
+    /// protocol version. The client identifier replaces `MqttConfig::client_id`,
+    /// and the packet IDs of unreleased flows are held on the next connection.
+    /// Their packets are not part of the snapshot: re-send each PUBLISH with
+    /// DUP set (or its PUBREL, for a flow awaiting PUBCOMP) once connected.
+    pub async fn import_session(&self, snapshot: SessionSnapshot) -> Result<()> {
         let (reply_sender, reply_receiver) = oneshot::channel();
-        let request = Request::Connect {
-            url: url.to_string(),
+        let request = Request::ImportSession {
+            snapshot,
             reply: reply_sender,
         };
 
@@ -696,11 +4678,76 @@ impl MqttClient {
             .map_err(|_| Error::Other("Request cancelled".to_string()))?
     }
 
-    /// Send MQTT packet
-    pub async fn send(&self, packet: mqtt::packet::Packet) -> Result<()> {
+    /// Get the session-present flag of the most recent accepted CONNACK
+    ///
+    /// None until a CONNACK has accepted a connection. See also
+    /// `ConnectionEvent::SessionLost`.
+    pub async fn session_present(&self) -> Option<bool> {
         let (reply_sender, reply_receiver) = oneshot::channel();
-        let request = Request::Send {
-            packet,
+        let request = Request::SessionPresent {
+            reply: reply_sender,
+        };
+
+        if self.request_sender.unbounded_send(request).is_err() {
+            return None;
+        }
+
+        reply_receiver.await.unwrap_or_default()
+    }
+
+    /// Get the reason code of the v5.0 DISCONNECT that ended the latest connection
+    ///
+    /// None if no DISCONNECT was received since the last CONNECT was sent
+    /// (including v3.1.1, where the broker never sends one). See also
+    /// `ConnectionEvent::KeepAliveTimeout`.
+    pub async fn last_disconnect_reason(&self) -> Option<u8> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::LastDisconnectReason {
+            reply: reply_sender,
+        };
+
+        if self.request_sender.unbounded_send(request).is_err() {
+            return None;
+        }
+
+        reply_receiver.await.unwrap_or_default()
+    }
+
+    /// Whether the broker ended the latest connection with Keep Alive timeout (0x8D)
+    ///
+    /// If so, PINGREQ did not go out often enough for the keep alive sent in
+    /// CONNECT: lower `pingreq_send_interval_ms` or raise the keep alive.
+    pub async fn was_keepalive_timeout(&self) -> bool {
+        self.last_disconnect_reason().await == Some(0x8D)
+    }
+
+    /// Pause inbound PUBLISH processing
+    ///
+    /// Received PUBLISH packets are buffered without being parsed (so they are
+    /// not acknowledged) and not delivered to `recv()` until `resume_recv()` is
+    /// called. Other packets are still handled by the connection: PINGRESP keeps
+    /// the keep alive intact and acknowledgements of outgoing PUBLISH complete
+    /// their flows. They reach `recv()` in wire order, behind the held PUBLISH
+    /// packets; DISCONNECT and AUTH wait unhandled behind them.
+    pub async fn pause_recv(&self) -> Result<()> {
+        self.set_recv_paused(true).await
+    }
+
+    /// Resume inbound packet processing from where it was paused
+    pub async fn resume_recv(&self) -> Result<()> {
+        self.set_recv_paused(false).await
+    }
+
+    /// Set or clear the hook that rewrites outgoing topics
+    ///
+    /// Replaces `MqttConfig::topic_rewrite` for subsequent sends.
+    pub async fn set_topic_rewriter(
+        &self,
+        rewriter: Option<Arc<dyn crate::topic_rewrite::TopicRewriter>>,
+    ) -> Result<()> {
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        let request = Request::SetTopicRewriter {
+            rewriter,
             reply: reply_sender,
         };
 
@@ -713,10 +4760,17 @@ impl MqttClient {
             .map_err(|_| Error::Other("Request cancelled".to_string()))?
     }
 
-    /// Receive MQTT packet
-    pub async fn recv(&self) -> Result<mqtt::packet::Packet> {
+    /// Set or clear the handler for streamed PUBLISH payloads
+    ///
+    /// Replaces `MqttConfig::payload_chunk_handler`. A payload already being
+    /// streamed continues to the new handler (or is dropped without one).
+    pub async fn set_payload_chunk_handler(
+        &self,
+        handler: Option<Arc<dyn crate::payload_stream::PayloadChunkHandler>>,
+    ) -> Result<()> {
         let (reply_sender, reply_receiver) = oneshot::channel();
-        let request = Request::Recv {
+        let request = Request::SetPayloadChunkHandler {
+            handler,
             reply: reply_sender,
         };
 
@@ -729,10 +4783,16 @@ impl MqttClient {
             .map_err(|_| Error::Other("Request cancelled".to_string()))?
     }
 
-    /// Close the connection
-    pub async fn close(&self) -> Result<()> {
+    /// Set or clear the hook that observes outgoing packets
+    ///
+    /// Replaces `MqttConfig::send_inspector` for subsequent sends.
+    pub async fn set_send_inspector(
+        &self,
+        inspector: Option<Arc<dyn crate::send_inspector::SendInspector>>,
+    ) -> Result<()> {
         let (reply_sender, reply_receiver) = oneshot::channel();
-        let request = Request::Close {
+        let request = Request::SetSendInspector {
+            inspector,
             reply: reply_sender,
         };
 
@@ -745,68 +4805,94 @@ impl MqttClient {
             .map_err(|_| Error::Other("Request cancelled".to_string()))?
     }
 
-    /// Get current connection state
-    pub async fn state(&self) -> ConnectionState {
+    /// Set or clear the hook that sees the wire bytes of every packet
+    ///
+    /// Replaces `MqttConfig::packet_tap`. Received packets are tapped as they
+    /// are decoded (streamed PUBLISH payloads chunk by chunk), sent ones as
+    /// they are written, including automatic ones.
+    pub async fn set_packet_tap(
+        &self,
+        tap: Option<Arc<dyn crate::packet_tap::PacketTap>>,
+    ) -> Result<()> {
         let (reply_sender, reply_receiver) = oneshot::channel();
-        let request = Request::State {
+        let request = Request::SetPacketTap {
+            tap,
             reply: reply_sender,
         };
 
-        if self.request_sender.unbounded_send(request).is_err() {
-            return ConnectionState::Closed;
-        }
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
 
-        reply_receiver.await.unwrap_or(ConnectionState::Closed)
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
     }
 
-    /// Check if connected
-    pub async fn is_connected(&self) -> bool {
+    async fn set_recv_paused(&self, paused: bool) -> Result<()> {
         let (reply_sender, reply_receiver) = oneshot::channel();
-        let request = Request::IsConnected {
+        let request = Request::SetRecvPaused {
+            paused,
             reply: reply_sender,
         };
 
-        if self.request_sender.unbounded_send(request).is_err() {
-            return false;
-        }
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
 
-        reply_receiver.await.unwrap_or(false)
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
     }
 
-    /// Acquire a packet ID for use with QoS 1 or 2 messages
-    pub async fn acquire_packet_id(&self) -> Option<u16> {
+    /// Fire the timer `kind` now (e.g. "PingreqSend", "PingrespRecv"), for tests
+    ///
+    /// Handled exactly like an expiration reported by the transport, so a timer
+    /// that is not running is ignored. Resolves once the expiration is handled.
+    /// Only available with the `test-hooks` feature.
+    #[cfg(feature = "test-hooks")]
+    pub async fn fire_timer(&self, kind: &str) -> Result<()> {
         let (reply_sender, reply_receiver) = oneshot::channel();
-        let request = Request::AcquirePacketId {
+        let request = Request::TimerExpired {
+            kind: kind.to_string(),
             reply: reply_sender,
         };
 
-        if self.request_sender.unbounded_send(request).is_err() {
-            return None;
-        }
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
 
-        reply_receiver.await.unwrap_or(None)
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))?
     }
 
-    /// Register a packet ID as in use
-    pub async fn register_packet_id(&self, packet_id: u16) -> bool {
+    /// Receive the next connection event
+    ///
+    /// Events not yet requested are queued (up to 256, oldest dropped first).
+    pub async fn next_event(&self) -> Result<ConnectionEvent> {
         let (reply_sender, reply_receiver) = oneshot::channel();
-        let request = Request::RegisterPacketId {
-            packet_id,
+        let request = Request::NextEvent {
             reply: reply_sender,
         };
 
-        if self.request_sender.unbounded_send(request).is_err() {
-            return false;
-        }
+        self.request_sender
+            .unbounded_send(request)
+            .map_err(|_| Error::Other("Client channel closed".to_string()))?;
 
-        reply_receiver.await.unwrap_or(false)
+        reply_receiver
+            .await
+            .map_err(|_| Error::Other("Request cancelled".to_string()))
     }
 
-    /// Release a packet ID
-    pub async fn release_packet_id(&self, packet_id: u16) -> Result<()> {
+    /// Receive the next connection state transition
+    ///
+    /// Every change of `state()` is reported once, e.g. to count how often
+    /// Connecting fails to reach Connected. Transitions not yet requested are
+    /// queued like events (up to 256, oldest dropped first).
+    pub async fn next_state_change(&self) -> Result<StateChange> {
         let (reply_sender, reply_receiver) = oneshot::channel();
-        let request = Request::ReleasePacketId {
-            packet_id,
+        let request = Request::NextStateChange {
             reply: reply_sender,
         };
 
@@ -816,6 +4902,6 @@ impl MqttClient {
 
         reply_receiver
             .await
-            .map_err(|_| Error::Other("Request cancelled".to_string()))?
+            .map_err(|_| Error::Other("Request cancelled".to_string()))
     }
 }
@@ -0,0 +1,139 @@
+//! Pluggable payload codec for PUBLISH packets
+//!
+//! A codec (e.g. gzip) is applied to MQTT v5.0 PUBLISH payloads on send and
+//! receive. Encoded packets carry a user property `PAYLOAD_CODEC_PROPERTY`
+//! whose value is the codec name, so only payloads encoded by the same codec
+//! are decoded. MQTT v3.1.1 has no properties, so its packets pass through as is.
+
+use crate::error::{Error, Result};
+use mqtt_protocol_core::mqtt;
+
+/// User property key marking an encoded payload
+pub const PAYLOAD_CODEC_PROPERTY: &str = "payload-codec";
+
+/// Payload codec hook
+#[cfg(target_arch = "wasm32")]
+pub trait PayloadCodec {
+    /// Codec name written as the `PAYLOAD_CODEC_PROPERTY` value
+    fn name(&self) -> &str;
+
+    /// Encode an outgoing payload
+    fn encode(&self, payload: &[u8]) -> std::result::Result<Vec<u8>, String>;
+
+    /// Decode an incoming payload
+    fn decode(&self, payload: &[u8]) -> std::result::Result<Vec<u8>, String>;
+}
+
+/// Payload codec hook
+#[cfg(not(target_arch = "wasm32"))]
+pub trait PayloadCodec: Send + Sync {
+    /// Codec name written as the `PAYLOAD_CODEC_PROPERTY` value
+    fn name(&self) -> &str;
+
+    /// Encode an outgoing payload
+    fn encode(&self, payload: &[u8]) -> std::result::Result<Vec<u8>, String>;
+
+    /// Decode an incoming payload
+    fn decode(&self, payload: &[u8]) -> std::result::Result<Vec<u8>, String>;
+}
+
+impl std::fmt::Debug for dyn PayloadCodec {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PayloadCodec({})", self.name())
+    }
+}
+
+/// Encode the payload of a v5.0 PUBLISH and add the codec marker
+pub(crate) fn encode_packet(
+    codec: &dyn PayloadCodec,
+    packet: mqtt::packet::Packet,
+) -> Result<mqtt::packet::Packet> {
+    let publish = match packet {
+        mqtt::packet::Packet::V5_0Publish(publish) => publish,
+        other => return Ok(other),
+    };
+    // Already encoded by the application
+    if find_marker(&publish.props).is_some() {
+        return Ok(mqtt::packet::Packet::V5_0Publish(publish));
+    }
+
+    let payload = codec
+        .encode(publish.payload().as_slice())
+        .map_err(Error::CodecError)?;
+    let marker = mqtt::packet::UserProperty::new(PAYLOAD_CODEC_PROPERTY, codec.name())
+        .map_err(|e| Error::CodecError(format!("Invalid codec name: {:?}", e)))?;
+
+    let mut props = publish.props.clone();
+    props.push(mqtt::packet::Property::UserProperty(marker));
+    rebuild_publish(&publish, payload, props).map(mqtt::packet::Packet::V5_0Publish)
+}
+
+/// Whether a packet is a v5.0 PUBLISH carrying this codec's marker
+pub(crate) fn is_encoded_by(codec: &dyn PayloadCodec, packet: &mqtt::packet::Packet) -> bool {
+    match packet {
+        mqtt::packet::Packet::V5_0Publish(publish) => {
+            matches!(find_marker(&publish.props), Some((_, name)) if name == codec.name())
+        }
+        _ => false,
+    }
+}
+
+/// Decode the payload of a v5.0 PUBLISH carrying this codec's marker
+pub(crate) fn decode_packet(
+    codec: &dyn PayloadCodec,
+    packet: mqtt::packet::Packet,
+) -> Result<mqtt::packet::Packet> {
+    let publish = match packet {
+        mqtt::packet::Packet::V5_0Publish(publish) => publish,
+        other => return Ok(other),
+    };
+    let index = match find_marker(&publish.props) {
+        Some((index, name)) if name == codec.name() => index,
+        _ => return Ok(mqtt::packet::Packet::V5_0Publish(publish)),
+    };
+
+    let payload = codec
+        .decode(publish.payload().as_slice())
+        .map_err(Error::CodecError)?;
+
+    let mut props = publish.props.clone();
+    props.remove(index);
+    rebuild_publish(&publish, payload, props).map(mqtt::packet::Packet::V5_0Publish)
+}
+
+/// Find the codec marker, returning its index and codec name
+fn find_marker(props: &mqtt::packet::Properties) -> Option<(usize, String)> {
+    props
+        .iter()
+        .enumerate()
+        .find_map(|(index, prop)| match prop {
+            mqtt::packet::Property::UserProperty(p) if p.key() == PAYLOAD_CODEC_PROPERTY => {
+                Some((index, p.val().to_string()))
+            }
+            _ => None,
+        })
+}
+
+/// Build a copy of a PUBLISH with a different payload and properties
+fn rebuild_publish(
+    publish: &mqtt::packet::v5_0::Publish,
+    payload: Vec<u8>,
+    props: mqtt::packet::Properties,
+) -> Result<mqtt::packet::v5_0::Publish> {
+    let mut builder = mqtt::packet::v5_0::Publish::builder()
+        .topic_name(publish.topic_name())
+        .map_err(|e| Error::CodecError(format!("Invalid topic: {:?}", e)))?
+        .qos(publish.qos())
+        .retain(publish.retain())
+        .dup(publish.dup())
+        .payload(payload);
+    if let Some(packet_id) = publish.packet_id() {
+        builder = builder.packet_id(packet_id);
+    }
+    if !props.is_empty() {
+        builder = builder.props(props);
+    }
+    builder
+        .build()
+        .map_err(|e| Error::CodecError(format!("Failed to rebuild PUBLISH: {:?}", e)))
+}
@@ -4,7 +4,7 @@ use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, Clone)]
 pub enum Error {
     #[error("WebSocket connection error: {0}")]
     WebSocketError(String),
@@ -27,6 +27,43 @@ pub enum Error {
     #[error("IO error: {0}")]
     Io(String),
 
+    #[error("Packet ID {0} is not in use")]
+    PacketIdNotInUse(u16),
+
+    #[error("QoS flow for packet ID {0} is not complete")]
+    QosFlowIncomplete(u16),
+
+    #[error("Decode error at byte {offset}: {detail}")]
+    DecodeError { offset: usize, detail: String },
+
+    #[error("Payload codec error: {0}")]
+    CodecError(String),
+
+    #[error("Topic rejected: {0}")]
+    TopicRejected(String),
+
+    #[error("Send failed: {0}")]
+    SendFailed(String),
+
+    #[error("PUBLISH too large: {payload_size} payload + {overhead} header bytes exceed the Maximum Packet Size {max_packet_size}")]
+    PayloadTooLarge {
+        payload_size: usize,
+        max_packet_size: u32,
+        overhead: usize,
+    },
+
+    #[error("Connection refused by the broker (reason code {reason_code:#04x})")]
+    ConnectionRefused {
+        reason_code: u8,
+        reason_string: Option<String>,
+    },
+
+    #[error("Subscription limit of {0} reached")]
+    SubscriptionLimitReached(usize),
+
+    #[error("Operation timed out")]
+    Timeout,
+
     #[error("Other error: {0}")]
     Other(String),
 }
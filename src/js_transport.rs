@@ -4,8 +4,10 @@
 //! and the Rust WASM client. It implements UnderlyingLayerInterface so that any JavaScript
 //! transport can be used with the MqttClient's state machine and timers.
 
+use crate::log_labeled;
 use crate::websocket::{
-    ConnectReplySender, UnderlyingLayerCommand, UnderlyingLayerEvent, UnderlyingLayerInterface,
+    complete_reply, ConnectReplySender, UnderlyingLayerCommand, UnderlyingLayerEvent,
+    UnderlyingLayerInterface,
 };
 use async_trait::async_trait;
 use futures::channel::mpsc;
@@ -26,8 +28,9 @@ extern "C" {
     pub type JsTransportCallbacks;
 
     /// Called by WASM to send data via the transport
-    #[wasm_bindgen(method, js_name = onSend)]
-    pub fn on_send(this: &JsTransportCallbacks, data: &[u8]);
+    /// A thrown exception fails the send() that wrote the data
+    #[wasm_bindgen(method, catch, js_name = onSend)]
+    pub fn on_send(this: &JsTransportCallbacks, data: &[u8]) -> Result<(), JsValue>;
 
     /// Called by WASM to close the transport
     #[wasm_bindgen(method, js_name = onClose)]
@@ -49,6 +52,8 @@ pub struct JsTransportHandle {
     event_receiver: Option<mpsc::UnboundedReceiver<UnderlyingLayerEvent>>,
     command_sender: mpsc::UnboundedSender<UnderlyingLayerCommand>,
     command_receiver: Option<mpsc::UnboundedReceiver<UnderlyingLayerCommand>>,
+    /// Log prefix of the owning client (`MqttConfig::instance_label`)
+    instance_label: Option<String>,
 }
 
 impl JsTransportHandle {
@@ -64,6 +69,7 @@ impl JsTransportHandle {
             event_receiver: Some(event_receiver),
             command_sender,
             command_receiver: Some(command_receiver),
+            instance_label: None,
         }
     }
 }
@@ -94,10 +100,20 @@ impl UnderlyingLayerInterface for JsTransportHandle {
                     // Store the reply for when JavaScript calls notifyConnected
                     shared.borrow_mut().connect_reply = Some(reply_arc);
                 }
-                UnderlyingLayerCommand::SendData(data) => {
+                UnderlyingLayerCommand::SendData(data, reply) => {
                     let shared_borrowed = shared.borrow();
-                    if let Some(ref callbacks) = shared_borrowed.js_callbacks {
-                        callbacks.on_send(&data);
+                    let result = match shared_borrowed.js_callbacks {
+                        Some(ref callbacks) => callbacks.on_send(&data).map_err(|e| {
+                            crate::error::Error::SendFailed(
+                                e.as_string().unwrap_or_else(|| format!("{:?}", e)),
+                            )
+                        }),
+                        None => Err(crate::error::Error::SendFailed(
+                            "Transport not attached".to_string(),
+                        )),
+                    };
+                    if let Some(reply) = reply {
+                        complete_reply(&reply, result);
                     }
                 }
                 UnderlyingLayerCommand::Close => {
@@ -109,14 +125,39 @@ impl UnderlyingLayerInterface for JsTransportHandle {
                 UnderlyingLayerCommand::TimerReset { kind, duration_ms } => {
                     // Timer handling for JsTransport should be done in JavaScript
                     // For now, just log the request
-                    web_sys::console::log_1(
-                        &format!("JsTransport: TimerReset {} for {}ms", kind, duration_ms).into(),
+                    log_labeled!(
+                        self.instance_label.as_deref(),
+                        "JsTransport: TimerReset {} for {}ms",
+                        kind,
+                        duration_ms
                     );
                 }
                 UnderlyingLayerCommand::TimerCancel { kind } => {
                     // Timer handling for JsTransport should be done in JavaScript
                     // For now, just log the request
-                    web_sys::console::log_1(&format!("JsTransport: TimerCancel {}", kind).into());
+                    log_labeled!(
+                        self.instance_label.as_deref(),
+                        "JsTransport: TimerCancel {}",
+                        kind
+                    );
+                }
+                UnderlyingLayerCommand::QueryBufferedAmount(reply) => {
+                    // JavaScript transports do not report their send buffer
+                    complete_reply(
+                        &reply,
+                        Err(crate::error::Error::Other(
+                            "JsTransport does not report buffered amount".to_string(),
+                        )),
+                    );
+                }
+                UnderlyingLayerCommand::QueryReadyState(reply) => {
+                    // JavaScript transports are not necessarily WebSockets
+                    complete_reply(
+                        &reply,
+                        Err(crate::error::Error::Other(
+                            "JsTransport does not report ready state".to_string(),
+                        )),
+                    );
                 }
             }
         }
@@ -241,10 +282,15 @@ pub fn create_client_with_js_transport(
     config: crate::wasm_interface::WasmMqttConfig,
     transport: &JsTransport,
 ) -> crate::wasm_interface::WasmMqttClient {
-    web_sys::console::log_1(&"Creating WasmMqttClient with JsTransport handle".into());
-    let handle = transport.create_handle();
     let version = config.version();
     let inner_config = config.into_inner();
+    log_labeled!(
+        inner_config.instance_label.as_deref(),
+        "Creating WasmMqttClient with JsTransport handle"
+    );
+    let strict_version = inner_config.strict_version;
+    let mut handle = transport.create_handle();
+    handle.instance_label = inner_config.instance_label.clone();
     let client = crate::MqttClient::new_with_websocket(inner_config, handle);
-    crate::wasm_interface::WasmMqttClient::from_client(client, version)
+    crate::wasm_interface::WasmMqttClient::from_client(client, version, strict_version)
 }
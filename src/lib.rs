@@ -4,28 +4,50 @@
 //! It offers a low-level endpoint API similar to mqtt-endpoint-tokio, providing
 //! basic operations like send, recv, and close without high-level publish/subscribe abstractions.
 
+mod build_info;
 mod client;
+mod codec;
 mod error;
+mod packet_id_allocator;
+mod packet_parse;
+mod packet_tap;
+mod payload_stream;
 pub mod platform;
+mod send_inspector;
+pub mod topic;
+mod topic_rewrite;
 mod types;
 mod websocket;
 
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(target_arch = "wasm32", feature = "js-wrappers"))]
 mod js_transport;
-#[cfg(target_arch = "wasm32")]
+#[cfg(all(target_arch = "wasm32", feature = "js-wrappers"))]
 mod wasm_interface;
 
+pub use build_info::{build_info, BuildInfo};
 pub use client::MqttClient;
+pub use codec::{PayloadCodec, PAYLOAD_CODEC_PROPERTY};
 pub use error::{Error, Result};
+pub use packet_id_allocator::{DefaultPacketIdAllocator, PacketIdAllocator};
+pub use packet_tap::{PacketDirection, PacketTap};
+pub use payload_stream::PayloadChunkHandler;
+pub use send_inspector::SendInspector;
+pub use topic_rewrite::TopicRewriter;
 pub use types::*;
-pub use websocket::{UnderlyingLayerCommand, UnderlyingLayerEvent, UnderlyingLayerInterface};
+pub use websocket::{
+    complete_reply, CommandReplySender, ConnectReplySender, UnderlyingLayerCommand,
+    UnderlyingLayerEvent, UnderlyingLayerInterface,
+};
 
 // WASM-specific exports - export the clean client implementation
 #[cfg(target_arch = "wasm32")]
 pub mod wasm {
+    #[cfg(feature = "js-wrappers")]
     pub use crate::js_transport::{create_client_with_js_transport, JsTransport};
+    #[cfg(feature = "js-wrappers")]
     pub use crate::wasm_interface::{
-        WasmMqttClient, WasmMqttConfig, WasmMqttPacket, WasmPacketType,
+        WasmMqttClient, WasmMqttConfig, WasmMqttPacket, WasmPacketFactory, WasmPacketType, WasmQos,
+        WasmReasonCode, WasmRetainHandling,
     };
     pub use crate::{MqttClient, MqttConfig};
 }
@@ -0,0 +1,67 @@
+//! Custom packet identifier allocation
+//!
+//! By default the MQTT connection hands out packet IDs itself. An allocator set
+//! in `MqttConfig::packet_id_allocator` decides instead, e.g. to make IDs
+//! deterministic in tests or to share one ID space between several clients.
+//! The client keeps the connection informed, so acknowledgements still free
+//! IDs automatically; those releases are passed on to the allocator.
+
+use std::collections::BTreeSet;
+use std::sync::Mutex;
+
+/// Source of packet IDs for outgoing QoS 1/2 PUBLISH, SUBSCRIBE and UNSUBSCRIBE
+#[cfg(target_arch = "wasm32")]
+pub trait PacketIdAllocator {
+    /// Pick a free packet ID (1-65535) and mark it in use; None when all are in use
+    fn acquire(&self) -> Option<u16>;
+    /// Mark a specific packet ID in use; false if it already is
+    fn register(&self, packet_id: u16) -> bool;
+    /// Mark a packet ID free again
+    fn release(&self, packet_id: u16);
+}
+
+/// Source of packet IDs for outgoing QoS 1/2 PUBLISH, SUBSCRIBE and UNSUBSCRIBE
+#[cfg(not(target_arch = "wasm32"))]
+pub trait PacketIdAllocator: Send + Sync {
+    /// Pick a free packet ID (1-65535) and mark it in use; None when all are in use
+    fn acquire(&self) -> Option<u16>;
+    /// Mark a specific packet ID in use; false if it already is
+    fn register(&self, packet_id: u16) -> bool;
+    /// Mark a packet ID free again
+    fn release(&self, packet_id: u16);
+}
+
+impl std::fmt::Debug for dyn PacketIdAllocator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PacketIdAllocator")
+    }
+}
+
+/// Allocator that behaves like the connection's own: the lowest free ID first
+#[derive(Debug, Default)]
+pub struct DefaultPacketIdAllocator {
+    in_use: Mutex<BTreeSet<u16>>,
+}
+
+impl DefaultPacketIdAllocator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl PacketIdAllocator for DefaultPacketIdAllocator {
+    fn acquire(&self) -> Option<u16> {
+        let mut in_use = self.in_use.lock().unwrap();
+        let packet_id = (1..=u16::MAX).find(|id| !in_use.contains(id))?;
+        in_use.insert(packet_id);
+        Some(packet_id)
+    }
+
+    fn register(&self, packet_id: u16) -> bool {
+        packet_id != 0 && self.in_use.lock().unwrap().insert(packet_id)
+    }
+
+    fn release(&self, packet_id: u16) {
+        self.in_use.lock().unwrap().remove(&packet_id);
+    }
+}
@@ -0,0 +1,115 @@
+//! Decoding of raw MQTT packets
+//!
+//! Used where bytes come from outside the connection: `WasmMqttPacket.fromBytes`
+//! and `MqttClient::send_encoded`. `frame_len` finds packet boundaries in the
+//! read buffer without decoding.
+
+use mqtt_protocol_core::mqtt;
+
+/// Length of the complete packet at the start of `data`
+///
+/// None while the packet is incomplete, or if its Remaining Length is malformed.
+pub(crate) fn frame_len(data: &[u8]) -> Option<usize> {
+    let mut remaining_length = 0usize;
+    for header_len in 1..=4 {
+        let byte = *data.get(header_len)?;
+        remaining_length |= ((byte & 0x7f) as usize) << (7 * (header_len - 1));
+        if byte & 0x80 == 0 {
+            let len = header_len + 1 + remaining_length;
+            return (len <= data.len()).then_some(len);
+        }
+    }
+    None
+}
+
+/// Decode one packet of `version` from the start of `data`
+///
+/// Returns the packet and the number of bytes it occupies.
+///
+/// A temporary `Connection` would drop packets its role and state don't
+/// expect (CONNECT on a client, an ack for a packet ID it never sent), so the
+/// fixed header is read here and the body handed to the packet's own parser.
+pub(crate) fn parse_packet(
+    data: &[u8],
+    version: mqtt::Version,
+) -> Result<(mqtt::packet::Packet, usize), String> {
+    use mqtt::packet::{v3_1_1, v5_0, Packet};
+
+    let first = *data.first().ok_or("empty buffer")?;
+    let mut remaining_length = 0usize;
+    let mut header_len = 1;
+    loop {
+        if header_len > 4 {
+            return Err("malformed remaining length".to_string());
+        }
+        let byte = *data.get(header_len).ok_or("incomplete fixed header")?;
+        remaining_length |= ((byte & 0x7f) as usize) << (7 * (header_len - 1));
+        header_len += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    let body = data
+        .get(header_len..header_len + remaining_length)
+        .ok_or("incomplete packet")?;
+
+    let packet_type = first >> 4;
+    let flags = first & 0x0f;
+    // PUBLISH flags carry DUP/QoS/RETAIN; the others are fixed by the spec
+    let expected_flags = match packet_type {
+        3 => flags,
+        6 | 8 | 10 => 0b0010,
+        _ => 0,
+    };
+    if flags != expected_flags {
+        return Err(format!(
+            "invalid flags {:#06b} for packet type {}",
+            flags, packet_type
+        ));
+    }
+
+    let v5 = matches!(version, mqtt::Version::V5_0);
+    let packet = match (packet_type, v5) {
+        (1, false) => v3_1_1::Connect::parse(body).map(|(p, _)| Packet::V3_1_1Connect(p)),
+        (1, true) => v5_0::Connect::parse(body).map(|(p, _)| Packet::V5_0Connect(p)),
+        (2, false) => v3_1_1::Connack::parse(body).map(|(p, _)| Packet::V3_1_1Connack(p)),
+        (2, true) => v5_0::Connack::parse(body).map(|(p, _)| Packet::V5_0Connack(p)),
+        (3, false) => {
+            v3_1_1::Publish::parse(flags, body.into()).map(|(p, _)| Packet::V3_1_1Publish(p))
+        }
+        (3, true) => v5_0::Publish::parse(flags, body.into()).map(|(p, _)| Packet::V5_0Publish(p)),
+        (4, false) => v3_1_1::Puback::parse(body).map(|(p, _)| Packet::V3_1_1Puback(p)),
+        (4, true) => v5_0::Puback::parse(body).map(|(p, _)| Packet::V5_0Puback(p)),
+        (5, false) => v3_1_1::Pubrec::parse(body).map(|(p, _)| Packet::V3_1_1Pubrec(p)),
+        (5, true) => v5_0::Pubrec::parse(body).map(|(p, _)| Packet::V5_0Pubrec(p)),
+        (6, false) => v3_1_1::Pubrel::parse(body).map(|(p, _)| Packet::V3_1_1Pubrel(p)),
+        (6, true) => v5_0::Pubrel::parse(body).map(|(p, _)| Packet::V5_0Pubrel(p)),
+        (7, false) => v3_1_1::Pubcomp::parse(body).map(|(p, _)| Packet::V3_1_1Pubcomp(p)),
+        (7, true) => v5_0::Pubcomp::parse(body).map(|(p, _)| Packet::V5_0Pubcomp(p)),
+        (8, false) => v3_1_1::Subscribe::parse(body).map(|(p, _)| Packet::V3_1_1Subscribe(p)),
+        (8, true) => v5_0::Subscribe::parse(body).map(|(p, _)| Packet::V5_0Subscribe(p)),
+        (9, false) => v3_1_1::Suback::parse(body).map(|(p, _)| Packet::V3_1_1Suback(p)),
+        (9, true) => v5_0::Suback::parse(body).map(|(p, _)| Packet::V5_0Suback(p)),
+        (10, false) => v3_1_1::Unsubscribe::parse(body).map(|(p, _)| Packet::V3_1_1Unsubscribe(p)),
+        (10, true) => v5_0::Unsubscribe::parse(body).map(|(p, _)| Packet::V5_0Unsubscribe(p)),
+        (11, false) => v3_1_1::Unsuback::parse(body).map(|(p, _)| Packet::V3_1_1Unsuback(p)),
+        (11, true) => v5_0::Unsuback::parse(body).map(|(p, _)| Packet::V5_0Unsuback(p)),
+        (12, false) => v3_1_1::Pingreq::parse(body).map(|(p, _)| Packet::V3_1_1Pingreq(p)),
+        (12, true) => v5_0::Pingreq::parse(body).map(|(p, _)| Packet::V5_0Pingreq(p)),
+        (13, false) => v3_1_1::Pingresp::parse(body).map(|(p, _)| Packet::V3_1_1Pingresp(p)),
+        (13, true) => v5_0::Pingresp::parse(body).map(|(p, _)| Packet::V5_0Pingresp(p)),
+        (14, false) => v3_1_1::Disconnect::parse(body).map(|(p, _)| Packet::V3_1_1Disconnect(p)),
+        (14, true) => v5_0::Disconnect::parse(body).map(|(p, _)| Packet::V5_0Disconnect(p)),
+        (15, true) => v5_0::Auth::parse(body).map(|(p, _)| Packet::V5_0Auth(p)),
+        _ => {
+            return Err(format!(
+                "packet type {} is not valid in MQTT {}",
+                packet_type,
+                if v5 { "5.0" } else { "3.1.1" }
+            ))
+        }
+    };
+    packet
+        .map(|packet| (packet, header_len + remaining_length))
+        .map_err(|e| format!("{:?}", e))
+}
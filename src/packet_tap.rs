@@ -0,0 +1,62 @@
+//! Wire-level packet tap
+//!
+//! A tap sees the encoded bytes of every packet crossing the transport, both
+//! received and sent, including packets generated by the protocol state
+//! machine. Received packets are reported as decoded from the read buffer,
+//! before the connection handles them; a PUBLISH whose payload is streamed to
+//! the `PayloadChunkHandler` is reported in pieces, its headers and then each
+//! payload chunk as it arrives. It is meant for protocol debugging;
+//! unlike `SendInspector` it works on bytes, not decoded packets.
+
+/// Which way a tapped packet crossed the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    /// Received from the broker
+    In,
+    /// Written to the transport
+    Out,
+}
+
+/// Read-only hook for the bytes of every received and sent packet
+#[cfg(target_arch = "wasm32")]
+pub trait PacketTap {
+    /// Called with the direction, the packet's wire bytes and its type name
+    /// (e.g. "Publish")
+    fn tap(&self, direction: PacketDirection, bytes: &[u8], packet_type: &str);
+}
+
+/// Read-only hook for the bytes of every received and sent packet
+#[cfg(not(target_arch = "wasm32"))]
+pub trait PacketTap: Send + Sync {
+    /// Called with the direction, the packet's wire bytes and its type name
+    /// (e.g. "Publish")
+    fn tap(&self, direction: PacketDirection, bytes: &[u8], packet_type: &str);
+}
+
+impl std::fmt::Debug for dyn PacketTap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PacketTap")
+    }
+}
+
+/// Packet type name from the first byte of a packet, as `packetTypeString()` names it
+pub(crate) fn packet_type_name(bytes: &[u8]) -> &'static str {
+    match bytes.first().map(|b| b >> 4) {
+        Some(1) => "Connect",
+        Some(2) => "Connack",
+        Some(3) => "Publish",
+        Some(4) => "Puback",
+        Some(5) => "Pubrec",
+        Some(6) => "Pubrel",
+        Some(7) => "Pubcomp",
+        Some(8) => "Subscribe",
+        Some(9) => "Suback",
+        Some(10) => "Unsubscribe",
+        Some(11) => "Unsuback",
+        Some(12) => "Pingreq",
+        Some(13) => "Pingresp",
+        Some(14) => "Disconnect",
+        Some(15) => "Auth",
+        _ => "Unknown",
+    }
+}
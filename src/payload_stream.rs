@@ -0,0 +1,113 @@
+//! Streaming of large received PUBLISH payloads
+//!
+//! Once the fixed and variable headers of a PUBLISH whose payload exceeds
+//! `MqttConfig::payload_stream_threshold` have arrived, the payload bytes are
+//! handed to a chunk handler as they come in instead of being buffered until
+//! the packet is complete. The PUBLISH itself is then processed as usual
+//! (acknowledgements, `recv()`) with an empty payload, so memory use stays
+//! bounded by the size of one transport message.
+
+/// Receives the payload of streamed PUBLISH packets piece by piece
+#[cfg(target_arch = "wasm32")]
+pub trait PayloadChunkHandler {
+    /// Called with each piece of a payload in order; `offset` is the position
+    /// of `chunk` in the payload of `total_len` bytes, so the last piece ends
+    /// at `total_len`
+    fn chunk(&self, topic: &str, offset: usize, chunk: &[u8], total_len: usize);
+}
+
+/// Receives the payload of streamed PUBLISH packets piece by piece
+#[cfg(not(target_arch = "wasm32"))]
+pub trait PayloadChunkHandler: Send + Sync {
+    /// Called with each piece of a payload in order; `offset` is the position
+    /// of `chunk` in the payload of `total_len` bytes, so the last piece ends
+    /// at `total_len`
+    fn chunk(&self, topic: &str, offset: usize, chunk: &[u8], total_len: usize);
+}
+
+impl std::fmt::Debug for dyn PayloadChunkHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PayloadChunkHandler")
+    }
+}
+
+/// Headers of a received PUBLISH, parsed ahead of its payload
+pub(crate) struct PublishHeaders {
+    /// Length of the fixed and variable headers
+    pub header_len: usize,
+    /// Length of the payload that follows them
+    pub payload_len: usize,
+    /// Topic name as sent (empty when a v5.0 topic alias is used)
+    pub topic: String,
+    /// The same PUBLISH with an empty payload
+    pub empty_packet: Vec<u8>,
+}
+
+/// Parse the headers of the PUBLISH at the start of `data`
+///
+/// Returns None for other packets, until the headers are complete, and for
+/// malformed headers (left to the regular parser to report).
+pub(crate) fn publish_headers(data: &[u8], v5: bool) -> Option<PublishHeaders> {
+    let first = *data.first()?;
+    let qos = (first >> 1) & 0x03;
+    if first >> 4 != 3 || qos == 3 {
+        return None;
+    }
+    let (remaining_length, fixed_len) = variable_byte_integer(data, 1)?;
+
+    let topic_len = u16::from_be_bytes([*data.get(fixed_len)?, *data.get(fixed_len + 1)?]) as usize;
+    let topic_start = fixed_len + 2;
+    let topic = std::str::from_utf8(data.get(topic_start..topic_start + topic_len)?).ok()?;
+    let mut header_len = topic_start + topic_len;
+    if qos > 0 {
+        header_len += 2;
+    }
+    if v5 {
+        let (props_len, props_start) = variable_byte_integer(data, header_len)?;
+        header_len = props_start + props_len;
+    }
+    if header_len > data.len() {
+        return None;
+    }
+    let payload_len = (fixed_len + remaining_length).checked_sub(header_len)?;
+
+    let mut empty_packet = vec![first];
+    encode_variable_byte_integer(header_len - fixed_len, &mut empty_packet);
+    empty_packet.extend_from_slice(&data[fixed_len..header_len]);
+
+    Some(PublishHeaders {
+        header_len,
+        payload_len,
+        topic: topic.to_string(),
+        empty_packet,
+    })
+}
+
+/// Decode an MQTT Variable Byte Integer at `start`, returning it and the
+/// position after it
+fn variable_byte_integer(data: &[u8], start: usize) -> Option<(usize, usize)> {
+    let mut value = 0;
+    for i in 0..4 {
+        let byte = *data.get(start + i)?;
+        value |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Some((value, start + i + 1));
+        }
+    }
+    None
+}
+
+/// Append `value` as an MQTT Variable Byte Integer
+fn encode_variable_byte_integer(mut value: usize, out: &mut Vec<u8>) {
+    loop {
+        let mut byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if value == 0 {
+            return;
+        }
+    }
+}
@@ -1,7 +1,9 @@
 //! Platform abstraction layer
 //!
-//! This module provides platform-agnostic APIs for timers, logging, and time.
+//! This module provides platform-agnostic APIs for timers, logging, time and randomness.
 //! These work in both browser and Node.js environments by using global JavaScript functions.
+//! They are resolved on the global scope rather than `window`, so they also work inside
+//! Web Workers (`WorkerGlobalScope`), where `web_sys::window()` is `None`.
 
 // ============================================================================
 // WASM32 Platform Functions
@@ -14,11 +16,11 @@ mod wasm32 {
 
     #[wasm_bindgen]
     extern "C" {
-        /// Set a timeout using global setTimeout (works in browser and Node.js)
+        /// Set a timeout using global setTimeout (works in browser, Web Workers and Node.js)
         #[wasm_bindgen(js_name = setTimeout)]
         fn set_timeout_internal(closure: &js_sys::Function, millis: i32) -> i32;
 
-        /// Clear a timeout using global clearTimeout (works in browser and Node.js)
+        /// Clear a timeout using global clearTimeout (works in browser, Web Workers and Node.js)
         #[wasm_bindgen(js_name = clearTimeout)]
         pub fn clear_timeout(id: i32);
     }
@@ -29,11 +31,30 @@ mod wasm32 {
         set_timeout_internal(closure.as_ref().unchecked_ref(), millis)
     }
 
+    /// Resolve after `millis` milliseconds
+    pub async fn sleep_ms(millis: i32) {
+        let promise = js_sys::Promise::new(&mut |resolve, _reject| {
+            set_timeout_internal(&resolve, millis);
+        });
+        let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+    }
+
     #[wasm_bindgen]
     extern "C" {
         /// Get current time in milliseconds (Date.now())
         #[wasm_bindgen(js_namespace = Date, js_name = now)]
         pub fn date_now() -> f64;
+
+        /// Get monotonic time in milliseconds (performance.now())
+        ///
+        /// Unaffected by system clock changes; use it for measuring durations.
+        #[wasm_bindgen(js_namespace = performance, js_name = now)]
+        pub fn monotonic_now() -> f64;
+    }
+
+    /// Get a random number in [0, 1) (Math.random())
+    pub fn random() -> f64 {
+        js_sys::Math::random()
     }
 
     #[wasm_bindgen]
@@ -52,6 +73,47 @@ mod wasm32 {
 #[cfg(target_arch = "wasm32")]
 pub use wasm32::*;
 
+// ============================================================================
+// Native Platform Functions
+// ============================================================================
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    /// Get current time in milliseconds since the Unix epoch (same as Date.now())
+    pub fn date_now() -> f64 {
+        std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs_f64() * 1000.0)
+            .unwrap_or(0.0)
+    }
+
+    /// Get monotonic time in milliseconds since the first call (like performance.now())
+    ///
+    /// Unaffected by system clock changes; use it for measuring durations.
+    pub fn monotonic_now() -> f64 {
+        static ORIGIN: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+        ORIGIN
+            .get_or_init(std::time::Instant::now)
+            .elapsed()
+            .as_secs_f64()
+            * 1000.0
+    }
+
+    /// Get a random number in [0, 1) (same as Math.random())
+    ///
+    /// Not for cryptographic use; each call hashes with a freshly seeded `RandomState`.
+    pub fn random() -> f64 {
+        use std::hash::{BuildHasher, Hasher};
+        let bits = std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish();
+        (bits >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::*;
+
 // ============================================================================
 // Logging Macros (work on all platforms)
 // ============================================================================
@@ -67,6 +129,21 @@ macro_rules! log {
     };
 }
 
+/// Log a message prefixed with a client's `instance_label` (`None` = no prefix)
+#[macro_export]
+macro_rules! log_labeled {
+    ($label:expr, $($arg:tt)*) => {
+        match $label {
+            Some(label) => {
+                $crate::log!("[{}] {}", label, format_args!($($arg)*));
+            }
+            None => {
+                $crate::log!($($arg)*);
+            }
+        }
+    };
+}
+
 /// Log a warning
 #[macro_export]
 macro_rules! log_warn {
@@ -0,0 +1,28 @@
+//! Outgoing packet inspection
+//!
+//! An inspector sees every packet right before its bytes are handed to the
+//! transport, including packets generated by the protocol state machine
+//! (PINGREQ, PUBACK, ...). It cannot modify or reject packets; it exists for
+//! audit logging and metrics.
+
+use mqtt_protocol_core::mqtt;
+
+/// Read-only hook for outgoing packets
+#[cfg(target_arch = "wasm32")]
+pub trait SendInspector {
+    /// Called with each outgoing packet and its encoded length in bytes
+    fn inspect(&self, packet: &mqtt::packet::Packet, len: usize);
+}
+
+/// Read-only hook for outgoing packets
+#[cfg(not(target_arch = "wasm32"))]
+pub trait SendInspector: Send + Sync {
+    /// Called with each outgoing packet and its encoded length in bytes
+    fn inspect(&self, packet: &mqtt::packet::Packet, len: usize);
+}
+
+impl std::fmt::Debug for dyn SendInspector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SendInspector")
+    }
+}
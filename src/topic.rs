@@ -0,0 +1,103 @@
+//! Topic name and topic filter validation
+//!
+//! Checks user input against the MQTT topic rules before it goes into a
+//! packet builder, so a UI can show what is wrong with a topic instead of a
+//! generic build error.
+
+/// Longest topic in bytes (UTF-8 encoded string length limit)
+const MAX_TOPIC_BYTES: usize = 65535;
+
+/// Check a topic name as used in PUBLISH
+///
+/// A name is at least one character, at most 65535 bytes of UTF-8, contains
+/// no null character and no wildcard (`+`, `#`).
+pub fn validate_name(name: &str) -> Result<(), String> {
+    check_common(name, "Topic name")?;
+    if let Some(position) = name.find(['+', '#']) {
+        return Err(format!(
+            "Topic name must not contain wildcards, found '{}' at byte {}",
+            &name[position..position + 1],
+            position
+        ));
+    }
+    Ok(())
+}
+
+/// Check a topic filter as used in SUBSCRIBE and UNSUBSCRIBE
+///
+/// Besides the length and character rules of a name, `+` must fill a whole
+/// level and `#` must fill the last level. A shared subscription
+/// (`$share/{group}/{filter}`) needs a group name without wildcards and a
+/// valid filter after it.
+pub fn validate_filter(filter: &str) -> Result<(), String> {
+    check_common(filter, "Topic filter")?;
+    let filter = match filter.strip_prefix("$share/") {
+        Some(shared) => {
+            let (group, rest) = shared
+                .split_once('/')
+                .ok_or_else(|| "Shared subscription must be $share/{group}/{filter}".to_string())?;
+            if group.is_empty() {
+                return Err("Shared subscription group name must not be empty".to_string());
+            }
+            if group.contains(['+', '#']) {
+                return Err(format!(
+                    "Shared subscription group name '{}' must not contain wildcards",
+                    group
+                ));
+            }
+            if rest.is_empty() {
+                return Err("Shared subscription needs a topic filter after the group".to_string());
+            }
+            rest
+        }
+        None => filter,
+    };
+
+    let levels: Vec<&str> = filter.split('/').collect();
+    for (index, level) in levels.iter().enumerate() {
+        if level.contains('#') {
+            if *level != "#" {
+                return Err(format!(
+                    "'#' must fill a whole level, found '{}' at level {}",
+                    level, index
+                ));
+            }
+            if index != levels.len() - 1 {
+                return Err(format!(
+                    "'#' must be the last level, found at level {} of {}",
+                    index,
+                    levels.len()
+                ));
+            }
+        }
+        if level.contains('+') && *level != "+" {
+            return Err(format!(
+                "'+' must fill a whole level, found '{}' at level {}",
+                level, index
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Rules shared by names and filters: length and forbidden characters
+fn check_common(topic: &str, what: &str) -> Result<(), String> {
+    if topic.is_empty() {
+        return Err(format!("{} must not be empty", what));
+    }
+    if topic.len() > MAX_TOPIC_BYTES {
+        return Err(format!(
+            "{} is {} bytes, more than the {} allowed",
+            what,
+            topic.len(),
+            MAX_TOPIC_BYTES
+        ));
+    }
+    if let Some(position) = topic.find('\0') {
+        return Err(format!(
+            "{} must not contain the null character, found at byte {}",
+            what, position
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,158 @@
+//! Outgoing topic rewriting
+//!
+//! A rewriter (e.g. adding a per-tenant prefix) is applied to the topic name of
+//! outgoing PUBLISH packets and to the topic filters of outgoing SUBSCRIBE and
+//! UNSUBSCRIBE packets before they reach the protocol state machine. Returning
+//! an error rejects the send. Incoming packets are not rewritten.
+
+use crate::error::{Error, Result};
+use mqtt_protocol_core::mqtt;
+
+/// Topic rewrite hook
+#[cfg(target_arch = "wasm32")]
+pub trait TopicRewriter {
+    /// Return the topic to send, or an error message to reject the send
+    fn rewrite(&self, topic: &str) -> std::result::Result<String, String>;
+}
+
+/// Topic rewrite hook
+#[cfg(not(target_arch = "wasm32"))]
+pub trait TopicRewriter: Send + Sync {
+    /// Return the topic to send, or an error message to reject the send
+    fn rewrite(&self, topic: &str) -> std::result::Result<String, String>;
+}
+
+impl std::fmt::Debug for dyn TopicRewriter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TopicRewriter")
+    }
+}
+
+/// Rewrite the topics of an outgoing PUBLISH, SUBSCRIBE or UNSUBSCRIBE
+pub(crate) fn rewrite_packet(
+    rewriter: &dyn TopicRewriter,
+    packet: mqtt::packet::Packet,
+) -> Result<mqtt::packet::Packet> {
+    match packet {
+        mqtt::packet::Packet::V3_1_1Publish(p) => {
+            let topic = rewrite_topic(rewriter, p.topic_name())?;
+            let mut builder = mqtt::packet::v3_1_1::Publish::builder()
+                .topic_name(&topic)
+                .map_err(|e| invalid("topic", e))?
+                .qos(p.qos())
+                .retain(p.retain())
+                .dup(p.dup())
+                .payload(p.payload().as_slice());
+            if let Some(packet_id) = p.packet_id() {
+                builder = builder.packet_id(packet_id);
+            }
+            builder
+                .build()
+                .map(mqtt::packet::Packet::V3_1_1Publish)
+                .map_err(|e| invalid("PUBLISH", e))
+        }
+        mqtt::packet::Packet::V5_0Publish(p) => {
+            // An empty topic name refers to a topic alias and is left as is
+            if p.topic_name().is_empty() {
+                return Ok(mqtt::packet::Packet::V5_0Publish(p));
+            }
+            let topic = rewrite_topic(rewriter, p.topic_name())?;
+            let mut builder = mqtt::packet::v5_0::Publish::builder()
+                .topic_name(&topic)
+                .map_err(|e| invalid("topic", e))?
+                .qos(p.qos())
+                .retain(p.retain())
+                .dup(p.dup())
+                .payload(p.payload().as_slice());
+            if let Some(packet_id) = p.packet_id() {
+                builder = builder.packet_id(packet_id);
+            }
+            if !p.props.is_empty() {
+                builder = builder.props(p.props.clone());
+            }
+            builder
+                .build()
+                .map(mqtt::packet::Packet::V5_0Publish)
+                .map_err(|e| invalid("PUBLISH", e))
+        }
+        mqtt::packet::Packet::V3_1_1Subscribe(p) => {
+            let entries = rewrite_entries(rewriter, p.entries())?;
+            mqtt::packet::v3_1_1::Subscribe::builder()
+                .packet_id(p.packet_id())
+                .entries(entries)
+                .build()
+                .map(mqtt::packet::Packet::V3_1_1Subscribe)
+                .map_err(|e| invalid("SUBSCRIBE", e))
+        }
+        mqtt::packet::Packet::V5_0Subscribe(p) => {
+            let entries = rewrite_entries(rewriter, p.entries())?;
+            let mut builder = mqtt::packet::v5_0::Subscribe::builder()
+                .packet_id(p.packet_id())
+                .entries(entries);
+            if !p.props.is_empty() {
+                builder = builder.props(p.props.clone());
+            }
+            builder
+                .build()
+                .map(mqtt::packet::Packet::V5_0Subscribe)
+                .map_err(|e| invalid("SUBSCRIBE", e))
+        }
+        mqtt::packet::Packet::V3_1_1Unsubscribe(p) => {
+            let topics = rewrite_filters(rewriter, p.entries())?;
+            mqtt::packet::v3_1_1::Unsubscribe::builder()
+                .packet_id(p.packet_id())
+                .entries(topics)
+                .map_err(|e| invalid("topic entries", e))?
+                .build()
+                .map(mqtt::packet::Packet::V3_1_1Unsubscribe)
+                .map_err(|e| invalid("UNSUBSCRIBE", e))
+        }
+        mqtt::packet::Packet::V5_0Unsubscribe(p) => {
+            let topics = rewrite_filters(rewriter, p.entries())?;
+            let mut builder = mqtt::packet::v5_0::Unsubscribe::builder()
+                .packet_id(p.packet_id())
+                .entries(topics)
+                .map_err(|e| invalid("topic entries", e))?;
+            if !p.props.is_empty() {
+                builder = builder.props(p.props.clone());
+            }
+            builder
+                .build()
+                .map(mqtt::packet::Packet::V5_0Unsubscribe)
+                .map_err(|e| invalid("UNSUBSCRIBE", e))
+        }
+        other => Ok(other),
+    }
+}
+
+fn rewrite_topic(rewriter: &dyn TopicRewriter, topic: &str) -> Result<String> {
+    rewriter.rewrite(topic).map_err(Error::TopicRejected)
+}
+
+fn rewrite_entries(
+    rewriter: &dyn TopicRewriter,
+    entries: &[mqtt::packet::SubEntry],
+) -> Result<Vec<mqtt::packet::SubEntry>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let topic = rewrite_topic(rewriter, entry.topic_filter())?;
+            mqtt::packet::SubEntry::new(&topic, *entry.sub_opts())
+                .map_err(|e| invalid("topic filter", e))
+        })
+        .collect()
+}
+
+fn rewrite_filters<T: AsRef<str>>(
+    rewriter: &dyn TopicRewriter,
+    topics: &[T],
+) -> Result<Vec<String>> {
+    topics
+        .iter()
+        .map(|topic| rewrite_topic(rewriter, topic.as_ref()))
+        .collect()
+}
+
+fn invalid(what: &str, e: impl std::fmt::Debug) -> Error {
+    Error::TopicRejected(format!("Rewritten {} is invalid: {:?}", what, e))
+}
@@ -1,13 +1,30 @@
 //! Common types and configuration
 
+use crate::codec::PayloadCodec;
+use crate::error::Error;
+use crate::packet_id_allocator::PacketIdAllocator;
+use crate::packet_tap::PacketTap;
+use crate::payload_stream::PayloadChunkHandler;
+use crate::send_inspector::SendInspector;
+use crate::topic_rewrite::TopicRewriter;
 use mqtt_protocol_core::mqtt;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Default for `MqttConfig::max_undelivered_packets`
+pub const DEFAULT_MAX_UNDELIVERED_PACKETS: usize = 1024;
 
 /// MQTT client configuration
 #[derive(Debug, Clone)]
 pub struct MqttConfig {
     pub url: String,
     pub version: mqtt::Version,
+    /// Client identifier used for this client's CONNECT (None = set only in the CONNECT packet)
+    pub client_id: Option<String>,
     pub pingreq_send_interval_ms: Option<u64>,
+    /// Add a random delay in [0, jitter) milliseconds to every PINGREQ interval,
+    /// so that many clients do not ping the broker in lockstep (None = exact interval)
+    pub pingreq_jitter_ms: Option<u32>,
     pub auto_pub_response: bool,
     pub auto_ping_response: bool,
     pub auto_map_topic_alias_send: bool,
@@ -15,6 +32,81 @@ pub struct MqttConfig {
     pub pingresp_recv_timeout_ms: u64,
     pub connection_establish_timeout_ms: u64,
     pub shutdown_timeout_ms: u64,
+    /// Maximum number of received packets kept for recv(); once reached, received
+    /// PUBLISH bytes stay unparsed until recv() catches up, while other packets are
+    /// still handled and reach recv() behind them (one WebSocket message may
+    /// overshoot). Must be at least 1
+    pub max_undelivered_packets: usize,
+    /// Cap on the bytes held by the client (read buffer capacity, packets waiting for
+    /// recv(), unacknowledged outgoing QoS 1/2 PUBLISH). While exceeded, parsing waits
+    /// for recv(), PUBLISH sends fail with `Error::BufferOverflow` and
+    /// `ConnectionEvent::MemoryPressure` is emitted (None = unlimited)
+    pub max_total_buffer_bytes: Option<usize>,
+    /// Limit outgoing PUBLISH packets to this many per second (bursts up to one
+    /// second's worth). Sends over the limit wait in order and
+    /// `ConnectionEvent::PublishThrottled` is emitted; a waiting v5.0 PUBLISH
+    /// has its Message Expiry Interval lowered by the time waited, or fails
+    /// once it expires. Other packets are never delayed (None or 0 = unlimited)
+    pub max_publish_rate_per_sec: Option<u32>,
+    /// First automatic reconnection delay after the connection is lost (None = no auto-reconnect).
+    /// The transport is reconnected to the last connect() URL; the app sends CONNECT again.
+    pub reconnect_initial_delay_ms: Option<u64>,
+    /// Upper bound for the doubling reconnection delay
+    pub reconnect_max_delay_ms: u64,
+    /// Stop reconnecting automatically after this many attempts in a row failed,
+    /// staying Closed and emitting `ConnectionEvent::ReconnectGaveUp`. The count
+    /// starts over once the transport connects (None = retry forever)
+    pub reconnect_max_attempts: Option<u32>,
+    /// Report a QoS 1/2 PUBLISH as `ConnectionEvent::PublishTimedOut` when its
+    /// final acknowledgement does not arrive within this time (None = wait forever)
+    pub publish_ack_timeout_ms: Option<u64>,
+    /// Release the packet ID of a publish that hit `publish_ack_timeout_ms`
+    pub release_packet_id_on_ack_timeout: bool,
+    /// Keep released packet IDs out of use for this long (0 = reuse immediately),
+    /// so a late retransmission cannot collide with a new flow using the same ID
+    pub packet_id_reuse_delay_ms: u64,
+    /// After a CONNACK without a session present, re-send the subscriptions
+    /// confirmed on earlier connections (see `MqttClient::subscriptions()`)
+    pub restore_subscriptions: bool,
+    /// Refuse a subscribe() that would take the session past this many topic
+    /// filters with `Error::SubscriptionLimitReached`, before the SUBSCRIBE is
+    /// sent. Confirmed and not yet acknowledged filters count (None = unlimited)
+    pub max_subscriptions: Option<usize>,
+    /// Have recv() fail with `Error::ConnectionRefused` for a CONNACK with a
+    /// failure return/reason code instead of returning the packet
+    pub error_on_connack_failure: bool,
+    /// Let recv() wait before connect() has ever been called, e.g. when one task
+    /// receives while another connects. When false, such a recv() fails with
+    /// `Error::NotConnected` instead of possibly waiting forever.
+    pub allow_recv_before_connect: bool,
+    /// Prefixed as `[label]` to this client's log lines, to tell several clients
+    /// apart in one console (None = no prefix)
+    pub instance_label: Option<String>,
+    /// When a v5.0 CONNACK or DISCONNECT says Use another server (0x9C) or
+    /// Server moved (0x9D) with a Server Reference, close the transport and
+    /// connect it to the referenced server (the app sends CONNECT again).
+    /// A reference that would go from `wss://` to `ws://` is not followed
+    pub follow_server_reference: bool,
+    /// Make the JavaScript packet factories of a v3.1.1 client reject v5.0-only
+    /// options (e.g. `messageExpiryInterval`) instead of silently dropping them
+    pub strict_version: bool,
+    /// Received PUBLISH payloads larger than this many bytes go to
+    /// `payload_chunk_handler` piece by piece as they arrive (when one is set)
+    pub payload_stream_threshold: usize,
+    /// Codec applied to v5.0 PUBLISH payloads on send and receive (None = unchanged)
+    pub payload_codec: Option<Arc<dyn PayloadCodec>>,
+    /// Rewrites (or rejects) outgoing PUBLISH/SUBSCRIBE/UNSUBSCRIBE topics (None = unchanged)
+    pub topic_rewrite: Option<Arc<dyn TopicRewriter>>,
+    /// Observes every outgoing packet, including automatic ones (None = no inspection)
+    pub send_inspector: Option<Arc<dyn SendInspector>>,
+    /// Sees the wire bytes of every received and sent packet (None = no tap)
+    pub packet_tap: Option<Arc<dyn PacketTap>>,
+    /// Receives streamed PUBLISH payloads; recv() then gets those PUBLISH with
+    /// an empty payload (None = payloads are always buffered)
+    pub payload_chunk_handler: Option<Arc<dyn PayloadChunkHandler>>,
+    /// Hands out packet IDs instead of the connection (None = the connection
+    /// allocates them); see `PacketIdAllocator`
+    pub packet_id_allocator: Option<Arc<dyn PacketIdAllocator>>,
 }
 
 impl Default for MqttConfig {
@@ -22,7 +114,9 @@ impl Default for MqttConfig {
         Self {
             url: String::new(),
             version: mqtt::Version::V5_0,
+            client_id: None,
             pingreq_send_interval_ms: None,
+            pingreq_jitter_ms: None,
             auto_pub_response: true,
             auto_ping_response: true,
             auto_map_topic_alias_send: false,
@@ -30,10 +124,239 @@ impl Default for MqttConfig {
             pingresp_recv_timeout_ms: 0,
             connection_establish_timeout_ms: 0,
             shutdown_timeout_ms: 5000,
+            max_undelivered_packets: DEFAULT_MAX_UNDELIVERED_PACKETS,
+            max_total_buffer_bytes: None,
+            max_publish_rate_per_sec: None,
+            reconnect_initial_delay_ms: None,
+            reconnect_max_delay_ms: 30000,
+            reconnect_max_attempts: None,
+            publish_ack_timeout_ms: None,
+            release_packet_id_on_ack_timeout: false,
+            packet_id_reuse_delay_ms: 0,
+            restore_subscriptions: false,
+            max_subscriptions: None,
+            error_on_connack_failure: false,
+            allow_recv_before_connect: false,
+            instance_label: None,
+            follow_server_reference: false,
+            strict_version: false,
+            payload_stream_threshold: 65536,
+            payload_codec: None,
+            topic_rewrite: None,
+            send_inspector: None,
+            packet_tap: None,
+            payload_chunk_handler: None,
+            packet_id_allocator: None,
         }
     }
 }
 
+impl MqttConfig {
+    /// Start building a configuration from the defaults
+    pub fn builder() -> MqttConfigBuilder {
+        MqttConfigBuilder::default()
+    }
+}
+
+/// Builder for `MqttConfig`
+///
+/// Unset fields keep their `MqttConfig::default()` values.
+#[derive(Debug, Clone, Default)]
+pub struct MqttConfigBuilder {
+    config: MqttConfig,
+}
+
+impl MqttConfigBuilder {
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.config.url = url.into();
+        self
+    }
+
+    pub fn version(mut self, version: mqtt::Version) -> Self {
+        self.config.version = version;
+        self
+    }
+
+    pub fn client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.config.client_id = Some(client_id.into());
+        self
+    }
+
+    pub fn pingreq_send_interval_ms(mut self, interval_ms: u64) -> Self {
+        self.config.pingreq_send_interval_ms = Some(interval_ms);
+        self
+    }
+
+    pub fn pingreq_jitter_ms(mut self, jitter_ms: u32) -> Self {
+        self.config.pingreq_jitter_ms = Some(jitter_ms);
+        self
+    }
+
+    pub fn auto_pub_response(mut self, enable: bool) -> Self {
+        self.config.auto_pub_response = enable;
+        self
+    }
+
+    pub fn auto_ping_response(mut self, enable: bool) -> Self {
+        self.config.auto_ping_response = enable;
+        self
+    }
+
+    pub fn auto_map_topic_alias_send(mut self, enable: bool) -> Self {
+        self.config.auto_map_topic_alias_send = enable;
+        self
+    }
+
+    pub fn auto_replace_topic_alias_send(mut self, enable: bool) -> Self {
+        self.config.auto_replace_topic_alias_send = enable;
+        self
+    }
+
+    pub fn pingresp_recv_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.config.pingresp_recv_timeout_ms = timeout_ms;
+        self
+    }
+
+    pub fn connection_establish_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.config.connection_establish_timeout_ms = timeout_ms;
+        self
+    }
+
+    pub fn shutdown_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.config.shutdown_timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Panics if `max` is 0, which would never let a PUBLISH be parsed
+    pub fn max_undelivered_packets(mut self, max: usize) -> Self {
+        assert!(max > 0, "max_undelivered_packets must be at least 1");
+        self.config.max_undelivered_packets = max;
+        self
+    }
+
+    pub fn max_total_buffer_bytes(mut self, max: usize) -> Self {
+        self.config.max_total_buffer_bytes = Some(max);
+        self
+    }
+
+    pub fn max_publish_rate_per_sec(mut self, rate: u32) -> Self {
+        self.config.max_publish_rate_per_sec = Some(rate);
+        self
+    }
+
+    pub fn reconnect_initial_delay_ms(mut self, delay_ms: u64) -> Self {
+        self.config.reconnect_initial_delay_ms = Some(delay_ms);
+        self
+    }
+
+    pub fn reconnect_max_delay_ms(mut self, delay_ms: u64) -> Self {
+        self.config.reconnect_max_delay_ms = delay_ms;
+        self
+    }
+
+    pub fn reconnect_max_attempts(mut self, attempts: u32) -> Self {
+        self.config.reconnect_max_attempts = Some(attempts);
+        self
+    }
+
+    pub fn publish_ack_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.config.publish_ack_timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    pub fn release_packet_id_on_ack_timeout(mut self, release: bool) -> Self {
+        self.config.release_packet_id_on_ack_timeout = release;
+        self
+    }
+
+    pub fn packet_id_reuse_delay_ms(mut self, delay_ms: u64) -> Self {
+        self.config.packet_id_reuse_delay_ms = delay_ms;
+        self
+    }
+
+    pub fn restore_subscriptions(mut self, restore: bool) -> Self {
+        self.config.restore_subscriptions = restore;
+        self
+    }
+
+    pub fn max_subscriptions(mut self, max: usize) -> Self {
+        self.config.max_subscriptions = Some(max);
+        self
+    }
+
+    pub fn error_on_connack_failure(mut self, enabled: bool) -> Self {
+        self.config.error_on_connack_failure = enabled;
+        self
+    }
+
+    pub fn allow_recv_before_connect(mut self, allow: bool) -> Self {
+        self.config.allow_recv_before_connect = allow;
+        self
+    }
+
+    pub fn instance_label(mut self, label: impl Into<String>) -> Self {
+        self.config.instance_label = Some(label.into());
+        self
+    }
+
+    pub fn follow_server_reference(mut self, follow: bool) -> Self {
+        self.config.follow_server_reference = follow;
+        self
+    }
+
+    pub fn strict_version(mut self, strict: bool) -> Self {
+        self.config.strict_version = strict;
+        self
+    }
+
+    pub fn payload_stream_threshold(mut self, bytes: usize) -> Self {
+        self.config.payload_stream_threshold = bytes;
+        self
+    }
+
+    pub fn payload_codec(mut self, codec: Arc<dyn PayloadCodec>) -> Self {
+        self.config.payload_codec = Some(codec);
+        self
+    }
+
+    pub fn topic_rewrite(mut self, rewriter: Arc<dyn TopicRewriter>) -> Self {
+        self.config.topic_rewrite = Some(rewriter);
+        self
+    }
+
+    pub fn send_inspector(mut self, inspector: Arc<dyn SendInspector>) -> Self {
+        self.config.send_inspector = Some(inspector);
+        self
+    }
+
+    pub fn packet_tap(mut self, tap: Arc<dyn PacketTap>) -> Self {
+        self.config.packet_tap = Some(tap);
+        self
+    }
+
+    pub fn payload_chunk_handler(mut self, handler: Arc<dyn PayloadChunkHandler>) -> Self {
+        self.config.payload_chunk_handler = Some(handler);
+        self
+    }
+
+    pub fn packet_id_allocator(mut self, allocator: Arc<dyn PacketIdAllocator>) -> Self {
+        self.config.packet_id_allocator = Some(allocator);
+        self
+    }
+
+    /// Finish building
+    pub fn build(self) -> MqttConfig {
+        self.config
+    }
+}
+
+/// A connection state transition, as reported by `MqttClient::next_state_change()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StateChange {
+    pub from: ConnectionState,
+    pub to: ConnectionState,
+}
+
 /// Connection state
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConnectionState {
@@ -49,5 +372,182 @@ pub enum ConnectionState {
     Closed,
 }
 
+/// Connection events delivered by `MqttClient::next_event()`
+#[derive(Debug, Clone)]
+pub enum ConnectionEvent {
+    /// Transport connection established
+    Connected,
+    /// Transport connection closed, with the close code and reason if the transport reported them
+    Closed {
+        code: Option<u16>,
+        reason: Option<String>,
+    },
+    /// Connection lost; automatic reconnection attempt `attempt` starts after `delay_ms`
+    Reconnecting { attempt: u32, delay_ms: u64 },
+    /// Automatic reconnection succeeded (the transport is connected again)
+    Reconnected,
+    /// `reconnect_max_attempts` attempts failed in a row; the client stays
+    /// Closed until connect() is called
+    ReconnectGaveUp { attempts: u32 },
+    /// The broker referred the client to `server` (`follow_server_reference`);
+    /// the transport is being reconnected there
+    Redirected { server: String },
+    /// The connection closed with received bytes still unparsed, partway through
+    /// a packet or with PUBLISH packets held back (`pause_recv()`,
+    /// `max_undelivered_packets`); those bytes are discarded
+    TruncatedFrame { bytes_lost: usize },
+    /// Bytes held by the client exceeded `max_total_buffer_bytes` (reported once
+    /// per excursion above the limit)
+    MemoryPressure {
+        used_bytes: usize,
+        limit_bytes: usize,
+    },
+    /// A PUBLISH was held back by `max_publish_rate_per_sec`; `queued` publishes
+    /// (including this one) are now waiting to be sent
+    PublishThrottled { queued: usize },
+    /// The Reason String of a v5.0 DISCONNECT sent by `close_with_disconnect()`
+    /// was removed because the packet would exceed the broker's Maximum Packet Size
+    ReasonStringDropped { max_packet_size: u32 },
+    /// Final acknowledgement (QoS 1 PUBACK or QoS 2 PUBCOMP) received for an
+    /// outgoing PUBLISH; the packet ID still has to be released with `complete_qos()`
+    PublishComplete { packet_id: u16, qos: u8 },
+    /// No final acknowledgement for an outgoing QoS 1/2 PUBLISH within `publish_ack_timeout_ms`
+    PublishTimedOut { packet_id: u16 },
+    /// A v5.0 PUBREC with a failure reason code (>= 0x80) ended an outgoing QoS 2
+    /// flow; no PUBREL/PUBCOMP follow and the packet ID is already released
+    PublishFailed { packet_id: u16, reason_code: u8 },
+    /// Remembered subscriptions were re-sent (`count` filters) because the broker
+    /// started a new session (`restore_subscriptions`)
+    SubscriptionsRestored { count: usize },
+    /// A CONNECT asked to resume a session (clean session/start false) but the
+    /// CONNACK reports no session present: in-flight QoS state and subscriptions
+    /// the app expected the broker to keep are gone
+    SessionLost,
+    /// PINGREQ received from the peer (answer with `send_pingresp()` if `auto_ping_response` is off)
+    PingReceived,
+    /// The broker disconnected with Keep Alive timeout (0x8D): no packet
+    /// arrived within its keep alive, e.g. `pingreq_send_interval_ms` is too
+    /// long for the keep alive in CONNECT
+    KeepAliveTimeout,
+    /// Error not tied to a specific request (e.g. malformed inbound bytes,
+    /// `Error::ProtocolError` from the MQTT state machine)
+    Error(Error),
+}
+
+/// Round-trip time statistics from recent PINGREQ/PINGRESP exchanges
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RttStats {
+    /// Most recent round-trip time in milliseconds
+    pub last_ms: f64,
+    /// Average round-trip time over the window in milliseconds
+    pub avg_ms: f64,
+    /// Maximum round-trip time over the window in milliseconds
+    pub max_ms: f64,
+    /// Number of samples in the window
+    pub sample_count: usize,
+}
+
+/// How the client processed a received packet, from `MqttClient::recv_with_meta()`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReceiveMeta {
+    /// The client answered the packet itself (PUBACK, PUBREC, PUBREL, PUBCOMP
+    /// or PINGRESP, per `auto_pub_response`/`auto_ping_response`)
+    pub auto_responded: bool,
+}
+
+/// Connection health snapshot from `MqttClient::health()`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthReport {
+    /// Current connection state
+    pub state: ConnectionState,
+    /// Most recent ping round-trip time in milliseconds (None before the first PINGRESP)
+    pub last_rtt_ms: Option<f64>,
+    /// Bytes queued by the transport but not yet sent
+    pub buffered_amount: u64,
+    /// Whether `buffered_amount` grew since the previous `health()` call
+    pub buffered_amount_growing: bool,
+    /// Outgoing QoS 1/2 PUBLISH awaiting their final acknowledgement
+    pub inflight_count: usize,
+    /// Seconds since bytes were last received (None before any were)
+    pub seconds_since_last_recv: Option<f64>,
+    /// Keep alive of the last CONNECT in seconds (0 = disabled)
+    pub keep_alive_secs: u16,
+}
+
+impl HealthReport {
+    /// Connected, the transport is keeping up with outgoing data, and the
+    /// broker was heard from within 1.5 times the keep alive
+    pub fn is_healthy(&self) -> bool {
+        if self.state != ConnectionState::Connected || self.buffered_amount_growing {
+            return false;
+        }
+        match self.seconds_since_last_recv {
+            Some(seconds) if self.keep_alive_secs > 0 => {
+                seconds <= self.keep_alive_secs as f64 * 1.5
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Session state of a client, from `MqttClient::export_session()`
+///
+/// Persist it (it is serde-serializable) and hand it to `import_session()`
+/// before connecting with clean session/start false to continue the session
+/// after a restart, e.g. a page reload.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionSnapshot {
+    /// Protocol version the session belongs to ("3.1.1" or "5.0")
+    pub version: String,
+    /// Client identifier used in CONNECT (a session is tied to it)
+    pub client_id: Option<String>,
+    /// Keep alive of the last CONNECT in seconds
+    pub keep_alive_secs: u16,
+    /// Session-present flag of the last accepted CONNACK
+    pub session_present: Option<bool>,
+    /// Maximum Packet Size of the last v5.0 CONNACK
+    pub server_maximum_packet_size: Option<u32>,
+    /// Outgoing QoS 1/2 PUBLISH flows not yet released, by packet ID
+    pub inflight: Vec<InflightEntry>,
+    /// Subscriptions confirmed by the broker
+    pub subscriptions: Vec<SubscriptionEntry>,
+    /// Packet IDs of inbound QoS 2 PUBLISH packets already delivered whose
+    /// PUBREL has not arrived; a re-sent PUBLISH with one of them is dropped
+    #[serde(default)]
+    pub inbound_qos2: Vec<u16>,
+}
+
+/// An outgoing QoS 1/2 PUBLISH flow in a `SessionSnapshot`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InflightEntry {
+    pub packet_id: u16,
+    pub phase: InflightPhase,
+}
+
+/// Progress of an outgoing QoS 1/2 PUBLISH
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum InflightPhase {
+    /// QoS 1 PUBLISH sent, waiting for PUBACK
+    AwaitingPuback,
+    /// QoS 2 PUBLISH sent, waiting for PUBREC
+    AwaitingPubrec,
+    /// PUBREC received, waiting for PUBCOMP
+    AwaitingPubcomp,
+    /// Acknowledged; the packet ID still has to be released with `complete_qos()`
+    Complete,
+}
+
+/// A confirmed subscription in a `SessionSnapshot`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SubscriptionEntry {
+    pub topic_filter: String,
+    /// Requested QoS
+    pub qos: u8,
+}
+
 // Note: Message type removed - now using mqtt::packet::Packet directly
 // Connection events are handled internally via state management
@@ -4,18 +4,39 @@
 //! Packet constructors accept JSON objects for flexible configuration.
 //! Optional fields can be omitted (null/undefined in JavaScript).
 
-use crate::{mqtt, MqttClient, MqttConfig};
+use crate::{
+    mqtt, ConnectionEvent, ConnectionState, Error, MqttClient, MqttConfig, PacketDirection,
+    PacketTap, PayloadChunkHandler, PayloadCodec, SendInspector, SessionSnapshot, TopicRewriter,
+};
 use mqtt::packet::{GenericPacketTrait, Properties, Property};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
 use wasm_bindgen::prelude::*;
 
-/// Check WASM version for debugging
-#[wasm_bindgen]
-pub fn check_version() -> String {
-    web_sys::console::log_1(
-        &"🔥 MANUAL: NEW WASM VERSION LOADED 2026-01-22 FLEXIBLE-PACKETS 🔥".into(),
-    );
-    "NEW WASM VERSION 2026-01-22 FLEXIBLE-PACKETS".to_string()
+/// Get the build metadata as `{ crateVersion, mqttProtocolCoreVersion,
+/// targetArch, features }`, e.g. to include in bug reports
+#[wasm_bindgen(js_name = buildInfo)]
+pub fn build_info() -> JsValue {
+    serde_wasm_bindgen::to_value(&crate::build_info()).unwrap_or(JsValue::NULL)
+}
+
+/// Check a topic filter (SUBSCRIBE/UNSUBSCRIBE) against the MQTT topic rules
+///
+/// Returns `null` when it is valid, otherwise a message saying what is wrong,
+/// e.g. for `sport/#/stats`.
+#[wasm_bindgen(js_name = validateTopicFilter)]
+pub fn validate_topic_filter(filter: &str) -> Option<String> {
+    crate::topic::validate_filter(filter).err()
+}
+
+/// Check a topic name (PUBLISH) against the MQTT topic rules
+///
+/// Returns `null` when it is valid, otherwise a message saying what is wrong.
+#[wasm_bindgen(js_name = validateTopicName)]
+pub fn validate_topic_name(name: &str) -> Option<String> {
+    crate::topic::validate_name(name).err()
 }
 
 // ============================================================================
@@ -36,6 +57,8 @@ pub struct ConnectOptions {
     pub clean_session: Option<bool>,
     pub user_name: Option<String>,
     pub password: Option<String>,
+    /// Will message (preferred over the flat will* fields below)
+    pub will: Option<WillOptions>,
     pub will_topic: Option<String>,
     pub will_payload: Option<String>,
     pub will_qos: Option<u8>,
@@ -52,8 +75,65 @@ pub struct ConnectOptions {
     pub authentication_data: Option<Vec<u8>>,
 }
 
+impl ConnectOptions {
+    /// Take the will message from `will` or the flat will* fields
+    fn will_options(&mut self) -> Result<Option<WillOptions>, JsValue> {
+        let has_flat = self.will_topic.is_some()
+            || self.will_payload.is_some()
+            || self.will_qos.is_some()
+            || self.will_retain.is_some();
+        let will = match (self.will.take(), has_flat) {
+            (Some(_), true) => {
+                return Err(JsValue::from_str(
+                    "Specify either will or willTopic/willPayload/willQos/willRetain, not both",
+                ))
+            }
+            (Some(will), false) => will,
+            (None, true) if self.will_topic.is_none() => {
+                // Flat will fields without a topic were always ignored
+                web_sys::console::warn_1(
+                    &"willPayload/willQos/willRetain ignored: no willTopic given".into(),
+                );
+                return Ok(None);
+            }
+            (None, true) => WillOptions {
+                topic: self.will_topic.take(),
+                payload: self.will_payload.take(),
+                qos: self.will_qos,
+                retain: self.will_retain,
+                ..Default::default()
+            },
+            (None, false) => return Ok(None),
+        };
+        if will.topic.is_none() {
+            return Err(JsValue::from_str("Will message requires a topic"));
+        }
+        Ok(Some(will))
+    }
+}
+
+/// Will message options (both versions)
+/// - V5.0 will properties are ignored when using V3.1.1 method
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WillOptions {
+    pub topic: Option<String>,
+    pub payload: Option<String>,
+    pub payload_bytes: Option<Vec<u8>>,
+    pub qos: Option<u8>,
+    pub retain: Option<bool>,
+    // V5.0 will properties
+    pub will_delay_interval: Option<u32>,
+    pub payload_format_indicator: Option<u8>,
+    pub message_expiry_interval: Option<u32>,
+    pub content_type: Option<String>,
+    pub response_topic: Option<String>,
+    pub correlation_data: Option<Vec<u8>>,
+    pub user_properties: Option<Vec<UserPropertyEntry>>,
+}
+
 /// User property key-value pair
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct UserPropertyEntry {
     pub key: String,
@@ -81,6 +161,21 @@ pub struct PublishOptions {
     pub user_properties: Option<Vec<UserPropertyEntry>>,
 }
 
+impl PublishOptions {
+    /// Take the payload to send: `payloadBytes`, else `payload`, else zero-length
+    ///
+    /// `payload: ""` and `payloadBytes: []` are valid and yield a zero-length
+    /// payload, e.g. a retained PUBLISH that clears the topic's retained message.
+    /// The deserialized buffer is moved out, not copied.
+    fn take_payload(&mut self) -> Vec<u8> {
+        match (self.payload_bytes.take(), self.payload.take()) {
+            (Some(bytes), _) => bytes,
+            (None, Some(payload)) => payload.into_bytes(),
+            (None, None) => Vec::new(),
+        }
+    }
+}
+
 /// Options for Subscribe packet
 #[derive(Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
@@ -101,6 +196,7 @@ pub struct SubscriptionEntry {
     // V5.0 options
     pub no_local: Option<bool>,
     pub retain_as_published: Option<bool>,
+    /// `WasmRetainHandling` value or the equivalent number (0, 1 or 2)
     pub retain_handling: Option<u8>,
 }
 
@@ -118,9 +214,14 @@ pub struct UnsubscribeOptions {
 #[serde(rename_all = "camelCase")]
 pub struct ConfigOptions {
     /// MQTT version: "3.1.1", "5.0", "v5.0", "V5_0", etc. Default: "3.1.1"
+    /// MQTT 3.1 ("3.1") is rejected; the protocol core does not implement it
     pub version: Option<String>,
+    /// Client identifier for CONNECT. Default: none (set in the CONNECT packet)
+    pub client_id: Option<String>,
     /// Ping request send interval in milliseconds. None = disabled
     pub pingreq_send_interval_ms: Option<u32>,
+    /// Random extra delay in [0, jitter) ms for each ping interval. None = exact interval
+    pub pingreq_jitter_ms: Option<u32>,
     /// Auto respond to QoS PUBLISH/PUBREC/PUBREL. Default: true
     pub auto_pub_response: Option<bool>,
     /// Auto respond to PINGREQ. Default: true
@@ -135,6 +236,40 @@ pub struct ConfigOptions {
     pub connection_establish_timeout_ms: Option<u32>,
     /// Shutdown timeout in milliseconds. 0 = disabled
     pub shutdown_timeout_ms: Option<u32>,
+    /// Maximum number of received packets waiting for recv() (at least 1). Default: 1024
+    pub max_undelivered_packets: Option<u32>,
+    /// Cap on bytes buffered by the client. None = unlimited
+    pub max_total_buffer_bytes: Option<u32>,
+    /// Maximum outgoing PUBLISH packets per second. None = unlimited
+    pub max_publish_rate_per_sec: Option<u32>,
+    /// First auto-reconnect delay in milliseconds. None = auto-reconnect disabled
+    pub reconnect_initial_delay_ms: Option<u32>,
+    /// Maximum auto-reconnect delay in milliseconds. Default: 30000
+    pub reconnect_max_delay_ms: Option<u32>,
+    /// Give up auto-reconnect after this many failed attempts. None = retry forever
+    pub reconnect_max_attempts: Option<u32>,
+    /// Report QoS 1/2 publishes not acknowledged within this many milliseconds. None = disabled
+    pub publish_ack_timeout_ms: Option<u32>,
+    /// Release the packet ID of a timed-out publish. Default: false
+    pub release_packet_id_on_ack_timeout: Option<bool>,
+    /// Keep released packet IDs unused for this many milliseconds. Default: 0
+    pub packet_id_reuse_delay_ms: Option<u32>,
+    /// Re-subscribe after connecting without a session present. Default: false
+    pub restore_subscriptions: Option<bool>,
+    /// Maximum topic filters subscribe() may reach. None = unlimited
+    pub max_subscriptions: Option<u32>,
+    /// Reject recv() for a failing CONNACK. Default: false (the CONNACK is returned)
+    pub error_on_connack_failure: Option<bool>,
+    /// Let recv() wait before connect() is called. Default: false (recv() rejects)
+    pub allow_recv_before_connect: Option<bool>,
+    /// Prefix for this client's log lines, e.g. "telemetry". Default: none
+    pub instance_label: Option<String>,
+    /// Reconnect to the server named by a v5.0 Server Reference (0x9C/0x9D). Default: false
+    pub follow_server_reference: Option<bool>,
+    /// Reject v5.0-only packet options on a v3.1.1 client. Default: false (ignored)
+    pub strict_version: Option<bool>,
+    /// Stream received payloads larger than this to onPayloadChunk(). Default: 65536
+    pub payload_stream_threshold: Option<u32>,
 }
 
 /// Options for Puback/Pubrec/Pubrel/Pubcomp packets
@@ -170,6 +305,243 @@ pub struct AuthOptions {
     pub user_properties: Option<Vec<UserPropertyEntry>>,
 }
 
+/// Options that only MQTT v5.0 packets carry
+///
+/// A V3.1.1 factory ignores them; with `strictVersion` it rejects them instead.
+trait V5OnlyOptions {
+    /// Names of the v5.0-only options that are set, as given in JavaScript
+    fn v5_only_options(&self) -> Vec<String>;
+}
+
+/// Collect the names of the set options among `fields`
+fn set_options(fields: &[(&str, bool)]) -> Vec<String> {
+    fields
+        .iter()
+        .filter(|(_, set)| *set)
+        .map(|(name, _)| name.to_string())
+        .collect()
+}
+
+impl V5OnlyOptions for ConnectOptions {
+    fn v5_only_options(&self) -> Vec<String> {
+        let mut names = set_options(&[
+            (
+                "sessionExpiryInterval",
+                self.session_expiry_interval.is_some(),
+            ),
+            ("receiveMaximum", self.receive_maximum.is_some()),
+            ("maximumPacketSize", self.maximum_packet_size.is_some()),
+            ("topicAliasMaximum", self.topic_alias_maximum.is_some()),
+            (
+                "requestResponseInformation",
+                self.request_response_information.is_some(),
+            ),
+            (
+                "requestProblemInformation",
+                self.request_problem_information.is_some(),
+            ),
+            ("userProperties", self.user_properties.is_some()),
+            ("authenticationMethod", self.authentication_method.is_some()),
+            ("authenticationData", self.authentication_data.is_some()),
+        ]);
+        if let Some(ref will) = self.will {
+            names.extend(set_options(&[
+                ("will.willDelayInterval", will.will_delay_interval.is_some()),
+                (
+                    "will.payloadFormatIndicator",
+                    will.payload_format_indicator.is_some(),
+                ),
+                (
+                    "will.messageExpiryInterval",
+                    will.message_expiry_interval.is_some(),
+                ),
+                ("will.contentType", will.content_type.is_some()),
+                ("will.responseTopic", will.response_topic.is_some()),
+                ("will.correlationData", will.correlation_data.is_some()),
+                ("will.userProperties", will.user_properties.is_some()),
+            ]));
+        }
+        names
+    }
+}
+
+impl V5OnlyOptions for PublishOptions {
+    fn v5_only_options(&self) -> Vec<String> {
+        set_options(&[
+            (
+                "payloadFormatIndicator",
+                self.payload_format_indicator.is_some(),
+            ),
+            (
+                "messageExpiryInterval",
+                self.message_expiry_interval.is_some(),
+            ),
+            ("topicAlias", self.topic_alias.is_some()),
+            ("responseTopic", self.response_topic.is_some()),
+            ("correlationData", self.correlation_data.is_some()),
+            ("contentType", self.content_type.is_some()),
+            ("userProperties", self.user_properties.is_some()),
+        ])
+    }
+}
+
+impl V5OnlyOptions for SubscribeOptions {
+    fn v5_only_options(&self) -> Vec<String> {
+        let mut names = set_options(&[
+            (
+                "subscriptionIdentifier",
+                self.subscription_identifier.is_some(),
+            ),
+            ("userProperties", self.user_properties.is_some()),
+        ]);
+        for (index, entry) in self.subscriptions.iter().enumerate() {
+            for (name, set) in [
+                ("noLocal", entry.no_local.is_some()),
+                ("retainAsPublished", entry.retain_as_published.is_some()),
+                ("retainHandling", entry.retain_handling.is_some()),
+            ] {
+                if set {
+                    names.push(format!("subscriptions[{}].{}", index, name));
+                }
+            }
+        }
+        names
+    }
+}
+
+impl V5OnlyOptions for UnsubscribeOptions {
+    fn v5_only_options(&self) -> Vec<String> {
+        set_options(&[("userProperties", self.user_properties.is_some())])
+    }
+}
+
+impl V5OnlyOptions for PubResponseOptions {
+    fn v5_only_options(&self) -> Vec<String> {
+        set_options(&[
+            ("reasonCode", self.reason_code.is_some()),
+            ("reasonString", self.reason_string.is_some()),
+            ("userProperties", self.user_properties.is_some()),
+        ])
+    }
+}
+
+impl V5OnlyOptions for DisconnectOptions {
+    fn v5_only_options(&self) -> Vec<String> {
+        set_options(&[
+            ("reasonCode", self.reason_code.is_some()),
+            ("reasonString", self.reason_string.is_some()),
+            (
+                "sessionExpiryInterval",
+                self.session_expiry_interval.is_some(),
+            ),
+            ("userProperties", self.user_properties.is_some()),
+        ])
+    }
+}
+
+/// Retain Handling subscription option exposed to JavaScript (v5.0 only)
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WasmRetainHandling {
+    /// Send retained messages at the time of the subscribe
+    SendAtSubscribe = 0,
+    /// Send retained messages only if the subscription does not already exist
+    SendIfNew = 1,
+    /// Do not send retained messages
+    DoNotSend = 2,
+}
+
+impl TryFrom<u8> for WasmRetainHandling {
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(WasmRetainHandling::SendAtSubscribe),
+            1 => Ok(WasmRetainHandling::SendIfNew),
+            2 => Ok(WasmRetainHandling::DoNotSend),
+            other => Err(other),
+        }
+    }
+}
+
+/// QoS level exposed to JavaScript (for `qos` options and received packets)
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WasmQos {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+    ExactlyOnce = 2,
+}
+
+/// MQTT v5.0 reason codes exposed to JavaScript (for `reasonCode` options and received packets)
+///
+/// Codes shared by several packets (e.g. 0 is Success, NormalDisconnection and
+/// GrantedQos0) appear once under their most common name.
+#[wasm_bindgen]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WasmReasonCode {
+    /// Success / normal disconnection / granted QoS 0
+    Success = 0,
+    GrantedQos1 = 1,
+    GrantedQos2 = 2,
+    DisconnectWithWillMessage = 4,
+    NoMatchingSubscribers = 16,
+    NoSubscriptionExisted = 17,
+    ContinueAuthentication = 24,
+    ReAuthenticate = 25,
+    UnspecifiedError = 128,
+    MalformedPacket = 129,
+    ProtocolError = 130,
+    ImplementationSpecificError = 131,
+    UnsupportedProtocolVersion = 132,
+    ClientIdentifierNotValid = 133,
+    BadUserNameOrPassword = 134,
+    NotAuthorized = 135,
+    ServerUnavailable = 136,
+    ServerBusy = 137,
+    Banned = 138,
+    ServerShuttingDown = 139,
+    BadAuthenticationMethod = 140,
+    KeepAliveTimeout = 141,
+    SessionTakenOver = 142,
+    TopicFilterInvalid = 143,
+    TopicNameInvalid = 144,
+    PacketIdentifierInUse = 145,
+    PacketIdentifierNotFound = 146,
+    ReceiveMaximumExceeded = 147,
+    TopicAliasInvalid = 148,
+    PacketTooLarge = 149,
+    MessageRateTooHigh = 150,
+    QuotaExceeded = 151,
+    AdministrativeAction = 152,
+    PayloadFormatInvalid = 153,
+    RetainNotSupported = 154,
+    QosNotSupported = 155,
+    UseAnotherServer = 156,
+    ServerMoved = 157,
+    SharedSubscriptionsNotSupported = 158,
+    ConnectionRateExceeded = 159,
+    MaximumConnectTime = 160,
+    SubscriptionIdentifiersNotSupported = 161,
+    WildcardSubscriptionsNotSupported = 162,
+}
+
+/// Convert the retainHandling of `subscriptions[index]`
+fn parse_retain_handling(index: usize, value: u8) -> Result<mqtt::packet::RetainHandling, JsValue> {
+    let rh = WasmRetainHandling::try_from(value).map_err(|v| {
+        JsValue::from_str(&format!(
+            "Invalid retainHandling {} in subscriptions[{}]: expected 0 (SendAtSubscribe), 1 (SendIfNew) or 2 (DoNotSend)",
+            v, index
+        ))
+    })?;
+    mqtt::packet::RetainHandling::try_from(rh as u8).map_err(|e| {
+        JsValue::from_str(&format!(
+            "Invalid retainHandling in subscriptions[{}]: {:?}",
+            index, e
+        ))
+    })
+}
+
 // ============================================================================
 // Helper functions for building Properties
 // ============================================================================
@@ -188,6 +560,65 @@ fn build_user_properties(
     Ok(())
 }
 
+/// Take topic and payload out of validated will options, along with QoS and retain
+fn will_message_parts(
+    will: &mut WillOptions,
+) -> Result<(String, Vec<u8>, mqtt::packet::Qos, bool), JsValue> {
+    let topic = will
+        .topic
+        .take()
+        .ok_or_else(|| JsValue::from_str("Will message requires a topic"))?;
+    let payload = match (will.payload_bytes.take(), will.payload.take()) {
+        (Some(payload_bytes), _) => payload_bytes,
+        (None, payload) => payload.unwrap_or_default().into_bytes(),
+    };
+    let qos = mqtt::packet::Qos::try_from(will.qos.unwrap_or(0))
+        .map_err(|e| JsValue::from_str(&format!("Invalid will QoS: {:?}", e)))?;
+    Ok((topic, payload, qos, will.retain.unwrap_or(false)))
+}
+
+/// Build V5.0 will properties
+fn build_will_properties(will: &WillOptions) -> Result<Vec<Property>, JsValue> {
+    let mut props: Vec<Property> = Vec::new();
+
+    if let Some(delay) = will.will_delay_interval {
+        let prop = mqtt::packet::WillDelayInterval::new(delay)
+            .map_err(|e| JsValue::from_str(&format!("Invalid will delay interval: {:?}", e)))?;
+        props.push(Property::WillDelayInterval(prop));
+    }
+    if let Some(indicator) = will.payload_format_indicator {
+        let format = mqtt::packet::PayloadFormat::try_from(indicator)
+            .map_err(|e| JsValue::from_str(&format!("Invalid payload format: {:?}", e)))?;
+        let prop = mqtt::packet::PayloadFormatIndicator::new(format).map_err(|e| {
+            JsValue::from_str(&format!("Invalid payload format indicator: {:?}", e))
+        })?;
+        props.push(Property::PayloadFormatIndicator(prop));
+    }
+    if let Some(interval) = will.message_expiry_interval {
+        let prop = mqtt::packet::MessageExpiryInterval::new(interval)
+            .map_err(|e| JsValue::from_str(&format!("Invalid message expiry interval: {:?}", e)))?;
+        props.push(Property::MessageExpiryInterval(prop));
+    }
+    if let Some(ref content_type) = will.content_type {
+        let prop = mqtt::packet::ContentType::new(content_type)
+            .map_err(|e| JsValue::from_str(&format!("Invalid content type: {:?}", e)))?;
+        props.push(Property::ContentType(prop));
+    }
+    if let Some(ref topic) = will.response_topic {
+        let prop = mqtt::packet::ResponseTopic::new(topic)
+            .map_err(|e| JsValue::from_str(&format!("Invalid response topic: {:?}", e)))?;
+        props.push(Property::ResponseTopic(prop));
+    }
+    if let Some(ref data) = will.correlation_data {
+        let prop = mqtt::packet::CorrelationData::new(data.clone())
+            .map_err(|e| JsValue::from_str(&format!("Invalid correlation data: {:?}", e)))?;
+        props.push(Property::CorrelationData(prop));
+    }
+    build_user_properties(&mut props, &will.user_properties)?;
+
+    Ok(props)
+}
+
 // ============================================================================
 // Packet Type Wrapper (for JavaScript)
 // ============================================================================
@@ -235,6 +666,55 @@ impl From<mqtt::packet::PacketType> for WasmPacketType {
     }
 }
 
+impl From<WasmPacketType> for mqtt::packet::PacketType {
+    fn from(pt: WasmPacketType) -> Self {
+        match pt {
+            WasmPacketType::Connect => mqtt::packet::PacketType::Connect,
+            WasmPacketType::Connack => mqtt::packet::PacketType::Connack,
+            WasmPacketType::Publish => mqtt::packet::PacketType::Publish,
+            WasmPacketType::Puback => mqtt::packet::PacketType::Puback,
+            WasmPacketType::Pubrec => mqtt::packet::PacketType::Pubrec,
+            WasmPacketType::Pubrel => mqtt::packet::PacketType::Pubrel,
+            WasmPacketType::Pubcomp => mqtt::packet::PacketType::Pubcomp,
+            WasmPacketType::Subscribe => mqtt::packet::PacketType::Subscribe,
+            WasmPacketType::Suback => mqtt::packet::PacketType::Suback,
+            WasmPacketType::Unsubscribe => mqtt::packet::PacketType::Unsubscribe,
+            WasmPacketType::Unsuback => mqtt::packet::PacketType::Unsuback,
+            WasmPacketType::Pingreq => mqtt::packet::PacketType::Pingreq,
+            WasmPacketType::Pingresp => mqtt::packet::PacketType::Pingresp,
+            WasmPacketType::Disconnect => mqtt::packet::PacketType::Disconnect,
+            WasmPacketType::Auth => mqtt::packet::PacketType::Auth,
+        }
+    }
+}
+
+/// Numeric `WasmPacketType` value as seen from JavaScript
+impl TryFrom<u32> for WasmPacketType {
+    type Error = u32;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        let packet_type = match value {
+            0 => WasmPacketType::Connect,
+            1 => WasmPacketType::Connack,
+            2 => WasmPacketType::Publish,
+            3 => WasmPacketType::Puback,
+            4 => WasmPacketType::Pubrec,
+            5 => WasmPacketType::Pubrel,
+            6 => WasmPacketType::Pubcomp,
+            7 => WasmPacketType::Subscribe,
+            8 => WasmPacketType::Suback,
+            9 => WasmPacketType::Unsubscribe,
+            10 => WasmPacketType::Unsuback,
+            11 => WasmPacketType::Pingreq,
+            12 => WasmPacketType::Pingresp,
+            13 => WasmPacketType::Disconnect,
+            14 => WasmPacketType::Auth,
+            _ => return Err(value),
+        };
+        Ok(packet_type)
+    }
+}
+
 // ============================================================================
 // PropertiesExt Trait for MQTT v5.0 Properties
 // ============================================================================
@@ -881,6 +1361,14 @@ impl WasmPublishPacketV5_0 {
     pub fn topic_name_extracted(&self) -> bool {
         self.inner.topic_name_extracted()
     }
+
+    /// Returns the Topic Alias the broker put in this PUBLISH, if any.
+    /// `topicName` is already resolved from the alias mapping; this is the raw
+    /// alias number, e.g. to check which alias resolved to which topic.
+    #[wasm_bindgen(js_name = topicAliasUsed)]
+    pub fn topic_alias_used(&self) -> Option<u16> {
+        self.inner.props.topic_alias()
+    }
 }
 
 /// WASM wrapper for V5.0 CONNACK packet
@@ -996,6 +1484,57 @@ impl WasmConnackPacketV5_0 {
         self.inner.props.authentication_data().map(|s| s.to_vec())
     }
 
+    /// Returns the server capabilities as one object, with the MQTT v5.0
+    /// defaults applied to absent properties:
+    /// `{ maximumQos, retainAvailable, wildcardSubscriptionAvailable,
+    /// subscriptionIdentifiersAvailable, sharedSubscriptionAvailable,
+    /// receiveMaximum, topicAliasMaximum, maximumPacketSize, serverKeepAlive }`.
+    /// `maximumPacketSize` and `serverKeepAlive` are `null` when the server sets no value.
+    pub fn capabilities(&self) -> JsValue {
+        let props = &self.inner.props;
+        let obj = js_sys::Object::new();
+        let set = |key: &str, value: JsValue| {
+            js_sys::Reflect::set(&obj, &key.into(), &value).unwrap();
+        };
+        set(
+            "maximumQos",
+            props.maximum_qos().map_or(2, |q| q as u8).into(),
+        );
+        set(
+            "retainAvailable",
+            props.retain_available().unwrap_or(true).into(),
+        );
+        set(
+            "wildcardSubscriptionAvailable",
+            props
+                .wildcard_subscription_available()
+                .unwrap_or(true)
+                .into(),
+        );
+        set(
+            "subscriptionIdentifiersAvailable",
+            props
+                .subscription_identifier_available()
+                .unwrap_or(true)
+                .into(),
+        );
+        set(
+            "sharedSubscriptionAvailable",
+            props.shared_subscription_available().unwrap_or(true).into(),
+        );
+        set(
+            "receiveMaximum",
+            props.receive_maximum().unwrap_or(65535).into(),
+        );
+        set(
+            "topicAliasMaximum",
+            props.topic_alias_maximum().unwrap_or(0).into(),
+        );
+        set("maximumPacketSize", props.maximum_packet_size().into());
+        set("serverKeepAlive", props.server_keep_alive().into());
+        obj.into()
+    }
+
     /// Returns the user properties from the CONNACK packet.
     /// Returns an array of {key, value} objects.
     #[wasm_bindgen(js_name = userProperties)]
@@ -1361,40 +1900,73 @@ impl WasmMqttPacket {
         format!("{:?}", self.inner.packet_type())
     }
 
+    /// Get the MQTT protocol version of this packet ("3.1.1" or "5.0")
+    ///
+    /// Determined by the packet itself, independent of any client's configured version.
+    #[wasm_bindgen(js_name = protocolVersion)]
+    pub fn protocol_version(&self) -> String {
+        let v3_1_1 = matches!(
+            self.inner,
+            mqtt::packet::Packet::V3_1_1Connect(_)
+                | mqtt::packet::Packet::V3_1_1Connack(_)
+                | mqtt::packet::Packet::V3_1_1Publish(_)
+                | mqtt::packet::Packet::V3_1_1Puback(_)
+                | mqtt::packet::Packet::V3_1_1Pubrec(_)
+                | mqtt::packet::Packet::V3_1_1Pubrel(_)
+                | mqtt::packet::Packet::V3_1_1Pubcomp(_)
+                | mqtt::packet::Packet::V3_1_1Subscribe(_)
+                | mqtt::packet::Packet::V3_1_1Suback(_)
+                | mqtt::packet::Packet::V3_1_1Unsubscribe(_)
+                | mqtt::packet::Packet::V3_1_1Unsuback(_)
+                | mqtt::packet::Packet::V3_1_1Pingreq(_)
+                | mqtt::packet::Packet::V3_1_1Pingresp(_)
+                | mqtt::packet::Packet::V3_1_1Disconnect(_)
+        );
+        if v3_1_1 { "3.1.1" } else { "5.0" }.to_string()
+    }
+
     /// Serialize packet to bytes
     #[wasm_bindgen(js_name = toBytes)]
     pub fn to_bytes(&self) -> Vec<u8> {
         self.inner.to_continuous_buffer()
     }
 
-    /// Parse packet from bytes
-    /// Note: This creates a temporary MQTT connection to use its parser
-    #[wasm_bindgen(js_name = fromBytes)]
-    pub fn from_bytes(data: &[u8], version: &str) -> Result<WasmMqttPacket, JsValue> {
-        use mqtt_protocol_core::mqtt as mqtt_core;
-
-        let mqtt_version = match version {
-            "5.0" | "v5.0" | "V5_0" => mqtt_core::Version::V5_0,
-            _ => mqtt_core::Version::V3_1_1,
-        };
-
-        // Create a temporary connection for parsing
-        let mut connection = mqtt_core::Connection::<mqtt_core::role::Client>::new(mqtt_version);
-        let mut cursor = mqtt_core::common::Cursor::new(data);
+    /// Check whether two packets have identical wire bytes
+    #[wasm_bindgen]
+    pub fn equals(&self, other: &WasmMqttPacket) -> bool {
+        self.inner.to_continuous_buffer() == other.inner.to_continuous_buffer()
+    }
 
-        // Use the connection's recv method to parse
-        let events = connection.recv(&mut cursor);
+    /// Get the wire bytes as a lowercase hex string (e.g. for snapshot tests)
+    #[wasm_bindgen(js_name = toHex)]
+    pub fn to_hex(&self) -> String {
+        self.inner
+            .to_continuous_buffer()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
 
-        // Find the received packet in events
-        for event in events {
-            if let mqtt_core::connection::Event::NotifyPacketReceived(packet) = event {
-                return Ok(WasmMqttPacket { inner: packet });
-            }
-        }
+    /// Get the packet as a plain object that `JSON.stringify` handles as is
+    ///
+    /// `{ type, protocolVersion, ...fields }` with the same field names as the
+    /// `asXxx()` accessors. Binary fields (`payload`, `correlationData`,
+    /// `authenticationData`) are base64 strings; absent fields are omitted.
+    #[wasm_bindgen(js_name = toJsonObject)]
+    pub fn to_json_object(&self) -> JsValue {
+        serde_wasm_bindgen::to_value(&JsonPacket::from(self)).unwrap_or(JsValue::NULL)
+    }
 
-        Err(JsValue::from_str(
-            "Failed to parse packet: no valid packet found",
-        ))
+    /// Parse packet from bytes
+    ///
+    /// Decodes the first packet in `data` as sent by either side, independent
+    /// of any connection state (e.g. a PUBACK needs no matching PUBLISH).
+    #[wasm_bindgen(js_name = fromBytes)]
+    pub fn from_bytes(data: &[u8], version: &str) -> Result<WasmMqttPacket, JsValue> {
+        let version = parse_version(Some(version))?;
+        let (inner, _) = crate::packet_parse::parse_packet(data, version)
+            .map_err(|e| JsValue::from_str(&format!("Failed to parse packet: {}", e)))?;
+        Ok(WasmMqttPacket { inner })
     }
 
     // ------------------------------------------------------------------------
@@ -1404,7 +1976,7 @@ impl WasmMqttPacket {
     /// Create V3.1.1 Connect packet from JSON options
     #[wasm_bindgen(js_name = newConnectV311)]
     pub fn new_connect_v311(options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        let opts: ConnectOptions = serde_wasm_bindgen::from_value(options)
+        let mut opts: ConnectOptions = serde_wasm_bindgen::from_value(options)
             .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
 
         let mut builder = mqtt::packet::v3_1_1::Connect::builder()
@@ -1429,19 +2001,10 @@ impl WasmMqttPacket {
         }
 
         // Will message
-        if let Some(ref will_topic) = opts.will_topic {
-            let will_payload = opts.will_payload.as_deref().unwrap_or("");
-            let will_qos = mqtt::packet::Qos::try_from(opts.will_qos.unwrap_or(0))
-                .map_err(|e| JsValue::from_str(&format!("Invalid will QoS: {:?}", e)))?;
-            let will_retain = opts.will_retain.unwrap_or(false);
-
+        if let Some(mut will) = opts.will_options()? {
+            let (will_topic, will_payload, will_qos, will_retain) = will_message_parts(&mut will)?;
             builder = builder
-                .will_message(
-                    will_topic,
-                    will_payload.as_bytes().to_vec(),
-                    will_qos,
-                    will_retain,
-                )
+                .will_message(&will_topic, will_payload, will_qos, will_retain)
                 .map_err(|e| JsValue::from_str(&format!("Invalid will message: {:?}", e)))?;
         }
 
@@ -1454,10 +2017,30 @@ impl WasmMqttPacket {
         })
     }
 
+    /// Create V3.1.1 PUBLISH that clears the retained message on `topic`
+    ///
+    /// A QoS 0 PUBLISH with retain set and a zero-length payload: the broker
+    /// deletes the retained message and does not store a new one.
+    #[wasm_bindgen(js_name = newRetainedClearV311)]
+    pub fn new_retained_clear_v311(topic: &str) -> Result<WasmMqttPacket, JsValue> {
+        let packet = mqtt::packet::v3_1_1::Publish::builder()
+            .topic_name(topic)
+            .map_err(|e| JsValue::from_str(&format!("Invalid topic: {:?}", e)))?
+            .qos(mqtt::packet::Qos::AtMostOnce)
+            .retain(true)
+            .payload(Vec::new())
+            .build()
+            .map_err(|e| JsValue::from_str(&format!("Failed to build PUBLISH: {:?}", e)))?;
+
+        Ok(WasmMqttPacket {
+            inner: mqtt::packet::Packet::V3_1_1Publish(packet),
+        })
+    }
+
     /// Create V3.1.1 Publish packet from JSON options
     #[wasm_bindgen(js_name = newPublishV311)]
     pub fn new_publish_v311(options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        let opts: PublishOptions = serde_wasm_bindgen::from_value(options)
+        let mut opts: PublishOptions = serde_wasm_bindgen::from_value(options)
             .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
 
         let qos = mqtt::packet::Qos::try_from(opts.qos.unwrap_or(0))
@@ -1468,12 +2051,8 @@ impl WasmMqttPacket {
             .map_err(|e| JsValue::from_str(&format!("Invalid topic: {:?}", e)))?
             .qos(qos);
 
-        // Payload (string or bytes)
-        if let Some(ref payload_bytes) = opts.payload_bytes {
-            builder = builder.payload(payload_bytes.clone());
-        } else if let Some(ref payload) = opts.payload {
-            builder = builder.payload(payload.as_bytes().to_vec());
-        }
+        // Payload (string or bytes); always set so an empty payload is explicit
+        builder = builder.payload(opts.take_payload());
 
         if let Some(retain) = opts.retain {
             builder = builder.retain(retain);
@@ -1499,7 +2078,10 @@ impl WasmMqttPacket {
     pub fn new_subscribe_v311(options: JsValue) -> Result<WasmMqttPacket, JsValue> {
         let opts: SubscribeOptions = serde_wasm_bindgen::from_value(options)
             .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
+        Self::subscribe_v311_from_options(opts)
+    }
 
+    fn subscribe_v311_from_options(opts: SubscribeOptions) -> Result<WasmMqttPacket, JsValue> {
         let entries: Result<Vec<_>, _> = opts
             .subscriptions
             .iter()
@@ -1615,11 +2197,22 @@ impl WasmMqttPacket {
     // ------------------------------------------------------------------------
 
     /// Create V5.0 Connect packet from JSON options
+    ///
+    /// `cleanStart: false` must come with an explicit `sessionExpiryInterval`:
+    /// without one the broker applies 0 and ends the resumed session as soon as
+    /// this connection closes. Pass 0 explicitly if that is what you want.
     #[wasm_bindgen(js_name = newConnectV50)]
     pub fn new_connect_v50(options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        let opts: ConnectOptions = serde_wasm_bindgen::from_value(options)
+        let mut opts: ConnectOptions = serde_wasm_bindgen::from_value(options)
             .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
 
+        if opts.clean_session == Some(false) && opts.session_expiry_interval.is_none() {
+            return Err(JsValue::from_str(
+                "cleanStart false requires sessionExpiryInterval: without it the session \
+                 expires on disconnect (pass 0 explicitly for that)",
+            ));
+        }
+
         let mut builder = mqtt::packet::v5_0::Connect::builder()
             .client_id(&opts.client_id)
             .map_err(|e| JsValue::from_str(&format!("Invalid client ID: {:?}", e)))?;
@@ -1643,20 +2236,16 @@ impl WasmMqttPacket {
         }
 
         // Will message
-        if let Some(ref will_topic) = opts.will_topic {
-            let will_payload = opts.will_payload.as_deref().unwrap_or("");
-            let will_qos = mqtt::packet::Qos::try_from(opts.will_qos.unwrap_or(0))
-                .map_err(|e| JsValue::from_str(&format!("Invalid will QoS: {:?}", e)))?;
-            let will_retain = opts.will_retain.unwrap_or(false);
-
+        if let Some(mut will) = opts.will_options()? {
+            let (will_topic, will_payload, will_qos, will_retain) = will_message_parts(&mut will)?;
             builder = builder
-                .will_message(
-                    will_topic,
-                    will_payload.as_bytes().to_vec(),
-                    will_qos,
-                    will_retain,
-                )
+                .will_message(&will_topic, will_payload, will_qos, will_retain)
                 .map_err(|e| JsValue::from_str(&format!("Invalid will message: {:?}", e)))?;
+
+            let will_props = build_will_properties(&will)?;
+            if !will_props.is_empty() {
+                builder = builder.will_props(Properties::from(will_props));
+            }
         }
 
         // V5.0 Properties
@@ -1721,10 +2310,30 @@ impl WasmMqttPacket {
         })
     }
 
+    /// Create V5.0 PUBLISH that clears the retained message on `topic`
+    ///
+    /// A QoS 0 PUBLISH with retain set and a zero-length payload: the broker
+    /// deletes the retained message and does not store a new one.
+    #[wasm_bindgen(js_name = newRetainedClearV50)]
+    pub fn new_retained_clear_v50(topic: &str) -> Result<WasmMqttPacket, JsValue> {
+        let packet = mqtt::packet::v5_0::Publish::builder()
+            .topic_name(topic)
+            .map_err(|e| JsValue::from_str(&format!("Invalid topic: {:?}", e)))?
+            .qos(mqtt::packet::Qos::AtMostOnce)
+            .retain(true)
+            .payload(Vec::new())
+            .build()
+            .map_err(|e| JsValue::from_str(&format!("Failed to build PUBLISH: {:?}", e)))?;
+
+        Ok(WasmMqttPacket {
+            inner: mqtt::packet::Packet::V5_0Publish(packet),
+        })
+    }
+
     /// Create V5.0 Publish packet from JSON options
     #[wasm_bindgen(js_name = newPublishV50)]
     pub fn new_publish_v50(options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        let opts: PublishOptions = serde_wasm_bindgen::from_value(options)
+        let mut opts: PublishOptions = serde_wasm_bindgen::from_value(options)
             .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
 
         let qos = mqtt::packet::Qos::try_from(opts.qos.unwrap_or(0))
@@ -1735,12 +2344,8 @@ impl WasmMqttPacket {
             .map_err(|e| JsValue::from_str(&format!("Invalid topic: {:?}", e)))?
             .qos(qos);
 
-        // Payload
-        if let Some(ref payload_bytes) = opts.payload_bytes {
-            builder = builder.payload(payload_bytes.clone());
-        } else if let Some(ref payload) = opts.payload {
-            builder = builder.payload(payload.as_bytes().to_vec());
-        }
+        // Payload (string or bytes); always set so an empty payload is explicit
+        builder = builder.payload(opts.take_payload());
 
         if let Some(retain) = opts.retain {
             builder = builder.retain(retain);
@@ -1779,8 +2384,8 @@ impl WasmMqttPacket {
                 .map_err(|e| JsValue::from_str(&format!("Invalid response topic: {:?}", e)))?;
             props_vec.push(Property::ResponseTopic(prop));
         }
-        if let Some(ref data) = opts.correlation_data {
-            let prop = mqtt::packet::CorrelationData::new(data.clone())
+        if let Some(data) = opts.correlation_data.take() {
+            let prop = mqtt::packet::CorrelationData::new(data)
                 .map_err(|e| JsValue::from_str(&format!("Invalid correlation data: {:?}", e)))?;
             props_vec.push(Property::CorrelationData(prop));
         }
@@ -1805,20 +2410,34 @@ impl WasmMqttPacket {
     }
 
     /// Create V5.0 Subscribe packet from JSON options
+    ///
+    /// Fails if `noLocal` is true on a shared subscription (`$share/...`),
+    /// which MQTT v5.0 forbids.
     #[wasm_bindgen(js_name = newSubscribeV50)]
     pub fn new_subscribe_v50(options: JsValue) -> Result<WasmMqttPacket, JsValue> {
         let opts: SubscribeOptions = serde_wasm_bindgen::from_value(options)
             .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
+        Self::subscribe_v50_from_options(opts)
+    }
 
+    fn subscribe_v50_from_options(opts: SubscribeOptions) -> Result<WasmMqttPacket, JsValue> {
         let entries: Result<Vec<_>, _> = opts
             .subscriptions
             .iter()
-            .map(|sub| {
+            .enumerate()
+            .map(|(index, sub)| {
                 let qos = mqtt::packet::Qos::try_from(sub.qos.unwrap_or(0))
                     .map_err(|e| JsValue::from_str(&format!("Invalid QoS: {:?}", e)))?;
 
                 let mut sub_opts = mqtt::packet::SubOpts::new().set_qos(qos);
 
+                // The broker would answer with a protocol error and disconnect
+                if sub.no_local == Some(true) && sub.topic.starts_with("$share/") {
+                    return Err(JsValue::from_str(&format!(
+                        "noLocal must not be set on shared subscription '{}' in subscriptions[{}]",
+                        sub.topic, index
+                    )));
+                }
                 if let Some(no_local) = sub.no_local {
                     sub_opts = sub_opts.set_nl(no_local);
                 }
@@ -1826,11 +2445,7 @@ impl WasmMqttPacket {
                     sub_opts = sub_opts.set_rap(rap);
                 }
                 if let Some(rh) = sub.retain_handling {
-                    let retain_handling =
-                        mqtt::packet::RetainHandling::try_from(rh).map_err(|e| {
-                            JsValue::from_str(&format!("Invalid retain handling: {:?}", e))
-                        })?;
-                    sub_opts = sub_opts.set_rh(retain_handling);
+                    sub_opts = sub_opts.set_rh(parse_retain_handling(index, rh)?);
                 }
 
                 mqtt::packet::SubEntry::new(&sub.topic, sub_opts)
@@ -2140,134 +2755,1695 @@ impl WasmMqttPacket {
 }
 
 // ============================================================================
-// WASM Config and Client
+// JSON-Friendly Packet Objects
 // ============================================================================
 
-/// WASM-friendly wrapper around MqttConfig
-#[wasm_bindgen]
-#[derive(Clone)]
-pub struct WasmMqttConfig {
-    inner: MqttConfig,
+/// Standard base64 (RFC 4648 alphabet, with padding)
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16
+            | (*chunk.get(1).unwrap_or(&0) as u32) << 8
+            | *chunk.get(2).unwrap_or(&0) as u32;
+        encoded.push(ALPHABET[(n >> 18) as usize & 63] as char);
+        encoded.push(ALPHABET[(n >> 12) as usize & 63] as char);
+        if chunk.len() > 1 {
+            encoded.push(ALPHABET[(n >> 6) as usize & 63] as char);
+        } else {
+            encoded.push('=');
+        }
+        if chunk.len() > 2 {
+            encoded.push(ALPHABET[n as usize & 63] as char);
+        } else {
+            encoded.push('=');
+        }
+    }
+    encoded
 }
 
-#[wasm_bindgen]
-impl WasmMqttConfig {
-    /// Create a new MQTT configuration from a JSON object.
-    ///
-    /// # Example (JavaScript)
-    /// ```js
-    /// const config = new WasmMqttConfig({
-    ///     version: '5.0',
-    ///     autoPubResponse: true,
-    ///     autoPingResponse: true,
-    ///     pingreqSendIntervalMs: 30000,
-    /// });
-    /// ```
-    #[wasm_bindgen(constructor)]
-    pub fn new(options: JsValue) -> Result<WasmMqttConfig, JsValue> {
-        let opts: ConfigOptions = if options.is_undefined() || options.is_null() {
-            ConfigOptions::default()
-        } else {
-            serde_wasm_bindgen::from_value(options)
-                .map_err(|e| JsValue::from_str(&format!("Invalid config options: {}", e)))?
-        };
+/// User property in `toJsonObject()` output
+#[derive(Serialize)]
+struct JsonUserProperty {
+    key: String,
+    value: String,
+}
 
-        let version = match opts.version.as_deref() {
-            Some("V5_0") | Some("v5.0") | Some("5.0") | Some("5") => mqtt::Version::V5_0,
-            _ => mqtt::Version::V3_1_1, // default
-        };
+/// JSON-serializable view of a packet, converted with `serde_wasm_bindgen`
+#[derive(Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct JsonPacket {
+    #[serde(rename = "type")]
+    packet_type: String,
+    protocol_version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    packet_id: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    topic_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    qos: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retain: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dup: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_present: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    return_code: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason_code: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    return_codes: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason_codes: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_format_indicator: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message_expiry_interval: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    topic_alias: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_topic: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    correlation_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    subscription_identifiers: Vec<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    session_expiry_interval: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    receive_maximum: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    maximum_qos: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retain_available: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    maximum_packet_size: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    assigned_client_identifier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    topic_alias_maximum: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wildcard_subscription_available: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subscription_identifiers_available: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shared_subscription_available: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server_keep_alive: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_information: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    server_reference: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authentication_method: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authentication_data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason_string: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    user_properties: Vec<JsonUserProperty>,
+}
 
-        let config = MqttConfig {
-            url: String::new(), // URL is set via connect()
-            version,
-            pingreq_send_interval_ms: opts.pingreq_send_interval_ms.map(|v| v as u64),
-            auto_pub_response: opts.auto_pub_response.unwrap_or(true),
-            auto_ping_response: opts.auto_ping_response.unwrap_or(true),
-            auto_map_topic_alias_send: opts.auto_map_topic_alias_send.unwrap_or(false),
-            auto_replace_topic_alias_send: opts.auto_replace_topic_alias_send.unwrap_or(false),
-            pingresp_recv_timeout_ms: opts.pingresp_recv_timeout_ms.map(|v| v as u64).unwrap_or(0),
-            connection_establish_timeout_ms: opts
-                .connection_establish_timeout_ms
-                .map(|v| v as u64)
-                .unwrap_or(0),
-            shutdown_timeout_ms: opts.shutdown_timeout_ms.map(|v| v as u64).unwrap_or(0),
-        };
+impl JsonPacket {
+    /// Reason string and user properties, present on every v5.0 packet with properties
+    fn common_props(&mut self, props: &impl PropertiesExt) {
+        self.reason_string = props.reason_string();
+        self.user_properties = props
+            .user_properties()
+            .into_iter()
+            .map(|(key, value)| JsonUserProperty { key, value })
+            .collect();
+    }
 
-        Ok(WasmMqttConfig { inner: config })
+    /// PUBACK/PUBREC/PUBREL/PUBCOMP (v5.0)
+    fn pub_response(
+        &mut self,
+        packet_id: u16,
+        reason_code: Option<u8>,
+        props: &impl PropertiesExt,
+    ) {
+        self.packet_id = Some(packet_id);
+        self.reason_code = Some(reason_code.unwrap_or(0));
+        self.common_props(props);
     }
 }
 
-/// Non-wasm_bindgen accessor methods for WasmMqttConfig
-impl WasmMqttConfig {
-    /// Get the inner config (for internal use)
-    pub fn into_inner(self) -> MqttConfig {
-        self.inner
+impl From<&WasmMqttPacket> for JsonPacket {
+    fn from(packet: &WasmMqttPacket) -> Self {
+        use mqtt::packet::Packet;
+
+        let mut json = JsonPacket {
+            packet_type: packet.packet_type_string(),
+            protocol_version: packet.protocol_version(),
+            ..Default::default()
+        };
+        match &packet.inner {
+            Packet::V3_1_1Publish(p) => {
+                json.packet_id = p.packet_id();
+                json.topic_name = Some(p.topic_name().to_string());
+                json.qos = Some(p.qos() as u8);
+                json.retain = Some(p.retain());
+                json.dup = Some(p.dup());
+                json.payload = Some(base64_encode(p.payload().as_slice()));
+            }
+            Packet::V5_0Publish(p) => {
+                json.packet_id = p.packet_id();
+                json.topic_name = Some(p.topic_name().to_string());
+                json.qos = Some(p.qos() as u8);
+                json.retain = Some(p.retain());
+                json.dup = Some(p.dup());
+                json.payload = Some(base64_encode(p.payload().as_slice()));
+                json.payload_format_indicator = p.props.payload_format_indicator();
+                json.message_expiry_interval = p.props.message_expiry_interval();
+                json.topic_alias = p.props.topic_alias();
+                json.response_topic = p.props.response_topic();
+                json.correlation_data = p.props.correlation_data().map(|d| base64_encode(&d));
+                json.content_type = p.props.content_type();
+                json.subscription_identifiers = p.props.subscription_identifiers();
+                json.common_props(&p.props);
+            }
+            Packet::V3_1_1Connack(p) => {
+                json.session_present = Some(p.session_present());
+                json.return_code = Some(p.return_code() as u8);
+            }
+            Packet::V5_0Connack(p) => {
+                json.session_present = Some(p.session_present());
+                json.reason_code = Some(p.reason_code() as u8);
+                json.session_expiry_interval = p.props.session_expiry_interval();
+                json.receive_maximum = p.props.receive_maximum();
+                json.maximum_qos = p.props.maximum_qos();
+                json.retain_available = p.props.retain_available();
+                json.maximum_packet_size = p.props.maximum_packet_size();
+                json.assigned_client_identifier = p.props.assigned_client_identifier();
+                json.topic_alias_maximum = p.props.topic_alias_maximum();
+                json.wildcard_subscription_available = p.props.wildcard_subscription_available();
+                json.subscription_identifiers_available =
+                    p.props.subscription_identifier_available();
+                json.shared_subscription_available = p.props.shared_subscription_available();
+                json.server_keep_alive = p.props.server_keep_alive();
+                json.response_information = p.props.response_information();
+                json.server_reference = p.props.server_reference();
+                json.authentication_method = p.props.authentication_method();
+                json.authentication_data = p.props.authentication_data().map(|d| base64_encode(&d));
+                json.common_props(&p.props);
+            }
+            Packet::V3_1_1Suback(p) => {
+                json.packet_id = Some(p.packet_id());
+                json.return_codes = Some(p.return_codes().iter().map(|c| *c as u8).collect());
+            }
+            Packet::V5_0Suback(p) => {
+                json.packet_id = Some(p.packet_id());
+                json.reason_codes = Some(p.reason_codes().iter().map(|c| *c as u8).collect());
+                json.common_props(&p.props);
+            }
+            Packet::V3_1_1Unsuback(p) => json.packet_id = Some(p.packet_id()),
+            Packet::V5_0Unsuback(p) => {
+                json.packet_id = Some(p.packet_id());
+                json.reason_codes = Some(p.reason_codes().iter().map(|c| *c as u8).collect());
+                json.common_props(&p.props);
+            }
+            Packet::V3_1_1Puback(p) => json.packet_id = Some(p.packet_id()),
+            Packet::V3_1_1Pubrec(p) => json.packet_id = Some(p.packet_id()),
+            Packet::V3_1_1Pubrel(p) => json.packet_id = Some(p.packet_id()),
+            Packet::V3_1_1Pubcomp(p) => json.packet_id = Some(p.packet_id()),
+            Packet::V5_0Puback(p) => {
+                json.pub_response(p.packet_id(), p.reason_code().map(|c| c as u8), &p.props)
+            }
+            Packet::V5_0Pubrec(p) => {
+                json.pub_response(p.packet_id(), p.reason_code().map(|c| c as u8), &p.props)
+            }
+            Packet::V5_0Pubrel(p) => {
+                json.pub_response(p.packet_id(), p.reason_code().map(|c| c as u8), &p.props)
+            }
+            Packet::V5_0Pubcomp(p) => {
+                json.pub_response(p.packet_id(), p.reason_code().map(|c| c as u8), &p.props)
+            }
+            Packet::V5_0Disconnect(p) => {
+                json.reason_code = Some(p.reason_code().map_or(0, |c| c as u8));
+                json.session_expiry_interval = p.props.session_expiry_interval();
+                json.server_reference = p.props.server_reference();
+                json.common_props(&p.props);
+            }
+            Packet::V5_0Auth(p) => {
+                json.reason_code = Some(p.reason_code().map_or(0, |c| c as u8));
+                json.authentication_method = p.props.authentication_method();
+                json.authentication_data = p.props.authentication_data().map(|d| base64_encode(&d));
+                json.common_props(&p.props);
+            }
+            _ => {}
+        }
+        json
     }
+}
 
-    /// Get the version
-    pub fn version(&self) -> mqtt::Version {
-        self.inner.version
+// ============================================================================
+// Version-Aware Packet Factory
+// ============================================================================
+
+/// Parse a JavaScript version string ("3.1.1", "5.0", "v5.0", "V5_0", ...; default 3.1.1)
+fn parse_version(version: Option<&str>) -> Result<mqtt::Version, JsValue> {
+    match version {
+        Some("V5_0") | Some("v5.0") | Some("5.0") | Some("5") => Ok(mqtt::Version::V5_0),
+        // mqtt-protocol-core has no protocol level 3; don't silently fall back to 3.1.1
+        Some("V3_1") | Some("v3.1") | Some("3.1") | Some("3") => Err(JsValue::from_str(
+            "MQTT 3.1 (protocol level 3) is not supported; use '3.1.1' or '5.0'",
+        )),
+        _ => Ok(mqtt::Version::V3_1_1), // default
     }
 }
 
-/// WASM-friendly wrapper around MqttClient
+/// Version-aware packet construction without a client
+///
+/// Offers the same `newXxxPacket` methods as `WasmMqttClient`, e.g. for
+/// building packets in a worker or in tooling before any client exists.
 #[wasm_bindgen]
-pub struct WasmMqttClient {
-    inner: MqttClient,
+#[derive(Clone, Copy)]
+pub struct WasmPacketFactory {
     version: mqtt::Version,
+    // Reject v5.0-only options on a V3.1.1 factory instead of ignoring them
+    strict_version: bool,
 }
 
 #[wasm_bindgen]
-impl WasmMqttClient {
+impl WasmPacketFactory {
+    /// Create a factory for `version` ("3.1.1" or "5.0"; same strings as the config)
     #[wasm_bindgen(constructor)]
-    pub fn new(config: WasmMqttConfig) -> WasmMqttClient {
-        #[cfg(target_arch = "wasm32")]
-        web_sys::console::log_1(
-            &"🔥🔥🔥 NEW WASM FILE LOADED - WASM_INTERFACE: Creating new WasmMqttClient 🔥🔥🔥"
-                .into(),
-        );
+    pub fn new(version: &str) -> Result<WasmPacketFactory, JsValue> {
+        Ok(WasmPacketFactory {
+            version: parse_version(Some(version))?,
+            strict_version: false,
+        })
+    }
+
+    /// Create a factory whose V3.1.1 packets reject v5.0-only options instead
+    /// of ignoring them (see the `strictVersion` config option)
+    #[wasm_bindgen(js_name = newStrict)]
+    pub fn new_strict(version: &str) -> Result<WasmPacketFactory, JsValue> {
+        Ok(WasmPacketFactory {
+            version: parse_version(Some(version))?,
+            strict_version: true,
+        })
+    }
+
+    /// Get the MQTT protocol version of created packets ("3.1.1" or "5.0")
+    #[wasm_bindgen(getter)]
+    pub fn version(&self) -> String {
+        match self.version {
+            mqtt::Version::V5_0 => "5.0",
+            _ => "3.1.1",
+        }
+        .to_string()
+    }
+
+    /// Create Connect packet (version-aware)
+    /// Automatically creates V3.1.1 or V5.0 packet based on the factory version
+    #[wasm_bindgen(js_name = newConnectPacket)]
+    pub fn new_connect_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
+        match self.version {
+            mqtt::Version::V3_1_1 => {
+                self.check_v311_options::<ConnectOptions>(&options)?;
+                WasmMqttPacket::new_connect_v311(options)
+            }
+            mqtt::Version::V5_0 => WasmMqttPacket::new_connect_v50(options),
+            _ => WasmMqttPacket::new_connect_v311(options), // default
+        }
+    }
+
+    /// Create Publish packet (version-aware)
+    /// Automatically creates V3.1.1 or V5.0 packet based on the factory version
+    #[wasm_bindgen(js_name = newPublishPacket)]
+    pub fn new_publish_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
+        match self.version {
+            mqtt::Version::V3_1_1 => {
+                self.check_v311_options::<PublishOptions>(&options)?;
+                WasmMqttPacket::new_publish_v311(options)
+            }
+            mqtt::Version::V5_0 => WasmMqttPacket::new_publish_v50(options),
+            _ => WasmMqttPacket::new_publish_v311(options),
+        }
+    }
+
+    /// Create a PUBLISH that clears the retained message on `topic` (version-aware)
+    #[wasm_bindgen(js_name = newRetainedClearPacket)]
+    pub fn new_retained_clear_packet(&self, topic: &str) -> Result<WasmMqttPacket, JsValue> {
+        match self.version {
+            mqtt::Version::V3_1_1 => WasmMqttPacket::new_retained_clear_v311(topic),
+            mqtt::Version::V5_0 => WasmMqttPacket::new_retained_clear_v50(topic),
+            _ => WasmMqttPacket::new_retained_clear_v311(topic),
+        }
+    }
+
+    /// Create Subscribe packet (version-aware)
+    /// Automatically creates V3.1.1 or V5.0 packet based on the factory version
+    #[wasm_bindgen(js_name = newSubscribePacket)]
+    pub fn new_subscribe_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
+        match self.version {
+            mqtt::Version::V3_1_1 => {
+                self.check_v311_options::<SubscribeOptions>(&options)?;
+                WasmMqttPacket::new_subscribe_v311(options)
+            }
+            mqtt::Version::V5_0 => WasmMqttPacket::new_subscribe_v50(options),
+            _ => WasmMqttPacket::new_subscribe_v311(options),
+        }
+    }
+
+    /// Create Unsubscribe packet (version-aware)
+    /// Automatically creates V3.1.1 or V5.0 packet based on the factory version
+    #[wasm_bindgen(js_name = newUnsubscribePacket)]
+    pub fn new_unsubscribe_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
+        match self.version {
+            mqtt::Version::V3_1_1 => {
+                self.check_v311_options::<UnsubscribeOptions>(&options)?;
+                WasmMqttPacket::new_unsubscribe_v311(options)
+            }
+            mqtt::Version::V5_0 => WasmMqttPacket::new_unsubscribe_v50(options),
+            _ => WasmMqttPacket::new_unsubscribe_v311(options),
+        }
+    }
+
+    /// Create Puback packet (version-aware)
+    /// For V3.1.1: only packet_id is used
+    /// For V5.0: packet_id, reason_code, reason_string, user_properties are used
+    #[wasm_bindgen(js_name = newPubackPacket)]
+    pub fn new_puback_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
+        match self.version {
+            mqtt::Version::V3_1_1 => {
+                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
+                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
+                self.reject_v5_only_options(&opts)?;
+                WasmMqttPacket::new_puback_v311(opts.packet_id)
+            }
+            mqtt::Version::V5_0 => WasmMqttPacket::new_puback_v50(options),
+            _ => {
+                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
+                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
+                WasmMqttPacket::new_puback_v311(opts.packet_id)
+            }
+        }
+    }
+
+    /// Create Pubrec packet (version-aware)
+    #[wasm_bindgen(js_name = newPubrecPacket)]
+    pub fn new_pubrec_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
+        match self.version {
+            mqtt::Version::V3_1_1 => {
+                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
+                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
+                self.reject_v5_only_options(&opts)?;
+                WasmMqttPacket::new_pubrec_v311(opts.packet_id)
+            }
+            mqtt::Version::V5_0 => WasmMqttPacket::new_pubrec_v50(options),
+            _ => {
+                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
+                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
+                WasmMqttPacket::new_pubrec_v311(opts.packet_id)
+            }
+        }
+    }
+
+    /// Create Pubrel packet (version-aware)
+    #[wasm_bindgen(js_name = newPubrelPacket)]
+    pub fn new_pubrel_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
+        match self.version {
+            mqtt::Version::V3_1_1 => {
+                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
+                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
+                self.reject_v5_only_options(&opts)?;
+                WasmMqttPacket::new_pubrel_v311(opts.packet_id)
+            }
+            mqtt::Version::V5_0 => WasmMqttPacket::new_pubrel_v50(options),
+            _ => {
+                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
+                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
+                WasmMqttPacket::new_pubrel_v311(opts.packet_id)
+            }
+        }
+    }
+
+    /// Create Pubcomp packet (version-aware)
+    #[wasm_bindgen(js_name = newPubcompPacket)]
+    pub fn new_pubcomp_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
+        match self.version {
+            mqtt::Version::V3_1_1 => {
+                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
+                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
+                self.reject_v5_only_options(&opts)?;
+                WasmMqttPacket::new_pubcomp_v311(opts.packet_id)
+            }
+            mqtt::Version::V5_0 => WasmMqttPacket::new_pubcomp_v50(options),
+            _ => {
+                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
+                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
+                WasmMqttPacket::new_pubcomp_v311(opts.packet_id)
+            }
+        }
+    }
+
+    /// Create Pingreq packet (version-aware)
+    #[wasm_bindgen(js_name = newPingreqPacket)]
+    pub fn new_pingreq_packet(&self) -> WasmMqttPacket {
+        match self.version {
+            mqtt::Version::V3_1_1 => WasmMqttPacket::new_pingreq_v311(),
+            mqtt::Version::V5_0 => WasmMqttPacket::new_pingreq_v50(),
+            _ => WasmMqttPacket::new_pingreq_v311(),
+        }
+    }
+
+    /// Create Disconnect packet (version-aware)
+    /// For V3.1.1: options are ignored (Disconnect has no fields)
+    /// For V5.0: reason_code, reason_string, session_expiry_interval, user_properties are used
+    /// Pass undefined/null for default options
+    #[wasm_bindgen(js_name = newDisconnectPacket)]
+    pub fn new_disconnect_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
+        match self.version {
+            mqtt::Version::V3_1_1 => {
+                if !options.is_undefined() && !options.is_null() {
+                    self.check_v311_options::<DisconnectOptions>(&options)?;
+                }
+                Ok(WasmMqttPacket::new_disconnect_v311())
+            }
+            mqtt::Version::V5_0 => {
+                let opts = if options.is_undefined() || options.is_null() {
+                    js_sys::Object::new().into()
+                } else {
+                    options
+                };
+                WasmMqttPacket::new_disconnect_v50(opts)
+            }
+            _ => Ok(WasmMqttPacket::new_disconnect_v311()),
+        }
+    }
+
+    /// Create Auth packet (V5.0 only)
+    /// Returns error for a V3.1.1 factory
+    #[wasm_bindgen(js_name = newAuthPacket)]
+    pub fn new_auth_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
+        match self.version {
+            mqtt::Version::V5_0 => WasmMqttPacket::new_auth_v50(options),
+            _ => Err(JsValue::from_str(
+                "AUTH packet is only available in MQTT v5.0",
+            )),
+        }
+    }
+}
+
+impl WasmPacketFactory {
+    /// Parse `options` and reject v5.0-only ones (`strict_version` only)
+    fn check_v311_options<T>(&self, options: &JsValue) -> Result<(), JsValue>
+    where
+        T: V5OnlyOptions + serde::de::DeserializeOwned,
+    {
+        if !self.strict_version {
+            return Ok(());
+        }
+        let opts: T = serde_wasm_bindgen::from_value(options.clone())
+            .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
+        self.reject_v5_only_options(&opts)
+    }
+
+    /// Fail with the names of the set v5.0-only options (`strict_version` only)
+    fn reject_v5_only_options(&self, opts: &impl V5OnlyOptions) -> Result<(), JsValue> {
+        if !self.strict_version {
+            return Ok(());
+        }
+        let names = opts.v5_only_options();
+        if names.is_empty() {
+            return Ok(());
+        }
+        Err(JsValue::from_str(&format!(
+            "MQTT v3.1.1 does not support these v5.0 options: {}",
+            names.join(", ")
+        )))
+    }
+}
+
+// ============================================================================
+// WASM Config and Client
+// ============================================================================
+
+/// WASM-friendly wrapper around MqttConfig
+#[wasm_bindgen]
+#[derive(Clone)]
+pub struct WasmMqttConfig {
+    inner: MqttConfig,
+}
+
+#[wasm_bindgen]
+impl WasmMqttConfig {
+    /// Create a new MQTT configuration from a JSON object.
+    ///
+    /// # Example (JavaScript)
+    /// ```js
+    /// const config = new WasmMqttConfig({
+    ///     version: '5.0',
+    ///     autoPubResponse: true,
+    ///     autoPingResponse: true,
+    ///     pingreqSendIntervalMs: 30000,
+    /// });
+    /// ```
+    #[wasm_bindgen(constructor)]
+    pub fn new(options: JsValue) -> Result<WasmMqttConfig, JsValue> {
+        let opts: ConfigOptions = if options.is_undefined() || options.is_null() {
+            ConfigOptions::default()
+        } else {
+            serde_wasm_bindgen::from_value(options)
+                .map_err(|e| JsValue::from_str(&format!("Invalid config options: {}", e)))?
+        };
+
+        let version = parse_version(opts.version.as_deref())?;
+
+        let config = MqttConfig {
+            url: String::new(), // URL is set via connect()
+            version,
+            client_id: opts.client_id,
+            pingreq_send_interval_ms: opts.pingreq_send_interval_ms.map(|v| v as u64),
+            pingreq_jitter_ms: opts.pingreq_jitter_ms,
+            auto_pub_response: opts.auto_pub_response.unwrap_or(true),
+            auto_ping_response: opts.auto_ping_response.unwrap_or(true),
+            auto_map_topic_alias_send: opts.auto_map_topic_alias_send.unwrap_or(false),
+            auto_replace_topic_alias_send: opts.auto_replace_topic_alias_send.unwrap_or(false),
+            pingresp_recv_timeout_ms: opts.pingresp_recv_timeout_ms.map(|v| v as u64).unwrap_or(0),
+            connection_establish_timeout_ms: opts
+                .connection_establish_timeout_ms
+                .map(|v| v as u64)
+                .unwrap_or(0),
+            shutdown_timeout_ms: opts.shutdown_timeout_ms.map(|v| v as u64).unwrap_or(0),
+            max_undelivered_packets: match opts.max_undelivered_packets {
+                Some(0) => {
+                    return Err(JsValue::from_str(
+                        "maxUndeliveredPackets must be at least 1",
+                    ))
+                }
+                Some(max) => max as usize,
+                None => crate::DEFAULT_MAX_UNDELIVERED_PACKETS,
+            },
+            max_total_buffer_bytes: opts.max_total_buffer_bytes.map(|v| v as usize),
+            max_publish_rate_per_sec: opts.max_publish_rate_per_sec,
+            reconnect_initial_delay_ms: opts.reconnect_initial_delay_ms.map(|v| v as u64),
+            reconnect_max_delay_ms: opts
+                .reconnect_max_delay_ms
+                .map(|v| v as u64)
+                .unwrap_or(30000),
+            reconnect_max_attempts: opts.reconnect_max_attempts,
+            publish_ack_timeout_ms: opts.publish_ack_timeout_ms.map(|v| v as u64),
+            release_packet_id_on_ack_timeout: opts
+                .release_packet_id_on_ack_timeout
+                .unwrap_or(false),
+            packet_id_reuse_delay_ms: opts.packet_id_reuse_delay_ms.map(|v| v as u64).unwrap_or(0),
+            restore_subscriptions: opts.restore_subscriptions.unwrap_or(false),
+            max_subscriptions: opts.max_subscriptions.map(|v| v as usize),
+            error_on_connack_failure: opts.error_on_connack_failure.unwrap_or(false),
+            allow_recv_before_connect: opts.allow_recv_before_connect.unwrap_or(false),
+            instance_label: opts.instance_label,
+            follow_server_reference: opts.follow_server_reference.unwrap_or(false),
+            strict_version: opts.strict_version.unwrap_or(false),
+            payload_stream_threshold: opts
+                .payload_stream_threshold
+                .map(|v| v as usize)
+                .unwrap_or(65536),
+            payload_codec: None,
+            topic_rewrite: None,
+            send_inspector: None,
+            packet_tap: None,
+            payload_chunk_handler: None,
+            packet_id_allocator: None,
+        };
+
+        Ok(WasmMqttConfig { inner: config })
+    }
+}
+
+/// Payload codec backed by synchronous JavaScript functions
+struct JsPayloadCodec {
+    name: String,
+    encode: js_sys::Function,
+    decode: js_sys::Function,
+}
+
+impl JsPayloadCodec {
+    fn call(function: &js_sys::Function, payload: &[u8]) -> Result<Vec<u8>, String> {
+        use wasm_bindgen::JsCast;
+
+        let input = js_sys::Uint8Array::from(payload);
+        let output = function
+            .call1(&JsValue::NULL, &input)
+            .map_err(|e| format!("{:?}", e))?;
+        output
+            .dyn_into::<js_sys::Uint8Array>()
+            .map(|array| array.to_vec())
+            .map_err(|_| "Codec function must return a Uint8Array".to_string())
+    }
+}
+
+impl PayloadCodec for JsPayloadCodec {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn encode(&self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        Self::call(&self.encode, payload)
+    }
+
+    fn decode(&self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        Self::call(&self.decode, payload)
+    }
+}
+
+/// Topic rewriter backed by a JavaScript function
+struct JsTopicRewriter {
+    rewrite: js_sys::Function,
+}
+
+impl TopicRewriter for JsTopicRewriter {
+    fn rewrite(&self, topic: &str) -> Result<String, String> {
+        let output = self
+            .rewrite
+            .call1(&JsValue::NULL, &JsValue::from_str(topic))
+            .map_err(|e| e.as_string().unwrap_or_else(|| format!("{:?}", e)))?;
+        output
+            .as_string()
+            .ok_or_else(|| "Topic rewriter must return a string".to_string())
+    }
+}
+
+/// Send inspector backed by a JavaScript function `(packetType, byteLength) => void`
+struct JsSendInspector {
+    inspect: js_sys::Function,
+}
+
+impl SendInspector for JsSendInspector {
+    fn inspect(&self, packet: &mqtt::packet::Packet, len: usize) {
+        let packet_type = JsValue::from(WasmPacketType::from(packet.packet_type()));
+        if let Err(e) = self
+            .inspect
+            .call2(&JsValue::NULL, &packet_type, &JsValue::from(len as u32))
+        {
+            web_sys::console::error_1(&format!("Send inspector failed: {:?}", e).into());
+        }
+    }
+}
+
+/// Packet tap backed by a JavaScript function `(direction, hexBytes, packetType) => void`
+struct JsPacketTap {
+    callback: js_sys::Function,
+}
+
+impl PacketTap for JsPacketTap {
+    fn tap(&self, direction: PacketDirection, bytes: &[u8], packet_type: &str) {
+        let direction = match direction {
+            PacketDirection::In => "in",
+            PacketDirection::Out => "out",
+        };
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        if let Err(e) = self.callback.call3(
+            &JsValue::NULL,
+            &JsValue::from_str(direction),
+            &JsValue::from_str(&hex),
+            &JsValue::from_str(packet_type),
+        ) {
+            web_sys::console::error_1(&format!("Packet tap failed: {:?}", e).into());
+        }
+    }
+}
+
+/// Payload chunk handler backed by a JavaScript function
+/// `(topic, chunk, offset, totalLength) => void`
+struct JsPayloadChunkHandler {
+    callback: js_sys::Function,
+}
+
+impl PayloadChunkHandler for JsPayloadChunkHandler {
+    fn chunk(&self, topic: &str, offset: usize, chunk: &[u8], total_len: usize) {
+        let args = js_sys::Array::of4(
+            &JsValue::from_str(topic),
+            &js_sys::Uint8Array::from(chunk),
+            &JsValue::from(offset as u32),
+            &JsValue::from(total_len as u32),
+        );
+        if let Err(e) = self.callback.apply(&JsValue::NULL, &args) {
+            web_sys::console::error_1(&format!("Payload chunk callback failed: {:?}", e).into());
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl WasmMqttConfig {
+    /// Set a payload codec applied to v5.0 PUBLISH payloads.
+    ///
+    /// `encode` and `decode` receive a Uint8Array and must synchronously return
+    /// a Uint8Array. Encoded packets carry a `payload-codec` user property set to
+    /// `name`; only received payloads with the same marker are decoded.
+    ///
+    /// # Example (JavaScript)
+    /// ```js
+    /// config.setPayloadCodec('gzip', (data) => pako.gzip(data), (data) => pako.ungzip(data));
+    /// ```
+    #[wasm_bindgen(js_name = setPayloadCodec)]
+    pub fn set_payload_codec(
+        &mut self,
+        name: &str,
+        encode: js_sys::Function,
+        decode: js_sys::Function,
+    ) {
+        #[allow(clippy::arc_with_non_send_sync)]
+        let codec = Arc::new(JsPayloadCodec {
+            name: name.to_string(),
+            encode,
+            decode,
+        });
+        self.inner.payload_codec = Some(codec);
+    }
+}
+
+/// Non-wasm_bindgen accessor methods for WasmMqttConfig
+impl WasmMqttConfig {
+    /// Get the inner config (for internal use)
+    pub fn into_inner(self) -> MqttConfig {
+        self.inner
+    }
+
+    /// Get the version
+    pub fn version(&self) -> mqtt::Version {
+        self.inner.version
+    }
+}
+
+/// Connection event as delivered to JavaScript: a plain object with a `type` field
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum JsConnectionEvent {
+    Connected,
+    Closed {
+        code: Option<u16>,
+        reason: Option<String>,
+    },
+    Reconnecting {
+        attempt: u32,
+        #[serde(rename = "delayMs")]
+        delay_ms: u64,
+    },
+    Reconnected,
+    ReconnectGaveUp {
+        attempts: u32,
+    },
+    Redirected {
+        server: String,
+    },
+    PublishComplete {
+        #[serde(rename = "packetId")]
+        packet_id: u16,
+        qos: u8,
+    },
+    PublishTimedOut {
+        #[serde(rename = "packetId")]
+        packet_id: u16,
+    },
+    PublishFailed {
+        #[serde(rename = "packetId")]
+        packet_id: u16,
+        #[serde(rename = "reasonCode")]
+        reason_code: u8,
+    },
+    SubscriptionsRestored {
+        count: usize,
+    },
+    SessionLost,
+    PingReceived,
+    KeepAliveTimeout,
+    TruncatedFrame {
+        #[serde(rename = "bytesLost")]
+        bytes_lost: usize,
+    },
+    MemoryPressure {
+        #[serde(rename = "usedBytes")]
+        used_bytes: usize,
+        #[serde(rename = "limitBytes")]
+        limit_bytes: usize,
+    },
+    PublishThrottled {
+        queued: usize,
+    },
+    ReasonStringDropped {
+        #[serde(rename = "maxPacketSize")]
+        max_packet_size: u32,
+    },
+    Error {
+        message: String,
+        offset: Option<u32>,
+        detail: Option<String>,
+    },
+    ProtocolError {
+        message: String,
+    },
+}
+
+impl From<&ConnectionEvent> for JsConnectionEvent {
+    fn from(event: &ConnectionEvent) -> Self {
+        match event {
+            ConnectionEvent::Connected => JsConnectionEvent::Connected,
+            ConnectionEvent::Closed { code, reason } => JsConnectionEvent::Closed {
+                code: *code,
+                reason: reason.clone(),
+            },
+            ConnectionEvent::Reconnecting { attempt, delay_ms } => {
+                JsConnectionEvent::Reconnecting {
+                    attempt: *attempt,
+                    delay_ms: *delay_ms,
+                }
+            }
+            ConnectionEvent::Reconnected => JsConnectionEvent::Reconnected,
+            ConnectionEvent::ReconnectGaveUp { attempts } => JsConnectionEvent::ReconnectGaveUp {
+                attempts: *attempts,
+            },
+            ConnectionEvent::Redirected { server } => JsConnectionEvent::Redirected {
+                server: server.clone(),
+            },
+            ConnectionEvent::PublishComplete { packet_id, qos } => {
+                JsConnectionEvent::PublishComplete {
+                    packet_id: *packet_id,
+                    qos: *qos,
+                }
+            }
+            ConnectionEvent::PublishTimedOut { packet_id } => JsConnectionEvent::PublishTimedOut {
+                packet_id: *packet_id,
+            },
+            ConnectionEvent::PublishFailed {
+                packet_id,
+                reason_code,
+            } => JsConnectionEvent::PublishFailed {
+                packet_id: *packet_id,
+                reason_code: *reason_code,
+            },
+            ConnectionEvent::SubscriptionsRestored { count } => {
+                JsConnectionEvent::SubscriptionsRestored { count: *count }
+            }
+            ConnectionEvent::SessionLost => JsConnectionEvent::SessionLost,
+            ConnectionEvent::PingReceived => JsConnectionEvent::PingReceived,
+            ConnectionEvent::KeepAliveTimeout => JsConnectionEvent::KeepAliveTimeout,
+            ConnectionEvent::TruncatedFrame { bytes_lost } => JsConnectionEvent::TruncatedFrame {
+                bytes_lost: *bytes_lost,
+            },
+            ConnectionEvent::MemoryPressure {
+                used_bytes,
+                limit_bytes,
+            } => JsConnectionEvent::MemoryPressure {
+                used_bytes: *used_bytes,
+                limit_bytes: *limit_bytes,
+            },
+            ConnectionEvent::PublishThrottled { queued } => {
+                JsConnectionEvent::PublishThrottled { queued: *queued }
+            }
+            ConnectionEvent::ReasonStringDropped { max_packet_size } => {
+                JsConnectionEvent::ReasonStringDropped {
+                    max_packet_size: *max_packet_size,
+                }
+            }
+            ConnectionEvent::Error(Error::ProtocolError(detail)) => {
+                JsConnectionEvent::ProtocolError {
+                    message: detail.clone(),
+                }
+            }
+            ConnectionEvent::Error(error) => {
+                let (offset, detail) = match error {
+                    Error::DecodeError { offset, detail } => {
+                        (Some(*offset as u32), Some(detail.clone()))
+                    }
+                    _ => (None, None),
+                };
+                JsConnectionEvent::Error {
+                    message: error.to_string(),
+                    offset,
+                    detail,
+                }
+            }
+        }
+    }
+}
+
+/// JavaScript name of a connection state
+fn state_name(state: ConnectionState) -> &'static str {
+    match state {
+        ConnectionState::Disconnected => "disconnected",
+        ConnectionState::Connecting => "connecting",
+        ConnectionState::Connected => "connected",
+        ConnectionState::Reconnecting => "reconnecting",
+        ConnectionState::Closed => "closed",
+    }
+}
+
+/// Convert a connection event to a plain JavaScript object with a `type` field
+fn connection_event_to_js(event: &ConnectionEvent) -> JsValue {
+    serde_wasm_bindgen::to_value(&JsConnectionEvent::from(event)).unwrap_or(JsValue::NULL)
+}
+
+/// Next packet for `messages()` and `pipeTo()`, or `None` once the stream ends
+///
+/// The stream ends only when a connection that existed has closed and no
+/// reconnection follows. Until the first connection, and through `recv()`
+/// errors that do not end it (requests cancelled while auto-reconnect resets
+/// the connection), it waits. `connected_once` carries that across calls.
+async fn next_streamed_packet(
+    client: &MqttClient,
+    connected_once: &std::cell::Cell<bool>,
+) -> Option<mqtt::packet::Packet> {
+    use futures::FutureExt;
+
+    loop {
+        if !connected_once.get() {
+            // Not connected yet: recv() would fail with NotConnected
+            if client.wait_connected().await.is_ok() {
+                connected_once.set(true);
+            }
+            continue;
+        }
+
+        // recv() goes first so a packet ready together with the close is not lost
+        futures::select_biased! {
+            result = client.recv().fuse() => {
+                if let Ok(packet) = result {
+                    return Some(packet);
+                }
+            }
+            _ = client.wait_closed().fuse() => {}
+        }
+
+        match client.state().await {
+            ConnectionState::Connected => {}
+            ConnectionState::Connecting | ConnectionState::Reconnecting => {
+                // Fails when this attempt closes; the next pass looks again
+                let _ = client.wait_connected().await;
+            }
+            ConnectionState::Disconnected | ConnectionState::Closed => return None,
+        }
+    }
+}
+
+/// WASM-friendly wrapper around MqttClient
+#[wasm_bindgen]
+pub struct WasmMqttClient {
+    inner: MqttClient,
+    version: mqtt::Version,
+    strict_version: bool,
+    auth_callback: Rc<RefCell<Option<js_sys::Function>>>,
+    event_callback: Rc<RefCell<Option<js_sys::Function>>>,
+    // Stops the onEvent() listener task; Some while it is running
+    event_listener_stop: Rc<RefCell<Option<futures::channel::oneshot::Sender<()>>>>,
+    state_change_callback: Rc<RefCell<Option<js_sys::Function>>>,
+    // Stops the onStateChange() listener task; Some while it is running
+    state_listener_stop: Rc<RefCell<Option<futures::channel::oneshot::Sender<()>>>>,
+}
+
+#[wasm_bindgen]
+impl WasmMqttClient {
+    #[wasm_bindgen(constructor)]
+    pub fn new(config: WasmMqttConfig) -> WasmMqttClient {
+        #[cfg(target_arch = "wasm32")]
+        web_sys::console::log_1(
+            &"🔥🔥🔥 NEW WASM FILE LOADED - WASM_INTERFACE: Creating new WasmMqttClient 🔥🔥🔥"
+                .into(),
+        );
         let version = config.inner.version;
+        let strict_version = config.inner.strict_version;
         let client = MqttClient::new(config.inner);
-        WasmMqttClient {
-            inner: client,
-            version,
+        Self::from_client(client, version, strict_version)
+    }
+
+    /// Create a client on a WebSocket the app already opened (e.g. a multiplexed one)
+    ///
+    /// The socket must be OPEN. Its onmessage/onerror/onclose handlers are
+    /// replaced and its binaryType set to "arraybuffer". The client is connected
+    /// without connect(): send CONNECT next. close() closes the socket, and it is
+    /// not reconnected automatically.
+    #[wasm_bindgen(js_name = fromExistingWebSocket)]
+    pub fn from_existing_websocket(
+        ws: web_sys::WebSocket,
+        config: WasmMqttConfig,
+    ) -> std::result::Result<WasmMqttClient, JsValue> {
+        if ws.ready_state() != web_sys::WebSocket::OPEN {
+            return Err(JsValue::from_str(&format!(
+                "WebSocket is not open (readyState {})",
+                ws.ready_state()
+            )));
+        }
+        let version = config.inner.version;
+        let strict_version = config.inner.strict_version;
+        let client = MqttClient::new_with_existing_websocket(config.inner, ws);
+        Ok(Self::from_client(client, version, strict_version))
+    }
+
+    /// Connect to MQTT broker
+    ///
+    /// With `timeoutMs`, rejects and closes the transport if the connection is
+    /// not established in time.
+    #[wasm_bindgen]
+    pub async fn connect(
+        &self,
+        url: &str,
+        timeout_ms: Option<u32>,
+    ) -> std::result::Result<(), JsValue> {
+        let result = match timeout_ms {
+            Some(timeout_ms) => self.inner.connect_timeout(url, timeout_ms as u64).await,
+            None => self.inner.connect(url).await,
+        };
+        result.map_err(|e| JsValue::from_str(&format!("Connection failed: {:?}", e)))
+    }
+
+    /// Connect to `url` unless already connected or connecting to it
+    ///
+    /// Idempotent, for UIs that may ask to connect on every render: resolves
+    /// at once when connected, and a call made while an attempt is in
+    /// progress settles with that attempt.
+    #[wasm_bindgen(js_name = ensureConnected)]
+    pub async fn ensure_connected(&self, url: &str) -> std::result::Result<(), JsValue> {
+        self.inner
+            .ensure_connected(url)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Connection failed: {:?}", e)))
+    }
+
+    /// Get connection state
+    #[wasm_bindgen(js_name = isConnected)]
+    pub async fn is_connected(&self) -> bool {
+        self.inner.is_connected().await
+    }
+
+    /// Check if connected synchronously (cached flag, no round-trip)
+    ///
+    /// Suitable for synchronous code such as render functions. It may briefly
+    /// lag behind a state change; use `isConnected()` when exactness matters.
+    #[wasm_bindgen(js_name = connectedSync)]
+    pub fn connected_sync(&self) -> bool {
+        self.inner.is_connected_sync()
+    }
+
+    /// Get the panic message of the client's background task, or null
+    ///
+    /// Once it is set the client no longer works and every call rejects;
+    /// include it in bug reports. Any panic in the WebAssembly module is
+    /// recorded (the first one), since it stops every client.
+    #[wasm_bindgen(js_name = lastFatalError)]
+    pub fn last_fatal_error(&self) -> Option<String> {
+        self.inner.last_fatal_error()
+    }
+
+    /// Acquire a packet ID
+    #[wasm_bindgen(js_name = acquirePacketId)]
+    pub async fn acquire_packet_id(&self) -> Option<u16> {
+        self.inner.acquire_packet_id().await
+    }
+
+    /// Acquire a packet ID, waiting until one is released if all are in use
+    #[wasm_bindgen(js_name = acquirePacketIdWait)]
+    pub async fn acquire_packet_id_wait(&self) -> std::result::Result<u16, JsValue> {
+        self.inner
+            .acquire_packet_id_wait()
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to acquire packet ID: {:?}", e)))
+    }
+
+    /// Register a packet ID
+    #[wasm_bindgen(js_name = registerPacketId)]
+    pub async fn register_packet_id(&self, packet_id: u16) -> bool {
+        self.inner.register_packet_id(packet_id).await
+    }
+
+    /// Register several packet IDs at once (e.g. when restoring a session)
+    ///
+    /// Resolves to an array with one boolean per ID: true if it was registered,
+    /// false if it was already in use.
+    #[wasm_bindgen(js_name = registerPacketIds)]
+    pub async fn register_packet_ids(&self, packet_ids: Vec<u16>) -> js_sys::Array {
+        self.inner
+            .register_packet_ids(&packet_ids)
+            .await
+            .into_iter()
+            .map(JsValue::from)
+            .collect()
+    }
+
+    /// Release a packet ID
+    #[wasm_bindgen(js_name = releasePacketId)]
+    pub async fn release_packet_id(&self, packet_id: u16) -> std::result::Result<(), JsValue> {
+        self.inner
+            .release_packet_id(packet_id)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to release packet ID: {:?}", e)))
+    }
+
+    /// Release a packet ID after verifying its QoS flow is complete
+    #[wasm_bindgen(js_name = completeQos)]
+    pub async fn complete_qos(&self, packet_id: u16) -> std::result::Result<(), JsValue> {
+        self.inner
+            .complete_qos(packet_id)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to complete QoS flow: {:?}", e)))
+    }
+
+    /// Register a callback for AUTH packets sent by the broker (v5.0 enhanced auth)
+    ///
+    /// The callback receives a WasmMqttPacket (use `asAuth()` to inspect it) and
+    /// is expected to send the next AUTH packet. Once registered, AUTH packets
+    /// are no longer returned by `recv()`. Calling this again replaces the callback;
+    /// pass `null` to unregister and get AUTH packets from `recv()` again.
+    #[wasm_bindgen(js_name = onAuthChallenge)]
+    pub fn on_auth_challenge(&self, callback: Option<js_sys::Function>) {
+        let Some(callback) = callback else {
+            if self.auth_callback.borrow_mut().take().is_some() {
+                let client = self.inner.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let _ = client.stop_recv_auth().await;
+                });
+            }
+            return;
+        };
+        let already_listening = self.auth_callback.borrow_mut().replace(callback).is_some();
+        if already_listening {
+            return;
+        }
+
+        let client = self.inner.clone();
+        let auth_callback = self.auth_callback.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            loop {
+                let packet = match client.recv_auth().await {
+                    Ok(packet) => packet,
+                    // Reconnecting; keep listening on the next connection
+                    Err(Error::ConnectionClosed) => continue,
+                    Err(_) => break,
+                };
+                let callback = auth_callback.borrow().clone();
+                if let Some(callback) = callback {
+                    let packet = JsValue::from(WasmMqttPacket { inner: packet });
+                    if let Err(e) = callback.call1(&JsValue::NULL, &packet) {
+                        web_sys::console::error_1(
+                            &format!("onAuthChallenge callback failed: {:?}", e).into(),
+                        );
+                    }
+                }
+            }
+        });
+    }
+
+    /// Register a callback for connection events
+    ///
+    /// The callback receives plain objects:
+    /// - `{ type: 'connected' }`
+    /// - `{ type: 'closed', code, reason }` (`code`/`reason` when the transport reports them)
+    /// - `{ type: 'reconnecting', attempt, delayMs }` / `{ type: 'reconnected' }` (auto-reconnect)
+    /// - `{ type: 'reconnectGaveUp', attempts }` (reconnectMaxAttempts reached)
+    /// - `{ type: 'redirected', server }` (Server Reference followed, see followServerReference)
+    /// - `{ type: 'publishComplete', packetId, qos }` (PUBACK/PUBCOMP for an outgoing PUBLISH)
+    /// - `{ type: 'publishTimedOut', packetId }` (no PUBACK/PUBCOMP within publishAckTimeoutMs)
+    /// - `{ type: 'publishFailed', packetId, reasonCode }` (v5.0 PUBREC with a failure reason code)
+    /// - `{ type: 'subscriptionsRestored', count }` (restoreSubscriptions re-subscribed)
+    /// - `{ type: 'sessionLost' }` (resumption requested but no session present)
+    /// - `{ type: 'pingReceived' }` (PINGREQ from the peer)
+    /// - `{ type: 'keepAliveTimeout' }` (broker disconnected with Keep Alive timeout, 0x8D)
+    /// - `{ type: 'truncatedFrame', bytesLost }` (closed in the middle of a packet or with held-back PUBLISH)
+    /// - `{ type: 'memoryPressure', usedBytes, limitBytes }` (maxTotalBufferBytes exceeded)
+    /// - `{ type: 'publishThrottled', queued }` (a publish waits for maxPublishRatePerSec)
+    /// - `{ type: 'reasonStringDropped', maxPacketSize }` (DISCONNECT Reason String too long)
+    /// - `{ type: 'error', message, offset, detail }` (`offset`/`detail` for decode errors)
+    /// - `{ type: 'protocolError', message }`
+    ///
+    /// Events are consumed by the callback, so do not combine this with `nextEvent()`.
+    /// Calling this again replaces the callback; `offEvent()` unregisters it.
+    #[wasm_bindgen(js_name = onEvent)]
+    pub fn on_event(&self, callback: js_sys::Function) {
+        use futures::FutureExt;
+
+        self.event_callback.borrow_mut().replace(callback);
+        if self.event_listener_stop.borrow().is_some() {
+            return;
+        }
+
+        let (stop_sender, stop_receiver) = futures::channel::oneshot::channel();
+        self.event_listener_stop.borrow_mut().replace(stop_sender);
+
+        let client = self.inner.clone();
+        let event_callback = self.event_callback.clone();
+        let event_listener_stop = self.event_listener_stop.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let mut stop_receiver = stop_receiver.fuse();
+            loop {
+                // Dropping a pending next_event() on stop leaves the event queued
+                let event = futures::select! {
+                    event = client.next_event().fuse() => event,
+                    _ = stop_receiver => break,
+                };
+                let Ok(event) = event else {
+                    break;
+                };
+                let callback = event_callback.borrow().clone();
+                if let Some(callback) = callback {
+                    if let Err(e) = callback.call1(&JsValue::NULL, &connection_event_to_js(&event))
+                    {
+                        web_sys::console::error_1(
+                            &format!("onEvent callback failed: {:?}", e).into(),
+                        );
+                    }
+                }
+            }
+
+            // Ended without offEvent() (next_event() failed): forget this
+            // listener so a later onEvent() starts a new one. A listener
+            // started since then still holds its receiver and is kept.
+            drop(stop_receiver);
+            let mut stop = event_listener_stop.borrow_mut();
+            if stop.as_ref().is_some_and(|sender| sender.is_canceled()) {
+                *stop = None;
+            }
+        });
+    }
+
+    /// Unregister the onEvent() callback
+    ///
+    /// Events raised afterwards are queued for `nextEvent()` or a later `onEvent()`.
+    #[wasm_bindgen(js_name = offEvent)]
+    pub fn off_event(&self) {
+        self.event_callback.borrow_mut().take();
+        if let Some(stop_sender) = self.event_listener_stop.borrow_mut().take() {
+            let _ = stop_sender.send(());
+        }
+    }
+
+    /// Register a callback for connection state transitions
+    ///
+    /// The callback is called with `(oldState, newState)`, each "disconnected",
+    /// "connecting", "connected", "reconnecting" or "closed", for every state
+    /// change, e.g. to measure how often "connecting" fails to reach
+    /// "connected". Calling this again replaces the callback;
+    /// `offStateChange()` unregisters it.
+    #[wasm_bindgen(js_name = onStateChange)]
+    pub fn on_state_change(&self, callback: js_sys::Function) {
+        use futures::FutureExt;
+
+        self.state_change_callback.borrow_mut().replace(callback);
+        if self.state_listener_stop.borrow().is_some() {
+            return;
+        }
+
+        let (stop_sender, stop_receiver) = futures::channel::oneshot::channel();
+        self.state_listener_stop.borrow_mut().replace(stop_sender);
+
+        let client = self.inner.clone();
+        let state_change_callback = self.state_change_callback.clone();
+        let state_listener_stop = self.state_listener_stop.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let mut stop_receiver = stop_receiver.fuse();
+            loop {
+                let change = futures::select! {
+                    change = client.next_state_change().fuse() => change,
+                    _ = stop_receiver => break,
+                };
+                let Ok(change) = change else {
+                    break;
+                };
+                let callback = state_change_callback.borrow().clone();
+                if let Some(callback) = callback {
+                    if let Err(e) = callback.call2(
+                        &JsValue::NULL,
+                        &state_name(change.from).into(),
+                        &state_name(change.to).into(),
+                    ) {
+                        web_sys::console::error_1(
+                            &format!("onStateChange callback failed: {:?}", e).into(),
+                        );
+                    }
+                }
+            }
+
+            // Ended without offStateChange() (next_state_change() failed):
+            // forget this listener so a later onStateChange() starts a new one
+            drop(stop_receiver);
+            let mut stop = state_listener_stop.borrow_mut();
+            if stop.as_ref().is_some_and(|sender| sender.is_canceled()) {
+                *stop = None;
+            }
+        });
+    }
+
+    /// Unregister the onStateChange() callback
+    #[wasm_bindgen(js_name = offStateChange)]
+    pub fn off_state_change(&self) {
+        self.state_change_callback.borrow_mut().take();
+        if let Some(stop_sender) = self.state_listener_stop.borrow_mut().take() {
+            let _ = stop_sender.send(());
+        }
+    }
+
+    /// Receive the next connection event as `{ type, ... }`
+    ///
+    /// See `onEvent()` for the event shapes.
+    #[wasm_bindgen(js_name = nextEvent)]
+    pub async fn next_event(&self) -> std::result::Result<JsValue, JsValue> {
+        self.inner
+            .next_event()
+            .await
+            .map(|event| connection_event_to_js(&event))
+            .map_err(|e| JsValue::from_str(&format!("Failed to receive event: {:?}", e)))
+    }
+
+    /// Set a function that rewrites outgoing topics, or `null` to remove it
+    ///
+    /// The function receives the topic name (PUBLISH) or topic filter
+    /// (SUBSCRIBE/UNSUBSCRIBE) and must synchronously return the topic to send.
+    /// Throwing rejects the send.
+    ///
+    /// # Example (JavaScript)
+    /// ```js
+    /// await client.setTopicRewriter((topic) => `tenant-a/${topic}`);
+    /// ```
+    #[wasm_bindgen(js_name = setTopicRewriter)]
+    pub async fn set_topic_rewriter(
+        &self,
+        rewrite: Option<js_sys::Function>,
+    ) -> std::result::Result<(), JsValue> {
+        let rewriter = rewrite.map(|rewrite| {
+            #[allow(clippy::arc_with_non_send_sync)]
+            let rewriter: Arc<dyn TopicRewriter> = Arc::new(JsTopicRewriter { rewrite });
+            rewriter
+        });
+        self.inner
+            .set_topic_rewriter(rewriter)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to set topic rewriter: {:?}", e)))
+    }
+
+    /// Set (or clear with `null`) a read-only observer of outgoing packets
+    ///
+    /// The function is called as `(packetType, byteLength)` for every packet
+    /// written to the transport, including automatic PINGREQ and acknowledgements.
+    ///
+    /// # Example (JavaScript)
+    /// ```js
+    /// await client.setSendInspector((type, length) => audit.push({ type, length }));
+    /// ```
+    #[wasm_bindgen(js_name = setSendInspector)]
+    pub async fn set_send_inspector(
+        &self,
+        inspect: Option<js_sys::Function>,
+    ) -> std::result::Result<(), JsValue> {
+        let inspector = inspect.map(|inspect| {
+            #[allow(clippy::arc_with_non_send_sync)]
+            let inspector: Arc<dyn SendInspector> = Arc::new(JsSendInspector { inspect });
+            inspector
+        });
+        self.inner
+            .set_send_inspector(inspector)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to set send inspector: {:?}", e)))
+    }
+
+    /// Set (or clear with `null`) a wire-level tap for protocol debugging
+    ///
+    /// The function is called as `(direction, hexBytes, packetType)` for every
+    /// packet crossing the wire: `direction` is `"in"` or `"out"`, `hexBytes`
+    /// the lowercase hex of the whole packet and `packetType` its name as in
+    /// `packetTypeString()`. Automatic packets (PINGREQ, acknowledgements) are
+    /// included. A PUBLISH streamed to `onPayloadChunk()` is reported in
+    /// pieces: its headers, then each payload chunk. Without a tap nothing is
+    /// converted.
+    ///
+    /// # Example (JavaScript)
+    /// ```js
+    /// await client.setPacketTap((dir, hex, type) => console.log(dir, type, hex));
+    /// ```
+    #[wasm_bindgen(js_name = setPacketTap)]
+    pub async fn set_packet_tap(
+        &self,
+        callback: Option<js_sys::Function>,
+    ) -> std::result::Result<(), JsValue> {
+        let tap = callback.map(|callback| {
+            #[allow(clippy::arc_with_non_send_sync)]
+            let tap: Arc<dyn PacketTap> = Arc::new(JsPacketTap { callback });
+            tap
+        });
+        self.inner
+            .set_packet_tap(tap)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to set packet tap: {:?}", e)))
+    }
+
+    /// Stream large received PUBLISH payloads to `callback` (null to stop)
+    ///
+    /// Payloads larger than `payloadStreamThreshold` are passed to
+    /// `callback(topic, chunk, offset, totalLength)` as their bytes arrive,
+    /// without waiting for the whole packet; the last chunk ends at
+    /// `totalLength`. `recv()` then returns the PUBLISH with an empty payload.
+    /// `topic` is empty when the broker used a v5.0 topic alias. Payload
+    /// codecs are not applied to streamed payloads.
+    ///
+    /// # Example (JavaScript)
+    /// ```js
+    /// await client.onPayloadChunk((topic, chunk, offset, total) => file.write(chunk));
+    /// ```
+    #[wasm_bindgen(js_name = onPayloadChunk)]
+    pub async fn on_payload_chunk(
+        &self,
+        callback: Option<js_sys::Function>,
+    ) -> std::result::Result<(), JsValue> {
+        let handler = callback.map(|callback| {
+            #[allow(clippy::arc_with_non_send_sync)]
+            let handler: Arc<dyn PayloadChunkHandler> =
+                Arc::new(JsPayloadChunkHandler { callback });
+            handler
+        });
+        self.inner
+            .set_payload_chunk_handler(handler)
+            .await
+            .map_err(|e| {
+                JsValue::from_str(&format!("Failed to set payload chunk handler: {:?}", e))
+            })
+    }
+
+    /// Pause inbound PUBLISH processing (other packets are still handled)
+    #[wasm_bindgen(js_name = pauseRecv)]
+    pub async fn pause_recv(&self) -> std::result::Result<(), JsValue> {
+        self.inner
+            .pause_recv()
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to pause recv: {:?}", e)))
+    }
+
+    /// Resume inbound PUBLISH processing
+    #[wasm_bindgen(js_name = resumeRecv)]
+    pub async fn resume_recv(&self) -> std::result::Result<(), JsValue> {
+        self.inner
+            .resume_recv()
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to resume recv: {:?}", e)))
+    }
+
+    /// Discard received packets not yet delivered to recv(); returns how many were dropped
+    #[wasm_bindgen(js_name = clearInbound)]
+    pub async fn clear_inbound(&self) -> u32 {
+        self.inner.clear_inbound().await as u32
+    }
+
+    /// Get ping round-trip statistics as `{ lastMs, avgMs, maxMs, sampleCount }`
+    #[wasm_bindgen(js_name = rttStats)]
+    pub async fn rtt_stats(&self) -> JsValue {
+        let stats = self.inner.rtt_stats().await;
+        let obj = js_sys::Object::new();
+        js_sys::Reflect::set(&obj, &"lastMs".into(), &stats.last_ms.into()).unwrap();
+        js_sys::Reflect::set(&obj, &"avgMs".into(), &stats.avg_ms.into()).unwrap();
+        js_sys::Reflect::set(&obj, &"maxMs".into(), &stats.max_ms.into()).unwrap();
+        js_sys::Reflect::set(
+            &obj,
+            &"sampleCount".into(),
+            &(stats.sample_count as u32).into(),
+        )
+        .unwrap();
+        obj.into()
+    }
+
+    /// Set the client identifier before connecting (rejects while connected)
+    #[wasm_bindgen(js_name = setClientId)]
+    pub async fn set_client_id(&self, client_id: String) -> std::result::Result<(), JsValue> {
+        self.inner
+            .set_client_id(client_id)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to set client id: {:?}", e)))
+    }
+
+    /// Wait until the transport is connected
+    ///
+    /// Resolves immediately if already connected, otherwise on the next successful
+    /// connection. Rejects if the connection closes first or after `timeoutMs`
+    /// (omit to wait indefinitely).
+    #[wasm_bindgen(js_name = waitConnected)]
+    pub async fn wait_connected(
+        &self,
+        timeout_ms: Option<u32>,
+    ) -> std::result::Result<(), JsValue> {
+        use futures::FutureExt;
+
+        let wait = self.inner.wait_connected().fuse();
+        let timeout = async {
+            match timeout_ms {
+                Some(ms) => crate::platform::sleep_ms(ms as i32).await,
+                None => futures::future::pending::<()>().await,
+            }
+        }
+        .fuse();
+        futures::pin_mut!(wait, timeout);
+
+        futures::select! {
+            result = wait => result
+                .map_err(|e| JsValue::from_str(&format!("Failed to wait for connection: {:?}", e))),
+            _ = timeout => Err(JsValue::from_str("Timed out waiting for connection")),
         }
     }
 
-    /// Connect to MQTT broker
-    #[wasm_bindgen]
-    pub async fn connect(&self, url: &str) -> std::result::Result<(), JsValue> {
+    /// Wait until every outgoing QoS 1/2 PUBLISH got its PUBACK/PUBCOMP
+    ///
+    /// Resolves immediately if nothing is inflight. Rejects after `timeoutMs`,
+    /// or if the connection is not open or closes first.
+    #[wasm_bindgen(js_name = waitInflightDrained)]
+    pub async fn wait_inflight_drained(&self, timeout_ms: u32) -> std::result::Result<(), JsValue> {
         self.inner
-            .connect(url)
+            .wait_inflight_drained(timeout_ms as u64)
             .await
-            .map_err(|e| JsValue::from_str(&format!("Connection failed: {:?}", e)))
+            .map_err(|e| {
+                JsValue::from_str(&format!("Failed to wait for inflight publishes: {:?}", e))
+            })
     }
 
-    /// Get connection state
-    #[wasm_bindgen(js_name = isConnected)]
-    pub async fn is_connected(&self) -> bool {
-        self.inner.is_connected().await
+    /// Export the session state as a JSON string, e.g. for localStorage
+    ///
+    /// Restore it with `importSession()` before connecting with
+    /// `cleanStart`/`cleanSession` false.
+    #[wasm_bindgen(js_name = exportSession)]
+    pub async fn export_session(&self) -> std::result::Result<String, JsValue> {
+        let snapshot = self.inner.export_session().await;
+        let value = snapshot
+            .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+            .map_err(|e| JsValue::from_str(&format!("Failed to export session: {:?}", e)))?;
+        let json = js_sys::JSON::stringify(&value)?;
+        Ok(json.into())
+    }
+
+    /// Restore a session exported by `exportSession()`; call before connect()
+    #[wasm_bindgen(js_name = importSession)]
+    pub async fn import_session(&self, json: &str) -> std::result::Result<(), JsValue> {
+        let value = js_sys::JSON::parse(json)?;
+        let snapshot: SessionSnapshot = serde_wasm_bindgen::from_value(value)
+            .map_err(|e| JsValue::from_str(&format!("Invalid session: {:?}", e)))?;
+        self.inner
+            .import_session(snapshot)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to import session: {:?}", e)))
     }
 
-    /// Acquire a packet ID
-    #[wasm_bindgen(js_name = acquirePacketId)]
-    pub async fn acquire_packet_id(&self) -> Option<u16> {
-        self.inner.acquire_packet_id().await
+    /// Get the session-present flag of the most recent accepted CONNACK
+    /// (undefined before the first one)
+    #[wasm_bindgen(js_name = sessionPresent)]
+    pub async fn session_present(&self) -> Option<bool> {
+        self.inner.session_present().await
     }
 
-    /// Register a packet ID
-    #[wasm_bindgen(js_name = registerPacketId)]
-    pub async fn register_packet_id(&self, packet_id: u16) -> bool {
-        self.inner.register_packet_id(packet_id).await
+    /// Get the reason code of the v5.0 DISCONNECT that ended the latest
+    /// connection (undefined if none was received since the last CONNECT)
+    #[wasm_bindgen(js_name = lastDisconnectReason)]
+    pub async fn last_disconnect_reason(&self) -> Option<u8> {
+        self.inner.last_disconnect_reason().await
     }
 
-    /// Release a packet ID
-    #[wasm_bindgen(js_name = releasePacketId)]
-    pub async fn release_packet_id(&self, packet_id: u16) -> std::result::Result<(), JsValue> {
+    /// Whether the broker ended the latest connection with Keep Alive timeout
+    /// (0x8D); if so, lower `pingreqSendIntervalMs` or raise the keep alive
+    #[wasm_bindgen(js_name = wasKeepaliveTimeout)]
+    pub async fn was_keepalive_timeout(&self) -> bool {
+        self.inner.was_keepalive_timeout().await
+    }
+
+    /// Get the number of subscriptions confirmed by the broker
+    #[wasm_bindgen(js_name = subscriptionCount)]
+    pub async fn subscription_count(&self) -> usize {
+        self.inner.subscription_count().await
+    }
+
+    /// Get the subscriptions confirmed by the broker as `[{ topic, qos }, ...]`
+    #[wasm_bindgen]
+    pub async fn subscriptions(&self) -> JsValue {
+        let results = js_sys::Array::new();
+        for (topic, qos) in self.inner.subscriptions().await {
+            let obj = js_sys::Object::new();
+            js_sys::Reflect::set(&obj, &"topic".into(), &topic.into()).unwrap();
+            js_sys::Reflect::set(&obj, &"qos".into(), &qos.into()).unwrap();
+            results.push(&obj);
+        }
+        results.into()
+    }
+
+    /// Get a connection health snapshot as `{ state, lastRttMs, bufferedAmount,
+    /// bufferedAmountGrowing, inflightCount, secondsSinceLastRecv,
+    /// keepAliveSecs, healthy }`
+    ///
+    /// `state` is "disconnected", "connecting", "connected", "reconnecting" or
+    /// "closed"; `lastRttMs` and `secondsSinceLastRecv` are null until known.
+    #[wasm_bindgen(js_name = health)]
+    pub async fn health(&self) -> std::result::Result<JsValue, JsValue> {
+        let report = self
+            .inner
+            .health()
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to get health: {:?}", e)))?;
+        let state = state_name(report.state);
+        let obj = js_sys::Object::new();
+        let set = |key: &str, value: JsValue| {
+            js_sys::Reflect::set(&obj, &key.into(), &value).unwrap();
+        };
+        set("state", state.into());
+        set("lastRttMs", report.last_rtt_ms.into());
+        set("bufferedAmount", (report.buffered_amount as f64).into());
+        set(
+            "bufferedAmountGrowing",
+            report.buffered_amount_growing.into(),
+        );
+        set("inflightCount", (report.inflight_count as u32).into());
+        set(
+            "secondsSinceLastRecv",
+            report.seconds_since_last_recv.into(),
+        );
+        set("keepAliveSecs", report.keep_alive_secs.into());
+        set("healthy", report.is_healthy().into());
+        Ok(obj.into())
+    }
+
+    /// Whether the connection looks healthy: connected, the transport keeps up
+    /// with outgoing data, and the broker was heard from within 1.5 times the
+    /// keep alive (see `health()`)
+    #[wasm_bindgen(js_name = isHealthy)]
+    pub async fn is_healthy(&self) -> bool {
         self.inner
-            .release_packet_id(packet_id)
+            .health()
             .await
-            .map_err(|e| JsValue::from_str(&format!("Failed to release packet ID: {:?}", e)))
+            .map(|report| report.is_healthy())
+            .unwrap_or(false)
+    }
+
+    /// Get the number of bytes queued by the transport but not yet sent
+    #[wasm_bindgen(js_name = bufferedAmount)]
+    pub async fn buffered_amount(&self) -> std::result::Result<f64, JsValue> {
+        self.inner
+            .buffered_amount()
+            .await
+            .map(|amount| amount as f64)
+            .map_err(|e| JsValue::from_str(&format!("Failed to get buffered amount: {:?}", e)))
+    }
+
+    /// Get the WebSocket readyState (0 CONNECTING, 1 OPEN, 2 CLOSING, 3 CLOSED)
+    #[wasm_bindgen(js_name = transportReadyState)]
+    pub async fn transport_ready_state(&self) -> std::result::Result<u8, JsValue> {
+        self.inner
+            .transport_ready_state()
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to get ready state: {:?}", e)))
     }
 
     /// Send MQTT packet
@@ -2279,6 +4455,144 @@ impl WasmMqttClient {
             .map_err(|e| JsValue::from_str(&format!("Failed to send packet: {:?}", e)))
     }
 
+    /// Send a PINGRESP (answer a `pingReceived` event when autoPingResponse is off)
+    #[wasm_bindgen(js_name = sendPingresp)]
+    pub async fn send_pingresp(&self) -> std::result::Result<(), JsValue> {
+        self.inner
+            .send_pingresp()
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to send PINGRESP: {:?}", e)))
+    }
+
+    /// Send MQTT packet and resolve with the number of bytes written to the WebSocket
+    #[wasm_bindgen(js_name = sendSized)]
+    pub async fn send_sized(&self, packet: WasmMqttPacket) -> std::result::Result<u32, JsValue> {
+        self.inner
+            .send_sized(packet.inner)
+            .await
+            .map(|size| size as u32)
+            .map_err(|e| JsValue::from_str(&format!("Failed to send packet: {:?}", e)))
+    }
+
+    /// Send one pre-encoded packet (a `Uint8Array`) exactly as given
+    ///
+    /// Rejects a buffer holding a partial packet, more than one packet or a
+    /// malformed one without sending anything.
+    #[wasm_bindgen(js_name = sendEncoded)]
+    pub async fn send_encoded(&self, bytes: Vec<u8>) -> std::result::Result<(), JsValue> {
+        self.inner
+            .send_encoded(bytes)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to send packet: {:?}", e)))
+    }
+
+    /// Subscribe to several topic filters in one SUBSCRIBE and wait for the SUBACK
+    ///
+    /// Each element is `{ topic, qos, noLocal, retainAsPublished, retainHandling }`.
+    /// A packet ID is acquired automatically. Resolves with `[{ topic, reasonCode }, ...]`
+    /// in request order; for v3.1.1 `reasonCode` is the SUBACK return code.
+    #[wasm_bindgen(js_name = subscribeMany)]
+    pub async fn subscribe_many(&self, filters: JsValue) -> std::result::Result<JsValue, JsValue> {
+        let subscriptions: Vec<SubscriptionEntry> = serde_wasm_bindgen::from_value(filters)
+            .map_err(|e| JsValue::from_str(&format!("Invalid filters: {:?}", e)))?;
+        // SUBSCRIBE without topic filters is a protocol error
+        if subscriptions.is_empty() {
+            return Err(JsValue::from_str(
+                "subscribeMany requires at least one topic filter",
+            ));
+        }
+        let topics: Vec<String> = subscriptions.iter().map(|s| s.topic.clone()).collect();
+
+        let packet_id = self
+            .inner
+            .acquire_packet_id()
+            .await
+            .ok_or_else(|| JsValue::from_str("No packet ID available"))?;
+        let opts = SubscribeOptions {
+            packet_id,
+            subscriptions,
+            ..Default::default()
+        };
+        let packet = match self.version {
+            mqtt::Version::V5_0 => WasmMqttPacket::subscribe_v50_from_options(opts),
+            _ => WasmMqttPacket::subscribe_v311_from_options(opts),
+        };
+        let packet = match packet {
+            Ok(packet) => packet,
+            Err(e) => {
+                let _ = self.inner.release_packet_id(packet_id).await;
+                return Err(e);
+            }
+        };
+
+        let suback = match self.inner.subscribe(packet.inner).await {
+            Ok(suback) => suback,
+            Err(e) => {
+                let _ = self.inner.release_packet_id(packet_id).await;
+                return Err(JsValue::from_str(&format!("Failed to subscribe: {:?}", e)));
+            }
+        };
+        let codes: Vec<u8> = match &suback {
+            mqtt::packet::Packet::V3_1_1Suback(p) => {
+                p.return_codes().iter().map(|c| *c as u8).collect()
+            }
+            mqtt::packet::Packet::V5_0Suback(p) => {
+                p.reason_codes().iter().map(|c| *c as u8).collect()
+            }
+            _ => Vec::new(),
+        };
+
+        let results = js_sys::Array::new();
+        for (topic, code) in topics.iter().zip(codes) {
+            let obj = js_sys::Object::new();
+            js_sys::Reflect::set(&obj, &"topic".into(), &topic.into()).unwrap();
+            js_sys::Reflect::set(&obj, &"reasonCode".into(), &code.into()).unwrap();
+            results.push(&obj);
+        }
+        Ok(results.into())
+    }
+
+    /// Get an async iterator over received packets
+    ///
+    /// `for await (const packet of client.messages()) { ... }` yields each packet
+    /// from `recv()`. It waits for the first connection and through automatic
+    /// reconnects, and ends when the connection closes for good. Use one
+    /// consumer at a time; packets are shared with `recv()` callers.
+    #[wasm_bindgen]
+    pub fn messages(&self) -> js_sys::Object {
+        let client = self.inner.clone();
+        let connected_once = Rc::new(std::cell::Cell::new(false));
+        let next = Closure::<dyn FnMut() -> js_sys::Promise>::new(move || {
+            let client = client.clone();
+            let connected_once = connected_once.clone();
+            wasm_bindgen_futures::future_to_promise(async move {
+                let packet = next_streamed_packet(&client, &connected_once).await;
+                let result = js_sys::Object::new();
+                let done = packet.is_none();
+                let value = match packet {
+                    Some(packet) => JsValue::from(WasmMqttPacket { inner: packet }),
+                    None => JsValue::UNDEFINED,
+                };
+                js_sys::Reflect::set(&result, &"value".into(), &value)?;
+                js_sys::Reflect::set(&result, &"done".into(), &done.into())?;
+                Ok(result.into())
+            })
+        });
+
+        let iterator = js_sys::Object::new();
+        js_sys::Reflect::set(&iterator, &"next".into(), &next.into_js_value()).unwrap();
+        // The iterator is its own async iterable
+        let this = iterator.clone();
+        let async_iterator = Closure::<dyn FnMut() -> js_sys::Object>::new(move || this.clone());
+        js_sys::Reflect::set(
+            &iterator,
+            &js_sys::Symbol::async_iterator(),
+            &async_iterator.into_js_value(),
+        )
+        .unwrap();
+        iterator
+    }
+
     /// Receive next packet
     #[wasm_bindgen]
     pub async fn recv(&self) -> std::result::Result<WasmMqttPacket, JsValue> {
@@ -2291,6 +4605,132 @@ impl WasmMqttClient {
         Ok(WasmMqttPacket { inner: packet })
     }
 
+    /// Receive the next packet with processing metadata
+    ///
+    /// Resolves to `{ packet, autoResponded }`, where `autoResponded` tells
+    /// whether the client answered the packet itself (e.g. the PUBACK for a
+    /// QoS 1 PUBLISH under `autoPubResponse`).
+    #[wasm_bindgen(js_name = recvWithMeta)]
+    pub async fn recv_with_meta(&self) -> std::result::Result<JsValue, JsValue> {
+        let (packet, meta) = self
+            .inner
+            .recv_with_meta()
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to receive packet: {:?}", e)))?;
+
+        let obj = js_sys::Object::new();
+        let set = |key: &str, value: JsValue| {
+            js_sys::Reflect::set(&obj, &key.into(), &value).unwrap();
+        };
+        set("packet", WasmMqttPacket { inner: packet }.into());
+        set("autoResponded", meta.auto_responded.into());
+        Ok(obj.into())
+    }
+
+    /// Receive the first packet whose type is one of `types` (`WasmPacketType` values)
+    ///
+    /// Packets of other types stay queued, in order, for `recv()`.
+    #[wasm_bindgen(js_name = recvOneOf)]
+    pub async fn recv_one_of(
+        &self,
+        types: js_sys::Array,
+    ) -> std::result::Result<WasmMqttPacket, JsValue> {
+        let types = types
+            .iter()
+            .map(|value| {
+                value
+                    .as_f64()
+                    .and_then(|n| WasmPacketType::try_from(n as u32).ok())
+                    .map(mqtt::packet::PacketType::from)
+                    .ok_or_else(|| JsValue::from_str(&format!("Invalid packet type: {:?}", value)))
+            })
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let packet = self
+            .inner
+            .recv_one_of(&types)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to receive packet: {:?}", e)))?;
+
+        Ok(WasmMqttPacket { inner: packet })
+    }
+
+    /// Receive the next v5.0 PUBLISH carrying subscription identifier `subscriptionId`
+    ///
+    /// Other packets stay queued, in order, for `recv()`. Rejects after
+    /// `timeoutMs` (omit to wait indefinitely); a packet arriving later is
+    /// kept for the next call.
+    #[wasm_bindgen(js_name = recvForSubscription)]
+    pub async fn recv_for_subscription(
+        &self,
+        subscription_id: u32,
+        timeout_ms: Option<u32>,
+    ) -> std::result::Result<WasmMqttPacket, JsValue> {
+        use futures::FutureExt;
+
+        let recv = self.inner.recv_for_subscription(subscription_id).fuse();
+        let timeout = async {
+            match timeout_ms {
+                Some(ms) => crate::platform::sleep_ms(ms as i32).await,
+                None => futures::future::pending::<()>().await,
+            }
+        }
+        .fuse();
+        futures::pin_mut!(recv, timeout);
+
+        futures::select! {
+            result = recv => result
+                .map(|packet| WasmMqttPacket { inner: packet })
+                .map_err(|e| JsValue::from_str(&format!("Failed to receive packet: {:?}", e))),
+            _ = timeout => Err(JsValue::from_str("Timed out waiting for packet")),
+        }
+    }
+
+    /// Forward received packets to `port`, e.g. from a Web Worker to the main thread
+    ///
+    /// Each packet from `recv()` is posted as its `toJsonObject()` form, which
+    /// survives structured cloning. Forwarding waits for the first connection
+    /// and through automatic reconnects; `null` is posted once the connection
+    /// closes for good, and forwarding stops. Packets are taken from `recv()`, so do not
+    /// receive them elsewhere meanwhile.
+    ///
+    /// # Example (JavaScript)
+    /// ```js
+    /// // worker
+    /// const channel = new MessageChannel();
+    /// postMessage({ port: channel.port2 }, [channel.port2]);
+    /// client.pipeTo(channel.port1);
+    /// // main thread
+    /// port.onmessage = (e) => { if (e.data) console.log(e.data.type, e.data.topicName); };
+    /// ```
+    #[wasm_bindgen(js_name = pipeTo)]
+    pub fn pipe_to(&self, port: web_sys::MessagePort) {
+        let client = self.inner.clone();
+        wasm_bindgen_futures::spawn_local(async move {
+            let connected_once = std::cell::Cell::new(false);
+            loop {
+                let packet = next_streamed_packet(&client, &connected_once).await;
+                let message = match packet {
+                    Some(packet) => WasmMqttPacket { inner: packet }.to_json_object(),
+                    None => JsValue::NULL,
+                };
+                let done = message.is_null();
+                if let Err(e) = port.post_message(&message) {
+                    web_sys::console::error_1(&format!("pipeTo post failed: {:?}", e).into());
+                }
+                if done {
+                    break;
+                }
+            }
+        });
+    }
+
+    /// Receive a packet as a JSON-serializable object (see `WasmMqttPacket.toJsonObject()`),
+    /// e.g. to ship received packets to a logging backend
+    #[wasm_bindgen(js_name = recvAsJsonObject)]
+    pub async fn recv_as_json_object(&self) -> std::result::Result<JsValue, JsValue> {
+        Ok(self.recv().await?.to_json_object())
+    }
+
     /// Close the connection
     #[wasm_bindgen]
     pub async fn close(&self) -> std::result::Result<(), JsValue> {
@@ -2300,54 +4740,81 @@ impl WasmMqttClient {
             .map_err(|e| JsValue::from_str(&format!("Failed to close connection: {:?}", e)))
     }
 
+    /// Send DISCONNECT so the broker discards the will message, then close
+    /// (v5.0 reason code 0x00)
+    #[wasm_bindgen(js_name = disconnectSuppressWill)]
+    pub async fn disconnect_suppress_will(&self) -> std::result::Result<(), JsValue> {
+        self.inner
+            .disconnect_suppress_will()
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to disconnect: {:?}", e)))
+    }
+
+    /// Send a DISCONNECT built from `options` (as for `newDisconnectPacket()`),
+    /// then close
+    ///
+    /// Waits up to `shutdownTimeoutMs` for the broker to close the connection
+    /// before closing it from this side.
+    #[wasm_bindgen(js_name = closeWithDisconnect)]
+    pub async fn close_with_disconnect(
+        &self,
+        options: JsValue,
+    ) -> std::result::Result<(), JsValue> {
+        let packet = self.new_disconnect_packet(options)?;
+        self.inner
+            .close_with_disconnect(packet.inner)
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to disconnect: {:?}", e)))
+    }
+
+    /// Disconnect so the broker publishes the will message, then close
+    /// (v5.0 DISCONNECT reason code 0x04; v3.1.1 closes without DISCONNECT)
+    #[wasm_bindgen(js_name = disconnectTriggerWill)]
+    pub async fn disconnect_trigger_will(&self) -> std::result::Result<(), JsValue> {
+        self.inner
+            .disconnect_trigger_will()
+            .await
+            .map_err(|e| JsValue::from_str(&format!("Failed to disconnect: {:?}", e)))
+    }
+
     // ------------------------------------------------------------------------
     // Version-Aware Packet Creation Methods
     // ------------------------------------------------------------------------
     // These methods automatically create packets for the correct protocol version
-    // based on the client's configured version.
+    // based on the client's configured version (see WasmPacketFactory).
 
     /// Create Connect packet (version-aware)
     /// Automatically creates V3.1.1 or V5.0 packet based on client version
     #[wasm_bindgen(js_name = newConnectPacket)]
     pub fn new_connect_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        match self.version {
-            mqtt::Version::V3_1_1 => WasmMqttPacket::new_connect_v311(options),
-            mqtt::Version::V5_0 => WasmMqttPacket::new_connect_v50(options),
-            _ => WasmMqttPacket::new_connect_v311(options), // default
-        }
+        self.packet_factory().new_connect_packet(options)
     }
 
     /// Create Publish packet (version-aware)
     /// Automatically creates V3.1.1 or V5.0 packet based on client version
     #[wasm_bindgen(js_name = newPublishPacket)]
     pub fn new_publish_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        match self.version {
-            mqtt::Version::V3_1_1 => WasmMqttPacket::new_publish_v311(options),
-            mqtt::Version::V5_0 => WasmMqttPacket::new_publish_v50(options),
-            _ => WasmMqttPacket::new_publish_v311(options),
-        }
+        self.packet_factory().new_publish_packet(options)
+    }
+
+    /// Create a PUBLISH that clears the retained message on `topic` (version-aware)
+    #[wasm_bindgen(js_name = newRetainedClearPacket)]
+    pub fn new_retained_clear_packet(&self, topic: &str) -> Result<WasmMqttPacket, JsValue> {
+        self.packet_factory().new_retained_clear_packet(topic)
     }
 
     /// Create Subscribe packet (version-aware)
     /// Automatically creates V3.1.1 or V5.0 packet based on client version
     #[wasm_bindgen(js_name = newSubscribePacket)]
     pub fn new_subscribe_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        match self.version {
-            mqtt::Version::V3_1_1 => WasmMqttPacket::new_subscribe_v311(options),
-            mqtt::Version::V5_0 => WasmMqttPacket::new_subscribe_v50(options),
-            _ => WasmMqttPacket::new_subscribe_v311(options),
-        }
+        self.packet_factory().new_subscribe_packet(options)
     }
 
     /// Create Unsubscribe packet (version-aware)
     /// Automatically creates V3.1.1 or V5.0 packet based on client version
     #[wasm_bindgen(js_name = newUnsubscribePacket)]
     pub fn new_unsubscribe_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        match self.version {
-            mqtt::Version::V3_1_1 => WasmMqttPacket::new_unsubscribe_v311(options),
-            mqtt::Version::V5_0 => WasmMqttPacket::new_unsubscribe_v50(options),
-            _ => WasmMqttPacket::new_unsubscribe_v311(options),
-        }
+        self.packet_factory().new_unsubscribe_packet(options)
     }
 
     /// Create Puback packet (version-aware)
@@ -2355,83 +4822,31 @@ impl WasmMqttClient {
     /// For V5.0: packet_id, reason_code, reason_string, user_properties are used
     #[wasm_bindgen(js_name = newPubackPacket)]
     pub fn new_puback_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        match self.version {
-            mqtt::Version::V3_1_1 => {
-                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
-                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
-                WasmMqttPacket::new_puback_v311(opts.packet_id)
-            }
-            mqtt::Version::V5_0 => WasmMqttPacket::new_puback_v50(options),
-            _ => {
-                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
-                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
-                WasmMqttPacket::new_puback_v311(opts.packet_id)
-            }
-        }
+        self.packet_factory().new_puback_packet(options)
     }
 
     /// Create Pubrec packet (version-aware)
     #[wasm_bindgen(js_name = newPubrecPacket)]
     pub fn new_pubrec_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        match self.version {
-            mqtt::Version::V3_1_1 => {
-                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
-                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
-                WasmMqttPacket::new_pubrec_v311(opts.packet_id)
-            }
-            mqtt::Version::V5_0 => WasmMqttPacket::new_pubrec_v50(options),
-            _ => {
-                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
-                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
-                WasmMqttPacket::new_pubrec_v311(opts.packet_id)
-            }
-        }
+        self.packet_factory().new_pubrec_packet(options)
     }
 
     /// Create Pubrel packet (version-aware)
     #[wasm_bindgen(js_name = newPubrelPacket)]
     pub fn new_pubrel_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        match self.version {
-            mqtt::Version::V3_1_1 => {
-                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
-                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
-                WasmMqttPacket::new_pubrel_v311(opts.packet_id)
-            }
-            mqtt::Version::V5_0 => WasmMqttPacket::new_pubrel_v50(options),
-            _ => {
-                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
-                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
-                WasmMqttPacket::new_pubrel_v311(opts.packet_id)
-            }
-        }
+        self.packet_factory().new_pubrel_packet(options)
     }
 
     /// Create Pubcomp packet (version-aware)
     #[wasm_bindgen(js_name = newPubcompPacket)]
     pub fn new_pubcomp_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        match self.version {
-            mqtt::Version::V3_1_1 => {
-                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
-                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
-                WasmMqttPacket::new_pubcomp_v311(opts.packet_id)
-            }
-            mqtt::Version::V5_0 => WasmMqttPacket::new_pubcomp_v50(options),
-            _ => {
-                let opts: PubResponseOptions = serde_wasm_bindgen::from_value(options)
-                    .map_err(|e| JsValue::from_str(&format!("Invalid options: {:?}", e)))?;
-                WasmMqttPacket::new_pubcomp_v311(opts.packet_id)
-            }
-        }
+        self.packet_factory().new_pubcomp_packet(options)
     }
 
     /// Create Pingreq packet (version-aware)
     #[wasm_bindgen(js_name = newPingreqPacket)]
     pub fn new_pingreq_packet(&self) -> WasmMqttPacket {
-        match self.version {
-            mqtt::Version::V3_1_1 => WasmMqttPacket::new_pingreq_v311(),
-            mqtt::Version::V5_0 => WasmMqttPacket::new_pingreq_v50(),
-            _ => WasmMqttPacket::new_pingreq_v311(),
-        }
+        self.packet_factory().new_pingreq_packet()
     }
 
     /// Create Disconnect packet (version-aware)
@@ -2440,30 +4855,14 @@ impl WasmMqttClient {
     /// Pass undefined/null for default options
     #[wasm_bindgen(js_name = newDisconnectPacket)]
     pub fn new_disconnect_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        match self.version {
-            mqtt::Version::V3_1_1 => Ok(WasmMqttPacket::new_disconnect_v311()),
-            mqtt::Version::V5_0 => {
-                let opts = if options.is_undefined() || options.is_null() {
-                    js_sys::Object::new().into()
-                } else {
-                    options
-                };
-                WasmMqttPacket::new_disconnect_v50(opts)
-            }
-            _ => Ok(WasmMqttPacket::new_disconnect_v311()),
-        }
+        self.packet_factory().new_disconnect_packet(options)
     }
 
     /// Create Auth packet (V5.0 only)
     /// Returns error if called on V3.1.1 client
     #[wasm_bindgen(js_name = newAuthPacket)]
     pub fn new_auth_packet(&self, options: JsValue) -> Result<WasmMqttPacket, JsValue> {
-        match self.version {
-            mqtt::Version::V5_0 => WasmMqttPacket::new_auth_v50(options),
-            _ => Err(JsValue::from_str(
-                "AUTH packet is only available in MQTT v5.0",
-            )),
-        }
+        self.packet_factory().new_auth_packet(options)
     }
 
     // ------------------------------------------------------------------------
@@ -2716,10 +5115,28 @@ impl WasmMqttClient {
 /// Non-wasm_bindgen methods for internal use
 impl WasmMqttClient {
     /// Create a WasmMqttClient from an existing MqttClient (internal use)
-    pub fn from_client(client: MqttClient, version: mqtt::Version) -> WasmMqttClient {
+    pub fn from_client(
+        client: MqttClient,
+        version: mqtt::Version,
+        strict_version: bool,
+    ) -> WasmMqttClient {
         WasmMqttClient {
             inner: client,
             version,
+            strict_version,
+            auth_callback: Rc::new(RefCell::new(None)),
+            event_callback: Rc::new(RefCell::new(None)),
+            event_listener_stop: Rc::new(RefCell::new(None)),
+            state_change_callback: Rc::new(RefCell::new(None)),
+            state_listener_stop: Rc::new(RefCell::new(None)),
+        }
+    }
+
+    /// Packet factory for this client's protocol version
+    fn packet_factory(&self) -> WasmPacketFactory {
+        WasmPacketFactory {
+            version: self.version,
+            strict_version: self.strict_version,
         }
     }
 }
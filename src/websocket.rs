@@ -10,6 +10,18 @@ use futures::channel::{mpsc, oneshot};
 pub type ConnectReplySender =
     std::sync::Arc<std::sync::Mutex<Option<oneshot::Sender<Result<(), crate::error::Error>>>>>;
 
+/// Type alias for reply senders carried by query commands
+pub type CommandReplySender<T> = std::sync::Arc<std::sync::Mutex<Option<oneshot::Sender<T>>>>;
+
+/// Complete a command reply (no-op if it was already completed)
+pub fn complete_reply<T>(reply: &CommandReplySender<T>, value: T) {
+    if let Ok(mut reply_opt) = reply.lock() {
+        if let Some(reply) = reply_opt.take() {
+            let _ = reply.send(value);
+        }
+    }
+}
+
 /// Underlying layer events (sent FROM transport TO message loop)
 #[derive(Debug, Clone)]
 pub enum UnderlyingLayerEvent {
@@ -17,6 +29,11 @@ pub enum UnderlyingLayerEvent {
     Message(Vec<u8>),
     Error(String),
     Closed,
+    /// Close code and reason reported by the transport, sent just before `Closed`
+    CloseInfo {
+        code: u16,
+        reason: String,
+    },
     /// Timer expired event
     /// The String is the timer kind (e.g., "PingreqSend")
     TimerExpired(String),
@@ -26,7 +43,12 @@ pub enum UnderlyingLayerEvent {
 #[derive(Debug, Clone)]
 pub enum UnderlyingLayerCommand {
     Connect(String, ConnectReplySender),
-    SendData(Vec<u8>),
+    /// Write bytes to the transport; the reply, if any, receives the outcome of
+    /// the write (e.g. `Error::SendFailed` when the WebSocket refused the data)
+    SendData(
+        Vec<u8>,
+        Option<CommandReplySender<Result<(), crate::error::Error>>>,
+    ),
     Close,
     /// Start or reset a timer
     /// When the timer expires, the underlying layer should send TimerExpired event
@@ -38,6 +60,10 @@ pub enum UnderlyingLayerCommand {
     TimerCancel {
         kind: String,
     },
+    /// Query the number of bytes queued but not yet sent by the transport
+    QueryBufferedAmount(CommandReplySender<Result<u64, crate::error::Error>>),
+    /// Query the transport's WebSocket readyState (0 CONNECTING, 1 OPEN, 2 CLOSING, 3 CLOSED)
+    QueryReadyState(CommandReplySender<Result<u8, crate::error::Error>>),
 }
 
 /// Abstract underlying layer interface for testing (pure message-passing)
@@ -77,6 +103,83 @@ pub struct BrowserWebSocket {
     command_receiver: mpsc::UnboundedReceiver<UnderlyingLayerCommand>,
     /// Active timers: kind -> timer_id
     active_timers: std::collections::HashMap<String, i32>,
+    /// Log prefix of the owning client (`MqttConfig::instance_label`)
+    instance_label: Option<String>,
+    /// Already open WebSocket adopted at the start of `run()`
+    existing: Option<web_sys::WebSocket>,
+}
+
+/// A received binary frame, in arrival order
+#[cfg(target_arch = "wasm32")]
+enum InboundFrame {
+    /// Blob still being read
+    Pending,
+    Ready(Vec<u8>),
+    /// Blob that could not be read; the error was already reported
+    Failed,
+}
+
+/// Keeps inbound frames in arrival order while Blob frames are read
+///
+/// A Blob can only be read asynchronously, so frames arriving after it wait
+/// here until it is ready. Without Blobs every frame passes straight through.
+#[cfg(target_arch = "wasm32")]
+#[derive(Default)]
+struct InboundFrames {
+    /// Sequence number of the first entry in `frames`
+    first_seq: u64,
+    frames: std::collections::VecDeque<InboundFrame>,
+}
+
+#[cfg(target_arch = "wasm32")]
+impl InboundFrames {
+    /// Append a frame and return its sequence number
+    fn push(&mut self, frame: InboundFrame) -> u64 {
+        self.frames.push_back(frame);
+        self.first_seq + self.frames.len() as u64 - 1
+    }
+
+    /// Settle a pending frame
+    fn settle(&mut self, seq: u64, frame: InboundFrame) {
+        if let Some(slot) = seq
+            .checked_sub(self.first_seq)
+            .and_then(|index| self.frames.get_mut(index as usize))
+        {
+            *slot = frame;
+        }
+    }
+
+    /// Take the frames that are ready, up to the first one still pending
+    fn take_ready(&mut self) -> Vec<Vec<u8>> {
+        let mut ready = Vec::new();
+        while let Some(frame) = self.frames.front() {
+            if matches!(frame, InboundFrame::Pending) {
+                break;
+            }
+            self.first_seq += 1;
+            if let Some(InboundFrame::Ready(data)) = self.frames.pop_front() {
+                ready.push(data);
+            }
+        }
+        ready
+    }
+}
+
+/// Pass received frames on to the message loop as Message events
+#[cfg(target_arch = "wasm32")]
+fn forward_frames(
+    event_sender: &mpsc::UnboundedSender<UnderlyingLayerEvent>,
+    label: Option<&str>,
+    frames: Vec<Vec<u8>>,
+) {
+    use crate::log_labeled;
+
+    for data in frames {
+        match event_sender.unbounded_send(UnderlyingLayerEvent::Message(data)) {
+            Ok(_) => log_labeled!(label, "Sent Message event successfully"),
+            Err(e) => log_labeled!(label, "Failed to send Message event: {:?}", e),
+        }
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -91,8 +194,159 @@ impl BrowserWebSocket {
             command_sender,
             command_receiver,
             active_timers: std::collections::HashMap::new(),
+            instance_label: None,
+            existing: None,
         }
     }
+
+    /// Prefix this transport's log lines with `[label]`
+    pub fn with_instance_label(mut self, label: Option<String>) -> Self {
+        self.instance_label = label;
+        self
+    }
+
+    /// Use a WebSocket the app already opened instead of connecting one
+    ///
+    /// `run()` takes the socket over and reports Connected without a Connect
+    /// command. A later Connect opens a new WebSocket as usual.
+    pub fn with_existing(mut self, ws: web_sys::WebSocket) -> Self {
+        self.existing = Some(ws);
+        self
+    }
+
+    /// Forward a WebSocket's messages, errors and close as UnderlyingLayerEvents
+    ///
+    /// The closures are kept in `closures` for as long as the handlers are set.
+    fn attach_handlers(
+        &self,
+        ws: &web_sys::WebSocket,
+        closures: &mut Vec<wasm_bindgen::closure::Closure<dyn FnMut(wasm_bindgen::JsValue)>>,
+    ) {
+        use crate::log_labeled;
+        use wasm_bindgen::prelude::*;
+        use wasm_bindgen::JsCast;
+        use web_sys::{ErrorEvent, MessageEvent};
+
+        // onmessage
+        let event_sender_clone = self.event_sender.clone();
+        let label = self.instance_label.clone();
+        let inbound = std::rc::Rc::new(std::cell::RefCell::new(InboundFrames::default()));
+        log_labeled!(self.instance_label.as_deref(), "Creating onmessage closure");
+        let onmessage = Closure::wrap(Box::new(move |e: JsValue| {
+            log_labeled!(label.as_deref(), "WebSocket onmessage fired");
+            let event: MessageEvent = e.dyn_into().unwrap();
+            let data = event.data();
+            if let Some(array_buffer) = data.dyn_ref::<js_sys::ArrayBuffer>() {
+                let data = js_sys::Uint8Array::new(array_buffer).to_vec();
+                log_labeled!(label.as_deref(), "Received {} bytes", data.len());
+                let ready = {
+                    let mut inbound = inbound.borrow_mut();
+                    inbound.push(InboundFrame::Ready(data));
+                    inbound.take_ready()
+                };
+                forward_frames(&event_sender_clone, label.as_deref(), ready);
+            } else if let Some(blob) = data.dyn_ref::<web_sys::Blob>() {
+                // binaryType was changed to "blob"; read it without reordering frames
+                log_labeled!(label.as_deref(), "Received a Blob, reading it");
+                let seq = inbound.borrow_mut().push(InboundFrame::Pending);
+                let read = wasm_bindgen_futures::JsFuture::from(blob.array_buffer());
+                let inbound = inbound.clone();
+                let event_sender = event_sender_clone.clone();
+                let label = label.clone();
+                wasm_bindgen_futures::spawn_local(async move {
+                    let frame = match read.await {
+                        Ok(array_buffer) => {
+                            InboundFrame::Ready(js_sys::Uint8Array::new(&array_buffer).to_vec())
+                        }
+                        Err(e) => {
+                            let _ = event_sender.unbounded_send(UnderlyingLayerEvent::Error(
+                                format!("Failed to read Blob message: {:?}", e),
+                            ));
+                            InboundFrame::Failed
+                        }
+                    };
+                    let ready = {
+                        let mut inbound = inbound.borrow_mut();
+                        inbound.settle(seq, frame);
+                        inbound.take_ready()
+                    };
+                    forward_frames(&event_sender, label.as_deref(), ready);
+                });
+            } else if data.is_string() {
+                // MQTT over WebSocket is binary only; a text frame cannot be parsed
+                let _ = event_sender_clone.unbounded_send(UnderlyingLayerEvent::Error(
+                    "Received a text WebSocket frame; MQTT requires binary frames".to_string(),
+                ));
+            } else {
+                let _ = event_sender_clone.unbounded_send(UnderlyingLayerEvent::Error(format!(
+                    "Received a WebSocket message of unsupported type: {:?}",
+                    data
+                )));
+            }
+        }) as Box<dyn FnMut(JsValue)>);
+        log_labeled!(
+            self.instance_label.as_deref(),
+            "onmessage closure created, setting on WebSocket"
+        );
+        ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
+        log_labeled!(
+            self.instance_label.as_deref(),
+            "onmessage set on WebSocket, pushing to closures vec"
+        );
+        closures.push(onmessage);
+        log_labeled!(
+            self.instance_label.as_deref(),
+            "onmessage closure pushed to vec"
+        );
+
+        // onerror - with detailed logging
+        let event_sender_clone = self.event_sender.clone();
+        let label = self.instance_label.clone();
+        let onerror = Closure::wrap(Box::new(move |e: JsValue| {
+            log_labeled!(label.as_deref(), "WebSocket onerror fired");
+            log_labeled!(label.as_deref(), "Error event: {:?}", e);
+
+            let error_msg = if let Ok(error_event) = e.dyn_into::<ErrorEvent>() {
+                let msg = error_event.message();
+                log_labeled!(label.as_deref(), "ErrorEvent message: {}", msg);
+                msg
+            } else {
+                log_labeled!(label.as_deref(), "Not an ErrorEvent - unknown error");
+                "Unknown WebSocket error".to_string()
+            };
+            let _ = event_sender_clone.unbounded_send(UnderlyingLayerEvent::Error(error_msg));
+        }) as Box<dyn FnMut(JsValue)>);
+        ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
+        closures.push(onerror);
+
+        // onclose
+        let event_sender_clone = self.event_sender.clone();
+        let label = self.instance_label.clone();
+        let onclose = Closure::wrap(Box::new(move |e: JsValue| {
+            log_labeled!(label.as_deref(), "WebSocket onclose fired");
+            log_labeled!(label.as_deref(), "Close event: {:?}", e);
+
+            // Try to get close details
+            if let Ok(close_event) = e.dyn_into::<web_sys::CloseEvent>() {
+                let code = close_event.code();
+                let reason = close_event.reason();
+                let was_clean = close_event.was_clean();
+                log_labeled!(
+                    label.as_deref(),
+                    "Close code: {}, reason: '{}', clean: {}",
+                    code,
+                    reason,
+                    was_clean
+                );
+                let _ = event_sender_clone
+                    .unbounded_send(UnderlyingLayerEvent::CloseInfo { code, reason });
+            }
+
+            let _ = event_sender_clone.unbounded_send(UnderlyingLayerEvent::Closed);
+        }) as Box<dyn FnMut(JsValue)>);
+        ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
+        closures.push(onclose);
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
@@ -110,12 +364,16 @@ impl UnderlyingLayerInterface for BrowserWebSocket {
     }
 
     async fn run(&mut self) {
+        use crate::log_labeled;
         use futures::stream::StreamExt;
         use wasm_bindgen::prelude::*;
         use wasm_bindgen::JsCast;
-        use web_sys::{BinaryType, ErrorEvent, MessageEvent, WebSocket};
+        use web_sys::{BinaryType, WebSocket};
 
-        web_sys::console::log_1(&"🚀 WEBSOCKET: NEW VERSION - CONNECT TIMING FIXED 🚀".into());
+        log_labeled!(
+            self.instance_label.as_deref(),
+            "🚀 WEBSOCKET: NEW VERSION - CONNECT TIMING FIXED 🚀"
+        );
 
         let mut websocket: Option<web_sys::WebSocket> = None;
         let mut _closures: Vec<wasm_bindgen::closure::Closure<dyn FnMut(wasm_bindgen::JsValue)>> =
@@ -123,16 +381,41 @@ impl UnderlyingLayerInterface for BrowserWebSocket {
         let _is_connected = false;
         let _pending_data: Vec<Vec<u8>> = Vec::new();
 
-        web_sys::console::log_1(&"WebSocket processor waiting for commands".into());
+        if let Some(ws) = self.existing.take() {
+            log_labeled!(
+                self.instance_label.as_deref(),
+                "Adopting existing WebSocket"
+            );
+            ws.set_binary_type(BinaryType::Arraybuffer);
+            self.attach_handlers(&ws, &mut _closures);
+            websocket = Some(ws);
+            let _ = self
+                .event_sender
+                .unbounded_send(UnderlyingLayerEvent::Connected);
+        }
+
+        log_labeled!(
+            self.instance_label.as_deref(),
+            "WebSocket processor waiting for commands"
+        );
 
         while let Some(command) = self.command_receiver.next().await {
-            web_sys::console::log_1(
-                &format!("WebSocket processor received command: {:?}", command).into(),
+            log_labeled!(
+                self.instance_label.as_deref(),
+                "WebSocket processor received command: {:?}",
+                command
             );
             match command {
                 UnderlyingLayerCommand::Connect(url, reply_arc) => {
-                    web_sys::console::log_1(&format!("WebSocket connecting to: {}", url).into());
-                    web_sys::console::log_1(&"✅ Received Connect command with reply_arc".into());
+                    log_labeled!(
+                        self.instance_label.as_deref(),
+                        "WebSocket connecting to: {}",
+                        url
+                    );
+                    log_labeled!(
+                        self.instance_label.as_deref(),
+                        "✅ Received Connect command with reply_arc"
+                    );
 
                     // MQTT subprotocol is required
                     let protocols = js_sys::Array::new();
@@ -141,45 +424,60 @@ impl UnderlyingLayerInterface for BrowserWebSocket {
 
                     match ws_result {
                         Ok(ws) => {
-                            web_sys::console::log_1(&"WebSocket created successfully".into());
+                            log_labeled!(
+                                self.instance_label.as_deref(),
+                                "WebSocket created successfully"
+                            );
                             ws.set_binary_type(BinaryType::Arraybuffer);
-                            web_sys::console::log_1(&"Binary type set to ArrayBuffer".into());
+                            log_labeled!(
+                                self.instance_label.as_deref(),
+                                "Binary type set to ArrayBuffer"
+                            );
 
                             let event_sender = self.event_sender.clone();
-                            web_sys::console::log_1(&"Event sender cloned for closures".into());
+                            log_labeled!(
+                                self.instance_label.as_deref(),
+                                "Event sender cloned for closures"
+                            );
 
                             // onopen
                             let event_sender_clone = event_sender.clone();
                             let reply_arc_clone = reply_arc.clone();
-                            web_sys::console::log_1(&"Creating onopen closure".into());
+                            let label = self.instance_label.clone();
+                            log_labeled!(self.instance_label.as_deref(), "Creating onopen closure");
                             let onopen = Closure::wrap(Box::new(move |_: JsValue| {
-                                web_sys::console::log_1(
-                                    &"🔥 NEW WEBSOCKET: WebSocket onopen fired 🔥".into(),
+                                log_labeled!(
+                                    label.as_deref(),
+                                    "🔥 NEW WEBSOCKET: WebSocket onopen fired 🔥"
                                 );
 
                                 // Send reply to complete the connect() await
-                                web_sys::console::log_1(
-                                    &"Attempting to lock reply_arc in onopen".into(),
+                                log_labeled!(
+                                    label.as_deref(),
+                                    "Attempting to lock reply_arc in onopen"
                                 );
                                 match reply_arc_clone.lock() {
                                     Ok(mut reply_opt) => {
-                                        web_sys::console::log_1(
-                                            &"Successfully locked reply_arc".into(),
+                                        log_labeled!(
+                                            label.as_deref(),
+                                            "Successfully locked reply_arc"
                                         );
                                         if let Some(reply) = reply_opt.take() {
                                             match reply.send(Ok(())) {
-                                                Ok(_) => web_sys::console::log_1(&"✅ Sent connect completion reply successfully".into()),
-                                                Err(_) => web_sys::console::log_1(&"❌ Failed to send connect completion reply - receiver dropped".into()),
+                                                Ok(_) => log_labeled!(label.as_deref(), "✅ Sent connect completion reply successfully"),
+                                                Err(_) => log_labeled!(label.as_deref(), "❌ Failed to send connect completion reply - receiver dropped"),
                                             }
                                         } else {
-                                            web_sys::console::log_1(
-                                                &"❌ No reply sender in Option".into(),
+                                            log_labeled!(
+                                                label.as_deref(),
+                                                "❌ No reply sender in Option"
                                             );
                                         }
                                     }
                                     Err(_) => {
-                                        web_sys::console::log_1(
-                                            &"❌ Failed to lock reply_arc".into(),
+                                        log_labeled!(
+                                            label.as_deref(),
+                                            "❌ Failed to lock reply_arc"
                                         );
                                     }
                                 }
@@ -187,116 +485,34 @@ impl UnderlyingLayerInterface for BrowserWebSocket {
                                 match event_sender_clone
                                     .unbounded_send(UnderlyingLayerEvent::Connected)
                                 {
-                                    Ok(_) => web_sys::console::log_1(
-                                        &"Sent Connected event successfully".into(),
+                                    Ok(_) => log_labeled!(
+                                        label.as_deref(),
+                                        "Sent Connected event successfully"
                                     ),
-                                    Err(e) => web_sys::console::log_1(
-                                        &format!("Failed to send Connected event: {:?}", e).into(),
+                                    Err(e) => log_labeled!(
+                                        label.as_deref(),
+                                        "Failed to send Connected event: {:?}",
+                                        e
                                     ),
                                 }
                             })
                                 as Box<dyn FnMut(JsValue)>);
-                            web_sys::console::log_1(
-                                &"onopen closure created, setting on WebSocket".into(),
+                            log_labeled!(
+                                self.instance_label.as_deref(),
+                                "onopen closure created, setting on WebSocket"
                             );
                             ws.set_onopen(Some(onopen.as_ref().unchecked_ref()));
-                            web_sys::console::log_1(
-                                &"onopen set on WebSocket, pushing to closures vec".into(),
+                            log_labeled!(
+                                self.instance_label.as_deref(),
+                                "onopen set on WebSocket, pushing to closures vec"
                             );
                             _closures.push(onopen);
-                            web_sys::console::log_1(&"onopen closure pushed to vec".into());
-
-                            // onmessage
-                            let event_sender_clone = event_sender.clone();
-                            web_sys::console::log_1(&"Creating onmessage closure".into());
-                            let onmessage = Closure::wrap(Box::new(move |e: JsValue| {
-                                web_sys::console::log_1(&"WebSocket onmessage fired".into());
-                                let event: MessageEvent = e.dyn_into().unwrap();
-                                if let Ok(array_buffer) =
-                                    event.data().dyn_into::<js_sys::ArrayBuffer>()
-                                {
-                                    let uint8_array = js_sys::Uint8Array::new(&array_buffer);
-                                    let mut data = vec![0; uint8_array.length() as usize];
-                                    uint8_array.copy_to(&mut data);
-                                    web_sys::console::log_1(
-                                        &format!("Received {} bytes", data.len()).into(),
-                                    );
-                                    match event_sender_clone
-                                        .unbounded_send(UnderlyingLayerEvent::Message(data))
-                                    {
-                                        Ok(_) => web_sys::console::log_1(
-                                            &"Sent Message event successfully".into(),
-                                        ),
-                                        Err(e) => web_sys::console::log_1(
-                                            &format!("Failed to send Message event: {:?}", e)
-                                                .into(),
-                                        ),
-                                    }
-                                }
-                            })
-                                as Box<dyn FnMut(JsValue)>);
-                            web_sys::console::log_1(
-                                &"onmessage closure created, setting on WebSocket".into(),
-                            );
-                            ws.set_onmessage(Some(onmessage.as_ref().unchecked_ref()));
-                            web_sys::console::log_1(
-                                &"onmessage set on WebSocket, pushing to closures vec".into(),
+                            log_labeled!(
+                                self.instance_label.as_deref(),
+                                "onopen closure pushed to vec"
                             );
-                            _closures.push(onmessage);
-                            web_sys::console::log_1(&"onmessage closure pushed to vec".into());
-
-                            // onerror - with detailed logging
-                            let event_sender_clone = event_sender.clone();
-                            let onerror = Closure::wrap(Box::new(move |e: JsValue| {
-                                web_sys::console::log_1(&"WebSocket onerror fired".into());
-                                web_sys::console::log_1(&format!("Error event: {:?}", e).into());
-
-                                let error_msg = if let Ok(error_event) = e.dyn_into::<ErrorEvent>()
-                                {
-                                    let msg = error_event.message();
-                                    web_sys::console::log_1(
-                                        &format!("ErrorEvent message: {}", msg).into(),
-                                    );
-                                    msg
-                                } else {
-                                    web_sys::console::log_1(
-                                        &"Not an ErrorEvent - unknown error".into(),
-                                    );
-                                    "Unknown WebSocket error".to_string()
-                                };
-                                let _ = event_sender_clone
-                                    .unbounded_send(UnderlyingLayerEvent::Error(error_msg));
-                            })
-                                as Box<dyn FnMut(JsValue)>);
-                            ws.set_onerror(Some(onerror.as_ref().unchecked_ref()));
-                            _closures.push(onerror);
 
-                            // onclose
-                            let event_sender_clone = event_sender.clone();
-                            let onclose = Closure::wrap(Box::new(move |e: JsValue| {
-                                web_sys::console::log_1(&"WebSocket onclose fired".into());
-                                web_sys::console::log_1(&format!("Close event: {:?}", e).into());
-
-                                // Try to get close details
-                                if let Ok(close_event) = e.dyn_into::<web_sys::CloseEvent>() {
-                                    let code = close_event.code();
-                                    let reason = close_event.reason();
-                                    let was_clean = close_event.was_clean();
-                                    web_sys::console::log_1(
-                                        &format!(
-                                            "Close code: {}, reason: '{}', clean: {}",
-                                            code, reason, was_clean
-                                        )
-                                        .into(),
-                                    );
-                                }
-
-                                let _ =
-                                    event_sender_clone.unbounded_send(UnderlyingLayerEvent::Closed);
-                            })
-                                as Box<dyn FnMut(JsValue)>);
-                            ws.set_onclose(Some(onclose.as_ref().unchecked_ref()));
-                            _closures.push(onclose);
+                            self.attach_handlers(&ws, &mut _closures);
 
                             websocket = Some(ws);
                         }
@@ -310,24 +526,39 @@ impl UnderlyingLayerInterface for BrowserWebSocket {
                         }
                     }
                 }
-                UnderlyingLayerCommand::SendData(data) => {
-                    web_sys::console::log_1(
-                        &format!("WebSocket SendData command: {} bytes", data.len()).into(),
+                UnderlyingLayerCommand::SendData(data, reply) => {
+                    log_labeled!(
+                        self.instance_label.as_deref(),
+                        "WebSocket SendData command: {} bytes",
+                        data.len()
                     );
                     if let Some(ref ws) = websocket {
-                        web_sys::console::log_1(
-                            &"WebSocket is available, attempting to send".into(),
+                        log_labeled!(
+                            self.instance_label.as_deref(),
+                            "WebSocket is available, attempting to send"
                         );
                         match ws.send_with_u8_array(&data) {
                             Ok(_) => {
-                                web_sys::console::log_1(
-                                    &"WebSocket send_with_u8_array succeeded".into(),
+                                log_labeled!(
+                                    self.instance_label.as_deref(),
+                                    "WebSocket send_with_u8_array succeeded"
                                 );
+                                if let Some(reply) = reply {
+                                    complete_reply(&reply, Ok(()));
+                                }
                             }
                             Err(e) => {
-                                web_sys::console::log_1(
-                                    &format!("WebSocket send_with_u8_array failed: {:?}", e).into(),
+                                log_labeled!(
+                                    self.instance_label.as_deref(),
+                                    "WebSocket send_with_u8_array failed: {:?}",
+                                    e
                                 );
+                                if let Some(reply) = reply {
+                                    complete_reply(
+                                        &reply,
+                                        Err(crate::error::Error::SendFailed(format!("{:?}", e))),
+                                    );
+                                }
                                 let _ =
                                     self.event_sender
                                         .unbounded_send(UnderlyingLayerEvent::Error(format!(
@@ -337,7 +568,18 @@ impl UnderlyingLayerInterface for BrowserWebSocket {
                             }
                         }
                     } else {
-                        web_sys::console::log_1(&"WebSocket not available for sending".into());
+                        log_labeled!(
+                            self.instance_label.as_deref(),
+                            "WebSocket not available for sending"
+                        );
+                        if let Some(reply) = reply {
+                            complete_reply(
+                                &reply,
+                                Err(crate::error::Error::SendFailed(
+                                    "WebSocket not connected".to_string(),
+                                )),
+                            );
+                        }
                         let _ = self
                             .event_sender
                             .unbounded_send(UnderlyingLayerEvent::Error(
@@ -366,9 +608,11 @@ impl UnderlyingLayerInterface for BrowserWebSocket {
                 UnderlyingLayerCommand::TimerReset { kind, duration_ms } => {
                     // Cancel existing timer if any
                     if let Some(old_timer_id) = self.active_timers.remove(&kind) {
-                        web_sys::console::log_1(
-                            &format!("Cancelling existing timer {} (ID: {})", kind, old_timer_id)
-                                .into(),
+                        log_labeled!(
+                            self.instance_label.as_deref(),
+                            "Cancelling existing timer {} (ID: {})",
+                            kind,
+                            old_timer_id
                         );
                         crate::platform::clear_timeout(old_timer_id);
                     }
@@ -376,8 +620,9 @@ impl UnderlyingLayerInterface for BrowserWebSocket {
                     // Create new timer
                     let event_sender = self.event_sender.clone();
                     let timer_kind = kind.clone();
+                    let label = self.instance_label.clone();
                     let callback = wasm_bindgen::closure::Closure::wrap(Box::new(move || {
-                        web_sys::console::log_1(&format!("Timer expired: {}", timer_kind).into());
+                        log_labeled!(label.as_deref(), "Timer expired: {}", timer_kind);
                         let _ = event_sender
                             .unbounded_send(UnderlyingLayerEvent::TimerExpired(timer_kind.clone()));
                     })
@@ -387,26 +632,46 @@ impl UnderlyingLayerInterface for BrowserWebSocket {
                     callback.forget();
 
                     self.active_timers.insert(kind.clone(), timer_id);
-                    web_sys::console::log_1(
-                        &format!(
-                            "Timer set: {} (ID: {}) for {}ms",
-                            kind, timer_id, duration_ms
-                        )
-                        .into(),
+                    log_labeled!(
+                        self.instance_label.as_deref(),
+                        "Timer set: {} (ID: {}) for {}ms",
+                        kind,
+                        timer_id,
+                        duration_ms
                     );
                 }
                 UnderlyingLayerCommand::TimerCancel { kind } => {
                     if let Some(timer_id) = self.active_timers.remove(&kind) {
-                        web_sys::console::log_1(
-                            &format!("Timer cancelled: {} (ID: {})", kind, timer_id).into(),
+                        log_labeled!(
+                            self.instance_label.as_deref(),
+                            "Timer cancelled: {} (ID: {})",
+                            kind,
+                            timer_id
                         );
                         crate::platform::clear_timeout(timer_id);
                     } else {
-                        web_sys::console::log_1(
-                            &format!("Timer cancel requested but not active: {}", kind).into(),
+                        log_labeled!(
+                            self.instance_label.as_deref(),
+                            "Timer cancel requested but not active: {}",
+                            kind
                         );
                     }
                 }
+                UnderlyingLayerCommand::QueryBufferedAmount(reply) => {
+                    let result = match websocket {
+                        Some(ref ws) => Ok(ws.buffered_amount() as u64),
+                        None => Err(crate::error::Error::NotConnected),
+                    };
+                    complete_reply(&reply, result);
+                }
+                UnderlyingLayerCommand::QueryReadyState(reply) => {
+                    // No WebSocket has been created yet: report CLOSED
+                    let ready_state = match websocket {
+                        Some(ref ws) => ws.ready_state() as u8,
+                        None => web_sys::WebSocket::CLOSED as u8,
+                    };
+                    complete_reply(&reply, Ok(ready_state));
+                }
             }
         }
     }
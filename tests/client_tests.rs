@@ -404,6 +404,7 @@ async fn test_reconnect_after_close() {
     let config = MqttConfig {
         url: String::new(),
         version: client_mqtt::Version::V3_1_1,
+        client_id: Some("all-options-client".to_string()),
         pingreq_send_interval_ms: Some(30000), // Covered in reset_for_reconnection
         pingresp_recv_timeout_ms: 5000,
         auto_pub_response: false,
@@ -412,6 +413,30 @@ async fn test_reconnect_after_close() {
         auto_replace_topic_alias_send: true,
         connection_establish_timeout_ms: 10000,
         shutdown_timeout_ms: 5000,
+        max_undelivered_packets: 1024,
+        max_total_buffer_bytes: None,
+        reconnect_initial_delay_ms: None,
+        reconnect_max_delay_ms: 30000,
+        reconnect_max_attempts: None,
+        publish_ack_timeout_ms: None,
+        release_packet_id_on_ack_timeout: false,
+        packet_id_reuse_delay_ms: 0,
+        restore_subscriptions: false,
+        max_subscriptions: None,
+        error_on_connack_failure: false,
+        allow_recv_before_connect: false,
+        instance_label: None,
+        follow_server_reference: false,
+        strict_version: false,
+        max_publish_rate_per_sec: None,
+        pingreq_jitter_ms: None,
+        payload_stream_threshold: 65536,
+        payload_codec: None,
+        topic_rewrite: None,
+        send_inspector: None,
+        packet_tap: None,
+        payload_chunk_handler: None,
+        packet_id_allocator: None,
     };
     let mock_ws = MockUnderlyingLayer::new();
 
@@ -927,6 +952,26 @@ async fn test_register_packet_id() {
     assert!(!result2); // Should fail because already registered
 }
 
+/// Test register_packet_ids registers a batch and reports each ID
+#[tokio::test]
+async fn test_register_packet_ids() {
+    let config = MqttConfig::default();
+    let mock_ws = MockUnderlyingLayer::new();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    assert!(client.register_packet_id(2).await);
+
+    // 2 is taken already and the second 3 repeats the first
+    let results = client.register_packet_ids(&[1, 2, 3, 3]).await;
+    assert_eq!(results, vec![true, false, true, false]);
+    assert!(!client.register_packet_id(1).await);
+    assert!(!client.register_packet_id(3).await);
+}
+
 /// Test release_packet_id functionality
 #[tokio::test]
 async fn test_release_packet_id() {
@@ -951,6 +996,4686 @@ async fn test_release_packet_id() {
     assert!(packet_id2.is_some());
 }
 
+/// Test complete_qos only releases packet IDs whose QoS flow has finished
+#[tokio::test]
+async fn test_complete_qos() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        auto_pub_response: false, // Send PUBREL manually
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-complete-qos").await;
+
+    // Unknown packet ID
+    let result = client.complete_qos(42).await;
+    assert!(matches!(
+        result,
+        Err(mqtt_client_wasm::Error::PacketIdNotInUse(42))
+    ));
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/complete")
+        .unwrap()
+        .qos(mqtt::packet::Qos::ExactlyOnce)
+        .packet_id(packet_id)
+        .payload(b"QoS 2 message")
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Publish(publish))
+        .await;
+
+    // Nothing acknowledged yet
+    let result = client.complete_qos(packet_id).await;
+    assert!(matches!(
+        result,
+        Err(mqtt_client_wasm::Error::QosFlowIncomplete(id)) if id == packet_id
+    ));
+
+    // PUBREC received but PUBCOMP not
+    let pubrec = mqtt::packet::v3_1_1::Pubrec::builder()
+        .packet_id(packet_id)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Pubrec(pubrec).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+
+    let result = client.complete_qos(packet_id).await;
+    assert!(matches!(
+        result,
+        Err(mqtt_client_wasm::Error::QosFlowIncomplete(id)) if id == packet_id
+    ));
+
+    let pubrel = mqtt::packet::v3_1_1::Pubrel::builder()
+        .packet_id(packet_id)
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Pubrel(pubrel))
+        .await;
+
+    let pubcomp = mqtt::packet::v3_1_1::Pubcomp::builder()
+        .packet_id(packet_id)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Pubcomp(pubcomp).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+
+    // An ID handed out after PUBCOMP stays in use; complete_qos must not
+    // release it a second time
+    let next_id = client.acquire_packet_id().await.unwrap();
+
+    // Flow complete - ID is released exactly once
+    assert!(client.complete_qos(packet_id).await.is_ok());
+    assert!(matches!(
+        client.complete_qos(packet_id).await,
+        Err(mqtt_client_wasm::Error::PacketIdNotInUse(_))
+    ));
+    assert!(!client.register_packet_id(next_id).await);
+}
+
+/// Test buffered_amount is answered by the underlying layer
+#[tokio::test]
+async fn test_buffered_amount() {
+    let config = MqttConfig::default();
+    let mock_ws = MockUnderlyingLayer::new();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    // Not connected yet
+    let result = client.buffered_amount().await;
+    assert!(matches!(result, Err(mqtt_client_wasm::Error::NotConnected)));
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let result = client.buffered_amount().await;
+    assert_eq!(result.unwrap(), 0);
+}
+
+/// Test transport_ready_state() reports CLOSED before connecting and OPEN after
+#[tokio::test]
+async fn test_transport_ready_state() {
+    let mock_ws = MockUnderlyingLayer::new();
+    let client = MqttClient::new_with_websocket(MqttConfig::default(), mock_ws);
+
+    assert_eq!(client.transport_ready_state().await.unwrap(), 3);
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    assert_eq!(client.transport_ready_state().await.unwrap(), 1);
+
+    let _ = client.close().await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    assert_eq!(client.transport_ready_state().await.unwrap(), 3);
+}
+
+/// Test recv() delivers inbound packets in wire order while the app sends
+/// and auto PUBACKs are generated in between
+#[tokio::test]
+async fn test_recv_preserves_wire_order() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-wire-order").await;
+
+    let inbound = |i: u16| {
+        let builder = mqtt::packet::v3_1_1::Publish::builder()
+            .topic_name(&format!("test/order/{}", i))
+            .unwrap()
+            .payload(format!("message {}", i).as_bytes());
+        // Odd messages are QoS 1 so an auto PUBACK goes out in between
+        let publish = if i % 2 == 1 {
+            builder
+                .qos(mqtt::packet::Qos::AtLeastOnce)
+                .packet_id(i)
+                .build()
+                .unwrap()
+        } else {
+            builder.qos(mqtt::packet::Qos::AtMostOnce).build().unwrap()
+        };
+        mqtt::packet::Packet::V3_1_1Publish(publish).to_continuous_buffer()
+    };
+
+    let receive_all = async {
+        // All recv requests are queued before any packet arrives
+        futures::join!(
+            client.recv(),
+            client.recv(),
+            client.recv(),
+            client.recv(),
+            client.recv()
+        )
+    };
+    let traffic = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        for i in 0..5u16 {
+            let _ = event_sender
+                .unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(inbound(i)));
+
+            // Interleave an app send with the inbound traffic
+            let publish = mqtt::packet::v3_1_1::Publish::builder()
+                .topic_name("test/outbound")
+                .unwrap()
+                .qos(mqtt::packet::Qos::AtMostOnce)
+                .payload(b"outbound")
+                .build()
+                .unwrap();
+            let _ = client
+                .send(mqtt::packet::Packet::V3_1_1Publish(publish))
+                .await;
+        }
+    };
+
+    let (received, _) = tokio::time::timeout(tokio::time::Duration::from_millis(1000), async {
+        futures::join!(receive_all, traffic)
+    })
+    .await
+    .expect("recv should complete");
+
+    let received = [received.0, received.1, received.2, received.3, received.4];
+    for (i, result) in received.into_iter().enumerate() {
+        match result.unwrap() {
+            mqtt::packet::Packet::V3_1_1Publish(p) => {
+                assert_eq!(p.topic_name(), format!("test/order/{}", i));
+            }
+            other => panic!("Expected PUBLISH, got {:?}", other),
+        }
+    }
+}
+
+/// Test AUTH sent by the broker before CONNACK is delivered to recv_auth()
+#[tokio::test]
+async fn test_recv_auth_during_connect() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    // Register for AUTH packets before starting the handshake
+    let auth_client = client.clone();
+    let auth_task = tokio::spawn(async move { auth_client.recv_auth().await });
+
+    let connect_packet = mqtt::packet::v5_0::Connect::builder()
+        .client_id("test-enhanced-auth")
+        .unwrap()
+        .clean_start(true)
+        .props(mqtt::packet::Properties::from(vec![
+            mqtt::packet::Property::AuthenticationMethod(
+                mqtt::packet::AuthenticationMethod::new("SCRAM-SHA-256").unwrap(),
+            ),
+        ]))
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V5_0Connect(connect_packet))
+        .await;
+
+    // Broker continues authentication instead of sending CONNACK
+    let auth = mqtt::packet::v5_0::Auth::builder()
+        .reason_code(client_mqtt::result_code::AuthReasonCode::ContinueAuthentication)
+        .props(mqtt::packet::Properties::from(vec![
+            mqtt::packet::Property::AuthenticationMethod(
+                mqtt::packet::AuthenticationMethod::new("SCRAM-SHA-256").unwrap(),
+            ),
+        ]))
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Auth(auth).to_continuous_buffer(),
+    ));
+
+    let auth_result = tokio::time::timeout(tokio::time::Duration::from_millis(500), auth_task)
+        .await
+        .expect("AUTH should be delivered")
+        .unwrap();
+    assert!(matches!(
+        auth_result.unwrap(),
+        mqtt::packet::Packet::V5_0Auth(_)
+    ));
+
+    // Handshake completes on CONNACK, which still goes to recv()
+    let connack = mqtt::packet::v5_0::Connack::builder()
+        .session_present(false)
+        .reason_code(client_mqtt::result_code::ConnectReasonCode::Success)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Connack(connack).to_continuous_buffer(),
+    ));
+
+    let recv_result =
+        tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    assert!(matches!(
+        recv_result.unwrap().unwrap(),
+        mqtt::packet::Packet::V5_0Connack(_)
+    ));
+}
+
+/// Test recv_auth() waiters across a reconnection and after stop_recv_auth()
+#[tokio::test]
+async fn test_stop_recv_auth() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-stop-recv-auth").await;
+
+    // A reconnection ends the waiting call with ConnectionClosed
+    let auth_client = client.clone();
+    let auth_task = tokio::spawn(async move { auth_client.recv_auth().await });
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Closed);
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    common::establish_v50(&client, &event_sender, "test-stop-recv-auth").await;
+    let auth_result = tokio::time::timeout(tokio::time::Duration::from_millis(500), auth_task)
+        .await
+        .expect("recv_auth should end on reconnection")
+        .unwrap();
+    assert!(matches!(
+        auth_result,
+        Err(mqtt_client_wasm::Error::ConnectionClosed)
+    ));
+
+    // After stop_recv_auth() waiting calls fail and AUTH goes to recv()
+    let auth_client = client.clone();
+    let auth_task = tokio::spawn(async move { auth_client.recv_auth().await });
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    client.stop_recv_auth().await.unwrap();
+    let auth_result = tokio::time::timeout(tokio::time::Duration::from_millis(500), auth_task)
+        .await
+        .expect("recv_auth should end after stop_recv_auth")
+        .unwrap();
+    assert!(auth_result.is_err());
+
+    let auth = mqtt::packet::v5_0::Auth::builder()
+        .reason_code(client_mqtt::result_code::AuthReasonCode::ContinueAuthentication)
+        .props(mqtt::packet::Properties::from(vec![
+            mqtt::packet::Property::AuthenticationMethod(
+                mqtt::packet::AuthenticationMethod::new("SCRAM-SHA-256").unwrap(),
+            ),
+        ]))
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Auth(auth).to_continuous_buffer(),
+    ));
+    let recv_result =
+        tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    assert!(matches!(
+        recv_result.unwrap().unwrap(),
+        mqtt::packet::Packet::V5_0Auth(_)
+    ));
+}
+
+/// Test rtt_stats records PINGREQ/PINGRESP round trips
+#[tokio::test]
+async fn test_rtt_stats() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-rtt").await;
+
+    assert_eq!(client.rtt_stats().await.sample_count, 0);
+
+    for _ in 0..2 {
+        let pingreq = mqtt::packet::v3_1_1::Pingreq::builder().build().unwrap();
+        let _ = client
+            .send(mqtt::packet::Packet::V3_1_1Pingreq(pingreq))
+            .await;
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+
+        let pingresp = mqtt::packet::v3_1_1::Pingresp::builder().build().unwrap();
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            mqtt::packet::Packet::V3_1_1Pingresp(pingresp).to_continuous_buffer(),
+        ));
+        let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    }
+
+    let stats = client.rtt_stats().await;
+    assert_eq!(stats.sample_count, 2);
+    assert!(stats.last_ms >= 10.0);
+    assert!(stats.max_ms >= stats.avg_ms);
+}
+
+/// Test codec that XORs every byte, so encode and decode are the same
+struct XorCodec;
+
+impl mqtt_client_wasm::PayloadCodec for XorCodec {
+    fn name(&self) -> &str {
+        "xor"
+    }
+
+    fn encode(&self, payload: &[u8]) -> std::result::Result<Vec<u8>, String> {
+        Ok(payload.iter().map(|b| b ^ 0x5a).collect())
+    }
+
+    fn decode(&self, payload: &[u8]) -> std::result::Result<Vec<u8>, String> {
+        Ok(payload.iter().map(|b| b ^ 0x5a).collect())
+    }
+}
+
+/// Test payload codec decodes marked PUBLISH payloads and leaves others alone
+#[tokio::test]
+async fn test_payload_codec_decode() {
+    use mqtt_protocol_core::mqtt::packet::Property;
+
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        payload_codec: Some(std::sync::Arc::new(XorCodec)),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let encoded: Vec<u8> = b"hello".iter().map(|b| b ^ 0x5a).collect();
+    let marked = mqtt::packet::v5_0::Publish::builder()
+        .topic_name("test/codec")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtMostOnce)
+        .payload(encoded)
+        .props(mqtt::packet::Properties::from(vec![
+            Property::UserProperty(
+                mqtt::packet::UserProperty::new(mqtt_client_wasm::PAYLOAD_CODEC_PROPERTY, "xor")
+                    .unwrap(),
+            ),
+        ]))
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Publish(marked).to_continuous_buffer(),
+    ));
+
+    let packet = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    if let mqtt::packet::Packet::V5_0Publish(p) = packet {
+        assert_eq!(p.payload().as_slice(), b"hello");
+        // Marker is removed after decoding
+        assert!(p.props.iter().all(|prop| !matches!(
+            prop,
+            Property::UserProperty(u) if u.key() == mqtt_client_wasm::PAYLOAD_CODEC_PROPERTY
+        )));
+    } else {
+        panic!("Expected v5.0 PUBLISH packet");
+    }
+
+    // Unmarked payloads are delivered unchanged
+    let plain = mqtt::packet::v5_0::Publish::builder()
+        .topic_name("test/codec")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtMostOnce)
+        .payload(b"plain")
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Publish(plain).to_continuous_buffer(),
+    ));
+
+    let packet = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    if let mqtt::packet::Packet::V5_0Publish(p) = packet {
+        assert_eq!(p.payload().as_slice(), b"plain");
+    } else {
+        panic!("Expected v5.0 PUBLISH packet");
+    }
+}
+
+/// Test codec whose decode always fails
+struct FailingCodec;
+
+impl mqtt_client_wasm::PayloadCodec for FailingCodec {
+    fn name(&self) -> &str {
+        "failing"
+    }
+
+    fn encode(&self, payload: &[u8]) -> std::result::Result<Vec<u8>, String> {
+        Ok(payload.to_vec())
+    }
+
+    fn decode(&self, _payload: &[u8]) -> std::result::Result<Vec<u8>, String> {
+        Err("corrupt payload".to_string())
+    }
+}
+
+/// Test a failed payload decode delivers the packet unchanged and emits a CodecError
+#[tokio::test]
+async fn test_payload_codec_decode_failure() {
+    use mqtt_protocol_core::mqtt::packet::Property;
+
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        payload_codec: Some(std::sync::Arc::new(FailingCodec)),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-codec-failure").await;
+
+    let marked = mqtt::packet::v5_0::Publish::builder()
+        .topic_name("test/codec")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtMostOnce)
+        .payload(b"garbled")
+        .props(mqtt::packet::Properties::from(vec![
+            Property::UserProperty(
+                mqtt::packet::UserProperty::new(
+                    mqtt_client_wasm::PAYLOAD_CODEC_PROPERTY,
+                    "failing",
+                )
+                .unwrap(),
+            ),
+        ]))
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Publish(marked).to_continuous_buffer(),
+    ));
+
+    let packet = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    if let mqtt::packet::Packet::V5_0Publish(p) = packet {
+        assert_eq!(p.payload().as_slice(), b"garbled");
+        // Marker is kept so the caller can tell the payload is still encoded
+        assert!(p.props.iter().any(|prop| matches!(
+            prop,
+            Property::UserProperty(u) if u.key() == mqtt_client_wasm::PAYLOAD_CODEC_PROPERTY
+        )));
+    } else {
+        panic!("Expected v5.0 PUBLISH packet");
+    }
+
+    let mut codec_error = None;
+    while let Ok(Ok(event)) =
+        tokio::time::timeout(tokio::time::Duration::from_millis(200), client.next_event()).await
+    {
+        if let mqtt_client_wasm::ConnectionEvent::Error(mqtt_client_wasm::Error::CodecError(
+            message,
+        )) = event
+        {
+            codec_error = Some(message);
+            break;
+        }
+    }
+    assert_eq!(codec_error.as_deref(), Some("corrupt payload"));
+}
+
+/// Test pause_recv holds inbound packets until resume_recv
+#[tokio::test]
+async fn test_pause_resume_recv() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    assert!(client.pause_recv().await.is_ok());
+
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/paused")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtMostOnce)
+        .payload(b"held back")
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Publish(publish).to_continuous_buffer(),
+    ));
+
+    // Nothing is delivered while paused
+    let recv_result =
+        tokio::time::timeout(tokio::time::Duration::from_millis(100), client.recv()).await;
+    assert!(recv_result.is_err());
+    assert!(client.is_connected().await);
+
+    assert!(client.resume_recv().await.is_ok());
+
+    let recv_result =
+        tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    match recv_result.unwrap().unwrap() {
+        mqtt::packet::Packet::V3_1_1Publish(p) => assert_eq!(p.topic_name(), "test/paused"),
+        other => panic!("Expected PUBLISH, got {:?}", other),
+    }
+}
+
+/// Test pause_recv holds only PUBLISH; PINGRESP behind it is handled but
+/// reaches recv() after it
+#[tokio::test]
+async fn test_pause_recv_handles_pingresp() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-pause-pingresp").await;
+
+    assert!(client.pause_recv().await.is_ok());
+
+    let pingreq = mqtt::packet::v3_1_1::Pingreq::builder().build().unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Pingreq(pingreq))
+        .await;
+
+    // PUBLISH and PINGRESP in one WebSocket message
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/paused")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtMostOnce)
+        .payload(b"held back")
+        .build()
+        .unwrap();
+    let pingresp = mqtt::packet::v3_1_1::Pingresp::builder().build().unwrap();
+    let mut bytes = mqtt::packet::Packet::V3_1_1Publish(publish).to_continuous_buffer();
+    bytes.extend(mqtt::packet::Packet::V3_1_1Pingresp(pingresp).to_continuous_buffer());
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(bytes));
+
+    // PINGRESP is handled while the PUBLISH waits, but not delivered ahead of it
+    let recv_result =
+        tokio::time::timeout(tokio::time::Duration::from_millis(100), client.recv()).await;
+    assert!(recv_result.is_err());
+    assert_eq!(client.rtt_stats().await.sample_count, 1);
+
+    assert!(client.resume_recv().await.is_ok());
+    let recv_result =
+        tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    match recv_result.unwrap().unwrap() {
+        mqtt::packet::Packet::V3_1_1Publish(p) => assert_eq!(p.topic_name(), "test/paused"),
+        other => panic!("Expected PUBLISH, got {:?}", other),
+    }
+    let recv_result =
+        tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    assert!(matches!(
+        recv_result.unwrap().unwrap(),
+        mqtt::packet::Packet::V3_1_1Pingresp(_)
+    ));
+}
+
+/// Test pause_recv does not let a DISCONNECT overtake the held PUBLISH
+#[tokio::test]
+async fn test_pause_recv_holds_disconnect() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-pause-disconnect").await;
+
+    assert!(client.pause_recv().await.is_ok());
+
+    // PUBLISH and DISCONNECT in one WebSocket message
+    let publish = mqtt::packet::v5_0::Publish::builder()
+        .topic_name("test/paused")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtMostOnce)
+        .payload(b"held back")
+        .build()
+        .unwrap();
+    let disconnect = mqtt::packet::v5_0::Disconnect::builder()
+        .reason_code(client_mqtt::result_code::DisconnectReasonCode::KeepAliveTimeout)
+        .build()
+        .unwrap();
+    let mut bytes = mqtt::packet::Packet::V5_0Publish(publish).to_continuous_buffer();
+    bytes.extend(mqtt::packet::Packet::V5_0Disconnect(disconnect).to_continuous_buffer());
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(bytes));
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    // The DISCONNECT waits behind the PUBLISH
+    assert_eq!(client.last_disconnect_reason().await, None);
+
+    assert!(client.resume_recv().await.is_ok());
+    let recv_result =
+        tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    match recv_result.unwrap().unwrap() {
+        mqtt::packet::Packet::V5_0Publish(p) => assert_eq!(p.topic_name(), "test/paused"),
+        other => panic!("Expected PUBLISH, got {:?}", other),
+    }
+    assert_eq!(client.last_disconnect_reason().await, Some(0x8D));
+}
+
+/// Test malformed inbound bytes are reported as DecodeError with an offset
+#[tokio::test]
+async fn test_decode_error_event() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-decode-error").await;
+
+    // A valid PUBLISH followed by packet type 0, which is reserved and never valid
+    let mut bytes = queued_publish_bytes(0);
+    let publish_len = bytes.len();
+    bytes.extend_from_slice(&[0x00, 0x00]);
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(bytes));
+
+    loop {
+        let event =
+            tokio::time::timeout(tokio::time::Duration::from_millis(500), client.next_event())
+                .await
+                .expect("DecodeError should be reported")
+                .unwrap();
+        if let mqtt_client_wasm::ConnectionEvent::Error(mqtt_client_wasm::Error::DecodeError {
+            offset,
+            detail,
+        }) = event
+        {
+            // The offset is where the invalid packet starts
+            assert_eq!(offset, publish_len);
+            assert!(!detail.is_empty());
+            break;
+        }
+    }
+}
+
+/// Test subscribe() returns the matching SUBACK instead of delivering it to recv()
+#[tokio::test]
+async fn test_subscribe_waits_for_suback() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-subscribe").await;
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let entries = vec![
+        mqtt::packet::SubEntry::new(
+            "test/a",
+            mqtt::packet::SubOpts::new().set_qos(mqtt::packet::Qos::AtLeastOnce),
+        )
+        .unwrap(),
+        mqtt::packet::SubEntry::new(
+            "test/b",
+            mqtt::packet::SubOpts::new().set_qos(mqtt::packet::Qos::ExactlyOnce),
+        )
+        .unwrap(),
+    ];
+    let subscribe = mqtt::packet::v3_1_1::Subscribe::builder()
+        .packet_id(packet_id)
+        .entries(entries)
+        .build()
+        .unwrap();
+
+    let suback = mqtt::packet::v3_1_1::Suback::builder()
+        .packet_id(packet_id)
+        .return_codes(vec![
+            client_mqtt::result_code::SubackReturnCode::SuccessMaximumQos1,
+            client_mqtt::result_code::SubackReturnCode::Failure,
+        ])
+        .build()
+        .unwrap();
+    let suback_bytes = mqtt::packet::Packet::V3_1_1Suback(suback).to_continuous_buffer();
+
+    let broker = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            suback_bytes,
+        ));
+    };
+    let (result, _) = futures::join!(
+        client.subscribe(mqtt::packet::Packet::V3_1_1Subscribe(subscribe)),
+        broker
+    );
+
+    match result.unwrap() {
+        mqtt::packet::Packet::V3_1_1Suback(p) => {
+            assert_eq!(p.packet_id(), packet_id);
+            assert_eq!(p.return_codes().len(), 2);
+        }
+        other => panic!("Expected SUBACK, got {:?}", other),
+    }
+
+    // The SUBACK was not also queued for recv()
+    let recv_result =
+        tokio::time::timeout(tokio::time::Duration::from_millis(100), client.recv()).await;
+    assert!(recv_result.is_err());
+
+    // Non-SUBSCRIBE packets are rejected
+    let pingreq = mqtt::packet::v3_1_1::Pingreq::new();
+    let result = client
+        .subscribe(mqtt::packet::Packet::V3_1_1Pingreq(pingreq))
+        .await;
+    assert!(result.is_err());
+}
+
+/// Rewriter that prefixes topics and rejects anything under `forbidden/`
+struct TenantRewriter;
+
+impl mqtt_client_wasm::TopicRewriter for TenantRewriter {
+    fn rewrite(&self, topic: &str) -> Result<String, String> {
+        if topic.starts_with("forbidden/") {
+            Err(format!("{} is not allowed", topic))
+        } else {
+            Ok(format!("tenant-a/{}", topic))
+        }
+    }
+}
+
+/// Test topic_rewrite rejects outgoing PUBLISH and SUBSCRIBE topics
+#[tokio::test]
+async fn test_topic_rewrite_rejects_send() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        topic_rewrite: Some(std::sync::Arc::new(TenantRewriter)),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-topic-rewrite").await;
+
+    let publish = |topic: &str| {
+        let publish = mqtt::packet::v3_1_1::Publish::builder()
+            .topic_name(topic)
+            .unwrap()
+            .qos(mqtt::packet::Qos::AtMostOnce)
+            .payload(b"data")
+            .build()
+            .unwrap();
+        mqtt::packet::Packet::V3_1_1Publish(publish)
+    };
+
+    // The rewritten topic is what goes on the wire
+    assert!(client.send(publish("allowed/topic")).await.is_ok());
+    assert_eq!(
+        control.sent_data().last().unwrap(),
+        &publish("tenant-a/allowed/topic").to_continuous_buffer()
+    );
+    let sent_count = control.sent_data().len();
+    let result = client.send(publish("forbidden/topic")).await;
+    assert!(matches!(
+        result,
+        Err(mqtt_client_wasm::Error::TopicRejected(_))
+    ));
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let subscribe = mqtt::packet::v3_1_1::Subscribe::builder()
+        .packet_id(packet_id)
+        .entries(vec![mqtt::packet::SubEntry::new(
+            "forbidden/#",
+            mqtt::packet::SubOpts::new(),
+        )
+        .unwrap()])
+        .build()
+        .unwrap();
+    let result = client
+        .send(mqtt::packet::Packet::V3_1_1Subscribe(subscribe))
+        .await;
+    assert!(matches!(
+        result,
+        Err(mqtt_client_wasm::Error::TopicRejected(_))
+    ));
+    // Nothing was written for the rejected packets
+    assert_eq!(control.sent_data().len(), sent_count);
+
+    // Removing the rewriter lets the topic through unchanged
+    client.set_topic_rewriter(None).await.unwrap();
+    assert!(client.send(publish("forbidden/topic")).await.is_ok());
+    assert_eq!(
+        control.sent_data().last().unwrap(),
+        &publish("forbidden/topic").to_continuous_buffer()
+    );
+}
+
+/// Test is_connected_sync() follows state changes without a round-trip
+#[tokio::test]
+async fn test_is_connected_sync() {
+    let config = MqttConfig::default();
+    let mock_ws = MockUnderlyingLayer::new();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    assert!(!client.is_connected_sync());
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    assert!(client.is_connected_sync());
+    assert_eq!(client.is_connected_sync(), client.is_connected().await);
+
+    // Clones share the flag
+    let cloned = client.clone();
+    let _ = client.close().await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    assert!(!cloned.is_connected_sync());
+    assert_eq!(cloned.is_connected_sync(), client.is_connected().await);
+}
+
+/// Test an abrupt close (will-triggering path) is distinguishable from a graceful one
+#[tokio::test]
+async fn test_abrupt_close_without_disconnect() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let connect_packet = mqtt::packet::v3_1_1::Connect::builder()
+        .client_id("test-abrupt-close")
+        .unwrap()
+        .keep_alive(60)
+        .clean_session(true)
+        .will_message(
+            "client/status",
+            b"offline".to_vec(),
+            mqtt::packet::Qos::AtLeastOnce,
+            false,
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Connect(connect_packet))
+        .await;
+    let connack = mqtt::packet::v3_1_1::Connack::builder()
+        .session_present(false)
+        .return_code(mqtt::result_code::ConnectReturnCode::Accepted)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Connack(connack).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    assert!(!control.sent_data().is_empty());
+
+    control.simulate_abrupt_close();
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    common::assert_closed_without_disconnect(&client, &control).await;
+
+    // A graceful close sends DISCONNECT first, which the helper can tell apart
+    common::establish_v311(&client, &event_sender, "test-abrupt-close").await;
+    let disconnect = mqtt::packet::v3_1_1::Disconnect::new();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Disconnect(disconnect))
+        .await;
+    assert!(control.disconnect_sent());
+}
+
+/// Test acquire_packet_id_wait() resolves once an exhausted ID space frees an ID
+#[tokio::test]
+async fn test_acquire_packet_id_wait() {
+    let config = MqttConfig::default();
+    let mock_ws = MockUnderlyingLayer::new();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    // Free IDs are returned immediately
+    let first = client.acquire_packet_id_wait().await.unwrap();
+
+    // Exhaust the remaining IDs
+    while client.acquire_packet_id().await.is_some() {}
+    assert_eq!(client.acquire_packet_id().await, None);
+
+    // Two waiters queue up and are served in request order
+    let waiter1 = client.acquire_packet_id_wait();
+    let waiter2 = client.acquire_packet_id_wait();
+    let releaser = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        client.release_packet_id(first).await.unwrap();
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        client.release_packet_id(100).await.unwrap();
+    };
+    let (result1, result2, _) = futures::join!(waiter1, waiter2, releaser);
+    assert_eq!(result1.unwrap(), first);
+    assert_eq!(result2.unwrap(), 100);
+}
+
+/// Test connected/closed lifecycle events, including the transport's close code and reason
+#[tokio::test]
+async fn test_lifecycle_events() {
+    let config = MqttConfig::default();
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    let _ = client.connect("ws://test.example.com").await;
+
+    let next =
+        || tokio::time::timeout(tokio::time::Duration::from_millis(500), client.next_event());
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Connected
+    ));
+
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::CloseInfo {
+        code: 1006,
+        reason: "abnormal".to_string(),
+    });
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Closed);
+    match next().await.unwrap().unwrap() {
+        mqtt_client_wasm::ConnectionEvent::Closed { code, reason } => {
+            assert_eq!(code, Some(1006));
+            assert_eq!(reason.as_deref(), Some("abnormal"));
+        }
+        other => panic!("Expected Closed, got {:?}", other),
+    }
+
+    // A close without details reports neither
+    let _ = client.connect("ws://test.example.com").await;
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Connected
+    ));
+    let _ = client.close().await;
+    match next().await.unwrap().unwrap() {
+        mqtt_client_wasm::ConnectionEvent::Closed { code, reason } => {
+            assert_eq!(code, None);
+            assert_eq!(reason, None);
+        }
+        other => panic!("Expected Closed, got {:?}", other),
+    }
+}
+
+/// Test automatic reconnection events: Reconnecting with attempt/delay, then Reconnected
+#[tokio::test]
+async fn test_reconnect_events() {
+    let config = MqttConfig {
+        reconnect_initial_delay_ms: Some(20),
+        reconnect_max_delay_ms: 30,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let control = mock_ws.control();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    let _ = client.connect("ws://test.example.com").await;
+
+    let next =
+        || tokio::time::timeout(tokio::time::Duration::from_millis(500), client.next_event());
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Connected
+    ));
+
+    control.simulate_abrupt_close();
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Closed { .. }
+    ));
+    match next().await.unwrap().unwrap() {
+        mqtt_client_wasm::ConnectionEvent::Reconnecting { attempt, delay_ms } => {
+            assert_eq!(attempt, 1);
+            assert_eq!(delay_ms, 20);
+        }
+        other => panic!("Expected Reconnecting, got {:?}", other),
+    }
+    assert_eq!(client.state().await, ConnectionState::Reconnecting);
+
+    // The mock transport reconnects immediately when the backoff expires
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Connected
+    ));
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Reconnected
+    ));
+
+    // The attempt counter starts over after a successful reconnect
+    control.simulate_abrupt_close();
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Closed { .. }
+    ));
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Reconnecting { attempt: 1, .. }
+    ));
+
+    // close() stops reconnecting
+    let _ = client.close().await;
+    assert_eq!(client.state().await, ConnectionState::Closed);
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    assert_eq!(client.state().await, ConnectionState::Closed);
+}
+
+/// Test send_sized() reports the bytes written to the transport for one send
+#[tokio::test]
+async fn test_send_sized() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "send-sized-client").await;
+
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/sized")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtMostOnce)
+        .payload(b"hello")
+        .build()
+        .unwrap();
+    let packet = mqtt::packet::Packet::V3_1_1Publish(publish);
+    let expected = packet.to_continuous_buffer().len();
+
+    let size = client.send_sized(packet).await.unwrap();
+    assert_eq!(size, expected);
+    assert_eq!(control.sent_data().last().unwrap().len(), size);
+}
+
+/// Test an incoming PINGREQ raises PingReceived and can be answered with send_pingresp()
+#[tokio::test]
+async fn test_ping_received_manual_response() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        auto_ping_response: false,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "ping-received-client").await;
+
+    let pingreq = mqtt::packet::v3_1_1::Pingreq::builder().build().unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Pingreq(pingreq).to_continuous_buffer(),
+    ));
+
+    // Skip the Connected event from establishing the connection
+    loop {
+        let event =
+            tokio::time::timeout(tokio::time::Duration::from_millis(500), client.next_event())
+                .await
+                .expect("PingReceived not emitted")
+                .unwrap();
+        if matches!(event, mqtt_client_wasm::ConnectionEvent::PingReceived) {
+            break;
+        }
+    }
+
+    client.send_pingresp().await.unwrap();
+    let pingresp = mqtt::packet::v3_1_1::Pingresp::builder().build().unwrap();
+    assert_eq!(
+        control.sent_data().last().unwrap(),
+        &mqtt::packet::Packet::V3_1_1Pingresp(pingresp).to_continuous_buffer()
+    );
+}
+
+/// Test MqttConfig::builder() sets the given fields and keeps defaults for the rest
+#[test]
+fn test_config_builder() {
+    let config = MqttConfig::builder()
+        .url("ws://test.example.com")
+        .version(client_mqtt::Version::V3_1_1)
+        .pingreq_send_interval_ms(30000)
+        .auto_ping_response(false)
+        .reconnect_initial_delay_ms(1000)
+        .instance_label("telemetry")
+        .build();
+
+    assert_eq!(config.url, "ws://test.example.com");
+    assert!(matches!(config.version, client_mqtt::Version::V3_1_1));
+    assert_eq!(config.pingreq_send_interval_ms, Some(30000));
+    assert!(!config.auto_ping_response);
+    assert_eq!(config.reconnect_initial_delay_ms, Some(1000));
+    assert_eq!(config.instance_label.as_deref(), Some("telemetry"));
+
+    let default = MqttConfig::default();
+    assert!(default.instance_label.is_none());
+    assert_eq!(config.auto_pub_response, default.auto_pub_response);
+    assert_eq!(config.shutdown_timeout_ms, default.shutdown_timeout_ms);
+    assert_eq!(
+        config.max_undelivered_packets,
+        default.max_undelivered_packets
+    );
+    assert!(config.payload_codec.is_none());
+}
+
+/// Test subscriptions() follows SUBACK grants and UNSUBACK removals
+#[tokio::test]
+async fn test_subscriptions_tracking() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-subscriptions").await;
+    assert!(client.subscriptions().await.is_empty());
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let entries = vec![
+        mqtt::packet::SubEntry::new(
+            "test/a",
+            mqtt::packet::SubOpts::new().set_qos(mqtt::packet::Qos::AtLeastOnce),
+        )
+        .unwrap(),
+        mqtt::packet::SubEntry::new(
+            "test/b",
+            mqtt::packet::SubOpts::new().set_qos(mqtt::packet::Qos::ExactlyOnce),
+        )
+        .unwrap(),
+    ];
+    let subscribe = mqtt::packet::v3_1_1::Subscribe::builder()
+        .packet_id(packet_id)
+        .entries(entries)
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V3_1_1Subscribe(subscribe))
+        .await
+        .unwrap();
+
+    // Not listed until the broker acknowledges
+    assert!(client.subscriptions().await.is_empty());
+
+    // The broker refuses test/b
+    let suback = mqtt::packet::v3_1_1::Suback::builder()
+        .packet_id(packet_id)
+        .return_codes(vec![
+            client_mqtt::result_code::SubackReturnCode::SuccessMaximumQos1,
+            client_mqtt::result_code::SubackReturnCode::Failure,
+        ])
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Suback(suback).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    assert_eq!(
+        client.subscriptions().await,
+        vec![("test/a".to_string(), 1u8)]
+    );
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let unsubscribe = mqtt::packet::v3_1_1::Unsubscribe::builder()
+        .packet_id(packet_id)
+        .entries(vec!["test/a"])
+        .unwrap()
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V3_1_1Unsubscribe(unsubscribe))
+        .await
+        .unwrap();
+    let unsuback = mqtt::packet::v3_1_1::Unsuback::builder()
+        .packet_id(packet_id)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Unsuback(unsuback).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    assert!(client.subscriptions().await.is_empty());
+}
+
+/// Test recv() before the first connect() fails fast unless allow_recv_before_connect is set
+#[tokio::test]
+async fn test_recv_before_connect() {
+    let client = MqttClient::new_with_websocket(MqttConfig::default(), MockUnderlyingLayer::new());
+    let result = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    assert!(matches!(
+        result.expect("recv() should not wait"),
+        Err(mqtt_client_wasm::Error::NotConnected)
+    ));
+
+    // With the flag set, recv() waits and is served once another task connects
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        allow_recv_before_connect: true,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    let receiver = async {
+        tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await
+    };
+    let connector = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        common::establish_v311(&client, &event_sender, "test-recv-early").await;
+    };
+    let (result, _) = futures::join!(receiver, connector);
+    // The early recv() was queued first, so it takes the CONNACK
+    assert!(matches!(
+        result.expect("recv() was not served"),
+        Ok(mqtt::packet::Packet::V3_1_1Connack(_))
+    ));
+}
+
+/// Test wait_connected() resolves every waiter on connect and fails on close
+#[tokio::test]
+async fn test_wait_connected() {
+    let mock_ws = MockUnderlyingLayer::new();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(MqttConfig::default(), mock_ws);
+
+    let connector = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        let _ = client.connect("ws://test.example.com").await;
+    };
+    let (first, second, _) = futures::join!(
+        tokio::time::timeout(
+            tokio::time::Duration::from_millis(500),
+            client.wait_connected()
+        ),
+        tokio::time::timeout(
+            tokio::time::Duration::from_millis(500),
+            client.wait_connected()
+        ),
+        connector
+    );
+    assert!(first.expect("first waiter timed out").is_ok());
+    assert!(second.expect("second waiter timed out").is_ok());
+
+    // Already connected: resolves immediately
+    assert!(client.wait_connected().await.is_ok());
+
+    control.simulate_abrupt_close();
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    // Not connected: a close before the next connection fails the waiter
+    let closer = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        control.simulate_abrupt_close();
+    };
+    let (result, _) = futures::join!(
+        tokio::time::timeout(
+            tokio::time::Duration::from_millis(500),
+            client.wait_connected()
+        ),
+        closer
+    );
+    assert!(matches!(
+        result.expect("waiter timed out"),
+        Err(mqtt_client_wasm::Error::ConnectionClosed)
+    ));
+}
+
+/// Test clear_inbound() drops queued and held-back packets but not later ones
+#[tokio::test]
+async fn test_clear_inbound() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        max_undelivered_packets: 2,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-clear-inbound").await;
+
+    // Two packets are queued, the third stays unparsed
+    for i in 0..3 {
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            queued_publish_bytes(i),
+        ));
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    assert_eq!(client.clear_inbound().await, 3);
+    assert_eq!(client.clear_inbound().await, 0);
+
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        queued_publish_bytes(0),
+    ));
+    assert_queue_order(&client, 1).await;
+}
+
+/// Test broker retransmissions in an inbound QoS 2 flow are answered again
+/// without redelivering the message or confusing the packet ID
+#[tokio::test]
+async fn test_qos2_inbound_retransmissions() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-qos2-retransmit").await;
+
+    let publish = |payload: &[u8], dup: bool| {
+        let publish = mqtt::packet::v3_1_1::Publish::builder()
+            .topic_name("test/qos2/in")
+            .unwrap()
+            .qos(mqtt::packet::Qos::ExactlyOnce)
+            .packet_id(7)
+            .dup(dup)
+            .payload(payload)
+            .build()
+            .unwrap();
+        mqtt::packet::Packet::V3_1_1Publish(publish).to_continuous_buffer()
+    };
+    let pubrel = || {
+        let pubrel = mqtt::packet::v3_1_1::Pubrel::builder()
+            .packet_id(7)
+            .build()
+            .unwrap();
+        mqtt::packet::Packet::V3_1_1Pubrel(pubrel).to_continuous_buffer()
+    };
+    let sent_count = |bytes: &[u8]| {
+        control
+            .sent_data()
+            .iter()
+            .filter(|d| d.as_slice() == bytes)
+            .count()
+    };
+    let pubrec_bytes = mqtt::packet::Packet::V3_1_1Pubrec(
+        mqtt::packet::v3_1_1::Pubrec::builder()
+            .packet_id(7)
+            .build()
+            .unwrap(),
+    )
+    .to_continuous_buffer();
+    let pubcomp_bytes = mqtt::packet::Packet::V3_1_1Pubcomp(
+        mqtt::packet::v3_1_1::Pubcomp::builder()
+            .packet_id(7)
+            .build()
+            .unwrap(),
+    )
+    .to_continuous_buffer();
+
+    // First delivery is received and answered with PUBREC
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(publish(
+        b"first", false,
+    )));
+    let received = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv())
+        .await
+        .expect("recv should complete")
+        .unwrap();
+    assert!(matches!(received, mqtt::packet::Packet::V3_1_1Publish(_)));
+    assert_eq!(sent_count(&pubrec_bytes), 1);
+
+    // Broker missed the PUBREC and re-sends the PUBLISH: PUBREC again, no redelivery
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(publish(
+        b"first", true,
+    )));
+    let redelivered =
+        tokio::time::timeout(tokio::time::Duration::from_millis(200), client.recv()).await;
+    assert!(
+        redelivered.is_err(),
+        "retransmitted PUBLISH was redelivered"
+    );
+    assert_eq!(sent_count(&pubrec_bytes), 2);
+
+    // Broker missed the PUBCOMP and re-sends the PUBREL: PUBCOMP again
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(pubrel()));
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(pubrel()));
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    assert_eq!(sent_count(&pubcomp_bytes), 2);
+
+    // The flow is finished, so packet ID 7 now carries a new message
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(publish(
+        b"second", false,
+    )));
+    let mut publishes = Vec::new();
+    while let Ok(Ok(packet)) =
+        tokio::time::timeout(tokio::time::Duration::from_millis(200), client.recv()).await
+    {
+        if let mqtt::packet::Packet::V3_1_1Publish(p) = packet {
+            publishes.push(p.payload().as_slice().to_vec());
+        }
+    }
+    assert_eq!(publishes, vec![b"second".to_vec()]);
+    assert_eq!(sent_count(&pubrec_bytes), 3);
+}
+
+/// Test a CONNACK without a session after a resume request emits SessionLost
+#[tokio::test]
+async fn test_session_lost_event() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    assert_eq!(client.session_present().await, None);
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    // Ask to resume a session the broker no longer has
+    let connect_packet = mqtt::packet::v3_1_1::Connect::builder()
+        .client_id("test-session-lost")
+        .unwrap()
+        .keep_alive(60)
+        .clean_session(false)
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Connect(connect_packet))
+        .await;
+    let connack = mqtt::packet::v3_1_1::Connack::builder()
+        .session_present(false)
+        .return_code(client_mqtt::result_code::ConnectReturnCode::Accepted)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Connack(connack).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+
+    let mut session_lost = false;
+    while let Ok(Ok(event)) =
+        tokio::time::timeout(tokio::time::Duration::from_millis(100), client.next_event()).await
+    {
+        if matches!(event, mqtt_client_wasm::ConnectionEvent::SessionLost) {
+            session_lost = true;
+        }
+    }
+    assert!(session_lost);
+    assert_eq!(client.session_present().await, Some(false));
+}
+
+/// Test disconnect_suppress_will/disconnect_trigger_will put the right DISCONNECT on the wire
+#[tokio::test]
+async fn test_disconnect_will_intent() {
+    for (trigger_will, reason_code) in [
+        (
+            false,
+            client_mqtt::result_code::DisconnectReasonCode::NormalDisconnection,
+        ),
+        (
+            true,
+            client_mqtt::result_code::DisconnectReasonCode::DisconnectWithWillMessage,
+        ),
+    ] {
+        let config = MqttConfig {
+            version: client_mqtt::Version::V5_0,
+            ..Default::default()
+        };
+        let mock_ws = MockUnderlyingLayer::new();
+        let event_sender = mock_ws.event_sender.clone();
+        let control = mock_ws.control();
+        let client = MqttClient::new_with_websocket(config, mock_ws);
+        common::establish_v50(&client, &event_sender, "test-will-intent-v5").await;
+
+        let result = if trigger_will {
+            client.disconnect_trigger_will().await
+        } else {
+            client.disconnect_suppress_will().await
+        };
+        assert!(result.is_ok());
+        let sent = control.sent_data();
+        let disconnect = sent
+            .iter()
+            .find(|data| data.first().map(|b| b >> 4) == Some(14))
+            .expect("DISCONNECT not sent");
+        let expected = mqtt::packet::v5_0::Disconnect::builder()
+            .reason_code(reason_code)
+            .build()
+            .unwrap();
+        assert_eq!(
+            disconnect,
+            &mqtt::packet::Packet::V5_0Disconnect(expected).to_continuous_buffer()
+        );
+        assert_eq!(client.state().await, ConnectionState::Closed);
+    }
+
+    // v3.1.1: only the suppressing variant sends DISCONNECT
+    for trigger_will in [false, true] {
+        let config = MqttConfig {
+            version: client_mqtt::Version::V3_1_1,
+            ..Default::default()
+        };
+        let mock_ws = MockUnderlyingLayer::new();
+        let event_sender = mock_ws.event_sender.clone();
+        let control = mock_ws.control();
+        let client = MqttClient::new_with_websocket(config, mock_ws);
+        common::establish_v311(&client, &event_sender, "test-will-intent-v311").await;
+
+        let result = if trigger_will {
+            client.disconnect_trigger_will().await
+        } else {
+            client.disconnect_suppress_will().await
+        };
+        assert!(result.is_ok());
+        assert_eq!(control.disconnect_sent(), !trigger_will);
+        assert_eq!(client.state().await, ConnectionState::Closed);
+    }
+}
+
+/// Test connect_timeout() fails with Error::Timeout when the transport never connects
+#[tokio::test]
+async fn test_connect_timeout() {
+    let mock_ws = MockUnderlyingLayer::new();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(MqttConfig::default(), mock_ws);
+
+    // The server never answers: the call gives up and closes the transport
+    control.set_hold_connects(true);
+    let result = tokio::time::timeout(
+        tokio::time::Duration::from_secs(2),
+        client.connect_timeout("ws://test", 100),
+    )
+    .await
+    .expect("connect_timeout() did not resolve");
+    assert!(matches!(result, Err(mqtt_client_wasm::Error::Timeout)));
+    assert_eq!(client.state().await, ConnectionState::Closed);
+
+    // A connection established in time is unaffected by the timer
+    control.set_hold_connects(false);
+    assert!(client.connect_timeout("ws://test", 100).await.is_ok());
+    tokio::time::sleep(tokio::time::Duration::from_millis(200)).await;
+    assert_eq!(client.state().await, ConnectionState::Connected);
+    assert_eq!(control.connect_urls().len(), 2);
+}
+
+/// Test send() and recv() from concurrent tasks neither deadlock nor reorder
+#[tokio::test]
+async fn test_concurrent_send_and_recv() {
+    const COUNT: usize = 200;
+
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(MqttConfig::default(), mock_ws);
+    common::establish_v311(&client, &event_sender, "test-concurrent").await;
+    let sent_before = control.sent_data().len();
+
+    let outbound: Vec<mqtt::packet::Packet> = (0..COUNT)
+        .map(|i| {
+            let publish = mqtt::packet::v3_1_1::Publish::builder()
+                .topic_name(&format!("test/outbound/{}", i))
+                .unwrap()
+                .qos(mqtt::packet::Qos::AtMostOnce)
+                .payload(b"outbound")
+                .build()
+                .unwrap();
+            mqtt::packet::Packet::V3_1_1Publish(publish)
+        })
+        .collect();
+
+    // Every send is queued at once, ahead of most of the inbound traffic
+    let sender =
+        futures::future::join_all(outbound.iter().map(|packet| client.send(packet.clone())));
+    let traffic = async {
+        for i in 0..COUNT {
+            let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+                queued_publish_bytes(i),
+            ));
+            if i % 10 == 0 {
+                tokio::task::yield_now().await;
+            }
+        }
+    };
+    let receiver = assert_queue_order(&client, COUNT);
+
+    let (sent, _, _) = tokio::time::timeout(tokio::time::Duration::from_secs(5), async {
+        futures::join!(sender, traffic, receiver)
+    })
+    .await
+    .expect("concurrent send/recv should not deadlock");
+    assert!(sent.iter().all(|result| result.is_ok()));
+
+    // Outbound PUBLISHes hit the wire in the order send() was called
+    let wire: Vec<Vec<u8>> = control.sent_data()[sent_before..]
+        .iter()
+        .filter(|data| data.first().map(|b| b >> 4) == Some(3))
+        .cloned()
+        .collect();
+    let expected: Vec<Vec<u8>> = outbound
+        .iter()
+        .map(|packet| packet.to_continuous_buffer())
+        .collect();
+    assert_eq!(wire, expected);
+}
+
+/// Test recv_one_of() takes the first packet of a wanted type and leaves the rest for recv()
+#[tokio::test]
+async fn test_recv_one_of() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-recv-one-of").await;
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let subscribe = mqtt::packet::v3_1_1::Subscribe::builder()
+        .packet_id(packet_id)
+        .entries(vec![mqtt::packet::SubEntry::new(
+            "test/queue/#",
+            mqtt::packet::SubOpts::new().set_qos(mqtt::packet::Qos::AtMostOnce),
+        )
+        .unwrap()])
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V3_1_1Subscribe(subscribe))
+        .await
+        .unwrap();
+
+    // Waiting before anything arrives; two PUBLISHes come ahead of the SUBACK
+    let waiter = client.recv_one_of(&[
+        mqtt::packet::PacketType::Suback,
+        mqtt::packet::PacketType::Disconnect,
+    ]);
+    let traffic = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        for i in 0..2 {
+            let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+                queued_publish_bytes(i),
+            ));
+        }
+        let suback = mqtt::packet::v3_1_1::Suback::builder()
+            .packet_id(packet_id)
+            .return_codes(vec![
+                client_mqtt::result_code::SubackReturnCode::SuccessMaximumQos0,
+            ])
+            .build()
+            .unwrap();
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            mqtt::packet::Packet::V3_1_1Suback(suback).to_continuous_buffer(),
+        ));
+    };
+    let (received, _) = tokio::time::timeout(tokio::time::Duration::from_millis(500), async {
+        futures::join!(waiter, traffic)
+    })
+    .await
+    .expect("recv_one_of() should resolve");
+    match received.unwrap() {
+        mqtt::packet::Packet::V3_1_1Suback(p) => assert_eq!(p.packet_id(), packet_id),
+        other => panic!("Expected SUBACK, got {:?}", other),
+    }
+
+    // The skipped PUBLISHes are still there, in order
+    assert_queue_order(&client, 2).await;
+
+    // Packets already queued are searched too
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        queued_publish_bytes(0),
+    ));
+    tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+    let packet = tokio::time::timeout(
+        tokio::time::Duration::from_millis(500),
+        client.recv_one_of(&[mqtt::packet::PacketType::Publish]),
+    )
+    .await
+    .expect("queued PUBLISH should be delivered")
+    .unwrap();
+    assert!(matches!(packet, mqtt::packet::Packet::V3_1_1Publish(_)));
+}
+
+/// Test follow_server_reference reconnects to the server named by a CONNACK
+#[tokio::test]
+async fn test_follow_server_reference() {
+    use mqtt_protocol_core::mqtt::packet::Property;
+
+    let config = MqttConfig::builder()
+        .version(client_mqtt::Version::V5_0)
+        .follow_server_reference(true)
+        .build();
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    let _ = client.connect("ws://broker.example.com:8080/mqtt").await;
+    let next =
+        || tokio::time::timeout(tokio::time::Duration::from_millis(500), client.next_event());
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Connected
+    ));
+
+    let connect_packet = mqtt::packet::v5_0::Connect::builder()
+        .client_id("test-server-reference")
+        .unwrap()
+        .clean_start(true)
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V5_0Connect(connect_packet))
+        .await
+        .unwrap();
+
+    let reference = mqtt::packet::ServerReference::new("backup.example.com:9001").unwrap();
+    let connack = mqtt::packet::v5_0::Connack::builder()
+        .session_present(false)
+        .reason_code(client_mqtt::result_code::ConnectReasonCode::UseAnotherServer)
+        .props(mqtt::packet::Properties::from(vec![
+            Property::ServerReference(reference),
+        ]))
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Connack(connack).to_continuous_buffer(),
+    ));
+
+    match next().await.unwrap().unwrap() {
+        mqtt_client_wasm::ConnectionEvent::Redirected { server } => {
+            assert_eq!(server, "backup.example.com:9001");
+        }
+        other => panic!("Expected Redirected, got {:?}", other),
+    }
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Closed { .. }
+    ));
+    // Straight to the referenced server, keeping the scheme and path
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Connected
+    ));
+    assert_eq!(client.state().await, ConnectionState::Connected);
+    assert_eq!(
+        control.connect_urls(),
+        vec![
+            "ws://broker.example.com:8080/mqtt".to_string(),
+            "ws://backup.example.com:9001/mqtt".to_string(),
+        ]
+    );
+}
+
+/// Test follow_server_reference refuses a reference that drops TLS
+#[tokio::test]
+async fn test_follow_server_reference_keeps_tls() {
+    use mqtt_protocol_core::mqtt::packet::Property;
+
+    let config = MqttConfig::builder()
+        .version(client_mqtt::Version::V5_0)
+        .follow_server_reference(true)
+        .build();
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    let _ = client.connect("wss://broker.example.com/mqtt").await;
+    let next =
+        || tokio::time::timeout(tokio::time::Duration::from_millis(200), client.next_event());
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Connected
+    ));
+
+    let connect_packet = mqtt::packet::v5_0::Connect::builder()
+        .client_id("test-server-reference-tls")
+        .unwrap()
+        .clean_start(true)
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V5_0Connect(connect_packet))
+        .await
+        .unwrap();
+
+    let reference = mqtt::packet::ServerReference::new("ws://backup.example.com/mqtt").unwrap();
+    let connack = mqtt::packet::v5_0::Connack::builder()
+        .session_present(false)
+        .reason_code(client_mqtt::result_code::ConnectReasonCode::UseAnotherServer)
+        .props(mqtt::packet::Properties::from(vec![
+            Property::ServerReference(reference),
+        ]))
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Connack(connack).to_continuous_buffer(),
+    ));
+
+    // The plain-text reference is not followed
+    while let Ok(Ok(event)) = next().await {
+        assert!(
+            !matches!(event, mqtt_client_wasm::ConnectionEvent::Redirected { .. }),
+            "wss:// connection redirected to ws://"
+        );
+    }
+    assert_eq!(
+        control.connect_urls(),
+        vec!["wss://broker.example.com/mqtt".to_string()]
+    );
+}
+
+/// Test fire_timer() triggers the PingreqSend timer without waiting for it
+#[tokio::test]
+async fn test_fire_timer() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        // Far longer than the test, so only fire_timer() can trigger it
+        pingreq_send_interval_ms: Some(60_000),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-fire-timer").await;
+
+    let pingreq = mqtt::packet::Packet::V3_1_1Pingreq(mqtt::packet::v3_1_1::Pingreq::new())
+        .to_continuous_buffer();
+    let pingreqs_sent = || {
+        control
+            .sent_data()
+            .iter()
+            .filter(|data| **data == pingreq)
+            .count()
+    };
+    assert_eq!(pingreqs_sent(), 0);
+
+    client.fire_timer("PingreqSend").await.unwrap();
+    // The mock records sends on its own thread
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    assert_eq!(pingreqs_sent(), 1);
+
+    // A timer that is not running is ignored
+    client.fire_timer("NoSuchTimer").await.unwrap();
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    assert_eq!(pingreqs_sent(), 1);
+    assert!(client.is_connected().await);
+}
+
+/// Test wait_inflight_drained() resolves on the PUBACK and times out without it
+#[tokio::test]
+async fn test_wait_inflight_drained() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-inflight-drained").await;
+
+    // Nothing inflight yet
+    client.wait_inflight_drained(10).await.unwrap();
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/drained")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtLeastOnce)
+        .packet_id(packet_id)
+        .payload(b"QoS 1 message")
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V3_1_1Publish(publish))
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        client.wait_inflight_drained(50).await,
+        Err(mqtt_client_wasm::Error::Timeout)
+    ));
+
+    let puback = mqtt::packet::v3_1_1::Puback::builder()
+        .packet_id(packet_id)
+        .build()
+        .unwrap();
+    let wait = client.wait_inflight_drained(1000);
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Puback(puback).to_continuous_buffer(),
+    ));
+    wait.await.unwrap();
+}
+
+#[derive(Default)]
+struct RecordingChunkHandler {
+    chunks: std::sync::Mutex<Vec<(String, usize, Vec<u8>, usize)>>,
+}
+
+impl mqtt_client_wasm::PayloadChunkHandler for RecordingChunkHandler {
+    fn chunk(&self, topic: &str, offset: usize, chunk: &[u8], total_len: usize) {
+        self.chunks
+            .lock()
+            .unwrap()
+            .push((topic.to_string(), offset, chunk.to_vec(), total_len));
+    }
+}
+
+/// Test payloads over payload_stream_threshold reach the chunk handler as they arrive
+#[tokio::test]
+async fn test_payload_chunk_handler() {
+    let handler = std::sync::Arc::new(RecordingChunkHandler::default());
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        payload_stream_threshold: 8,
+        payload_chunk_handler: Some(handler.clone()),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-payload-chunks").await;
+
+    let large = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/large")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtLeastOnce)
+        .packet_id(1)
+        .payload(b"0123456789abcdef")
+        .build()
+        .unwrap();
+    let small = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/small")
+        .unwrap()
+        .payload(b"tiny")
+        .build()
+        .unwrap();
+    let mut bytes = mqtt::packet::Packet::V3_1_1Publish(large).to_continuous_buffer();
+    bytes.extend(mqtt::packet::Packet::V3_1_1Publish(small).to_continuous_buffer());
+
+    // Headers plus 2 payload bytes, then 10, then the rest with the small PUBLISH
+    let header_len = 2 + 2 + "test/large".len() + 2;
+    for piece in [
+        &bytes[..header_len + 2],
+        &bytes[header_len + 2..header_len + 12],
+        &bytes[header_len + 12..],
+    ] {
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            piece.to_vec(),
+        ));
+    }
+
+    // The streamed PUBLISH arrives without payload and is acknowledged as usual
+    let packet = client.recv().await.unwrap();
+    if let mqtt::packet::Packet::V3_1_1Publish(publish) = packet {
+        assert_eq!(publish.topic_name(), "test/large");
+        assert!(publish.payload().as_slice().is_empty());
+    } else {
+        panic!("Expected V3_1_1Publish, got {:?}", packet);
+    }
+    let packet = client.recv().await.unwrap();
+    if let mqtt::packet::Packet::V3_1_1Publish(publish) = packet {
+        assert_eq!(publish.payload().as_slice(), b"tiny");
+    } else {
+        panic!("Expected V3_1_1Publish, got {:?}", packet);
+    }
+
+    let chunks = handler.chunks.lock().unwrap().clone();
+    assert_eq!(
+        chunks,
+        vec![
+            ("test/large".to_string(), 0, b"01".to_vec(), 16),
+            ("test/large".to_string(), 2, b"23456789ab".to_vec(), 16),
+            ("test/large".to_string(), 12, b"cdef".to_vec(), 16),
+        ]
+    );
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    assert!(control.sent_data().iter().any(|data| data[0] == 0x40));
+}
+
+/// Test health() reports the tracked state and its healthy verdict
+#[tokio::test]
+async fn test_health() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    let report = client.health().await.unwrap();
+    assert_eq!(
+        report.state,
+        mqtt_client_wasm::ConnectionState::Disconnected
+    );
+    assert_eq!(report.seconds_since_last_recv, None);
+    assert!(!report.is_healthy());
+
+    common::establish_v311(&client, &event_sender, "test-health").await;
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/health")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtLeastOnce)
+        .packet_id(client.acquire_packet_id().await.unwrap())
+        .payload(b"pending")
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V3_1_1Publish(publish))
+        .await
+        .unwrap();
+
+    let report = client.health().await.unwrap();
+    assert_eq!(report.state, mqtt_client_wasm::ConnectionState::Connected);
+    assert_eq!(report.last_rtt_ms, None);
+    assert_eq!(report.buffered_amount, 0);
+    assert!(!report.buffered_amount_growing);
+    assert_eq!(report.inflight_count, 1);
+    assert_eq!(report.keep_alive_secs, 60);
+    assert!(report.seconds_since_last_recv.unwrap() < 60.0);
+    assert!(report.is_healthy());
+
+    // Silent for longer than 1.5 times the keep alive
+    let stale = mqtt_client_wasm::HealthReport {
+        seconds_since_last_recv: Some(91.0),
+        ..report
+    };
+    assert!(!stale.is_healthy());
+}
+
+/// Test a Keep Alive timeout DISCONNECT is reported and remembered
+#[tokio::test]
+async fn test_keepalive_timeout_disconnect() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-keepalive-timeout").await;
+    assert_eq!(client.last_disconnect_reason().await, None);
+    assert!(!client.was_keepalive_timeout().await);
+
+    let disconnect = mqtt::packet::v5_0::Disconnect::builder()
+        .reason_code(client_mqtt::result_code::DisconnectReasonCode::KeepAliveTimeout)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Disconnect(disconnect).to_continuous_buffer(),
+    ));
+
+    loop {
+        let event =
+            tokio::time::timeout(tokio::time::Duration::from_millis(500), client.next_event())
+                .await
+                .expect("KeepAliveTimeout not emitted")
+                .unwrap();
+        if matches!(event, mqtt_client_wasm::ConnectionEvent::KeepAliveTimeout) {
+            break;
+        }
+    }
+    assert_eq!(client.last_disconnect_reason().await, Some(0x8D));
+    assert!(client.was_keepalive_timeout().await);
+}
+
+/// Test pingreq_jitter_ms spreads PINGREQ intervals above the base interval
+#[tokio::test]
+async fn test_pingreq_jitter() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        pingreq_send_interval_ms: Some(1000),
+        pingreq_jitter_ms: Some(500),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-pingreq-jitter").await;
+
+    // Every send restarts the PINGREQ timer with a fresh offset
+    for _ in 0..5 {
+        client
+            .send(mqtt::packet::Packet::V3_1_1Pingreq(
+                mqtt::packet::v3_1_1::Pingreq::new(),
+            ))
+            .await
+            .unwrap();
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let intervals: Vec<u64> = control
+        .timer_resets()
+        .into_iter()
+        .filter(|(kind, _)| kind == "PingreqSend")
+        .map(|(_, duration_ms)| duration_ms)
+        .collect();
+    assert!(!intervals.is_empty());
+    assert!(
+        intervals.iter().all(|ms| (1000..1500).contains(ms)),
+        "intervals out of range: {:?}",
+        intervals
+    );
+}
+
+/// Test close_with_disconnect() sends the DISCONNECT and closes after shutdown_timeout_ms
+#[tokio::test]
+async fn test_close_with_disconnect() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        shutdown_timeout_ms: 100,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-close-with-disconnect").await;
+
+    assert!(matches!(
+        client
+            .close_with_disconnect(mqtt::packet::Packet::V5_0Pingreq(
+                mqtt::packet::v5_0::Pingreq::new(),
+            ))
+            .await,
+        Err(mqtt_client_wasm::Error::Other(_))
+    ));
+    assert!(client.is_connected().await);
+
+    let disconnect = mqtt::packet::v5_0::Disconnect::builder()
+        .reason_code(client_mqtt::result_code::DisconnectReasonCode::DisconnectWithWillMessage)
+        .build()
+        .unwrap();
+    // The mock broker never closes; the shutdown timeout bounds the wait
+    tokio::time::timeout(
+        tokio::time::Duration::from_millis(1000),
+        client.close_with_disconnect(mqtt::packet::Packet::V5_0Disconnect(disconnect)),
+    )
+    .await
+    .expect("close_with_disconnect() did not finish")
+    .unwrap();
+    assert!(control.disconnect_sent());
+    assert_eq!(
+        client.state().await,
+        mqtt_client_wasm::ConnectionState::Closed
+    );
+}
+
+/// Test close_with_disconnect() finishes when replace_transport() drops the connection
+#[tokio::test]
+async fn test_close_with_disconnect_then_replace_transport() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        shutdown_timeout_ms: 10_000,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-close-then-replace").await;
+
+    let disconnect = mqtt::packet::v5_0::Disconnect::builder()
+        .reason_code(client_mqtt::result_code::DisconnectReasonCode::NormalDisconnection)
+        .build()
+        .unwrap();
+    let close_client = client.clone();
+    let close_task = tokio::spawn(async move {
+        close_client
+            .close_with_disconnect(mqtt::packet::Packet::V5_0Disconnect(disconnect))
+            .await
+    });
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    // The old transport's close is never reported; the wait ends anyway
+    client
+        .replace_transport(MockUnderlyingLayer::new())
+        .await
+        .unwrap();
+    tokio::time::timeout(tokio::time::Duration::from_millis(500), close_task)
+        .await
+        .expect("close_with_disconnect() should finish with the old transport")
+        .unwrap()
+        .unwrap();
+}
+
+/// Test retained PUBLISH packets delivered after a SUBACK keep retain=true
+#[tokio::test]
+async fn test_retained_publish_after_suback() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-retained").await;
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let sub_opts = mqtt::packet::SubOpts::new().set_qos(mqtt::packet::Qos::AtMostOnce);
+    let sub_entry = mqtt::packet::SubEntry::new("test/retained", sub_opts).unwrap();
+    let subscribe = mqtt::packet::v5_0::Subscribe::builder()
+        .packet_id(packet_id)
+        .entries(vec![sub_entry])
+        .build()
+        .unwrap();
+
+    let suback = mqtt::packet::v5_0::Suback::builder()
+        .packet_id(packet_id)
+        .reason_codes(vec![
+            client_mqtt::result_code::SubackReasonCode::GrantedQos0,
+        ])
+        .build()
+        .unwrap();
+    let mut broker_bytes = mqtt::packet::Packet::V5_0Suback(suback).to_continuous_buffer();
+    // The broker sends the retained message right behind the SUBACK, then a live one
+    for (payload, retain) in [("stored", true), ("live", false)] {
+        let publish = mqtt::packet::v5_0::Publish::builder()
+            .topic_name("test/retained")
+            .unwrap()
+            .qos(mqtt::packet::Qos::AtMostOnce)
+            .retain(retain)
+            .payload(payload.as_bytes())
+            .build()
+            .unwrap();
+        broker_bytes.extend(mqtt::packet::Packet::V5_0Publish(publish).to_continuous_buffer());
+    }
+
+    let broker = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            broker_bytes,
+        ));
+    };
+    let (result, _) = futures::join!(
+        client.subscribe(mqtt::packet::Packet::V5_0Subscribe(subscribe)),
+        broker
+    );
+    assert!(matches!(
+        result.unwrap(),
+        mqtt::packet::Packet::V5_0Suback(_)
+    ));
+
+    for (payload, retain) in [("stored", true), ("live", false)] {
+        let packet = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv())
+            .await
+            .expect("PUBLISH should be delivered")
+            .unwrap();
+        match packet {
+            mqtt::packet::Packet::V5_0Publish(p) => {
+                assert_eq!(p.payload().as_slice(), payload.as_bytes());
+                assert_eq!(p.retain(), retain);
+            }
+            other => panic!("Expected PUBLISH, got {:?}", other),
+        }
+    }
+}
+
+/// Test max_publish_rate_per_sec holds publishes over the rate back and sends them in order
+#[tokio::test]
+async fn test_max_publish_rate() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        max_publish_rate_per_sec: Some(5),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-publish-rate").await;
+    let sent_before = control.sent_data().len();
+
+    // A burst of 5 goes out at once; the other 3 follow at 200ms intervals
+    let start = tokio::time::Instant::now();
+    let sends = (0..8).map(|i| {
+        let client = client.clone();
+        async move {
+            let publish = mqtt::packet::v3_1_1::Publish::builder()
+                .topic_name(&format!("test/queue/{}", i))
+                .unwrap()
+                .qos(mqtt::packet::Qos::AtMostOnce)
+                .payload(format!("message {}", i).as_bytes())
+                .build()
+                .unwrap();
+            client
+                .send(mqtt::packet::Packet::V3_1_1Publish(publish))
+                .await
+                .unwrap();
+            start.elapsed()
+        }
+    });
+    let pings = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+        // Other packets bypass the queued publishes
+        client
+            .send(mqtt::packet::Packet::V3_1_1Pingreq(
+                mqtt::packet::v3_1_1::Pingreq::new(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(control.sent_data().len() - sent_before, 6);
+    };
+    let (elapsed, _) = futures::join!(futures::future::join_all(sends), pings);
+
+    assert!(
+        elapsed[..5].iter().all(|e| e.as_millis() < 100),
+        "{:?}",
+        elapsed
+    );
+    assert!(elapsed[7].as_millis() >= 500, "{:?}", elapsed);
+    assert!(elapsed[7].as_millis() < 2000, "{:?}", elapsed);
+
+    let publishes: Vec<Vec<u8>> = control.sent_data()[sent_before..]
+        .iter()
+        .filter(|data| data[0] >> 4 == 3)
+        .cloned()
+        .collect();
+    let expected: Vec<Vec<u8>> = (0..8).map(queued_publish_bytes).collect();
+    assert_eq!(publishes, expected);
+
+    // The 6th publish was the first one held back
+    let queued = loop {
+        let event =
+            tokio::time::timeout(tokio::time::Duration::from_millis(500), client.next_event())
+                .await
+                .expect("PublishThrottled should be emitted")
+                .unwrap();
+        if let mqtt_client_wasm::ConnectionEvent::PublishThrottled { queued } = event {
+            break queued;
+        }
+    };
+    assert_eq!(queued, 1);
+}
+
+/// Test a throttled v5.0 PUBLISH is sent with its expiry lowered, or dropped once expired
+#[tokio::test]
+async fn test_max_publish_rate_message_expiry() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        max_publish_rate_per_sec: Some(1),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-publish-rate-expiry").await;
+    let sent_before = control.sent_data().len();
+
+    // One goes out at once, then one per second: expiring ones wait about 2s and 3s
+    let sends = [None, None, Some(10), Some(2)]
+        .into_iter()
+        .enumerate()
+        .map(|(i, expiry)| {
+            let client = client.clone();
+            async move {
+                let mut builder = mqtt::packet::v5_0::Publish::builder()
+                    .topic_name(&format!("test/expiry/{}", i))
+                    .unwrap()
+                    .qos(mqtt::packet::Qos::AtMostOnce)
+                    .payload(b"data");
+                if let Some(interval) = expiry {
+                    builder = builder.props(mqtt::packet::Properties::from(vec![
+                        mqtt::packet::Property::MessageExpiryInterval(
+                            mqtt::packet::MessageExpiryInterval::new(interval).unwrap(),
+                        ),
+                    ]));
+                }
+                client
+                    .send(mqtt::packet::Packet::V5_0Publish(builder.build().unwrap()))
+                    .await
+            }
+        });
+    let results = futures::future::join_all(sends).await;
+    assert!(results[..3].iter().all(|r| r.is_ok()), "{:?}", results);
+    assert!(matches!(
+        results[3],
+        Err(mqtt_client_wasm::Error::SendFailed(_))
+    ));
+
+    let publishes: Vec<Vec<u8>> = control.sent_data()[sent_before..]
+        .iter()
+        .filter(|data| data[0] >> 4 == 3)
+        .cloned()
+        .collect();
+    assert_eq!(publishes.len(), 3);
+    // Message Expiry Interval property (0x02) lowered from 10 by the seconds waited
+    let interval = publishes[2]
+        .windows(5)
+        .find(|w| w[0] == 0x02 && w[1..4] == [0, 0, 0])
+        .map(|w| w[4])
+        .unwrap();
+    assert!((7..10).contains(&interval), "{}", interval);
+}
+
+/// Test recv_with_meta() reports whether the client auto-acknowledged a packet
+#[tokio::test]
+async fn test_recv_with_meta_auto_responded() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-recv-meta").await;
+
+    let mut broker_bytes = Vec::new();
+    for (qos, packet_id) in [
+        (mqtt::packet::Qos::AtLeastOnce, Some(1)),
+        (mqtt::packet::Qos::AtMostOnce, None),
+    ] {
+        let mut builder = mqtt::packet::v5_0::Publish::builder()
+            .topic_name("test/meta")
+            .unwrap()
+            .qos(qos)
+            .payload(b"data");
+        if let Some(packet_id) = packet_id {
+            builder = builder.packet_id(packet_id);
+        }
+        let publish = builder.build().unwrap();
+        broker_bytes.extend(mqtt::packet::Packet::V5_0Publish(publish).to_continuous_buffer());
+    }
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        broker_bytes,
+    ));
+
+    // QoS 1: the PUBACK went out automatically
+    let (packet, meta) = tokio::time::timeout(
+        tokio::time::Duration::from_millis(500),
+        client.recv_with_meta(),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert!(matches!(packet, mqtt::packet::Packet::V5_0Publish(ref p) if p.packet_id() == Some(1)));
+    assert!(meta.auto_responded);
+
+    // QoS 0: nothing to answer
+    let (packet, meta) = tokio::time::timeout(
+        tokio::time::Duration::from_millis(500),
+        client.recv_with_meta(),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert!(matches!(packet, mqtt::packet::Packet::V5_0Publish(_)));
+    assert!(!meta.auto_responded);
+}
+
+/// Packet ID allocator counting up from 100 that logs what the client asks of it
+struct RecordingAllocator {
+    next: std::sync::Mutex<u16>,
+    log: std::sync::Mutex<Vec<String>>,
+}
+
+impl mqtt_client_wasm::PacketIdAllocator for RecordingAllocator {
+    fn acquire(&self) -> Option<u16> {
+        let mut next = self.next.lock().unwrap();
+        *next += 1;
+        self.log.lock().unwrap().push(format!("acquire {}", *next));
+        Some(*next)
+    }
+
+    fn register(&self, packet_id: u16) -> bool {
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("register {}", packet_id));
+        true
+    }
+
+    fn release(&self, packet_id: u16) {
+        self.log
+            .lock()
+            .unwrap()
+            .push(format!("release {}", packet_id));
+    }
+}
+
+/// Test packet IDs come from a custom packet_id_allocator and go back to it
+#[tokio::test]
+async fn test_custom_packet_id_allocator() {
+    let allocator = std::sync::Arc::new(RecordingAllocator {
+        next: std::sync::Mutex::new(100),
+        log: std::sync::Mutex::new(Vec::new()),
+    });
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        packet_id_allocator: Some(allocator.clone()),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-allocator").await;
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    assert_eq!(packet_id, 101);
+    assert!(client.register_packet_id(7).await);
+    client.release_packet_id(7).await.unwrap();
+
+    // The connection accepts the allocator's ID for a QoS 1 PUBLISH
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/allocator")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtLeastOnce)
+        .packet_id(packet_id)
+        .payload(b"data")
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V3_1_1Publish(publish))
+        .await
+        .unwrap();
+    let puback = mqtt::packet::v3_1_1::Puback::builder()
+        .packet_id(packet_id)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Puback(puback).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    let _ = client.complete_qos(packet_id).await;
+
+    assert_eq!(
+        *allocator.log.lock().unwrap(),
+        vec!["acquire 101", "register 7", "release 7", "release 101"]
+    );
+}
+
+/// Test DefaultPacketIdAllocator hands out the lowest free ID
+#[test]
+fn test_default_packet_id_allocator() {
+    use mqtt_client_wasm::PacketIdAllocator;
+
+    let allocator = mqtt_client_wasm::DefaultPacketIdAllocator::new();
+    assert_eq!(allocator.acquire(), Some(1));
+    assert!(allocator.register(2));
+    assert!(!allocator.register(2));
+    assert!(!allocator.register(0));
+    assert_eq!(allocator.acquire(), Some(3));
+    allocator.release(1);
+    assert_eq!(allocator.acquire(), Some(1));
+}
+
+/// Test a PUBLISH over the broker's Maximum Packet Size is rejected with a size breakdown
+#[tokio::test]
+async fn test_publish_payload_too_large() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    let connect = mqtt::packet::v5_0::Connect::builder()
+        .client_id("test-payload-too-large")
+        .unwrap()
+        .clean_start(true)
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V5_0Connect(connect))
+        .await;
+    let connack = mqtt::packet::v5_0::Connack::builder()
+        .session_present(false)
+        .reason_code(client_mqtt::result_code::ConnectReasonCode::Success)
+        .props(mqtt::packet::Properties::from(vec![
+            mqtt::packet::Property::MaximumPacketSize(
+                mqtt::packet::MaximumPacketSize::new(64).unwrap(),
+            ),
+        ]))
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Connack(connack).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+
+    let publish = |payload: Vec<u8>| {
+        mqtt::packet::Packet::V5_0Publish(
+            mqtt::packet::v5_0::Publish::builder()
+                .topic_name("test/size")
+                .unwrap()
+                .qos(mqtt::packet::Qos::AtMostOnce)
+                .payload(payload)
+                .build()
+                .unwrap(),
+        )
+    };
+    let sent_before = control.sent_data().len();
+
+    let packet = publish(vec![0; 100]);
+    let size = packet.to_continuous_buffer().len();
+    match client.send(packet).await {
+        Err(mqtt_client_wasm::Error::PayloadTooLarge {
+            payload_size,
+            max_packet_size,
+            overhead,
+        }) => {
+            assert_eq!(payload_size, 100);
+            assert_eq!(max_packet_size, 64);
+            assert_eq!(overhead, size - 100);
+        }
+        other => panic!("Expected PayloadTooLarge, got {:?}", other),
+    }
+    assert_eq!(control.sent_data().len(), sent_before);
+
+    // Trimmed to fit
+    client.send(publish(vec![0; 32])).await.unwrap();
+    assert_eq!(control.sent_data().len(), sent_before + 1);
+}
+
+/// Test ensure_connected() shares an attempt in progress and is a no-op once connected
+#[tokio::test]
+async fn test_ensure_connected() {
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(MqttConfig::default(), mock_ws);
+
+    // The first call starts the attempt; the transport has not answered yet
+    control.set_hold_connects(true);
+    let first_client = client.clone();
+    let _first = tokio::spawn(async move { first_client.ensure_connected("ws://test").await });
+    tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+    assert_eq!(client.state().await, ConnectionState::Connecting);
+
+    // A different URL is refused; the same URL waits for the attempt in progress
+    assert!(client.ensure_connected("ws://other").await.is_err());
+    let connected = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Connected);
+    };
+    let (result, _) = futures::join!(
+        tokio::time::timeout(
+            tokio::time::Duration::from_millis(500),
+            client.ensure_connected("ws://test")
+        ),
+        connected
+    );
+    assert!(result.expect("ensure_connected() did not resolve").is_ok());
+
+    // Connected: resolves without another attempt
+    assert!(client.ensure_connected("ws://test").await.is_ok());
+    assert_eq!(control.connect_urls(), vec!["ws://test".to_string()]);
+}
+
+/// Test a session exported from one client continues on another
+#[tokio::test]
+async fn test_export_import_session() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config.clone(), mock_ws);
+    common::establish_v311(&client, &event_sender, "test-export-session").await;
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let subscribe = mqtt::packet::v3_1_1::Subscribe::builder()
+        .packet_id(packet_id)
+        .entries(vec![mqtt::packet::SubEntry::new(
+            "test/session",
+            mqtt::packet::SubOpts::new().set_qos(mqtt::packet::Qos::AtLeastOnce),
+        )
+        .unwrap()])
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V3_1_1Subscribe(subscribe))
+        .await
+        .unwrap();
+    let suback = mqtt::packet::v3_1_1::Suback::builder()
+        .packet_id(packet_id)
+        .return_codes(vec![
+            client_mqtt::result_code::SubackReturnCode::SuccessMaximumQos1,
+        ])
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Suback(suback).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+
+    // A QoS 1 PUBLISH the broker never acknowledges
+    let publish_id = client.acquire_packet_id().await.unwrap();
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/session")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtLeastOnce)
+        .packet_id(publish_id)
+        .payload(b"unacknowledged")
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V3_1_1Publish(publish))
+        .await
+        .unwrap();
+
+    let snapshot = client.export_session().await;
+    assert_eq!(snapshot.version, "3.1.1");
+    assert_eq!(snapshot.client_id.as_deref(), Some("test-export-session"));
+    assert_eq!(snapshot.keep_alive_secs, 60);
+    assert_eq!(
+        snapshot.inflight,
+        vec![mqtt_client_wasm::InflightEntry {
+            packet_id: publish_id,
+            phase: mqtt_client_wasm::InflightPhase::AwaitingPuback,
+        }]
+    );
+    assert_eq!(
+        snapshot.subscriptions,
+        vec![mqtt_client_wasm::SubscriptionEntry {
+            topic_filter: "test/session".to_string(),
+            qos: 1,
+        }]
+    );
+
+    // A snapshot only fits a client speaking the same protocol version
+    let v5_client = MqttClient::new_with_websocket(
+        MqttConfig::default().version(client_mqtt::Version::V5_0),
+        MockUnderlyingLayer::new(),
+    );
+    assert!(v5_client.import_session(snapshot.clone()).await.is_err());
+
+    // Connected clients refuse to import
+    assert!(client.import_session(snapshot.clone()).await.is_err());
+
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let resumed = MqttClient::new_with_websocket(config, mock_ws);
+    resumed.import_session(snapshot.clone()).await.unwrap();
+    let _ = resumed.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let connect_packet = mqtt::packet::v3_1_1::Connect::builder()
+        .client_id("test-export-session")
+        .unwrap()
+        .keep_alive(60)
+        .clean_session(false)
+        .build()
+        .unwrap();
+    let _ = resumed
+        .send(mqtt::packet::Packet::V3_1_1Connect(connect_packet))
+        .await;
+    let connack = mqtt::packet::v3_1_1::Connack::builder()
+        .session_present(true)
+        .return_code(client_mqtt::result_code::ConnectReturnCode::Accepted)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Connack(connack).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), resumed.recv()).await;
+
+    // The imported flow holds its packet ID until it completes
+    let resumed_snapshot = resumed.export_session().await;
+    assert_eq!(resumed_snapshot.inflight, snapshot.inflight);
+    assert_eq!(resumed_snapshot.subscriptions, snapshot.subscriptions);
+    assert_eq!(resumed_snapshot.session_present, Some(true));
+    assert!(matches!(
+        resumed.complete_qos(publish_id).await,
+        Err(mqtt_client_wasm::Error::QosFlowIncomplete(id)) if id == publish_id
+    ));
+}
+
+/// Build a v3.1.1 SUBSCRIBE for `filters` at QoS 1
+fn subscribe_v311(packet_id: u16, filters: &[&str]) -> mqtt::packet::Packet {
+    let entries = filters
+        .iter()
+        .map(|filter| {
+            mqtt::packet::SubEntry::new(
+                *filter,
+                mqtt::packet::SubOpts::new().set_qos(mqtt::packet::Qos::AtLeastOnce),
+            )
+            .unwrap()
+        })
+        .collect();
+    let subscribe = mqtt::packet::v3_1_1::Subscribe::builder()
+        .packet_id(packet_id)
+        .entries(entries)
+        .build()
+        .unwrap();
+    mqtt::packet::Packet::V3_1_1Subscribe(subscribe)
+}
+
+/// Test subscribe() fails right away when the connection refuses the SUBSCRIBE
+#[tokio::test]
+async fn test_subscribe_rejected_by_connection() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-subscribe-rejected").await;
+
+    // Packet ID 42 was never acquired, so the SUBSCRIBE is not sent
+    let result = tokio::time::timeout(
+        tokio::time::Duration::from_millis(500),
+        client.subscribe(subscribe_v311(42, &["test/a"])),
+    )
+    .await
+    .expect("subscribe() should not wait for a SUBACK");
+    assert!(matches!(
+        result,
+        Err(mqtt_client_wasm::Error::ProtocolError(_))
+    ));
+    assert!(client.subscriptions().await.is_empty());
+}
+
+/// Test max_subscriptions refuses a subscribe() before it reaches the broker
+#[tokio::test]
+async fn test_max_subscriptions() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        max_subscriptions: Some(2),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-max-subscriptions").await;
+    assert_eq!(client.subscription_count().await, 0);
+
+    // Subscribe to `filters` and let the broker accept all of them
+    let subscribe_accepted = |filters: &'static [&'static str]| {
+        let client = client.clone();
+        let event_sender = event_sender.clone();
+        async move {
+            let packet_id = client.acquire_packet_id().await.unwrap();
+            let suback = mqtt::packet::v3_1_1::Suback::builder()
+                .packet_id(packet_id)
+                .return_codes(vec![
+                    client_mqtt::result_code::SubackReturnCode::SuccessMaximumQos1;
+                    filters.len()
+                ])
+                .build()
+                .unwrap();
+            let broker = async {
+                tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+                let _ =
+                    event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+                        mqtt::packet::Packet::V3_1_1Suback(suback).to_continuous_buffer(),
+                    ));
+            };
+            let (result, _) =
+                futures::join!(client.subscribe(subscribe_v311(packet_id, filters)), broker);
+            result
+        }
+    };
+
+    subscribe_accepted(&["test/a", "test/b"]).await.unwrap();
+    assert_eq!(client.subscription_count().await, 2);
+
+    // A third filter is refused without sending anything
+    let sent_before = control.sent_data().len();
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let result = client
+        .subscribe(subscribe_v311(packet_id, &["test/c"]))
+        .await;
+    assert!(matches!(
+        result,
+        Err(mqtt_client_wasm::Error::SubscriptionLimitReached(2))
+    ));
+    assert_eq!(control.sent_data().len(), sent_before);
+    client.release_packet_id(packet_id).await.unwrap();
+
+    // Subscribing again to a filter already held does not add one
+    subscribe_accepted(&["test/a"]).await.unwrap();
+    assert_eq!(client.subscription_count().await, 2);
+}
+
+/// Test reconnect_max_attempts stops auto-reconnect after that many failed attempts
+#[tokio::test]
+async fn test_reconnect_max_attempts() {
+    let config = MqttConfig {
+        reconnect_initial_delay_ms: Some(10),
+        reconnect_max_delay_ms: 10,
+        reconnect_max_attempts: Some(2),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let control = mock_ws.control();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    let _ = client.connect("ws://test.example.com").await;
+
+    let next =
+        || tokio::time::timeout(tokio::time::Duration::from_millis(500), client.next_event());
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Connected
+    ));
+
+    // The broker is down: every attempt hangs until the transport closes
+    control.set_hold_connects(true);
+    control.simulate_abrupt_close();
+    let mut attempts = Vec::new();
+    let gave_up = loop {
+        match next().await.unwrap().unwrap() {
+            mqtt_client_wasm::ConnectionEvent::Reconnecting { attempt, .. } => {
+                attempts.push(attempt);
+                // Let the backoff expire, then fail the attempt
+                tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+                control.simulate_abrupt_close();
+            }
+            mqtt_client_wasm::ConnectionEvent::ReconnectGaveUp { attempts } => break attempts,
+            mqtt_client_wasm::ConnectionEvent::Closed { .. } => {}
+            other => panic!("Unexpected event {:?}", other),
+        }
+    };
+    assert_eq!(attempts, vec![1, 2]);
+    assert_eq!(gave_up, 2);
+
+    // No further attempts
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    assert_eq!(client.state().await, ConnectionState::Closed);
+    assert_eq!(control.connect_urls().len(), 3);
+
+    // A manual connect() works, and the attempt count starts over
+    control.set_hold_connects(false);
+    let _ = client.connect("ws://test.example.com").await;
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Connected
+    ));
+    control.simulate_abrupt_close();
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Closed { .. }
+    ));
+    assert!(matches!(
+        next().await.unwrap().unwrap(),
+        mqtt_client_wasm::ConnectionEvent::Reconnecting { attempt: 1, .. }
+    ));
+    let _ = client.close().await;
+}
+
+/// Test build_info() reports this build's versions and features
+#[test]
+fn test_build_info() {
+    let info = mqtt_client_wasm::build_info();
+    assert_eq!(info.crate_version, env!("CARGO_PKG_VERSION"));
+    assert_ne!(info.mqtt_protocol_core_version, "unknown");
+    assert!(info.mqtt_protocol_core_version.starts_with("0.7"));
+    assert_eq!(info.target_arch, std::env::consts::ARCH);
+    // The integration tests enable test-hooks
+    assert!(info.features.contains(&"test-hooks"));
+}
+
+/// Test close() racing with the broker closing ends in one clean Closed state
+#[tokio::test]
+async fn test_simultaneous_close() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        pingreq_send_interval_ms: Some(5000),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-simultaneous-close").await;
+    assert!(control
+        .timer_resets()
+        .iter()
+        .any(|(kind, _)| kind.contains("PingreqSend")));
+
+    // The broker closes while close() is closing the transport too
+    let cancels_before = control.timer_cancels().len();
+    let (result, _) = futures::join!(client.close(), async {
+        control.simulate_abrupt_close();
+    });
+    assert!(result.is_ok());
+    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
+    assert_eq!(client.state().await, ConnectionState::Closed);
+
+    // Closed was handled once
+    let mut closed_events = 0;
+    while let Ok(Ok(event)) =
+        tokio::time::timeout(tokio::time::Duration::from_millis(50), client.next_event()).await
+    {
+        if matches!(event, mqtt_client_wasm::ConnectionEvent::Closed { .. }) {
+            closed_events += 1;
+        }
+    }
+    assert_eq!(closed_events, 1);
+
+    // Every timer was cancelled exactly once
+    let cancels = control.timer_cancels().split_off(cancels_before);
+    assert!(cancels.iter().any(|kind| kind.contains("PingreqSend")));
+    let unique: std::collections::HashSet<&String> = cancels.iter().collect();
+    assert_eq!(
+        unique.len(),
+        cancels.len(),
+        "timers cancelled twice: {:?}",
+        cancels
+    );
+}
+
+/// Test a QoS 2 PUBLISH re-sent on a resumed session is not delivered twice
+#[tokio::test]
+async fn test_qos2_inbound_replay_after_reconnect() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-qos2-replay").await;
+
+    let publish = |payload: &[u8], dup: bool| {
+        let publish = mqtt::packet::v3_1_1::Publish::builder()
+            .topic_name("test/qos2/replay")
+            .unwrap()
+            .qos(mqtt::packet::Qos::ExactlyOnce)
+            .packet_id(9)
+            .dup(dup)
+            .payload(payload)
+            .build()
+            .unwrap();
+        mqtt::packet::Packet::V3_1_1Publish(publish).to_continuous_buffer()
+    };
+    let pubrec_bytes = mqtt::packet::Packet::V3_1_1Pubrec(
+        mqtt::packet::v3_1_1::Pubrec::builder()
+            .packet_id(9)
+            .build()
+            .unwrap(),
+    )
+    .to_continuous_buffer();
+    let pubrec_count = || {
+        control
+            .sent_data()
+            .iter()
+            .filter(|d| **d == pubrec_bytes)
+            .count()
+    };
+    // Payloads of the PUBLISH packets received until recv() goes quiet
+    async fn recv_publishes(client: &MqttClient) -> Vec<Vec<u8>> {
+        let mut payloads = Vec::new();
+        while let Ok(Ok(packet)) =
+            tokio::time::timeout(tokio::time::Duration::from_millis(200), client.recv()).await
+        {
+            if let mqtt::packet::Packet::V3_1_1Publish(p) = packet {
+                payloads.push(p.payload().as_slice().to_vec());
+            }
+        }
+        payloads
+    }
+
+    // Delivered and answered with PUBREC; the connection drops before PUBREL
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(publish(
+        b"once", false,
+    )));
+    assert_eq!(recv_publishes(&client).await, vec![b"once".to_vec()]);
+    assert_eq!(pubrec_count(), 1);
+    control.simulate_abrupt_close();
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    // Resume the session
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    let connect_packet = mqtt::packet::v3_1_1::Connect::builder()
+        .client_id("test-qos2-replay")
+        .unwrap()
+        .keep_alive(60)
+        .clean_session(false)
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Connect(connect_packet))
+        .await;
+    let connack = mqtt::packet::v3_1_1::Connack::builder()
+        .session_present(true)
+        .return_code(client_mqtt::result_code::ConnectReturnCode::Accepted)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Connack(connack).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+
+    // The broker never got the PUBREC and re-sends the PUBLISH
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(publish(
+        b"once", true,
+    )));
+    assert!(recv_publishes(&client).await.is_empty());
+    assert_eq!(pubrec_count(), 2);
+
+    // After the PUBREL, packet ID 9 carries a new message again
+    let pubrel = mqtt::packet::v3_1_1::Pubrel::builder()
+        .packet_id(9)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Pubrel(pubrel).to_continuous_buffer(),
+    ));
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(publish(
+        b"next", false,
+    )));
+    assert_eq!(recv_publishes(&client).await, vec![b"next".to_vec()]);
+}
+
+/// Test next_state_change() reports each state transition once, in order
+#[tokio::test]
+async fn test_state_changes() {
+    use mqtt_client_wasm::StateChange;
+
+    let mock_ws = MockUnderlyingLayer::new();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(MqttConfig::default(), mock_ws);
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    control.simulate_abrupt_close();
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let mut changes = Vec::new();
+    while let Ok(Ok(change)) = tokio::time::timeout(
+        tokio::time::Duration::from_millis(50),
+        client.next_state_change(),
+    )
+    .await
+    {
+        changes.push(change);
+    }
+    assert_eq!(
+        changes,
+        vec![
+            StateChange {
+                from: ConnectionState::Disconnected,
+                to: ConnectionState::Connecting,
+            },
+            StateChange {
+                from: ConnectionState::Connecting,
+                to: ConnectionState::Connected,
+            },
+            StateChange {
+                from: ConnectionState::Connected,
+                to: ConnectionState::Closed,
+            },
+        ]
+    );
+
+    // A transition is delivered to a waiting caller
+    let (change, _) = futures::join!(
+        client.next_state_change(),
+        client.connect("ws://test.example.com")
+    );
+    assert_eq!(
+        change.unwrap(),
+        StateChange {
+            from: ConnectionState::Closed,
+            to: ConnectionState::Connecting,
+        }
+    );
+}
+
+/// Test send_encoded() sends exactly one well-formed packet as is
+#[tokio::test]
+async fn test_send_encoded() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-send-encoded").await;
+
+    let frame = queued_publish_bytes(0);
+    let sent_before = control.sent_data().len();
+    client.send_encoded(frame.clone()).await.unwrap();
+    let sent = control.sent_data();
+    assert_eq!(sent.len(), sent_before + 1);
+    assert_eq!(sent.last().unwrap(), &frame);
+
+    // A remaining length in two bytes is sent as given, not re-encoded
+    let mut long_length = vec![frame[0], frame[1] | 0x80, 0x00];
+    long_length.extend_from_slice(&frame[2..]);
+    client.send_encoded(long_length.clone()).await.unwrap();
+    assert_eq!(control.sent_data().last().unwrap(), &long_length);
+
+    // Partial packet
+    assert!(matches!(
+        client.send_encoded(frame[..frame.len() - 1].to_vec()).await,
+        Err(mqtt_client_wasm::Error::DecodeError { .. })
+    ));
+    // Two packets in one buffer
+    let mut two = frame.clone();
+    two.extend_from_slice(&queued_publish_bytes(1));
+    assert!(matches!(
+        client.send_encoded(two).await,
+        Err(mqtt_client_wasm::Error::DecodeError { offset, .. }) if offset == frame.len()
+    ));
+    // Empty buffer
+    assert!(matches!(
+        client.send_encoded(Vec::new()).await,
+        Err(mqtt_client_wasm::Error::DecodeError { .. })
+    ));
+    assert_eq!(control.sent_data().len(), sent_before + 2);
+}
+
+/// Test send_encoded() refuses a PUBLISH while topic aliases are mapped automatically
+#[tokio::test]
+async fn test_send_encoded_with_topic_alias_mapping() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        auto_map_topic_alias_send: true,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-send-encoded-alias").await;
+
+    let publish = mqtt::packet::v5_0::Publish::builder()
+        .topic_name("test/alias")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtMostOnce)
+        .payload(b"data")
+        .build()
+        .unwrap();
+    let frame = mqtt::packet::Packet::V5_0Publish(publish).to_continuous_buffer();
+    let sent_before = control.sent_data().len();
+    assert!(matches!(
+        client.send_encoded(frame).await,
+        Err(mqtt_client_wasm::Error::SendFailed(_))
+    ));
+    assert_eq!(control.sent_data().len(), sent_before);
+}
+
+/// Test close_with_disconnect() drops a Reason String over the broker's Maximum Packet Size
+#[tokio::test]
+async fn test_disconnect_reason_string_dropped() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        shutdown_timeout_ms: 0,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    let connect = mqtt::packet::v5_0::Connect::builder()
+        .client_id("test-reason-string")
+        .unwrap()
+        .clean_start(true)
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V5_0Connect(connect))
+        .await;
+    let connack = mqtt::packet::v5_0::Connack::builder()
+        .session_present(false)
+        .reason_code(client_mqtt::result_code::ConnectReasonCode::Success)
+        .props(mqtt::packet::Properties::from(vec![
+            mqtt::packet::Property::MaximumPacketSize(
+                mqtt::packet::MaximumPacketSize::new(64).unwrap(),
+            ),
+        ]))
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Connack(connack).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+
+    let disconnect = mqtt::packet::v5_0::Disconnect::builder()
+        .reason_code(client_mqtt::result_code::DisconnectReasonCode::NormalDisconnection)
+        .props(mqtt::packet::Properties::from(vec![
+            mqtt::packet::Property::ReasonString(
+                mqtt::packet::ReasonString::new(&"x".repeat(100)).unwrap(),
+            ),
+        ]))
+        .build()
+        .unwrap();
+    client
+        .close_with_disconnect(mqtt::packet::Packet::V5_0Disconnect(disconnect))
+        .await
+        .unwrap();
+
+    let expected = mqtt::packet::v5_0::Disconnect::builder()
+        .reason_code(client_mqtt::result_code::DisconnectReasonCode::NormalDisconnection)
+        .build()
+        .unwrap();
+    assert_eq!(
+        control.sent_data().last().unwrap(),
+        &mqtt::packet::Packet::V5_0Disconnect(expected).to_continuous_buffer()
+    );
+
+    let mut dropped = None;
+    while let Ok(Ok(event)) =
+        tokio::time::timeout(tokio::time::Duration::from_millis(200), client.next_event()).await
+    {
+        if let mqtt_client_wasm::ConnectionEvent::ReasonStringDropped { max_packet_size } = event {
+            dropped = Some(max_packet_size);
+            break;
+        }
+    }
+    assert_eq!(dropped, Some(64));
+}
+
+/// Test error_on_connack_failure turns a refusing CONNACK into Error::ConnectionRefused
+#[tokio::test]
+async fn test_error_on_connack_failure() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        error_on_connack_failure: true,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    let connect = mqtt::packet::v5_0::Connect::builder()
+        .client_id("test-connack-failure")
+        .unwrap()
+        .clean_start(true)
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V5_0Connect(connect))
+        .await
+        .unwrap();
+
+    let connack = mqtt::packet::v5_0::Connack::builder()
+        .session_present(false)
+        .reason_code(client_mqtt::result_code::ConnectReasonCode::NotAuthorized)
+        .props(mqtt::packet::Properties::from(vec![
+            mqtt::packet::Property::ReasonString(
+                mqtt::packet::ReasonString::new("bad token").unwrap(),
+            ),
+        ]))
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Connack(connack).to_continuous_buffer(),
+    ));
+
+    match tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv())
+        .await
+        .expect("recv() should settle")
+    {
+        Err(mqtt_client_wasm::Error::ConnectionRefused {
+            reason_code,
+            reason_string,
+        }) => {
+            assert_eq!(reason_code, 0x87);
+            assert_eq!(reason_string.as_deref(), Some("bad token"));
+        }
+        other => panic!("Expected ConnectionRefused, got {:?}", other),
+    }
+}
+
+/// Test topic name and filter validation
+#[test]
+fn test_validate_topics() {
+    use mqtt_client_wasm::topic::{validate_filter, validate_name};
+
+    for filter in [
+        "sport/tennis/player1",
+        "sport/+/player1",
+        "sport/#",
+        "#",
+        "+",
+        "+/+",
+        "/",
+        "sport/tennis/",
+        "$SYS/#",
+        "$share/group/sport/#",
+    ] {
+        assert!(
+            validate_filter(filter).is_ok(),
+            "{} should be valid",
+            filter
+        );
+    }
+    for filter in [
+        "",
+        "sport/#/stats",
+        "sport/tennis#",
+        "sport+",
+        "sport/+tennis/x",
+        "a\0b",
+        "$share/group",
+        "$share//sport",
+        "$share/gr+oup/sport",
+    ] {
+        assert!(
+            validate_filter(filter).is_err(),
+            "{:?} should be invalid",
+            filter
+        );
+    }
+    assert_eq!(
+        validate_filter("sport/#/stats").unwrap_err(),
+        "'#' must be the last level, found at level 1 of 3"
+    );
+    assert!(validate_filter(&"a".repeat(65536)).is_err());
+
+    assert!(validate_name("sport/tennis/player1").is_ok());
+    assert!(validate_name("/").is_ok());
+    assert!(validate_name("").is_err());
+    assert!(validate_name("sport/+").is_err());
+    assert!(validate_name("sport/#").is_err());
+    assert!(validate_name("a\0b").is_err());
+}
+
+/// Test recv_for_subscription() takes PUBLISHes by subscription identifier and leaves the rest
+#[tokio::test]
+async fn test_recv_for_subscription() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-recv-for-subscription").await;
+
+    let publish = |topic: &str, subscription_id: u32| {
+        let publish = mqtt::packet::v5_0::Publish::builder()
+            .topic_name(topic)
+            .unwrap()
+            .qos(mqtt::packet::Qos::AtMostOnce)
+            .props(mqtt::packet::Properties::from(vec![
+                mqtt::packet::Property::SubscriptionIdentifier(
+                    mqtt::packet::SubscriptionIdentifier::new(subscription_id).unwrap(),
+                ),
+            ]))
+            .payload(b"data")
+            .build()
+            .unwrap();
+        mqtt::packet::Packet::V5_0Publish(publish).to_continuous_buffer()
+    };
+    for (topic, subscription_id) in [("other/1", 3), ("mine/1", 7), ("other/2", 3)] {
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            publish(topic, subscription_id),
+        ));
+    }
+
+    let topic = |packet: mqtt::packet::Packet| match packet {
+        mqtt::packet::Packet::V5_0Publish(p) => p.topic_name().to_string(),
+        other => panic!("Expected PUBLISH, got {:?}", other),
+    };
+    let received = tokio::time::timeout(
+        tokio::time::Duration::from_millis(500),
+        client.recv_for_subscription(7),
+    )
+    .await
+    .expect("recv_for_subscription() should resolve")
+    .unwrap();
+    assert_eq!(topic(received), "mine/1");
+
+    // Nothing else carries identifier 7
+    assert!(tokio::time::timeout(
+        tokio::time::Duration::from_millis(100),
+        client.recv_for_subscription(7),
+    )
+    .await
+    .is_err());
+
+    // The others are still queued for recv(), in order
+    for expected in ["other/1", "other/2"] {
+        let packet = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(topic(packet), expected);
+    }
+}
+
+/// Tap that panics, standing in for a bug inside the processor
+struct PanickingTap;
+
+impl mqtt_client_wasm::PacketTap for PanickingTap {
+    fn tap(&self, _direction: mqtt_client_wasm::PacketDirection, _bytes: &[u8], _type: &str) {
+        panic!("tap exploded");
+    }
+}
+
+/// Test a panic in the processor is reported by last_fatal_error()
+#[tokio::test]
+async fn test_last_fatal_error() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-fatal-error").await;
+    assert_eq!(client.last_fatal_error(), None);
+
+    client
+        .set_packet_tap(Some(std::sync::Arc::new(PanickingTap)))
+        .await
+        .unwrap();
+    let result = client
+        .send(mqtt::packet::Packet::V3_1_1Pingreq(
+            mqtt::packet::v3_1_1::Pingreq::new(),
+        ))
+        .await;
+    assert!(result.is_err());
+
+    // Recorded once the processor thread has unwound
+    let mut fatal_error = None;
+    for _ in 0..50 {
+        fatal_error = client.last_fatal_error();
+        if fatal_error.is_some() {
+            break;
+        }
+        tokio::time::sleep(tokio::time::Duration::from_millis(10)).await;
+    }
+    assert_eq!(fatal_error.as_deref(), Some("tap exploded"));
+    // The client no longer works
+    assert!(!client.is_connected_sync());
+    assert!(client.health().await.is_err());
+}
+
+/// Build a v3.1.1 QoS 0 PUBLISH on `test/queue/{i}`
+fn queued_publish_bytes(i: usize) -> Vec<u8> {
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name(&format!("test/queue/{}", i))
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtMostOnce)
+        .payload(format!("message {}", i).as_bytes())
+        .build()
+        .unwrap();
+    mqtt::packet::Packet::V3_1_1Publish(publish).to_continuous_buffer()
+}
+
+/// Receive `count` PUBLISH packets and check they arrive as test/queue/0..count
+async fn assert_queue_order(client: &MqttClient, count: usize) {
+    for i in 0..count {
+        let packet = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv())
+            .await
+            .expect("packet should be delivered")
+            .unwrap();
+        match packet {
+            mqtt::packet::Packet::V3_1_1Publish(p) => {
+                assert_eq!(p.topic_name(), format!("test/queue/{}", i));
+            }
+            other => panic!("Expected PUBLISH, got {:?}", other),
+        }
+    }
+}
+
+/// Test packets received while no recv() is pending are all kept in wire order
+#[tokio::test]
+async fn test_multiple_packets_without_receiver() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-no-receiver").await;
+
+    for i in 0..5 {
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            queued_publish_bytes(i),
+        ));
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    assert_queue_order(&client, 5).await;
+}
+
+/// Test max_undelivered_packets holds bytes back instead of dropping packets
+#[tokio::test]
+async fn test_max_undelivered_packets() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        max_undelivered_packets: 2,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-undelivered-cap").await;
+
+    for i in 0..6 {
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            queued_publish_bytes(i),
+        ));
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    // Packets beyond the cap are parsed as recv() drains the queue
+    assert_queue_order(&client, 6).await;
+    let recv_result =
+        tokio::time::timeout(tokio::time::Duration::from_millis(100), client.recv()).await;
+    assert!(recv_result.is_err());
+}
+
+/// Test packets other than PUBLISH are handled while max_undelivered_packets holds PUBLISH back
+#[tokio::test]
+async fn test_max_undelivered_packets_handles_control() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        max_undelivered_packets: 2,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-undelivered-control").await;
+
+    let pingreq = mqtt::packet::v3_1_1::Pingreq::builder().build().unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Pingreq(pingreq))
+        .await;
+    for i in 0..3 {
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            queued_publish_bytes(i),
+        ));
+    }
+    let pingresp = mqtt::packet::v3_1_1::Pingresp::builder().build().unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Pingresp(pingresp).to_continuous_buffer(),
+    ));
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    // The PINGRESP behind the held-back PUBLISH reached the connection
+    assert_eq!(client.rtt_stats().await.sample_count, 1);
+
+    // recv() still gets it in wire order, after the PUBLISH that was unparsed
+    assert_queue_order(&client, 3).await;
+    let recv_result =
+        tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    assert!(matches!(
+        recv_result.unwrap().unwrap(),
+        mqtt::packet::Packet::V3_1_1Pingresp(_)
+    ));
+}
+
+/// Test recv_for_subscription() is served while max_undelivered_packets is reached
+#[tokio::test]
+async fn test_recv_for_subscription_past_full_queue() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        max_undelivered_packets: 1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-subscription-full-queue").await;
+
+    for (topic, subscription_id) in [("other/1", 3), ("mine/1", 7)] {
+        let publish = mqtt::packet::v5_0::Publish::builder()
+            .topic_name(topic)
+            .unwrap()
+            .qos(mqtt::packet::Qos::AtMostOnce)
+            .props(mqtt::packet::Properties::from(vec![
+                mqtt::packet::Property::SubscriptionIdentifier(
+                    mqtt::packet::SubscriptionIdentifier::new(subscription_id).unwrap(),
+                ),
+            ]))
+            .payload(b"data")
+            .build()
+            .unwrap();
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            mqtt::packet::Packet::V5_0Publish(publish).to_continuous_buffer(),
+        ));
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let received = tokio::time::timeout(
+        tokio::time::Duration::from_millis(500),
+        client.recv_for_subscription(7),
+    )
+    .await
+    .expect("recv_for_subscription() should resolve")
+    .unwrap();
+    match received {
+        mqtt::packet::Packet::V5_0Publish(p) => assert_eq!(p.topic_name(), "mine/1"),
+        other => panic!("Expected PUBLISH, got {:?}", other),
+    }
+}
+
+/// Test the builder rejects a max_undelivered_packets of 0
+#[test]
+#[should_panic(expected = "max_undelivered_packets must be at least 1")]
+fn test_max_undelivered_packets_zero() {
+    let _ = MqttConfig::builder().max_undelivered_packets(0);
+}
+
+/// Test max_total_buffer_bytes holds inbound bytes back, refuses PUBLISH sends
+/// and reports MemoryPressure until recv() frees memory
+#[tokio::test]
+async fn test_max_total_buffer_bytes() {
+    // Read buffer capacity plus room for one undelivered PUBLISH
+    let limit = 8192 + 200;
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        max_total_buffer_bytes: Some(limit),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-memory-cap").await;
+
+    let inbound = |i: u8| {
+        let publish = mqtt::packet::v3_1_1::Publish::builder()
+            .topic_name("test/memory")
+            .unwrap()
+            .qos(mqtt::packet::Qos::AtMostOnce)
+            .payload(&[i; 120])
+            .build()
+            .unwrap();
+        mqtt::packet::Packet::V3_1_1Publish(publish).to_continuous_buffer()
+    };
+    let outbound = || {
+        let publish = mqtt::packet::v3_1_1::Publish::builder()
+            .topic_name("test/memory/out")
+            .unwrap()
+            .qos(mqtt::packet::Qos::AtMostOnce)
+            .payload(b"out")
+            .build()
+            .unwrap();
+        mqtt::packet::Packet::V3_1_1Publish(publish)
+    };
+
+    for i in 0..3 {
+        let _ = event_sender
+            .unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(inbound(i)));
+    }
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let mut pressure = None;
+    while let Ok(Ok(event)) =
+        tokio::time::timeout(tokio::time::Duration::from_millis(100), client.next_event()).await
+    {
+        if let mqtt_client_wasm::ConnectionEvent::MemoryPressure {
+            used_bytes,
+            limit_bytes,
+        } = event
+        {
+            pressure = Some((used_bytes, limit_bytes));
+        }
+    }
+    let (used_bytes, limit_bytes) = pressure.expect("MemoryPressure not emitted");
+    assert!(used_bytes > limit);
+    assert_eq!(limit_bytes, limit);
+
+    assert!(matches!(
+        client.send(outbound()).await,
+        Err(mqtt_client_wasm::Error::BufferOverflow)
+    ));
+
+    // Held-back bytes are parsed as recv() frees memory; nothing is lost
+    for i in 0..3u8 {
+        let packet = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv())
+            .await
+            .expect("recv should complete")
+            .unwrap();
+        match packet {
+            mqtt::packet::Packet::V3_1_1Publish(p) => {
+                assert_eq!(p.payload().as_slice(), vec![i; 120].as_slice())
+            }
+            other => panic!("Expected PUBLISH, got {:?}", other),
+        }
+    }
+    assert!(client.send(outbound()).await.is_ok());
+}
+
+/// Test a close in the middle of a packet reports the discarded bytes
+#[tokio::test]
+async fn test_truncated_frame_on_close() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-truncated").await;
+
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/truncated")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtMostOnce)
+        .payload(b"this payload never fully arrives")
+        .build()
+        .unwrap();
+    let packet_bytes = mqtt::packet::Packet::V3_1_1Publish(publish).to_continuous_buffer();
+    let partial = packet_bytes[..packet_bytes.len() / 2].to_vec();
+    let partial_len = partial.len();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(partial));
+    control.simulate_abrupt_close();
+
+    let mut truncated = None;
+    loop {
+        let event =
+            tokio::time::timeout(tokio::time::Duration::from_millis(500), client.next_event())
+                .await
+                .expect("Closed not emitted")
+                .unwrap();
+        match event {
+            mqtt_client_wasm::ConnectionEvent::TruncatedFrame { bytes_lost } => {
+                truncated = Some(bytes_lost)
+            }
+            mqtt_client_wasm::ConnectionEvent::Closed { .. } => break,
+            _ => {}
+        }
+    }
+    assert_eq!(truncated, Some(partial_len));
+}
+
+/// Test PUBLISH packets still held back on close are reported as TruncatedFrame
+#[tokio::test]
+async fn test_truncated_frame_reports_held_back_publish() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-truncated-held").await;
+    assert!(client.pause_recv().await.is_ok());
+
+    let packet_bytes = queued_publish_bytes(0);
+    let packet_len = packet_bytes.len();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        packet_bytes,
+    ));
+    control.simulate_abrupt_close();
+
+    let mut truncated = None;
+    loop {
+        let event =
+            tokio::time::timeout(tokio::time::Duration::from_millis(500), client.next_event())
+                .await
+                .expect("Closed not emitted")
+                .unwrap();
+        match event {
+            mqtt_client_wasm::ConnectionEvent::TruncatedFrame { bytes_lost } => {
+                truncated = Some(bytes_lost)
+            }
+            mqtt_client_wasm::ConnectionEvent::Closed { .. } => break,
+            _ => {}
+        }
+    }
+    assert_eq!(truncated, Some(packet_len));
+}
+
+/// Test replace_transport() closes the old transport and connects through the new one
+#[tokio::test]
+async fn test_replace_transport() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let old_control = mock_ws.control();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-replace-old").await;
+    let old_sent = old_control.sent_data().len();
+
+    let new_ws = MockUnderlyingLayer::new();
+    let new_event_sender = new_ws.event_sender.clone();
+    let new_control = new_ws.control();
+    client.replace_transport(new_ws).await.unwrap();
+    common::assert_closed_without_disconnect(&client, &old_control).await;
+
+    common::establish_v311(&client, &new_event_sender, "test-replace-new").await;
+    assert!(client.is_connected().await);
+    // The CONNECT went out through the new transport only
+    assert_eq!(old_control.sent_data().len(), old_sent);
+    assert_eq!(new_control.sent_data().len(), 1);
+}
+
+/// Test replace_transport() during connect_timeout() fails the attempt and cancels its timer
+#[tokio::test]
+async fn test_replace_transport_while_connecting() {
+    let mock_ws = MockUnderlyingLayer::new();
+    let old_control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(MqttConfig::default(), mock_ws);
+
+    old_control.set_hold_connects(true);
+    let connect_client = client.clone();
+    let connect_task =
+        tokio::spawn(async move { connect_client.connect_timeout("ws://test", 10_000).await });
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    client
+        .replace_transport(MockUnderlyingLayer::new())
+        .await
+        .unwrap();
+    let result = tokio::time::timeout(tokio::time::Duration::from_millis(500), connect_task)
+        .await
+        .expect("connect_timeout() should not outlive the transport")
+        .unwrap();
+    assert!(matches!(
+        result,
+        Err(mqtt_client_wasm::Error::ConnectionClosed)
+    ));
+    assert!(old_control
+        .timer_cancels()
+        .iter()
+        .any(|kind| kind == "Connect"));
+    assert_eq!(client.state().await, ConnectionState::Closed);
+}
+
+/// Test packet_id_reuse_delay_ms keeps a released ID unavailable until the delay passes
+#[tokio::test]
+async fn test_packet_id_reuse_delay() {
+    let config = MqttConfig {
+        packet_id_reuse_delay_ms: 200,
+        ..Default::default()
+    };
+    let client = MqttClient::new_with_websocket(config, MockUnderlyingLayer::new());
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    client.release_packet_id(packet_id).await.unwrap();
+
+    // Still held: neither handed out again nor registrable
+    assert_ne!(client.acquire_packet_id().await, Some(packet_id));
+    assert!(!client.register_packet_id(packet_id).await);
+
+    tokio::time::sleep(tokio::time::Duration::from_millis(250)).await;
+    assert!(client.register_packet_id(packet_id).await);
+}
+
+/// Allocator with the single packet ID 1
+struct SingleIdAllocator {
+    in_use: std::sync::Mutex<bool>,
+}
+
+impl mqtt_client_wasm::PacketIdAllocator for SingleIdAllocator {
+    fn acquire(&self) -> Option<u16> {
+        let mut in_use = self.in_use.lock().unwrap();
+        if *in_use {
+            return None;
+        }
+        *in_use = true;
+        Some(1)
+    }
+
+    fn register(&self, packet_id: u16) -> bool {
+        packet_id == 1 && self.acquire().is_some()
+    }
+
+    fn release(&self, _packet_id: u16) {
+        *self.in_use.lock().unwrap() = false;
+    }
+}
+
+/// Test a waiting acquire_packet_id_wait() is served when the reuse delay ends
+#[tokio::test]
+async fn test_packet_id_reuse_delay_serves_waiter() {
+    let config = MqttConfig {
+        packet_id_reuse_delay_ms: 100,
+        packet_id_allocator: Some(std::sync::Arc::new(SingleIdAllocator {
+            in_use: std::sync::Mutex::new(false),
+        })),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-reuse-delay-waiter").await;
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    client.release_packet_id(packet_id).await.unwrap();
+
+    // No further request is made; the cool-down timer frees the ID
+    let waited = tokio::time::timeout(
+        tokio::time::Duration::from_millis(1000),
+        client.acquire_packet_id_wait(),
+    )
+    .await
+    .expect("waiter should be served once the delay ends")
+    .unwrap();
+    assert_eq!(waited, packet_id);
+    assert!(control
+        .timer_resets()
+        .iter()
+        .any(|(kind, _)| kind == "PacketIdCooldown"));
+}
+
+/// Test wait_closed() resolves on close and immediately when not connected
+#[tokio::test]
+async fn test_wait_closed() {
+    let mock_ws = MockUnderlyingLayer::new();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(MqttConfig::default(), mock_ws);
+
+    assert!(client.wait_closed().await.is_ok());
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let closer = async {
+        tokio::time::sleep(tokio::time::Duration::from_millis(20)).await;
+        control.simulate_abrupt_close();
+    };
+    let (result, _) = futures::join!(
+        tokio::time::timeout(
+            tokio::time::Duration::from_millis(500),
+            client.wait_closed()
+        ),
+        closer
+    );
+    assert!(result.expect("wait_closed() timed out").is_ok());
+}
+
+/// Test one WebSocket message carrying several packets yields all of them
+#[tokio::test]
+async fn test_concatenated_packets_in_one_message() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-concatenated").await;
+
+    let message: Vec<u8> = (0..3).flat_map(queued_publish_bytes).collect();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(message));
+
+    assert_queue_order(&client, 3).await;
+}
+
+/// Test set_client_id() works before connecting and is rejected while connected
+#[tokio::test]
+async fn test_set_client_id() {
+    let client = MqttClient::new_with_websocket(MqttConfig::default(), MockUnderlyingLayer::new());
+    assert!(client.set_client_id("before-connect").await.is_ok());
+
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    assert!(client.set_client_id("while-connected").await.is_err());
+
+    let _ = client.close().await;
+    assert!(client.set_client_id("after-close").await.is_ok());
+}
+
+/// Test PublishComplete is emitted once the PUBACK for an outgoing QoS 1 PUBLISH arrives
+#[tokio::test]
+async fn test_publish_complete_event() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-publish-complete").await;
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/complete")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtLeastOnce)
+        .packet_id(packet_id)
+        .payload(b"QoS 1 message")
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Publish(publish))
+        .await;
+
+    let puback = mqtt::packet::v3_1_1::Puback::builder()
+        .packet_id(packet_id)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Puback(puback).to_continuous_buffer(),
+    ));
+
+    let mut completed = Vec::new();
+    while let Ok(Ok(event)) =
+        tokio::time::timeout(tokio::time::Duration::from_millis(200), client.next_event()).await
+    {
+        if let mqtt_client_wasm::ConnectionEvent::PublishComplete { packet_id, qos } = event {
+            completed.push((packet_id, qos));
+        }
+    }
+    assert_eq!(completed, vec![(packet_id, 1)]);
+    assert!(client.complete_qos(packet_id).await.is_ok());
+}
+
+/// Test a failing v5.0 PUBREC ends the QoS 2 flow with PublishFailed and releases the ID
+#[tokio::test]
+async fn test_publish_failed_on_pubrec_error() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V5_0,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v50(&client, &event_sender, "test-publish-failed").await;
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let publish = mqtt::packet::v5_0::Publish::builder()
+        .topic_name("test/failed")
+        .unwrap()
+        .qos(mqtt::packet::Qos::ExactlyOnce)
+        .packet_id(packet_id)
+        .payload(b"QoS 2 message")
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V5_0Publish(publish))
+        .await
+        .unwrap();
+
+    let pubrec = mqtt::packet::v5_0::Pubrec::builder()
+        .packet_id(packet_id)
+        .reason_code(client_mqtt::result_code::PubrecReasonCode::NotAuthorized)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Pubrec(pubrec).to_continuous_buffer(),
+    ));
+
+    let mut failed = Vec::new();
+    while let Ok(Ok(event)) =
+        tokio::time::timeout(tokio::time::Duration::from_millis(200), client.next_event()).await
+    {
+        if let mqtt_client_wasm::ConnectionEvent::PublishFailed {
+            packet_id,
+            reason_code,
+        } = event
+        {
+            failed.push((packet_id, reason_code));
+        }
+    }
+    assert_eq!(failed, vec![(packet_id, 0x87)]);
+
+    // No PUBREL follows, and the ID is free again without complete_qos()
+    assert!(!control.sent_data().iter().any(|data| data[0] == 0x62));
+    assert!(matches!(
+        client.complete_qos(packet_id).await,
+        Err(mqtt_client_wasm::Error::PacketIdNotInUse(_))
+    ));
+    assert_eq!(client.acquire_packet_id().await.unwrap(), packet_id);
+}
+
+/// Test publish_ack_timeout_ms reports a QoS 1 PUBLISH whose PUBACK never arrives
+#[tokio::test]
+async fn test_publish_ack_timeout() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        publish_ack_timeout_ms: Some(100),
+        release_packet_id_on_ack_timeout: true,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-publish-ack-timeout").await;
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/timeout")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtLeastOnce)
+        .packet_id(packet_id)
+        .payload(b"never acknowledged")
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Publish(publish))
+        .await;
+
+    // The mock never answers with a PUBACK
+    let mut timed_out = None;
+    while let Ok(Ok(event)) =
+        tokio::time::timeout(tokio::time::Duration::from_millis(500), client.next_event()).await
+    {
+        if let mqtt_client_wasm::ConnectionEvent::PublishTimedOut { packet_id } = event {
+            timed_out = Some(packet_id);
+            break;
+        }
+    }
+    assert_eq!(timed_out, Some(packet_id));
+
+    // The ID was released
+    assert!(matches!(
+        client.complete_qos(packet_id).await,
+        Err(mqtt_client_wasm::Error::PacketIdNotInUse(_))
+    ));
+    assert!(client.register_packet_id(packet_id).await);
+}
+
+/// Test connect() to a different URL after close() works on the same client
+#[tokio::test]
+async fn test_connect_different_url_after_close() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+
+    common::establish_v311_at(
+        &client,
+        &event_sender,
+        "ws://primary.example.com",
+        "failover",
+    )
+    .await;
+    assert_eq!(client.state().await, ConnectionState::Connected);
+
+    assert!(client.close().await.is_ok());
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    assert_eq!(client.state().await, ConnectionState::Closed);
+
+    common::establish_v311_at(
+        &client,
+        &event_sender,
+        "ws://backup.example.com",
+        "failover",
+    )
+    .await;
+    assert_eq!(client.state().await, ConnectionState::Connected);
+    assert_eq!(
+        control.connect_urls(),
+        vec![
+            "ws://primary.example.com".to_string(),
+            "ws://backup.example.com".to_string()
+        ]
+    );
+
+    // The MQTT session on the second connection is usable
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/failover")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtMostOnce)
+        .payload(b"via backup")
+        .build()
+        .unwrap();
+    assert!(client
+        .send(mqtt::packet::Packet::V3_1_1Publish(publish))
+        .await
+        .is_ok());
+}
+
+/// Test restore_subscriptions re-subscribes after connecting without a session present
+#[tokio::test]
+async fn test_restore_subscriptions() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        restore_subscriptions: true,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-restore").await;
+
+    let packet_id = client.acquire_packet_id().await.unwrap();
+    let subscribe = mqtt::packet::v3_1_1::Subscribe::builder()
+        .packet_id(packet_id)
+        .entries(vec![mqtt::packet::SubEntry::new(
+            "test/restore",
+            mqtt::packet::SubOpts::new().set_qos(mqtt::packet::Qos::AtLeastOnce),
+        )
+        .unwrap()])
+        .build()
+        .unwrap();
+    client
+        .send(mqtt::packet::Packet::V3_1_1Subscribe(subscribe))
+        .await
+        .unwrap();
+    let suback = mqtt::packet::v3_1_1::Suback::builder()
+        .packet_id(packet_id)
+        .return_codes(vec![
+            client_mqtt::result_code::SubackReturnCode::SuccessMaximumQos1,
+        ])
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Suback(suback).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+
+    control.simulate_abrupt_close();
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+    let sent_before = control.sent_data().len();
+
+    // establish_v311 answers with session_present = false
+    common::establish_v311(&client, &event_sender, "test-restore").await;
+
+    let mut restored = None;
+    while let Ok(Ok(event)) =
+        tokio::time::timeout(tokio::time::Duration::from_millis(200), client.next_event()).await
+    {
+        if let mqtt_client_wasm::ConnectionEvent::SubscriptionsRestored { count } = event {
+            restored = Some(count);
+        }
+    }
+    assert_eq!(restored, Some(1));
+
+    // CONNECT followed by the restoring SUBSCRIBE (control packet type 8)
+    let sent = control.sent_data();
+    let resubscribe = &sent[sent_before + 1];
+    assert_eq!(resubscribe[0] >> 4, 8);
+    let filter = b"test/restore";
+    assert!(resubscribe
+        .windows(filter.len())
+        .any(|window| window == filter));
+}
+
+/// Inspector that records the type and length of every outgoing packet
+#[derive(Default)]
+struct RecordingInspector {
+    seen: std::sync::Mutex<Vec<(mqtt::packet::PacketType, usize)>>,
+}
+
+impl mqtt_client_wasm::SendInspector for RecordingInspector {
+    fn inspect(&self, packet: &mqtt::packet::Packet, len: usize) {
+        self.seen.lock().unwrap().push((packet.packet_type(), len));
+    }
+}
+
+/// Test send_inspector sees app packets and automatic acknowledgements
+#[tokio::test]
+async fn test_send_inspector() {
+    let inspector = std::sync::Arc::new(RecordingInspector::default());
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        send_inspector: Some(inspector.clone()),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-send-inspector").await;
+
+    // The PUBACK for this is generated by auto_pub_response
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/inspect")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtLeastOnce)
+        .packet_id(1)
+        .payload(b"inspect me")
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Publish(publish).to_continuous_buffer(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+
+    let seen = inspector.seen.lock().unwrap().clone();
+    let types: Vec<_> = seen.iter().map(|(t, _)| *t).collect();
+    assert_eq!(
+        types,
+        vec![
+            mqtt::packet::PacketType::Connect,
+            mqtt::packet::PacketType::Puback
+        ]
+    );
+    let lengths: Vec<_> = seen.iter().map(|(_, len)| *len).collect();
+    let sent: Vec<_> = control.sent_data().iter().map(|d| d.len()).collect();
+    assert_eq!(lengths, sent);
+
+    // Removing the inspector stops the reports
+    client.set_send_inspector(None).await.unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Pingreq(
+            mqtt::packet::v3_1_1::Pingreq::new(),
+        ))
+        .await;
+    assert_eq!(inspector.seen.lock().unwrap().len(), 2);
+}
+
+/// Tap that records every packet crossing the wire
+#[derive(Default)]
+struct RecordingTap {
+    seen: std::sync::Mutex<Vec<(mqtt_client_wasm::PacketDirection, Vec<u8>, String)>>,
+}
+
+impl mqtt_client_wasm::PacketTap for RecordingTap {
+    fn tap(&self, direction: mqtt_client_wasm::PacketDirection, bytes: &[u8], packet_type: &str) {
+        self.seen
+            .lock()
+            .unwrap()
+            .push((direction, bytes.to_vec(), packet_type.to_string()));
+    }
+}
+
+/// Test the packet tap sees received and sent packets, including automatic ones
+#[tokio::test]
+async fn test_packet_tap() {
+    use mqtt_client_wasm::PacketDirection::{In, Out};
+
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    let tap = std::sync::Arc::new(RecordingTap::default());
+    client.set_packet_tap(Some(tap.clone())).await.unwrap();
+    common::establish_v311(&client, &event_sender, "test-packet-tap").await;
+
+    // The PUBACK for this is generated by auto_pub_response
+    let publish = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/tap")
+        .unwrap()
+        .qos(mqtt::packet::Qos::AtLeastOnce)
+        .packet_id(1)
+        .payload(b"tap me")
+        .build()
+        .unwrap();
+    let publish_bytes = mqtt::packet::Packet::V3_1_1Publish(publish).to_continuous_buffer();
+    let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+        publish_bytes.clone(),
+    ));
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+
+    let seen = tap.seen.lock().unwrap().clone();
+    let summary: Vec<_> = seen.iter().map(|(d, _, t)| (*d, t.as_str())).collect();
+    assert_eq!(
+        summary,
+        vec![
+            (Out, "Connect"),
+            (In, "Connack"),
+            (In, "Publish"),
+            (Out, "Puback")
+        ]
+    );
+    assert_eq!(seen[2].1, publish_bytes);
+    let sent: Vec<_> = seen
+        .iter()
+        .filter(|(d, _, _)| *d == Out)
+        .map(|(_, bytes, _)| bytes.clone())
+        .collect();
+    assert_eq!(sent, control.sent_data());
+
+    // Removing the tap stops the reports
+    client.set_packet_tap(None).await.unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Pingreq(
+            mqtt::packet::v3_1_1::Pingreq::new(),
+        ))
+        .await;
+    assert_eq!(tap.seen.lock().unwrap().len(), 4);
+}
+
+/// Test a streamed PUBLISH is tapped as its wire bytes arrive, not in rebuilt form
+#[tokio::test]
+async fn test_packet_tap_streamed_publish() {
+    use mqtt_client_wasm::PacketDirection::In;
+
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        payload_stream_threshold: 8,
+        payload_chunk_handler: Some(std::sync::Arc::new(RecordingChunkHandler::default())),
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-packet-tap-stream").await;
+    let tap = std::sync::Arc::new(RecordingTap::default());
+    client.set_packet_tap(Some(tap.clone())).await.unwrap();
+
+    let large = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/large")
+        .unwrap()
+        .payload(b"0123456789abcdef")
+        .build()
+        .unwrap();
+    let small = mqtt::packet::v3_1_1::Publish::builder()
+        .topic_name("test/small")
+        .unwrap()
+        .payload(b"tiny")
+        .build()
+        .unwrap();
+    let mut bytes = mqtt::packet::Packet::V3_1_1Publish(large).to_continuous_buffer();
+    bytes.extend(mqtt::packet::Packet::V3_1_1Publish(small).to_continuous_buffer());
+
+    let header_len = 2 + 2 + "test/large".len();
+    for piece in [&bytes[..header_len + 2], &bytes[header_len + 2..]] {
+        let _ = event_sender.unbounded_send(mqtt_client_wasm::UnderlyingLayerEvent::Message(
+            piece.to_vec(),
+        ));
+    }
+    for _ in 0..2 {
+        let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+    }
+
+    // Header, both payload chunks, then the small PUBLISH: exactly the wire bytes
+    let seen = tap.seen.lock().unwrap().clone();
+    assert!(seen
+        .iter()
+        .all(|(d, _, t)| *d == In && t.as_str() == "Publish"));
+    assert_eq!(seen.len(), 4);
+    let tapped: Vec<u8> = seen.iter().flat_map(|(_, b, _)| b.clone()).collect();
+    assert_eq!(tapped, bytes);
+}
+
+/// Test send() reports data the transport refused instead of succeeding silently
+#[tokio::test]
+async fn test_send_failed_reaches_caller() {
+    let config = MqttConfig {
+        version: client_mqtt::Version::V3_1_1,
+        ..Default::default()
+    };
+    let mock_ws = MockUnderlyingLayer::new();
+    let event_sender = mock_ws.event_sender.clone();
+    let control = mock_ws.control();
+    let client = MqttClient::new_with_websocket(config, mock_ws);
+    common::establish_v311(&client, &event_sender, "test-send-failed").await;
+
+    let publish = || {
+        let publish = mqtt::packet::v3_1_1::Publish::builder()
+            .topic_name("test/send")
+            .unwrap()
+            .qos(mqtt::packet::Qos::AtMostOnce)
+            .payload(b"data")
+            .build()
+            .unwrap();
+        mqtt::packet::Packet::V3_1_1Publish(publish)
+    };
+
+    control.set_refuse_sends(true);
+    assert!(matches!(
+        client.send(publish()).await,
+        Err(mqtt_client_wasm::Error::SendFailed(_))
+    ));
+    assert!(matches!(
+        client.send_sized(publish()).await,
+        Err(mqtt_client_wasm::Error::SendFailed(_))
+    ));
+
+    control.set_refuse_sends(false);
+    assert!(client.send(publish()).await.is_ok());
+}
+
 /// Test partial packet reassembly (buffer compaction)
 #[tokio::test]
 async fn test_partial_packet_reassembly() {
@@ -1007,6 +5732,7 @@ async fn test_config_with_all_options() {
     let config = MqttConfig {
         url: String::new(),
         version: client_mqtt::Version::V3_1_1,
+        client_id: Some("all-options-client".to_string()),
         pingreq_send_interval_ms: Some(30000),
         pingresp_recv_timeout_ms: 5000,
         auto_pub_response: false,
@@ -1015,6 +5741,30 @@ async fn test_config_with_all_options() {
         auto_replace_topic_alias_send: true,
         connection_establish_timeout_ms: 10000,
         shutdown_timeout_ms: 5000,
+        max_undelivered_packets: 1024,
+        max_total_buffer_bytes: None,
+        reconnect_initial_delay_ms: None,
+        reconnect_max_delay_ms: 30000,
+        reconnect_max_attempts: None,
+        publish_ack_timeout_ms: None,
+        release_packet_id_on_ack_timeout: false,
+        packet_id_reuse_delay_ms: 0,
+        restore_subscriptions: false,
+        max_subscriptions: None,
+        error_on_connack_failure: false,
+        allow_recv_before_connect: false,
+        instance_label: None,
+        follow_server_reference: false,
+        strict_version: false,
+        max_publish_rate_per_sec: None,
+        pingreq_jitter_ms: None,
+        payload_stream_threshold: 65536,
+        payload_codec: None,
+        topic_rewrite: None,
+        send_inspector: None,
+        packet_tap: None,
+        payload_chunk_handler: None,
+        packet_id_allocator: None,
     };
     let mock_ws = MockUnderlyingLayer::new();
 
@@ -1424,6 +6174,11 @@ async fn test_v50_publish_topic_name_extracted() {
         assert_eq!(pub_packet.topic_name(), "test/alias/topic");
         // topic_name_extracted should be true since it was restored from alias
         assert!(pub_packet.topic_name_extracted());
+        // The alias the broker used is still available next to the resolved topic
+        assert!(pub_packet
+            .props
+            .iter()
+            .any(|prop| matches!(prop, Property::TopicAlias(alias) if alias.val() == 1)));
     } else {
         panic!("Expected v5.0 PUBLISH packet");
     }
@@ -4,8 +4,14 @@
 
 use async_trait::async_trait;
 use futures::channel::mpsc;
-use mqtt_client_wasm::{UnderlyingLayerCommand, UnderlyingLayerEvent, UnderlyingLayerInterface};
+use mqtt::packet::GenericPacketTrait;
+use mqtt_client_wasm::{
+    complete_reply, ConnectReplySender, ConnectionState, Error, MqttClient, UnderlyingLayerCommand,
+    UnderlyingLayerEvent, UnderlyingLayerInterface,
+};
+use mqtt_protocol_core::mqtt;
 use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 use tokio::task::JoinHandle;
 
 /// Mock underlying layer for testing (pure message-passing)
@@ -15,9 +21,23 @@ pub struct MockUnderlyingLayer {
     command_sender: mpsc::UnboundedSender<UnderlyingLayerCommand>,
     command_receiver: mpsc::UnboundedReceiver<UnderlyingLayerCommand>,
     connected: bool,
-    sent_data: Vec<Vec<u8>>,
+    /// Data sent by the client, shared so tests can inspect it after the
+    /// mock has been moved into the client
+    sent_data: Arc<Mutex<Vec<Vec<u8>>>>,
+    /// URLs passed to Connect, in order
+    connect_urls: Arc<Mutex<Vec<String>>>,
+    /// When set, SendData is refused as if the transport's buffer were full
+    refuse_sends: Arc<Mutex<bool>>,
+    /// When set, Connect never completes, as if the server did not answer
+    hold_connects: Arc<Mutex<bool>>,
+    /// Reply of a held Connect, kept so the client's await stays pending
+    held_connect: Option<ConnectReplySender>,
     /// Active timers: kind -> JoinHandle for the timer task
     active_timers: HashMap<String, JoinHandle<()>>,
+    /// Every TimerReset (kind, duration_ms), in order
+    timer_resets: Arc<Mutex<Vec<(String, u64)>>>,
+    /// Every TimerCancel kind, in order
+    timer_cancels: Arc<Mutex<Vec<String>>>,
 }
 
 #[allow(dead_code)]
@@ -32,8 +52,14 @@ impl MockUnderlyingLayer {
             command_sender,
             command_receiver,
             connected: false,
-            sent_data: Vec::new(),
+            sent_data: Arc::new(Mutex::new(Vec::new())),
+            connect_urls: Arc::new(Mutex::new(Vec::new())),
+            refuse_sends: Arc::new(Mutex::new(false)),
+            hold_connects: Arc::new(Mutex::new(false)),
+            held_connect: None,
             active_timers: HashMap::new(),
+            timer_resets: Arc::new(Mutex::new(Vec::new())),
+            timer_cancels: Arc::new(Mutex::new(Vec::new())),
         }
     }
 
@@ -67,12 +93,106 @@ impl MockUnderlyingLayer {
             .unbounded_send(UnderlyingLayerEvent::Closed);
     }
 
+    /// Simulate an abrupt connection loss (e.g. network drop)
+    ///
+    /// Unlike a graceful close, the transport reports `Closed` without the
+    /// client having sent DISCONNECT, which is what makes a broker publish the
+    /// will message.
+    pub fn simulate_abrupt_close(&mut self) {
+        self.connected = false;
+        self.control().simulate_abrupt_close();
+    }
+
     /// Get sent data for verification
-    pub fn sent_data(&self) -> &[Vec<u8>] {
-        &self.sent_data
+    pub fn sent_data(&self) -> Vec<Vec<u8>> {
+        self.sent_data.lock().unwrap().clone()
+    }
+
+    /// Get a handle that keeps working after the mock is moved into the client
+    pub fn control(&self) -> MockControl {
+        MockControl {
+            event_sender: self.event_sender.clone(),
+            sent_data: self.sent_data.clone(),
+            connect_urls: self.connect_urls.clone(),
+            refuse_sends: self.refuse_sends.clone(),
+            hold_connects: self.hold_connects.clone(),
+            timer_resets: self.timer_resets.clone(),
+            timer_cancels: self.timer_cancels.clone(),
+        }
+    }
+}
+
+/// Test-side handle to a MockUnderlyingLayer owned by a client
+#[derive(Clone)]
+pub struct MockControl {
+    event_sender: mpsc::UnboundedSender<UnderlyingLayerEvent>,
+    sent_data: Arc<Mutex<Vec<Vec<u8>>>>,
+    connect_urls: Arc<Mutex<Vec<String>>>,
+    refuse_sends: Arc<Mutex<bool>>,
+    hold_connects: Arc<Mutex<bool>>,
+    timer_resets: Arc<Mutex<Vec<(String, u64)>>>,
+    timer_cancels: Arc<Mutex<Vec<String>>>,
+}
+
+#[allow(dead_code)]
+impl MockControl {
+    /// Simulate an abrupt connection loss (no DISCONNECT from the client)
+    pub fn simulate_abrupt_close(&self) {
+        let _ = self
+            .event_sender
+            .unbounded_send(UnderlyingLayerEvent::Closed);
+    }
+
+    /// Get data sent by the client so far
+    pub fn sent_data(&self) -> Vec<Vec<u8>> {
+        self.sent_data.lock().unwrap().clone()
+    }
+
+    /// Make the transport refuse (or accept again) the data it is asked to send
+    pub fn set_refuse_sends(&self, refuse: bool) {
+        *self.refuse_sends.lock().unwrap() = refuse;
+    }
+
+    /// Make Connect hang (or complete again) as if the server did not answer
+    pub fn set_hold_connects(&self, hold: bool) {
+        *self.hold_connects.lock().unwrap() = hold;
+    }
+
+    /// Get the timers the client has (re)started so far as (kind, duration_ms)
+    pub fn timer_resets(&self) -> Vec<(String, u64)> {
+        self.timer_resets.lock().unwrap().clone()
+    }
+
+    /// Get the timers the client has cancelled so far
+    pub fn timer_cancels(&self) -> Vec<String> {
+        self.timer_cancels.lock().unwrap().clone()
+    }
+
+    /// Get the URLs the client has connected to so far
+    pub fn connect_urls(&self) -> Vec<String> {
+        self.connect_urls.lock().unwrap().clone()
+    }
+
+    /// Check whether the client has sent a DISCONNECT packet
+    pub fn disconnect_sent(&self) -> bool {
+        // Each SendData carries one packet; DISCONNECT is control packet type 14
+        self.sent_data()
+            .iter()
+            .any(|data| data.first().map(|b| b >> 4) == Some(14))
     }
 }
 
+/// Assert the client saw an abrupt close: it is closed and never sent DISCONNECT
+#[allow(dead_code)]
+pub async fn assert_closed_without_disconnect(client: &MqttClient, control: &MockControl) {
+    assert_eq!(client.state().await, ConnectionState::Closed);
+    assert!(!client.is_connected().await);
+    assert!(
+        !control.disconnect_sent(),
+        "DISCONNECT was sent before the connection closed"
+    );
+}
+
 #[async_trait(?Send)]
 impl UnderlyingLayerInterface for MockUnderlyingLayer {
     fn event_receiver(&mut self) -> mpsc::UnboundedReceiver<UnderlyingLayerEvent> {
@@ -93,6 +213,11 @@ impl UnderlyingLayerInterface for MockUnderlyingLayer {
             match command {
                 UnderlyingLayerCommand::Connect(url, reply_arc) => {
                     println!("MockUnderlyingLayer connecting to: {}", url);
+                    self.connect_urls.lock().unwrap().push(url);
+                    if *self.hold_connects.lock().unwrap() {
+                        self.held_connect = Some(reply_arc);
+                        continue;
+                    }
                     self.connected = true;
 
                     // Send reply to complete the connect() await
@@ -106,10 +231,26 @@ impl UnderlyingLayerInterface for MockUnderlyingLayer {
                         .event_sender
                         .unbounded_send(UnderlyingLayerEvent::Connected);
                 }
-                UnderlyingLayerCommand::SendData(data) => {
-                    if self.connected {
-                        self.sent_data.push(data);
+                UnderlyingLayerCommand::SendData(data, reply) => {
+                    if *self.refuse_sends.lock().unwrap() {
+                        if let Some(reply) = reply {
+                            complete_reply(
+                                &reply,
+                                Err(Error::SendFailed("Send buffer full".to_string())),
+                            );
+                        }
+                    } else if self.connected {
+                        self.sent_data.lock().unwrap().push(data);
+                        if let Some(reply) = reply {
+                            complete_reply(&reply, Ok(()));
+                        }
                     } else {
+                        if let Some(reply) = reply {
+                            complete_reply(
+                                &reply,
+                                Err(Error::SendFailed("Not connected".to_string())),
+                            );
+                        }
                         let _ = self
                             .event_sender
                             .unbounded_send(UnderlyingLayerEvent::Error(
@@ -119,6 +260,7 @@ impl UnderlyingLayerInterface for MockUnderlyingLayer {
                 }
                 UnderlyingLayerCommand::Close => {
                     self.connected = false;
+                    self.held_connect = None;
 
                     // Cancel all active timers on close
                     for (_, handle) in self.active_timers.drain() {
@@ -135,6 +277,10 @@ impl UnderlyingLayerInterface for MockUnderlyingLayer {
                         "MockUnderlyingLayer: TimerReset {} for {}ms",
                         kind, duration_ms
                     );
+                    self.timer_resets
+                        .lock()
+                        .unwrap()
+                        .push((kind.clone(), duration_ms));
 
                     // Cancel existing timer if any
                     if let Some(old_handle) = self.active_timers.remove(&kind) {
@@ -156,6 +302,7 @@ impl UnderlyingLayerInterface for MockUnderlyingLayer {
                 }
                 UnderlyingLayerCommand::TimerCancel { kind } => {
                     println!("MockUnderlyingLayer: TimerCancel {}", kind);
+                    self.timer_cancels.lock().unwrap().push(kind.clone());
 
                     if let Some(handle) = self.active_timers.remove(&kind) {
                         println!("MockUnderlyingLayer: Cancelling timer {}", kind);
@@ -167,7 +314,100 @@ impl UnderlyingLayerInterface for MockUnderlyingLayer {
                         );
                     }
                 }
+                UnderlyingLayerCommand::QueryBufferedAmount(reply) => {
+                    // Mock sends complete immediately, so nothing is ever buffered
+                    let result = if self.connected {
+                        Ok(0)
+                    } else {
+                        Err(Error::NotConnected)
+                    };
+                    complete_reply(&reply, result);
+                }
+                UnderlyingLayerCommand::QueryReadyState(reply) => {
+                    // OPEN while connected, CLOSED otherwise
+                    let ready_state = if self.connected { 1 } else { 3 };
+                    complete_reply(&reply, Ok(ready_state));
+                }
             }
         }
     }
 }
+
+/// Connect the WebSocket and complete a v3.1.1 CONNECT/CONNACK exchange
+#[allow(dead_code)]
+pub async fn establish_v311(
+    client: &MqttClient,
+    event_sender: &mpsc::UnboundedSender<UnderlyingLayerEvent>,
+    client_id: &str,
+) {
+    establish_v311_at(client, event_sender, "ws://test.example.com", client_id).await;
+}
+
+/// Connect the WebSocket and complete a v5.0 CONNECT/CONNACK exchange
+#[allow(dead_code)]
+pub async fn establish_v50(
+    client: &MqttClient,
+    event_sender: &mpsc::UnboundedSender<UnderlyingLayerEvent>,
+    client_id: &str,
+) {
+    let _ = client.connect("ws://test.example.com").await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let connect_packet = mqtt::packet::v5_0::Connect::builder()
+        .client_id(client_id)
+        .unwrap()
+        .keep_alive(60)
+        .clean_start(true)
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V5_0Connect(connect_packet))
+        .await;
+
+    let connack = mqtt::packet::v5_0::Connack::builder()
+        .session_present(false)
+        .reason_code(mqtt::result_code::ConnectReasonCode::Success)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V5_0Connack(connack).to_continuous_buffer(),
+    ));
+
+    // Consume the CONNACK
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+}
+
+/// Connect the WebSocket to `url` and complete a v3.1.1 CONNECT/CONNACK exchange
+#[allow(dead_code)]
+pub async fn establish_v311_at(
+    client: &MqttClient,
+    event_sender: &mpsc::UnboundedSender<UnderlyingLayerEvent>,
+    url: &str,
+    client_id: &str,
+) {
+    let _ = client.connect(url).await;
+    tokio::time::sleep(tokio::time::Duration::from_millis(50)).await;
+
+    let connect_packet = mqtt::packet::v3_1_1::Connect::builder()
+        .client_id(client_id)
+        .unwrap()
+        .keep_alive(60)
+        .clean_session(true)
+        .build()
+        .unwrap();
+    let _ = client
+        .send(mqtt::packet::Packet::V3_1_1Connect(connect_packet))
+        .await;
+
+    let connack = mqtt::packet::v3_1_1::Connack::builder()
+        .session_present(false)
+        .return_code(mqtt::result_code::ConnectReturnCode::Accepted)
+        .build()
+        .unwrap();
+    let _ = event_sender.unbounded_send(UnderlyingLayerEvent::Message(
+        mqtt::packet::Packet::V3_1_1Connack(connack).to_continuous_buffer(),
+    ));
+
+    // Consume the CONNACK
+    let _ = tokio::time::timeout(tokio::time::Duration::from_millis(500), client.recv()).await;
+}
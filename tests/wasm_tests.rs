@@ -2,11 +2,14 @@
 //!
 //! Run with: wasm-pack test --node
 
-#![cfg(target_arch = "wasm32")]
+#![cfg(all(target_arch = "wasm32", feature = "js-wrappers"))]
 
 use wasm_bindgen_test::*;
 
-use mqtt_client_wasm::wasm::{WasmMqttConfig, WasmMqttPacket, WasmPacketType};
+use mqtt_client_wasm::wasm::{
+    WasmMqttConfig, WasmMqttPacket, WasmPacketFactory, WasmPacketType, WasmQos, WasmReasonCode,
+    WasmRetainHandling,
+};
 
 // ============================================================================
 // WasmMqttConfig Tests
@@ -60,6 +63,14 @@ mod config_tests {
         js_sys::Reflect::set(&options3, &"version".into(), &"5".into()).unwrap();
         assert!(WasmMqttConfig::new(options3.into()).is_ok());
     }
+
+    #[wasm_bindgen_test]
+    fn test_config_rejects_v31() {
+        // MQTT 3.1 must not silently become 3.1.1
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"version".into(), &"3.1".into()).unwrap();
+        assert!(WasmMqttConfig::new(options.into()).is_err());
+    }
 }
 
 // ============================================================================
@@ -192,6 +203,23 @@ mod v3_1_1_packet_factory_tests {
         let packet = WasmMqttPacket::new_disconnect_v311();
         assert_eq!(packet.packet_type(), WasmPacketType::Disconnect);
     }
+
+    #[wasm_bindgen_test]
+    fn test_equals_and_to_hex() {
+        let pingreq = WasmMqttPacket::new_pingreq_v311();
+        assert!(pingreq.equals(&WasmMqttPacket::new_pingreq_v311()));
+        assert!(!pingreq.equals(&WasmMqttPacket::new_disconnect_v311()));
+        assert_eq!(pingreq.to_hex(), "c000");
+    }
+
+    #[wasm_bindgen_test]
+    fn test_protocol_version() {
+        assert_eq!(
+            WasmMqttPacket::new_pingreq_v311().protocol_version(),
+            "3.1.1"
+        );
+        assert_eq!(WasmMqttPacket::new_pingreq_v50().protocol_version(), "5.0");
+    }
 }
 
 // ============================================================================
@@ -219,6 +247,58 @@ mod v5_0_packet_factory_tests {
         assert_eq!(packet.packet_type(), WasmPacketType::Connect);
     }
 
+    #[wasm_bindgen_test]
+    fn test_new_connect_v50_with_will_options() {
+        let will = js_sys::Object::new();
+        js_sys::Reflect::set(&will, &"topic".into(), &"client/status".into()).unwrap();
+        js_sys::Reflect::set(&will, &"payload".into(), &"offline".into()).unwrap();
+        js_sys::Reflect::set(&will, &"qos".into(), &1u32.into()).unwrap();
+        js_sys::Reflect::set(&will, &"willDelayInterval".into(), &30u32.into()).unwrap();
+
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"clientId".into(), &"test-client".into()).unwrap();
+        js_sys::Reflect::set(&options, &"will".into(), &will).unwrap();
+
+        let result = WasmMqttPacket::new_connect_v50(options.into());
+        assert!(
+            result.is_ok(),
+            "CONNECT V5 with will failed: {:?}",
+            result.err()
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_new_connect_v50_will_requires_topic() {
+        let will = js_sys::Object::new();
+        js_sys::Reflect::set(&will, &"retain".into(), &true.into()).unwrap();
+
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"clientId".into(), &"test-client".into()).unwrap();
+        js_sys::Reflect::set(&options, &"will".into(), &will).unwrap();
+        assert!(WasmMqttPacket::new_connect_v50(options.into()).is_err());
+
+        // Flat fields without a topic are rejected as well
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"clientId".into(), &"test-client".into()).unwrap();
+        js_sys::Reflect::set(&options, &"willRetain".into(), &true.into()).unwrap();
+        assert!(WasmMqttPacket::new_connect_v50(options.into()).is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_new_connect_v50_resume_requires_session_expiry() {
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"clientId".into(), &"test-client".into()).unwrap();
+        js_sys::Reflect::set(&options, &"cleanStart".into(), &false.into()).unwrap();
+        assert!(WasmMqttPacket::new_connect_v50(options.clone().into()).is_err());
+
+        // 0 stated explicitly is accepted, as is a real interval
+        js_sys::Reflect::set(&options, &"sessionExpiryInterval".into(), &0u32.into()).unwrap();
+        assert!(WasmMqttPacket::new_connect_v50(options.clone().into()).is_ok());
+        js_sys::Reflect::set(&options, &"sessionExpiryInterval".into(), &3600u32.into()).unwrap();
+        let packet = WasmMqttPacket::new_connect_v50(options.into()).unwrap();
+        assert_eq!(packet.packet_type(), WasmPacketType::Connect);
+    }
+
     #[wasm_bindgen_test]
     fn test_new_publish_v50() {
         let options = js_sys::Object::new();
@@ -279,6 +359,78 @@ mod v5_0_packet_factory_tests {
         assert_eq!(packet.packet_type(), WasmPacketType::Subscribe);
     }
 
+    #[wasm_bindgen_test]
+    fn test_new_subscribe_v50_retain_handling() {
+        let first = js_sys::Object::new();
+        js_sys::Reflect::set(&first, &"topic".into(), &"test/a".into()).unwrap();
+        js_sys::Reflect::set(
+            &first,
+            &"retainHandling".into(),
+            &(WasmRetainHandling::DoNotSend as u32).into(),
+        )
+        .unwrap();
+
+        let second = js_sys::Object::new();
+        js_sys::Reflect::set(&second, &"topic".into(), &"test/b".into()).unwrap();
+        js_sys::Reflect::set(&second, &"retainHandling".into(), &1u32.into()).unwrap();
+
+        let subscriptions = js_sys::Array::new();
+        subscriptions.push(&first);
+        subscriptions.push(&second);
+
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"packetId".into(), &1u32.into()).unwrap();
+        js_sys::Reflect::set(&options, &"subscriptions".into(), &subscriptions).unwrap();
+
+        let result = WasmMqttPacket::new_subscribe_v50(options.into());
+        assert!(
+            result.is_ok(),
+            "SUBSCRIBE V5 with retainHandling failed: {:?}",
+            result.err()
+        );
+
+        // Out-of-range value on the second entry
+        js_sys::Reflect::set(&second, &"retainHandling".into(), &3u32.into()).unwrap();
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"packetId".into(), &1u32.into()).unwrap();
+        js_sys::Reflect::set(&options, &"subscriptions".into(), &subscriptions).unwrap();
+
+        let err = WasmMqttPacket::new_subscribe_v50(options.into())
+            .err()
+            .and_then(|e| e.as_string())
+            .unwrap();
+        assert!(
+            err.contains("subscriptions[1]"),
+            "unexpected error: {}",
+            err
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn test_new_subscribe_v50_no_local_shared() {
+        let subscription = js_sys::Object::new();
+        js_sys::Reflect::set(&subscription, &"topic".into(), &"$share/g/test/#".into()).unwrap();
+        js_sys::Reflect::set(&subscription, &"noLocal".into(), &true.into()).unwrap();
+
+        let subscriptions = js_sys::Array::new();
+        subscriptions.push(&subscription);
+
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"packetId".into(), &1u32.into()).unwrap();
+        js_sys::Reflect::set(&options, &"subscriptions".into(), &subscriptions).unwrap();
+
+        let err = WasmMqttPacket::new_subscribe_v50(options.clone().into())
+            .err()
+            .and_then(|e| e.as_string())
+            .unwrap();
+        assert!(err.contains("$share/g/test/#"), "unexpected error: {}", err);
+
+        // No Local stays allowed on regular subscriptions
+        js_sys::Reflect::set(&subscription, &"topic".into(), &"test/#".into()).unwrap();
+        let result = WasmMqttPacket::new_subscribe_v50(options.into());
+        assert!(result.is_ok(), "SUBSCRIBE V5 failed: {:?}", result.err());
+    }
+
     #[wasm_bindgen_test]
     fn test_new_puback_v50() {
         let options = js_sys::Object::new();
@@ -571,6 +723,18 @@ mod version_aware_packet_tests {
         assert_eq!(result.unwrap().packet_type(), WasmPacketType::Subscribe);
     }
 
+    #[wasm_bindgen_test]
+    async fn test_subscribe_many_rejects_empty_filters() {
+        let client = create_client_v50();
+
+        // Rejected before a packet ID is acquired or anything is sent
+        let result = client.subscribe_many(js_sys::Array::new().into()).await;
+        assert!(
+            result.is_err(),
+            "subscribeMany should reject an empty array"
+        );
+    }
+
     // ------------------------------------------------------------------------
     // newUnsubscribePacket tests
     // ------------------------------------------------------------------------
@@ -886,6 +1050,75 @@ mod version_aware_packet_tests {
             "newAuthPacket should fail for V3.1.1 client"
         );
     }
+
+    // ------------------------------------------------------------------------
+    // WasmPacketFactory tests
+    // ------------------------------------------------------------------------
+
+    #[wasm_bindgen_test]
+    fn test_packet_factory_without_client() {
+        let factory_v50 = WasmPacketFactory::new("5.0").unwrap();
+        assert_eq!(factory_v50.version(), "5.0");
+
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"topicName".into(), &"test/topic".into()).unwrap();
+        js_sys::Reflect::set(&options, &"payload".into(), &"hello".into()).unwrap();
+        let packet = factory_v50.new_publish_packet(options.into()).unwrap();
+        assert_eq!(packet.packet_type(), WasmPacketType::Publish);
+        assert_eq!(packet.protocol_version(), "5.0");
+
+        let factory_v311 = WasmPacketFactory::new("3.1.1").unwrap();
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"clientId".into(), &"factory".into()).unwrap();
+        let packet = factory_v311.new_connect_packet(options.into()).unwrap();
+        assert_eq!(packet.packet_type(), WasmPacketType::Connect);
+        assert_eq!(packet.protocol_version(), "3.1.1");
+
+        assert!(factory_v311
+            .new_auth_packet(js_sys::Object::new().into())
+            .is_err());
+        assert!(WasmPacketFactory::new("3.1").is_err());
+    }
+
+    #[wasm_bindgen_test]
+    fn test_packet_factory_strict_version() {
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"topicName".into(), &"test/topic".into()).unwrap();
+        js_sys::Reflect::set(&options, &"messageExpiryInterval".into(), &60u32.into()).unwrap();
+
+        // Ignored by default, rejected with the option named when strict
+        let lenient = WasmPacketFactory::new("3.1.1").unwrap();
+        assert!(lenient.new_publish_packet(options.clone().into()).is_ok());
+        let strict = WasmPacketFactory::new_strict("3.1.1").unwrap();
+        let err = strict
+            .new_publish_packet(options.into())
+            .err()
+            .and_then(|e| e.as_string())
+            .unwrap();
+        assert!(err.contains("messageExpiryInterval"), "{}", err);
+
+        // v3.1.1 options alone still work
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"topicName".into(), &"test/topic".into()).unwrap();
+        js_sys::Reflect::set(&options, &"qos".into(), &1u32.into()).unwrap();
+        js_sys::Reflect::set(&options, &"packetId".into(), &1u32.into()).unwrap();
+        assert!(strict.new_publish_packet(options.into()).is_ok());
+
+        let puback = js_sys::Object::new();
+        js_sys::Reflect::set(&puback, &"packetId".into(), &1u32.into()).unwrap();
+        js_sys::Reflect::set(&puback, &"reasonString".into(), &"ok".into()).unwrap();
+        assert!(strict.new_puback_packet(puback.into()).is_err());
+        assert!(strict
+            .new_disconnect_packet(wasm_bindgen::JsValue::UNDEFINED)
+            .is_ok());
+
+        // A v5.0 factory accepts them regardless
+        let strict_v50 = WasmPacketFactory::new_strict("5.0").unwrap();
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"topicName".into(), &"test/topic".into()).unwrap();
+        js_sys::Reflect::set(&options, &"messageExpiryInterval".into(), &60u32.into()).unwrap();
+        assert!(strict_v50.new_publish_packet(options.into()).is_ok());
+    }
 }
 
 // ============================================================================
@@ -966,6 +1199,47 @@ mod packet_accessor_tests {
         assert_eq!(get_number(&publish, "packetId"), Some(42.0));
     }
 
+    #[wasm_bindgen_test]
+    fn test_publish_v311_empty_retained_payload() {
+        let client = create_client_v311();
+
+        // An empty retained PUBLISH clears the retained message on the topic
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"topicName".into(), &"test/retained".into()).unwrap();
+        js_sys::Reflect::set(&options, &"payload".into(), &"".into()).unwrap();
+        js_sys::Reflect::set(&options, &"retain".into(), &true.into()).unwrap();
+
+        let packet = client.new_publish_packet(options.into()).unwrap();
+        let publish = client.as_publish(&packet);
+        assert_eq!(get_string(&publish, "payload"), Some(String::new()));
+        assert_eq!(get_bool(&publish, "retain"), Some(true));
+
+        // payloadBytes: [] yields the same packet
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"topicName".into(), &"test/retained".into()).unwrap();
+        js_sys::Reflect::set(&options, &"payloadBytes".into(), &js_sys::Array::new()).unwrap();
+        js_sys::Reflect::set(&options, &"retain".into(), &true.into()).unwrap();
+        let from_bytes = client.new_publish_packet(options.into()).unwrap();
+        assert!(packet.equals(&from_bytes));
+    }
+
+    #[wasm_bindgen_test]
+    fn test_retained_clear_packet() {
+        let client = create_client_v311();
+        let packet = client.new_retained_clear_packet("test/retained").unwrap();
+        let publish = client.as_publish(&packet);
+        assert_eq!(
+            get_string(&publish, "topicName"),
+            Some("test/retained".to_string())
+        );
+        assert_eq!(get_string(&publish, "payload"), Some(String::new()));
+        assert_eq!(get_bool(&publish, "retain"), Some(true));
+        assert_eq!(get_number(&publish, "qos"), Some(0.0));
+
+        let v5 = WasmMqttPacket::new_retained_clear_v50("test/retained").unwrap();
+        assert_eq!(v5.protocol_version(), "5.0");
+    }
+
     #[wasm_bindgen_test]
     fn test_publish_v311_qos0_no_packet_id() {
         let client = create_client_v311();
@@ -1130,6 +1404,43 @@ mod packet_accessor_tests {
         );
     }
 
+    #[wasm_bindgen_test]
+    fn test_publish_v50_to_json_object() {
+        let client = create_client_v50();
+
+        let correlation_data = js_sys::Array::new();
+        for byte in [1u32, 2, 3] {
+            correlation_data.push(&byte.into());
+        }
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"topicName".into(), &"test/json".into()).unwrap();
+        js_sys::Reflect::set(&options, &"payload".into(), &"hi".into()).unwrap();
+        js_sys::Reflect::set(&options, &"correlationData".into(), &correlation_data).unwrap();
+
+        let packet = client.new_publish_packet(options.into()).unwrap();
+        let json = packet.to_json_object();
+
+        assert_eq!(get_string(&json, "type"), Some("Publish".to_string()));
+        assert_eq!(
+            get_string(&json, "protocolVersion"),
+            Some("5.0".to_string())
+        );
+        assert_eq!(
+            get_string(&json, "topicName"),
+            Some("test/json".to_string())
+        );
+        // Binary fields are base64 strings
+        assert_eq!(get_string(&json, "payload"), Some("aGk=".to_string()));
+        assert_eq!(
+            get_string(&json, "correlationData"),
+            Some("AQID".to_string())
+        );
+        // Absent fields are omitted
+        assert!(js_sys::Reflect::get(&json, &"contentType".into())
+            .unwrap()
+            .is_undefined());
+    }
+
     // ------------------------------------------------------------------------
     // V5.0 PUBACK/PUBREC/PUBREL/PUBCOMP accessor tests
     // ------------------------------------------------------------------------
@@ -1154,6 +1465,40 @@ mod packet_accessor_tests {
         );
     }
 
+    #[wasm_bindgen_test]
+    fn test_qos_and_reason_code_constants() {
+        let client = create_client_v50();
+
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"packetId".into(), &7u32.into()).unwrap();
+        js_sys::Reflect::set(
+            &options,
+            &"reasonCode".into(),
+            &(WasmReasonCode::NoMatchingSubscribers as u32).into(),
+        )
+        .unwrap();
+        let packet = client.new_puback_packet(options.into()).unwrap();
+        let puback = client.as_puback(&packet);
+        assert_eq!(get_number(&puback, "reasonCode"), Some(16.0));
+
+        let options = js_sys::Object::new();
+        js_sys::Reflect::set(&options, &"topicName".into(), &"test/constants".into()).unwrap();
+        js_sys::Reflect::set(
+            &options,
+            &"qos".into(),
+            &(WasmQos::ExactlyOnce as u32).into(),
+        )
+        .unwrap();
+        js_sys::Reflect::set(&options, &"packetId".into(), &8u32.into()).unwrap();
+        let packet = client.new_publish_packet(options.into()).unwrap();
+        let publish = client.as_publish(&packet);
+        assert_eq!(get_number(&publish, "qos"), Some(2.0));
+
+        assert_eq!(WasmReasonCode::Success as u8, 0);
+        assert_eq!(WasmReasonCode::NotAuthorized as u8, 135);
+        assert_eq!(WasmReasonCode::WildcardSubscriptionsNotSupported as u8, 162);
+    }
+
     #[wasm_bindgen_test]
     fn test_pubrec_v50_accessor() {
         let client = create_client_v50();
@@ -1326,3 +1671,273 @@ mod packet_accessor_tests {
         assert_eq!(packet.packet_type(), WasmPacketType::Disconnect);
     }
 }
+
+// ============================================================================
+// Round-Trip Tests (build -> toBytes -> fromBytes)
+// ============================================================================
+
+mod round_trip_tests {
+    use super::*;
+    use wasm_bindgen::JsValue;
+
+    fn object(fields: &[(&str, JsValue)]) -> JsValue {
+        let options = js_sys::Object::new();
+        for (key, value) in fields {
+            js_sys::Reflect::set(&options, &(*key).into(), value).unwrap();
+        }
+        options.into()
+    }
+
+    fn bytes(data: &[u8]) -> JsValue {
+        js_sys::Uint8Array::from(data).into()
+    }
+
+    fn user_properties() -> JsValue {
+        let entries = js_sys::Array::new();
+        entries.push(&object(&[("key", "k1".into()), ("value", "v1".into())]));
+        entries.push(&object(&[("key", "k2".into()), ("value", "v2".into())]));
+        entries.into()
+    }
+
+    /// Serialize, re-parse and check nothing was lost on the way
+    fn assert_round_trip(packet: &WasmMqttPacket, version: &str) {
+        let wire = packet.to_bytes();
+        let parsed = WasmMqttPacket::from_bytes(&wire, version).unwrap_or_else(|e| {
+            panic!(
+                "{} did not parse back: {:?}",
+                packet.packet_type_string(),
+                e
+            )
+        });
+        assert_eq!(parsed.packet_type(), packet.packet_type());
+        assert_eq!(parsed.protocol_version(), version);
+        assert!(
+            packet.equals(&parsed),
+            "{} changed in a round trip: {} -> {}",
+            packet.packet_type_string(),
+            packet.to_hex(),
+            parsed.to_hex()
+        );
+    }
+
+    /// Packets only the server sends: parse, then serialize to the same bytes
+    fn assert_bytes_round_trip(wire: &[u8], version: &str, packet_type: WasmPacketType) {
+        let parsed = WasmMqttPacket::from_bytes(wire, version).unwrap();
+        assert_eq!(parsed.packet_type(), packet_type);
+        assert_eq!(parsed.to_bytes(), wire);
+    }
+
+    fn client_packets(factory: &WasmPacketFactory, v5: bool) -> Vec<WasmMqttPacket> {
+        let will = object(&[
+            ("topic", "client/status".into()),
+            ("payloadBytes", bytes(&[0x00, 0xff])),
+            ("qos", 1u32.into()),
+            ("retain", true.into()),
+            ("willDelayInterval", 30u32.into()),
+            ("payloadFormatIndicator", 0u32.into()),
+            ("messageExpiryInterval", 60u32.into()),
+            ("contentType", "application/octet-stream".into()),
+            ("responseTopic", "client/reply".into()),
+            ("correlationData", bytes(&[1, 2, 3])),
+            ("userProperties", user_properties()),
+        ]);
+        let mut packets = vec![
+            factory
+                .new_connect_packet(object(&[
+                    ("clientId", "round-trip".into()),
+                    ("keepAlive", 30u32.into()),
+                    ("cleanSession", false.into()),
+                    ("userName", "user".into()),
+                    ("password", "secret".into()),
+                    ("will", will),
+                    ("sessionExpiryInterval", 120u32.into()),
+                    ("receiveMaximum", 10u32.into()),
+                    ("maximumPacketSize", 65536u32.into()),
+                    ("topicAliasMaximum", 5u32.into()),
+                    ("requestResponseInformation", true.into()),
+                    ("requestProblemInformation", false.into()),
+                    ("userProperties", user_properties()),
+                    ("authenticationMethod", "SCRAM-SHA-256".into()),
+                    ("authenticationData", bytes(&[9, 8, 7])),
+                ]))
+                .unwrap(),
+            factory
+                .new_publish_packet(object(&[
+                    ("topicName", "test/qos0".into()),
+                    ("payload", "".into()),
+                    ("retain", true.into()),
+                ]))
+                .unwrap(),
+            factory
+                .new_publish_packet(object(&[
+                    ("topicName", "test/qos2".into()),
+                    ("payloadBytes", bytes(&[0x00, 0x01, 0xfe, 0xff])),
+                    ("qos", 2u32.into()),
+                    ("dup", true.into()),
+                    ("packetId", 42u32.into()),
+                    ("payloadFormatIndicator", 1u32.into()),
+                    ("messageExpiryInterval", 3600u32.into()),
+                    ("topicAlias", 3u32.into()),
+                    ("responseTopic", "test/reply".into()),
+                    ("correlationData", bytes(&[4, 5, 6])),
+                    ("contentType", "text/plain".into()),
+                    ("userProperties", user_properties()),
+                ]))
+                .unwrap(),
+            factory.new_retained_clear_packet("test/retained").unwrap(),
+        ];
+
+        let subscriptions = js_sys::Array::new();
+        subscriptions.push(&object(&[
+            ("topic", "test/a/#".into()),
+            ("qos", 2u32.into()),
+        ]));
+        subscriptions.push(&object(&[
+            ("topic", "test/b/+".into()),
+            ("qos", 1u32.into()),
+            ("noLocal", true.into()),
+            ("retainAsPublished", true.into()),
+            ("retainHandling", 2u32.into()),
+        ]));
+        packets.push(
+            factory
+                .new_subscribe_packet(object(&[
+                    ("packetId", 7u32.into()),
+                    ("subscriptions", subscriptions.into()),
+                    ("subscriptionIdentifier", 268435455u32.into()),
+                    ("userProperties", user_properties()),
+                ]))
+                .unwrap(),
+        );
+
+        let topics = js_sys::Array::new();
+        topics.push(&"test/a/#".into());
+        topics.push(&"test/b/+".into());
+        packets.push(
+            factory
+                .new_unsubscribe_packet(object(&[
+                    ("packetId", 8u32.into()),
+                    ("topics", topics.into()),
+                    ("userProperties", user_properties()),
+                ]))
+                .unwrap(),
+        );
+
+        // v5.0 responses carry a non-success reason code so it is encoded
+        let response = |packet_id: u32, reason_code: u32| {
+            object(&[
+                ("packetId", packet_id.into()),
+                ("reasonCode", reason_code.into()),
+                ("reasonString", "because".into()),
+                ("userProperties", user_properties()),
+            ])
+        };
+        let (puback, pubrec, pubrel, pubcomp) = if v5 {
+            (
+                response(1, 0x10),
+                response(2, 0x10),
+                response(3, 0x92),
+                response(4, 0x92),
+            )
+        } else {
+            (
+                object(&[("packetId", 1u32.into())]),
+                object(&[("packetId", 2u32.into())]),
+                object(&[("packetId", 3u32.into())]),
+                object(&[("packetId", 4u32.into())]),
+            )
+        };
+        packets.push(factory.new_puback_packet(puback).unwrap());
+        packets.push(factory.new_pubrec_packet(pubrec).unwrap());
+        packets.push(factory.new_pubrel_packet(pubrel).unwrap());
+        packets.push(factory.new_pubcomp_packet(pubcomp).unwrap());
+        packets.push(factory.new_pingreq_packet());
+        packets.push(
+            factory
+                .new_disconnect_packet(object(&[
+                    ("reasonCode", 0x04u32.into()),
+                    ("reasonString", "bye".into()),
+                    ("sessionExpiryInterval", 0u32.into()),
+                    ("userProperties", user_properties()),
+                ]))
+                .unwrap(),
+        );
+        if v5 {
+            packets.push(
+                factory
+                    .new_auth_packet(object(&[
+                        ("reasonCode", 0x18u32.into()),
+                        ("authenticationMethod", "SCRAM-SHA-256".into()),
+                        ("authenticationData", bytes(&[1, 2, 3, 4])),
+                        ("reasonString", "continue".into()),
+                        ("userProperties", user_properties()),
+                    ]))
+                    .unwrap(),
+            );
+        }
+        packets
+    }
+
+    #[wasm_bindgen_test]
+    fn test_round_trip_v311_client_packets() {
+        let factory = WasmPacketFactory::new("3.1.1").unwrap();
+        for packet in client_packets(&factory, false) {
+            assert_round_trip(&packet, "3.1.1");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_round_trip_v50_client_packets() {
+        let factory = WasmPacketFactory::new("5.0").unwrap();
+        for packet in client_packets(&factory, true) {
+            assert_round_trip(&packet, "5.0");
+        }
+    }
+
+    #[wasm_bindgen_test]
+    fn test_round_trip_v311_server_packets() {
+        // CONNACK with session present, SUBACK with a failure return code
+        assert_bytes_round_trip(&[0x20, 0x02, 0x01, 0x00], "3.1.1", WasmPacketType::Connack);
+        assert_bytes_round_trip(
+            &[0x90, 0x05, 0x00, 0x07, 0x02, 0x01, 0x80],
+            "3.1.1",
+            WasmPacketType::Suback,
+        );
+        assert_bytes_round_trip(&[0xb0, 0x02, 0x00, 0x08], "3.1.1", WasmPacketType::Unsuback);
+        assert_bytes_round_trip(&[0xd0, 0x00], "3.1.1", WasmPacketType::Pingresp);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_round_trip_v50_server_packets() {
+        // CONNACK: session present, success, Receive Maximum 10
+        assert_bytes_round_trip(
+            &[0x20, 0x06, 0x01, 0x00, 0x03, 0x21, 0x00, 0x0a],
+            "5.0",
+            WasmPacketType::Connack,
+        );
+        assert_bytes_round_trip(
+            &[0x90, 0x06, 0x00, 0x07, 0x00, 0x02, 0x01, 0x87],
+            "5.0",
+            WasmPacketType::Suback,
+        );
+        assert_bytes_round_trip(
+            &[0xb0, 0x05, 0x00, 0x08, 0x00, 0x00, 0x11],
+            "5.0",
+            WasmPacketType::Unsuback,
+        );
+        assert_bytes_round_trip(&[0xd0, 0x00], "5.0", WasmPacketType::Pingresp);
+    }
+
+    #[wasm_bindgen_test]
+    fn test_from_bytes_rejects_malformed() {
+        // Empty, truncated body and truncated remaining length
+        assert!(WasmMqttPacket::from_bytes(&[], "3.1.1").is_err());
+        assert!(WasmMqttPacket::from_bytes(&[0x40, 0x02, 0x00], "3.1.1").is_err());
+        assert!(WasmMqttPacket::from_bytes(&[0x30, 0x80], "3.1.1").is_err());
+        // PUBREL must have flags 0b0010
+        assert!(WasmMqttPacket::from_bytes(&[0x60, 0x02, 0x00, 0x01], "3.1.1").is_err());
+        // AUTH does not exist in v3.1.1
+        assert!(WasmMqttPacket::from_bytes(&[0xf0, 0x00], "3.1.1").is_err());
+        assert!(WasmMqttPacket::from_bytes(&[0xf0, 0x00], "5.0").is_ok());
+    }
+}